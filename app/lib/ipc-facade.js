@@ -10,19 +10,21 @@ export type AccountData = { expiry: string };
 export type AccountToken = string;
 export type Ip = string;
 export type Location = {
-  ip: Ip,
+  ip: ?Ip,
   country: string,
   city: ?string,
   latitude: number,
   longitude: number,
+  mullvad_exit_ip_hostname: ?string,
   mullvad_exit_ip: boolean,
 };
 const LocationSchema = object({
-  ip: string,
+  ip: maybe(string),
   country: string,
   city: maybe(string),
   latitude: number,
   longitude: number,
+  mullvad_exit_ip_hostname: maybe(string),
   mullvad_exit_ip: boolean,
 });
 
@@ -31,7 +31,7 @@ type DisconnectedAction = {
 type NewLocationAction = {
   type: 'NEW_LOCATION',
   newLocation: {
-    ip: Ip,
+    ip: ?Ip,
     country: string,
     city: ?string,
     latitude: number,
@@ -63,7 +63,7 @@ impl Account {
         let account_token: Option<AccountToken> = rpc::call("get_account", &[] as &[u8; 0])?;
         if let Some(account_token) = account_token {
             let expiry: AccountData = rpc::call("get_account_data", &[&account_token])?;
-            println!("Mullvad account: {}", account_token);
+            println!("Mullvad account: {}", account_token.as_str());
             println!("Expires at     : {}", expiry.expiry);
         } else {
             println!("No account configured");
@@ -0,0 +1,59 @@
+use clap;
+use rpc;
+use {Command, Result};
+
+pub struct BlockWhenDisconnected;
+
+impl Command for BlockWhenDisconnected {
+    fn name(&self) -> &'static str {
+        "block-when-disconnected"
+    }
+
+    fn clap_subcommand(&self) -> clap::App<'static, 'static> {
+        clap::SubCommand::with_name(self.name())
+            .about("Control if all traffic should be blocked while disconnected")
+            .setting(clap::AppSettings::SubcommandRequired)
+            .subcommand(
+                clap::SubCommand::with_name("set")
+                    .about("Change the block when disconnected setting")
+                    .arg(
+                        clap::Arg::with_name("policy")
+                            .required(true)
+                            .possible_values(&["on", "off"]),
+                    ),
+            )
+            .subcommand(
+                clap::SubCommand::with_name("get")
+                    .about("Display the current block when disconnected setting"),
+            )
+    }
+
+    fn run(&self, matches: &clap::ArgMatches) -> Result<()> {
+        if let Some(set_matches) = matches.subcommand_matches("set") {
+            let block_when_disconnected = value_t_or_exit!(set_matches.value_of("policy"), String);
+            self.set(block_when_disconnected == "on")
+        } else if let Some(_matches) = matches.subcommand_matches("get") {
+            self.get()
+        } else {
+            unreachable!("No block-when-disconnected command given");
+        }
+    }
+}
+
+impl BlockWhenDisconnected {
+    fn set(&self, block_when_disconnected: bool) -> Result<()> {
+        rpc::call("set_block_when_disconnected", &[block_when_disconnected]).map(|_: Option<()>| {
+            println!("Changed block when disconnected setting");
+        })
+    }
+
+    fn get(&self) -> Result<()> {
+        let block_when_disconnected: bool =
+            rpc::call("get_block_when_disconnected", &[] as &[u8; 0])?;
+        println!(
+            "Block traffic when disconnected: {}",
+            if block_when_disconnected { "on" } else { "off" }
+        );
+        Ok(())
+    }
+}
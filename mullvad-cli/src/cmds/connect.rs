@@ -3,6 +3,9 @@ use rpc;
 use Command;
 use Result;
 
+use super::wait;
+use mullvad_types::states::TargetState;
+
 pub struct Connect;
 
 impl Command for Connect {
@@ -13,10 +16,20 @@ impl Command for Connect {
     fn clap_subcommand(&self) -> clap::App<'static, 'static> {
         clap::SubCommand::with_name(self.name())
             .about("Command the client to start establishing a VPN tunnel")
+            .arg(wait::wait_arg())
     }
 
-    fn run(&self, _matches: &clap::ArgMatches) -> Result<()> {
+    fn run(&self, matches: &clap::ArgMatches) -> Result<()> {
+        wait::ensure_account_is_set()?;
+        if wait::has_reached(wait::get_state()?, TargetState::Secured) {
+            println!("Already connected");
+            return Ok(());
+        }
+
         let _response: Option<()> = rpc::call("connect", &[] as &[u8; 0])?;
+        if matches.is_present("wait") {
+            wait::wait_for_target_state(TargetState::Secured)?;
+        }
         Ok(())
     }
 }
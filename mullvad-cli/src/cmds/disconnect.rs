@@ -3,6 +3,9 @@ use rpc;
 use Command;
 use Result;
 
+use super::wait;
+use mullvad_types::states::TargetState;
+
 pub struct Disconnect;
 
 impl Command for Disconnect {
@@ -13,10 +16,19 @@ impl Command for Disconnect {
     fn clap_subcommand(&self) -> clap::App<'static, 'static> {
         clap::SubCommand::with_name(self.name())
             .about("Command the client to disconnect the VPN tunnel")
+            .arg(wait::wait_arg())
     }
 
-    fn run(&self, _matches: &clap::ArgMatches) -> Result<()> {
+    fn run(&self, matches: &clap::ArgMatches) -> Result<()> {
+        if wait::has_reached(wait::get_state()?, TargetState::Unsecured) {
+            println!("Already disconnected");
+            return Ok(());
+        }
+
         let _response: Option<()> = rpc::call("disconnect", &[] as &[u8; 0])?;
+        if matches.is_present("wait") {
+            wait::wait_for_target_state(TargetState::Unsecured)?;
+        }
         Ok(())
     }
 }
@@ -4,6 +4,9 @@ use Command;
 mod account;
 pub use self::account::Account;
 
+mod block_when_disconnected;
+pub use self::block_when_disconnected::BlockWhenDisconnected;
+
 mod status;
 pub use self::status::Status;
 
@@ -13,6 +16,9 @@ pub use self::connect::Connect;
 mod disconnect;
 pub use self::disconnect::Disconnect;
 
+mod reconnect;
+pub use self::reconnect::Reconnect;
+
 mod relay;
 pub use self::relay::Relay;
 
@@ -28,13 +34,17 @@ pub use self::tunnel::Tunnel;
 mod version;
 pub use self::version::Version;
 
+mod wait;
+
 /// Returns a map of all available subcommands with their name as key.
 pub fn get_commands() -> HashMap<&'static str, Box<Command>> {
     let commands: Vec<Box<Command>> = vec![
         Box::new(Account),
+        Box::new(BlockWhenDisconnected),
         Box::new(Status),
         Box::new(Connect),
         Box::new(Disconnect),
+        Box::new(Reconnect),
         Box::new(Shutdown),
         Box::new(Relay),
         Box::new(Lan),
@@ -49,3 +59,25 @@ pub fn get_commands() -> HashMap<&'static str, Box<Command>> {
     }
     map
 }
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tunnel_commands_take_wait_flag() {
+        let commands = get_commands();
+        for name in &["connect", "disconnect", "reconnect"] {
+            let command = &commands[name];
+            let matches = command
+                .clap_subcommand()
+                .get_matches_from_safe(&[*name, "--wait"])
+                .unwrap();
+            assert!(matches.is_present("wait"));
+
+            let matches = command.clap_subcommand().get_matches_from_safe(&[*name]).unwrap();
+            assert!(!matches.is_present("wait"));
+        }
+    }
+}
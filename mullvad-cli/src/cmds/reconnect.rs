@@ -0,0 +1,36 @@
+use clap;
+use rpc;
+use Command;
+use Result;
+
+use super::wait;
+use mullvad_types::states::TargetState;
+
+pub struct Reconnect;
+
+impl Command for Reconnect {
+    fn name(&self) -> &'static str {
+        "reconnect"
+    }
+
+    fn clap_subcommand(&self) -> clap::App<'static, 'static> {
+        clap::SubCommand::with_name(self.name())
+            .about("Command the client to disconnect the VPN tunnel and then establish it again")
+            .arg(wait::wait_arg())
+    }
+
+    fn run(&self, matches: &clap::ArgMatches) -> Result<()> {
+        wait::ensure_account_is_set()?;
+
+        // Connecting does nothing while the old tunnel is still up, so it has to be fully
+        // disconnected first.
+        let _response: Option<()> = rpc::call("disconnect", &[] as &[u8; 0])?;
+        wait::wait_for_target_state(TargetState::Unsecured)?;
+
+        let _response: Option<()> = rpc::call("connect", &[] as &[u8; 0])?;
+        if matches.is_present("wait") {
+            wait::wait_for_target_state(TargetState::Secured)?;
+        }
+        Ok(())
+    }
+}
@@ -64,17 +64,16 @@ impl Command for Relay {
                             .arg(
                                 clap::Arg::with_name("country")
                                     .help(
-                                        "The two letter country code, or 'any' for no preference.",
+                                        "The two letter country code or the country name, or \
+                                         'any' for no preference.",
                                     )
                                     .required(true)
-                                    .index(1)
-                                    .validator(country_code_validator),
+                                    .index(1),
                             )
                             .arg(
                                 clap::Arg::with_name("city")
-                                    .help("The three letter city code")
-                                    .index(2)
-                                    .validator(city_code_validator),
+                                    .help("The three letter city code or the city name")
+                                    .index(2),
                             ),
                     )
                     .subcommand(
@@ -142,31 +141,34 @@ impl Relay {
             "wireguard" => TunnelEndpointData::Wireguard(WireguardEndpointData { port }),
             _ => unreachable!("Invalid tunnel protocol"),
         };
-        self.update_constraints(RelaySettingsUpdate::CustomTunnelEndpoint(
-            CustomTunnelEndpoint { host, tunnel },
-        ))
+        let endpoint =
+            CustomTunnelEndpoint::new(host, tunnel).chain_err(|| "Invalid custom relay")?;
+        self.update_constraints(RelaySettingsUpdate::CustomTunnelEndpoint(endpoint))
     }
 
     fn set_location(&self, matches: &clap::ArgMatches) -> Result<()> {
         let country = matches.value_of("country").unwrap();
         let city = matches.value_of("city");
 
-        let location_constraint = match (country, city) {
-            ("any", None) => Constraint::Any,
-            ("any", _) => clap::Error::with_description(
-                "City can't be given when selecting 'any' country",
-                clap::ErrorKind::InvalidValue,
-            ).exit(),
-            (country, None) => Constraint::Only(LocationConstraint::Country(country.to_owned())),
-            (country, Some(city)) => Constraint::Only(LocationConstraint::City(
-                country.to_owned(),
-                city.to_owned(),
-            )),
+        let location_constraint = if country.eq_ignore_ascii_case("any") {
+            if city.is_some() {
+                clap::Error::with_description(
+                    "City can't be given when selecting 'any' country",
+                    clap::ErrorKind::InvalidValue,
+                ).exit();
+            }
+            Constraint::Any
+        } else {
+            let locations: RelayList = rpc::call("get_relay_locations", &[] as &[u8; 0])?;
+            let location = locations
+                .location_constraint(country, city, None)
+                .chain_err(|| "Invalid location")?;
+            Constraint::Only(location)
         };
 
         self.update_constraints(RelaySettingsUpdate::Normal(RelayConstraintsUpdate {
             location: Some(location_constraint),
-            tunnel: None,
+            ..RelayConstraintsUpdate::default()
         }))
     }
 
@@ -175,16 +177,33 @@ impl Relay {
         let protocol = parse_protocol_constraint(matches.value_of("protocol").unwrap());
 
         self.update_constraints(RelaySettingsUpdate::Normal(RelayConstraintsUpdate {
-            location: None,
             tunnel: Some(Constraint::Only(TunnelConstraints::OpenVpn(
                 OpenVpnConstraints { port, protocol },
             ))),
+            ..RelayConstraintsUpdate::default()
         }))
     }
 
     fn get(&self) -> Result<()> {
         let constraints: RelaySettings = rpc::call("get_relay_settings", &[] as &[u8; 0])?;
-        println!("Current constraints: {:#?}", constraints);
+        match constraints {
+            RelaySettings::Normal(ref constraints) => {
+                println!("Location: {}", format_location(&constraints.location));
+                match constraints.tunnel {
+                    Constraint::Only(TunnelConstraints::OpenVpn(ref openvpn)) => println!(
+                        "Tunnel  : OpenVPN, port {}, protocol {}",
+                        openvpn.port, openvpn.protocol
+                    ),
+                    Constraint::Only(TunnelConstraints::Wireguard(ref wireguard)) => {
+                        println!("Tunnel  : WireGuard, port {}", wireguard.port)
+                    }
+                    Constraint::Any => println!("Tunnel  : any"),
+                }
+            }
+            RelaySettings::CustomTunnelEndpoint(ref custom) => {
+                println!("Custom relay: {:#?}", custom)
+            }
+        }
 
         Ok(())
     }
@@ -209,35 +228,25 @@ impl Relay {
 
 
 fn parse_port_constraint(raw_port: &str) -> Result<Constraint<u16>> {
-    match raw_port.to_lowercase().as_str() {
-        "any" => Ok(Constraint::Any),
-        port => Ok(Constraint::Only(u16::from_str(port).chain_err(|| "Invalid port")?)),
-    }
+    Constraint::from_str(raw_port).chain_err(|| "Invalid port")
 }
 
 /// Parses a protocol constraint string. Can be infallible because the possible values are limited
 /// with clap.
 fn parse_protocol_constraint(raw_protocol: &str) -> Constraint<TransportProtocol> {
-    match raw_protocol.to_lowercase().as_str() {
-        "any" => Constraint::Any,
-        "udp" => Constraint::Only(TransportProtocol::Udp),
-        "tcp" => Constraint::Only(TransportProtocol::Tcp),
-        _ => unreachable!(),
-    }
+    Constraint::from_str(&raw_protocol.to_lowercase()).unwrap()
 }
 
-fn country_code_validator(code: String) -> ::std::result::Result<(), String> {
-    if code.len() == 2 || code == "any" {
-        Ok(())
-    } else {
-        Err(String::from("Country codes must be two letters, or 'any'."))
-    }
-}
-
-fn city_code_validator(code: String) -> ::std::result::Result<(), String> {
-    if code.len() == 3 {
-        Ok(())
-    } else {
-        Err(String::from("City codes must be three letters"))
+fn format_location(location: &Constraint<LocationConstraint>) -> String {
+    match *location {
+        Constraint::Any => "any".to_owned(),
+        Constraint::Only(LocationConstraint::Country(ref country)) => country.name().to_owned(),
+        Constraint::Only(LocationConstraint::City(ref country, ref city)) => {
+            format!("{}, {}", city.name(country), country.name())
+        }
+        Constraint::Only(LocationConstraint::Hostname(ref country, ref city, ref hostname)) => {
+            format!("{} in {}, {}", hostname, city.name(country), country.name())
+        }
+        Constraint::Only(LocationConstraint::Nearest) => "nearest country".to_owned(),
     }
 }
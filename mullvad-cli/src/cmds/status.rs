@@ -3,7 +3,7 @@ use Command;
 use Result;
 
 use mullvad_types::location::GeoIpLocation;
-use mullvad_types::states::{DaemonState, SecurityState, TargetState};
+use mullvad_types::states::DaemonState;
 use rpc;
 
 pub struct Status;
@@ -19,13 +19,7 @@ impl Command for Status {
 
     fn run(&self, _matches: &clap::ArgMatches) -> Result<()> {
         let state: DaemonState = rpc::call("get_state", &[] as &[u8; 0])?;
-        print!("Tunnel status: ");
-        match (state.state, state.target_state) {
-            (SecurityState::Unsecured, TargetState::Unsecured) => println!("Disconnected"),
-            (SecurityState::Unsecured, TargetState::Secured) => println!("Connecting..."),
-            (SecurityState::Secured, TargetState::Unsecured) => println!("Disconnecting..."),
-            (SecurityState::Secured, TargetState::Secured) => println!("Connected"),
-        }
+        println!("Tunnel status: {}", state);
 
         let location: GeoIpLocation = rpc::call("get_current_location", &[] as &[u8; 0])?;
         let city_and_country = if let Some(city) = location.city {
@@ -38,7 +32,12 @@ impl Command for Status {
             "Position: {:.5}°N, {:.5}°W",
             location.latitude, location.longitude
         );
-        println!("IP: {}", location.ip);
+        if let Some(ip) = location.ip {
+            println!("IP: {}", ip);
+        }
+        if let Some(hostname) = location.hostname {
+            println!("Relay: {}", hostname);
+        }
         Ok(())
     }
 }
@@ -2,7 +2,8 @@ use clap;
 use {Command, Result};
 
 use rpc;
-use talpid_types::net::{OpenVpnTunnelOptions, TunnelOptions};
+use talpid_types::net::{OpenVpnTunnelOptions, TunnelOptions, WireguardTunnelOptions,
+                        WIREGUARD_MTU_RANGE};
 
 pub struct Tunnel;
 
@@ -35,7 +36,40 @@ impl Command for Tunnel {
                     )
                     .subcommand(
                         clap::SubCommand::with_name("get")
-                            .help("Retrieves the current setting for mssfix"),
+                            .help("Retrieves the current OpenVPN tunnel options"),
+                    ),
+            )
+            .subcommand(
+                clap::SubCommand::with_name("wireguard")
+                    .about("Manage options for WireGuard tunnels")
+                    .setting(clap::AppSettings::SubcommandRequired)
+                    .subcommand(
+                        clap::SubCommand::with_name("set")
+                            .subcommand(
+                                clap::SubCommand::with_name("mtu").arg(
+                                    clap::Arg::with_name("mtu")
+                                        .help(
+                                            "Sets the MTU of the tunnel interface. \
+                                             Set an empty string to clear it.",
+                                        )
+                                        .required(true),
+                                ),
+                            )
+                            .subcommand(
+                                clap::SubCommand::with_name("keepalive").arg(
+                                    clap::Arg::with_name("keepalive")
+                                        .help(
+                                            "Sets how often, in seconds, to send keepalive \
+                                             packets. Set an empty string to clear it.",
+                                        )
+                                        .required(true),
+                                ),
+                            )
+                            .setting(clap::AppSettings::SubcommandRequired),
+                    )
+                    .subcommand(
+                        clap::SubCommand::with_name("get")
+                            .help("Retrieves the current WireGuard tunnel options"),
                     ),
             )
     }
@@ -43,6 +77,8 @@ impl Command for Tunnel {
     fn run(&self, matches: &clap::ArgMatches) -> Result<()> {
         if let Some(openvpn_matches) = matches.subcommand_matches("openvpn") {
             Self::handle_openvpn_cmd(openvpn_matches)
+        } else if let Some(wireguard_matches) = matches.subcommand_matches("wireguard") {
+            Self::handle_wireguard_cmd(wireguard_matches)
         } else {
             unreachable!("No tunnel command given")
         }
@@ -64,12 +100,7 @@ impl Tunnel {
 
     fn set_openvpn_option(matches: &clap::ArgMatches) -> Result<()> {
         if let Some(mssfix_args) = matches.subcommand_matches("mssfix") {
-            let mssfix_str = mssfix_args.value_of("mssfix").unwrap();
-            let mssfix: Option<u16> = if mssfix_str == "" {
-                None
-            } else {
-                Some(mssfix_str.parse()?)
-            };
+            let mssfix = parse_optional_u16(mssfix_args.value_of("mssfix").unwrap())?;
 
             rpc::call("set_openvpn_mssfix", &[mssfix])
                 .map(|_: ()| println!("mssfix parameter updated"))
@@ -78,6 +109,31 @@ impl Tunnel {
         }
     }
 
+    fn handle_wireguard_cmd(matches: &clap::ArgMatches) -> Result<()> {
+        if let Some(set_matches) = matches.subcommand_matches("set") {
+            Self::set_wireguard_option(set_matches)
+        } else if let Some(_) = matches.subcommand_matches("get") {
+            let wireguard_options = Self::get_tunnel_options()?.wireguard;
+            Self::print_wireguard_tunnel_options(&wireguard_options);
+            Ok(())
+        } else {
+            unreachable!("Unrecognized subcommand");
+        }
+    }
+
+    fn set_wireguard_option(matches: &clap::ArgMatches) -> Result<()> {
+        if let Some(mtu_args) = matches.subcommand_matches("mtu") {
+            let mtu = parse_wireguard_mtu(mtu_args.value_of("mtu").unwrap())?;
+            rpc::call("set_wireguard_mtu", &[mtu]).map(|_: ()| println!("MTU parameter updated"))
+        } else if let Some(keepalive_args) = matches.subcommand_matches("keepalive") {
+            let keepalive = parse_optional_u16(keepalive_args.value_of("keepalive").unwrap())?;
+            rpc::call("set_wireguard_keepalive", &[keepalive])
+                .map(|_: ()| println!("keepalive parameter updated"))
+        } else {
+            unreachable!("Invalid option passed to 'wireguard set'");
+        }
+    }
+
     fn get_tunnel_options() -> Result<TunnelOptions> {
         rpc::call("get_tunnel_options", &[] as &[u8; 0])
     }
@@ -91,5 +147,95 @@ impl Tunnel {
                 .map(|v| v.to_string())
                 .unwrap_or("UNSET".to_string())
         );
+        println!(
+            "\tfragment: {}",
+            options
+                .fragment
+                .map(|v| v.to_string())
+                .unwrap_or("UNSET".to_string())
+        );
+    }
+
+    fn print_wireguard_tunnel_options(options: &WireguardTunnelOptions) {
+        println!("WireGuard tunnel options");
+        println!(
+            "\tmtu: {}",
+            options
+                .mtu
+                .map(|v| v.to_string())
+                .unwrap_or("UNSET".to_string())
+        );
+        println!(
+            "\tkeepalive: {}",
+            options
+                .keepalive
+                .map(|v| v.to_string())
+                .unwrap_or("UNSET".to_string())
+        );
+    }
+}
+
+
+/// Parses an optional tunnel option. An empty string means the option should be unset.
+fn parse_optional_u16(value: &str) -> Result<Option<u16>> {
+    if value == "" {
+        Ok(None)
+    } else {
+        Ok(Some(value.parse()?))
+    }
+}
+
+/// Parses a WireGuard MTU, checking that it is within `WIREGUARD_MTU_RANGE` so an invalid value is
+/// rejected before it is sent to the daemon.
+fn parse_wireguard_mtu(value: &str) -> Result<Option<u16>> {
+    let mtu = parse_optional_u16(value)?;
+    if let Some(mtu) = mtu {
+        let (min, max) = WIREGUARD_MTU_RANGE;
+        if mtu < min || mtu > max {
+            bail!("MTU {} is not between {} and {}", mtu, min, max);
+        }
+    }
+    Ok(mtu)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_args(args: &[&str]) -> clap::Result<clap::ArgMatches<'static>> {
+        Tunnel.clap_subcommand().get_matches_from_safe(args)
+    }
+
+    #[test]
+    fn parses_wireguard_set_commands() {
+        let matches = parse_args(&["tunnel", "wireguard", "set", "mtu", "1380"]).unwrap();
+        let mtu = matches
+            .subcommand_matches("wireguard")
+            .and_then(|matches| matches.subcommand_matches("set"))
+            .and_then(|matches| matches.subcommand_matches("mtu"))
+            .and_then(|matches| matches.value_of("mtu"));
+        assert_eq!(mtu, Some("1380"));
+
+        assert!(parse_args(&["tunnel", "wireguard", "set", "keepalive", ""]).is_ok());
+        assert!(parse_args(&["tunnel", "wireguard", "get"]).is_ok());
+        assert!(parse_args(&["tunnel", "wireguard", "set", "mtu"]).is_err());
+        assert!(parse_args(&["tunnel", "wireguard", "set"]).is_err());
+    }
+
+    #[test]
+    fn empty_value_unsets_option() {
+        assert_eq!(parse_optional_u16("").unwrap(), None);
+        assert_eq!(parse_optional_u16("25").unwrap(), Some(25));
+        assert!(parse_optional_u16("abc").is_err());
+        assert_eq!(parse_wireguard_mtu("").unwrap(), None);
+    }
+
+    #[test]
+    fn mtu_outside_range_is_rejected() {
+        assert_eq!(parse_wireguard_mtu("1280").unwrap(), Some(1280));
+        assert_eq!(parse_wireguard_mtu("1500").unwrap(), Some(1500));
+        assert!(parse_wireguard_mtu("1279").is_err());
+        assert!(parse_wireguard_mtu("1501").is_err());
     }
 }
@@ -0,0 +1,132 @@
+use clap;
+use Result;
+
+use mullvad_types::account::AccountToken;
+use mullvad_types::states::{DaemonState, SecurityState, TargetState};
+use rpc;
+
+use std::thread;
+use std::time::Duration;
+
+/// How often to ask the daemon for its state while waiting for it to change.
+const POLL_INTERVAL_MS: u64 = 200;
+
+
+/// The `--wait` flag shared by the commands that change the target state of the daemon.
+pub fn wait_arg() -> clap::Arg<'static, 'static> {
+    clap::Arg::with_name("wait")
+        .long("wait")
+        .short("w")
+        .help("Wait until the tunnel has reached the new state, printing its state as it changes")
+}
+
+pub fn get_state() -> Result<DaemonState> {
+    rpc::call("get_state", &[] as &[u8; 0])
+}
+
+/// Fails with a message telling the user how to set an account if the daemon has none, since it
+/// can not connect without one.
+pub fn ensure_account_is_set() -> Result<()> {
+    let account_token: Option<AccountToken> = rpc::call("get_account", &[] as &[u8; 0])?;
+    if account_token.is_none() {
+        bail!("No account is set. Set one with 'mullvad account set <token>' before connecting");
+    }
+    Ok(())
+}
+
+/// Waits until the daemon has reached `target`, printing the states it goes through on the way.
+pub fn wait_for_target_state(target: TargetState) -> Result<()> {
+    wait_for_state(target, Duration::from_millis(POLL_INTERVAL_MS), get_state)
+}
+
+/// Returns true if the daemon strives towards `target` and has reached it.
+pub fn has_reached(state: DaemonState, target: TargetState) -> bool {
+    let security_state = match target {
+        TargetState::Secured => SecurityState::Secured,
+        TargetState::Unsecured => SecurityState::Unsecured,
+    };
+    state.target_state == target && state.state == security_state
+}
+
+/// Polls `get_state` every `interval` until the daemon has reached `target`. Fails if the daemon
+/// stops striving towards `target` before reaching it, like when the tunnel can not be started.
+fn wait_for_state<F>(target: TargetState, interval: Duration, mut get_state: F) -> Result<()>
+where
+    F: FnMut() -> Result<DaemonState>,
+{
+    let mut last_state = None;
+    loop {
+        let state = get_state()?;
+        if last_state != Some(state) {
+            println!("Tunnel status: {}", state);
+            last_state = Some(state);
+        }
+        if has_reached(state, target) {
+            return Ok(());
+        }
+        if state.target_state != target {
+            match target {
+                TargetState::Secured => bail!("Unable to connect, see the daemon log for details"),
+                TargetState::Unsecured => bail!("The tunnel was connected while disconnecting"),
+            }
+        }
+        thread::sleep(interval);
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::VecDeque;
+
+    fn state(state: SecurityState, target_state: TargetState) -> DaemonState {
+        DaemonState {
+            state,
+            target_state,
+        }
+    }
+
+    /// Waits for `target` while the daemon goes through `states`, and returns how many of them
+    /// were never asked for.
+    fn wait_through(target: TargetState, states: Vec<DaemonState>) -> (Result<()>, usize) {
+        let mut states = VecDeque::from(states);
+        let result = wait_for_state(target, Duration::from_millis(0), || {
+            Ok(states
+                .pop_front()
+                .expect("Asked for more states than expected"))
+        });
+        (result, states.len())
+    }
+
+    #[test]
+    fn waits_until_the_target_state_is_reached() {
+        let connecting = state(SecurityState::Unsecured, TargetState::Secured);
+        let connected = state(SecurityState::Secured, TargetState::Secured);
+        let (result, left) = wait_through(
+            TargetState::Secured,
+            vec![connecting, connecting, connected, connected],
+        );
+        assert!(result.is_ok());
+        assert_eq!(left, 1);
+
+        let disconnecting = state(SecurityState::Secured, TargetState::Unsecured);
+        let disconnected = state(SecurityState::Unsecured, TargetState::Unsecured);
+        let (result, left) =
+            wait_through(TargetState::Unsecured, vec![disconnecting, disconnected]);
+        assert!(result.is_ok());
+        assert_eq!(left, 0);
+    }
+
+    #[test]
+    fn fails_if_the_daemon_gives_up_on_the_target_state() {
+        let connecting = state(SecurityState::Unsecured, TargetState::Secured);
+        let disconnected = state(SecurityState::Unsecured, TargetState::Unsecured);
+        let (result, left) = wait_through(
+            TargetState::Secured,
+            vec![connecting, disconnected, connecting],
+        );
+        assert!(result.is_err());
+        assert_eq!(left, 1);
+    }
+}
@@ -15,8 +15,10 @@ extern crate lazy_static;
 extern crate regex;
 
 extern crate mullvad_rpc;
+extern crate mullvad_types;
 
 use error_chain::ChainedError;
+use mullvad_types::problem_report::ProblemReportMetadata;
 use regex::Regex;
 
 use std::borrow::Cow;
@@ -89,7 +91,8 @@ fn run() -> Result<()> {
                         .value_name("PHRASE")
                         .multiple(true)
                         .takes_value(true),
-                ),
+                )
+                .arg(account_present_arg()),
         )
         .subcommand(
             clap::SubCommand::with_name("send")
@@ -117,7 +120,8 @@ fn run() -> Result<()> {
                         .help("Reporter's message")
                         .takes_value(true)
                         .required(false),
-                ),
+                )
+                .arg(account_present_arg()),
         );
 
     let matches = app.get_matches();
@@ -131,23 +135,42 @@ fn run() -> Result<()> {
             .map(|os_values| os_values.map(Path::new).collect())
             .unwrap_or(Vec::new());
         let output_path = Path::new(collect_matches.value_of_os("output").unwrap());
-        collect_report(&log_paths, output_path, redact_custom_strings)
+        let metadata = collect_metadata(account_present(collect_matches));
+        collect_report(&log_paths, output_path, redact_custom_strings, metadata)
     } else if let Some(send_matches) = matches.subcommand_matches("send") {
         let report_path = Path::new(send_matches.value_of_os("report").unwrap());
         let user_email = send_matches.value_of("email").unwrap_or("");
         let user_message = send_matches.value_of("message").unwrap_or("");
-        send_problem_report(user_email, user_message, report_path)
+        let metadata = collect_metadata(account_present(send_matches));
+        send_problem_report(user_email, user_message, report_path, metadata)
     } else {
         unreachable!("No sub command given");
     }
 }
 
+fn account_present_arg() -> clap::Arg<'static, 'static> {
+    clap::Arg::with_name("account-present")
+        .long("account-present")
+        .help(
+            "Tell support whether the reporter has an account set in the app. Left out of the \
+             report if not given.",
+        )
+        .takes_value(true)
+        .possible_values(&["true", "false"])
+}
+
+/// Returns what the caller said about the account with `--account-present`, if anything.
+fn account_present(matches: &clap::ArgMatches) -> Option<bool> {
+    matches.value_of("account-present").map(|value| value == "true")
+}
+
 fn collect_report(
     log_paths: &[&Path],
     output_path: &Path,
     redact_custom_strings: Vec<String>,
+    metadata: ProblemReportMetadata,
 ) -> Result<()> {
-    let mut problem_report = ProblemReport::new(redact_custom_strings);
+    let mut problem_report = ProblemReport::new(redact_custom_strings, metadata);
     for log_path in log_paths {
         problem_report.add_log(log_path);
     }
@@ -155,10 +178,15 @@ fn collect_report(
         .chain_err(|| ErrorKind::WriteReportError(output_path.to_path_buf()))
 }
 
-fn send_problem_report(user_email: &str, user_message: &str, report_path: &Path) -> Result<()> {
+fn send_problem_report(
+    user_email: &str,
+    user_message: &str,
+    report_path: &Path,
+    metadata: ProblemReportMetadata,
+) -> Result<()> {
     let report_content = read_file_lossy(report_path, REPORT_MAX_SIZE)
         .chain_err(|| ErrorKind::ReadLogError(report_path.to_path_buf()))?;
-    let metadata = collect_metadata();
+    let metadata = metadata.into_map();
     let mut rpc_manager = mullvad_rpc::MullvadRpcFactory::new();
     let mut rpc_client = mullvad_rpc::ProblemReportProxy::connect(&mut rpc_manager)
         .chain_err(|| ErrorKind::RpcError)?;
@@ -188,9 +216,9 @@ struct ProblemReport {
 impl ProblemReport {
     /// Creates a new problem report with system information. Logs can be added with `add_log`.
     /// Logs will have all strings in `redact_custom_strings` removed from them.
-    pub fn new(redact_custom_strings: Vec<String>) -> Self {
+    pub fn new(redact_custom_strings: Vec<String>, metadata: ProblemReportMetadata) -> Self {
         ProblemReport {
-            metadata: collect_metadata(),
+            metadata: metadata.into_map(),
             logs: Vec::new(),
             redact_custom_strings,
         }
@@ -338,11 +366,13 @@ fn read_file_lossy(path: &Path, max_bytes: usize) -> io::Result<String> {
     Ok(String::from_utf8_lossy(&buffer).into_owned())
 }
 
-fn collect_metadata() -> HashMap<String, String> {
-    let mut metadata = HashMap::new();
-    metadata.insert(String::from("mullvad-daemon-version"), daemon_version());
-    metadata.insert(String::from("os"), os_version());
-    metadata
+fn collect_metadata(account_present: Option<bool>) -> ProblemReportMetadata {
+    ProblemReportMetadata {
+        os: os_name(),
+        os_version: os_version(),
+        app_version: daemon_version(),
+        account_present,
+    }
 }
 
 fn daemon_version() -> String {
@@ -353,31 +383,40 @@ fn daemon_version() -> String {
     )
 }
 
+#[cfg(target_os = "linux")]
+fn os_name() -> String {
+    String::from("Linux")
+}
+
 #[cfg(target_os = "linux")]
 fn os_version() -> String {
-    format!(
-        "Linux, {}",
-        command_stdout_lossy("lsb_release", &["-ds"])
-            .unwrap_or(String::from("[Failed to get LSB release]"))
-    )
+    command_stdout_lossy("lsb_release", &["-ds"])
+        .unwrap_or(String::from("[Failed to get LSB release]"))
+}
+
+#[cfg(target_os = "macos")]
+fn os_name() -> String {
+    String::from("macOS")
 }
 
 #[cfg(target_os = "macos")]
 fn os_version() -> String {
-    format!(
-        "macOS {}",
-        command_stdout_lossy("sw_vers", &["-productVersion"])
-            .unwrap_or(String::from("[Failed to detect version]"))
-    )
+    command_stdout_lossy("sw_vers", &["-productVersion"])
+        .unwrap_or(String::from("[Failed to detect version]"))
 }
 
 #[cfg(windows)]
-fn os_version() -> String {
+fn os_name() -> String {
     String::from("Windows")
 }
 
+#[cfg(windows)]
+fn os_version() -> String {
+    command_stdout_lossy("cmd", &["/C", "ver"])
+        .unwrap_or(String::from("[Failed to detect version]"))
+}
+
 /// Helper for getting stdout of some command as a String. Ignores the exit code of the command.
-#[cfg(any(target_os = "linux", target_os = "macos"))]
 fn command_stdout_lossy(cmd: &str, args: &[&str]) -> Option<String> {
     Command::new(cmd)
         .args(args)
@@ -390,6 +429,16 @@ fn command_stdout_lossy(cmd: &str, args: &[&str]) -> Option<String> {
 mod tests {
     use super::*;
 
+    /// Metadata that does not depend on the host the tests run on.
+    fn test_metadata() -> ProblemReportMetadata {
+        ProblemReportMetadata {
+            os: String::from("Linux"),
+            os_version: String::from("Test OS 1.0"),
+            app_version: String::from("2018.1"),
+            account_present: Some(false),
+        }
+    }
+
     #[test]
     fn redacts_ipv4() {
         assert_redacts_ipv4("1.2.3.4");
@@ -401,14 +450,14 @@ mod tests {
     }
 
     fn assert_redacts_ipv4(input: &str) {
-        let report = ProblemReport::new(vec![]);
+        let report = ProblemReport::new(vec![], test_metadata());
         let actual = report.redact(&format!("pre {} post", input));
         assert_eq!("pre [REDACTED] post", actual);
     }
 
     #[test]
     fn does_not_redact_localhost_ipv4() {
-        let report = ProblemReport::new(vec![]);
+        let report = ProblemReport::new(vec![], test_metadata());
         let res = report.redact("127.0.0.1");
         assert_eq!("127.0.0.1", res);
     }
@@ -430,27 +479,27 @@ mod tests {
 
     #[test]
     fn doesnt_redact_not_ipv6() {
-        let report = ProblemReport::new(vec![]);
+        let report = ProblemReport::new(vec![], test_metadata());
         let actual = report.redact("[talpid_core::firewall]");
         assert_eq!("[talpid_core::firewall]", actual);
     }
 
     fn assert_redacts_ipv6(input: &str) {
-        let report = ProblemReport::new(vec![]);
+        let report = ProblemReport::new(vec![], test_metadata());
         let actual = report.redact(&format!("pre {} post", input));
         assert_eq!("pre [REDACTED] post", actual);
     }
 
     #[test]
     fn test_does_not_redact_localhost_ipv6() {
-        let report = ProblemReport::new(vec![]);
+        let report = ProblemReport::new(vec![], test_metadata());
         let res = report.redact("::1");
         assert_eq!("::1", res);
     }
 
     #[test]
     fn test_does_not_redact_time() {
-        let report = ProblemReport::new(vec![]);
+        let report = ProblemReport::new(vec![], test_metadata());
         let res = report.redact("09:47:59");
         assert_eq!("09:47:59", res);
     }
@@ -11,6 +11,7 @@ pub struct Config {
     pub tunnel_log_file: Option<PathBuf>,
     pub resource_dir: Option<PathBuf>,
     pub require_auth: bool,
+    pub restore_dns: bool,
 }
 
 pub fn get_config() -> Config {
@@ -26,6 +27,7 @@ pub fn get_config() -> Config {
     let tunnel_log_file = matches.value_of_os("tunnel_log_file").map(PathBuf::from);
     let resource_dir = matches.value_of_os("resource_dir").map(PathBuf::from);
     let require_auth = !matches.is_present("disable_rpc_auth");
+    let restore_dns = matches.is_present("restore_dns");
 
     Config {
         log_level,
@@ -33,11 +35,12 @@ pub fn get_config() -> Config {
         tunnel_log_file,
         resource_dir,
         require_auth,
+        restore_dns,
     }
 }
 
 fn create_app() -> App<'static, 'static> {
-    App::new(crate_name!())
+    let app = App::new(crate_name!())
         .version(version::current())
         .author(crate_authors!())
         .about(crate_description!())
@@ -72,5 +75,14 @@ fn create_app() -> App<'static, 'static> {
             Arg::with_name("disable_rpc_auth")
                 .long("disable-rpc-auth")
                 .help("Don't require authentication on the RPC management interface."),
+        );
+    if cfg!(target_os = "linux") {
+        app.arg(
+            Arg::with_name("restore_dns")
+                .long("restore-dns")
+                .help("Restore DNS settings left behind by a daemon that was killed, and exit."),
         )
+    } else {
+        app
+    }
 }
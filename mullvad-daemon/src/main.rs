@@ -54,22 +54,24 @@ mod shutdown;
 mod version;
 
 use app_dirs::AppInfo;
+use chrono::Utc;
 use error_chain::ChainedError;
-use futures::Future;
+use futures::{future, Future};
 use jsonrpc_core::futures::sync::oneshot::Sender as OneshotSender;
 
 use management_interface::{BoxFuture, ManagementInterfaceServer, TunnelCommand};
 use mullvad_rpc::{AccountsProxy, AppVersionProxy, HttpHandle};
 
-use mullvad_types::account::{AccountData, AccountToken};
+use mullvad_types::account::{AccountData, AccountToken, ExpiryCache};
 use mullvad_types::location::GeoIpLocation;
-use mullvad_types::relay_constraints::{RelaySettings, RelaySettingsUpdate};
+use mullvad_types::relay_constraints::{RelaySettings, RelaySettingsUpdate, TunnelProtocol};
 use mullvad_types::relay_list::{Relay, RelayList};
-use mullvad_types::states::{DaemonState, SecurityState, TargetState};
+use mullvad_types::states::{DaemonState, TargetState, TunnelState};
+use mullvad_types::tunnel_exit_cause::{self, ReconnectDecision, TunnelExitCause};
 use mullvad_types::version::{AppVersion, AppVersionInfo};
 
 use std::env;
-use std::io;
+use std::io::{self, Read};
 use std::net::IpAddr;
 use std::path::{Path, PathBuf};
 use std::sync::{mpsc, Arc, Mutex};
@@ -97,6 +99,12 @@ error_chain!{
         InvalidState {
             description("Client is in an invalid state for the requested operation")
         }
+        /// The daemon tried to move the tunnel between two states that are not connected in the
+        /// tunnel state graph.
+        InvalidStateTransition(from: TunnelState, to: TunnelState) {
+            description("Invalid tunnel state transition")
+            display("Invalid tunnel state transition: {:?} => {:?}", from, to)
+        }
         TunnelError(msg: &'static str) {
             description("Error in the tunnel monitor")
             display("Tunnel monitor error: {}", msg)
@@ -122,8 +130,14 @@ lazy_static! {
     static ref MIN_TUNNEL_ALIVE_TIME_MS: Duration = Duration::from_millis(1000);
     static ref MAX_RELAY_CACHE_AGE: Duration = Duration::from_secs(3600);
     static ref RELAY_CACHE_UPDATE_TIMEOUT: Duration = Duration::from_millis(3000);
+    /// How long an account expiry fetched from the API is used before it is fetched again.
+    static ref ACCOUNT_EXPIRY_CACHE_TTL: chrono::Duration = chrono::Duration::minutes(1);
 }
 
+/// The tunnel protocol the relay selector prefers when the constraints allow any tunnel protocol.
+/// OpenVPN is the only tunnel the daemon can establish so far.
+static PREFERRED_TUNNEL_PROTOCOL: TunnelProtocol = TunnelProtocol::OpenVpn;
+
 static APP_INFO: AppInfo = AppInfo {
     name: crate_name!(),
     author: "Mullvad",
@@ -146,6 +160,11 @@ pub enum DaemonEvent {
     ManagementInterfaceExited(talpid_ipc::Result<()>),
     /// Daemon shutdown triggered by a signal, ctrl-c or similar.
     TriggerShutdown,
+    /// Triggered when the delay before reconnecting a tunnel that exited has passed. Contains the
+    /// number of the delay, so delays that have been replaced by a newer one can be ignored.
+    ReconnectDelayElapsed(u32),
+    /// Triggered when the expiry of an account has been fetched from the API.
+    AccountExpiryFetched(AccountToken, ExpiryCache),
 }
 
 impl From<TunnelEvent> for DaemonEvent {
@@ -160,32 +179,6 @@ impl From<TunnelCommand> for DaemonEvent {
     }
 }
 
-/// Represents the internal state of the actual tunnel.
-// TODO(linus): Put the tunnel::CloseHandle into this state, so it can't exist when not running.
-#[derive(Debug, Copy, Clone, Eq, PartialEq)]
-pub enum TunnelState {
-    /// No tunnel is running.
-    NotRunning,
-    /// The tunnel has been started, but it is not established/functional.
-    Connecting,
-    /// The tunnel is up and working.
-    Connected,
-    /// This state is active from when we manually trigger a tunnel kill until the tunnel wait
-    /// operation (TunnelExit) returned.
-    Exiting,
-}
-
-impl TunnelState {
-    pub fn as_security_state(&self) -> SecurityState {
-        use TunnelState::*;
-        match *self {
-            NotRunning | Connecting => SecurityState::Unsecured,
-            Connected | Exiting => SecurityState::Secured,
-        }
-    }
-}
-
-
 struct Daemon {
     state: TunnelState,
     // The tunnel_close_handle must only exist in the Connecting and Connected states!
@@ -206,8 +199,18 @@ struct Daemon {
     current_relay: Option<Relay>,
     tunnel_endpoint: Option<TunnelEndpoint>,
     tunnel_metadata: Option<TunnelMetadata>,
+    // Set when the daemon closes the tunnel on request of the user, before the tunnel has exited.
+    tunnel_exit_cause: Option<TunnelExitCause>,
+    // The number of times the tunnel has been reconnected since it was last up.
+    reconnect_attempt: u32,
+    // Set while waiting for a reconnection backoff delay. The tunnel is not started meanwhile.
+    reconnect_delay_pending: bool,
+    // Incremented for every reconnection backoff delay. Only the latest delay may end the wait.
+    reconnect_delay_id: u32,
     tunnel_log: Option<PathBuf>,
     resource_dir: PathBuf,
+    // The last account expiry fetched from the API, and the account it belongs to.
+    account_expiry: Option<(AccountToken, ExpiryCache)>,
 }
 
 impl Daemon {
@@ -264,8 +267,13 @@ impl Daemon {
             current_relay: None,
             tunnel_endpoint: None,
             tunnel_metadata: None,
+            tunnel_exit_cause: None,
+            reconnect_attempt: 0,
+            reconnect_delay_pending: false,
+            reconnect_delay_id: 0,
             tunnel_log: tunnel_log,
             resource_dir,
+            account_expiry: None,
         })
     }
 
@@ -273,7 +281,8 @@ impl Daemon {
         rpc_handle: mullvad_rpc::HttpHandle,
         resource_dir: &Path,
     ) -> relays::RelaySelector {
-        let mut relay_selector = relays::RelaySelector::new(rpc_handle, &resource_dir);
+        let mut relay_selector =
+            relays::RelaySelector::new(rpc_handle, &resource_dir, PREFERRED_TUNNEL_PROTOCOL);
         if let Ok(elapsed) = relay_selector.get_last_updated().elapsed() {
             if elapsed > *MAX_RELAY_CACHE_AGE {
                 if let Err(e) = relay_selector.update(*RELAY_CACHE_UPDATE_TIMEOUT) {
@@ -336,6 +345,9 @@ impl Daemon {
     /// Consume the `Daemon` and run the main event loop. Blocks until an error happens or a
     /// shutdown event is received.
     pub fn run(mut self) -> Result<()> {
+        if self.settings.get_block_when_disconnected() {
+            self.set_disconnected_policy()?;
+        }
         while let Ok(event) = self.rx.recv() {
             self.handle_event(event)?;
             if self.shutdown && self.state == TunnelState::NotRunning {
@@ -354,6 +366,11 @@ impl Daemon {
             ManagementInterfaceEvent(event) => self.handle_management_interface_event(event),
             ManagementInterfaceExited(result) => self.handle_management_interface_exited(result),
             TriggerShutdown => self.handle_trigger_shutdown_event(),
+            ReconnectDelayElapsed(delay_id) => self.handle_reconnect_delay_elapsed(delay_id),
+            AccountExpiryFetched(account_token, expiry) => {
+                self.account_expiry = Some((account_token, expiry));
+                Ok(())
+            }
         }
     }
 
@@ -362,6 +379,7 @@ impl Daemon {
         if self.state == TunnelState::Connecting {
             if let TunnelEvent::Up(metadata) = tunnel_event {
                 self.tunnel_metadata = Some(metadata);
+                self.reconnect_attempt = 0;
                 self.set_security_policy()?;
                 self.set_state(TunnelState::Connected)
             } else {
@@ -375,9 +393,23 @@ impl Daemon {
     }
 
     fn handle_tunnel_exited(&mut self, result: tunnel::Result<()>) -> Result<()> {
+        let exit_cause = match self.tunnel_exit_cause.take() {
+            Some(exit_cause) => exit_cause,
+            None => self.classify_tunnel_exit(&result),
+        };
         if let Err(e) = result.chain_err(|| "Tunnel exited in an unexpected way") {
             error!("{}", e.display_chain());
         }
+        if exit_cause.is_user_initiated() {
+            info!("Tunnel closed: {}", exit_cause);
+        } else {
+            warn!("Tunnel exited: {}", exit_cause);
+        }
+        // If the user closed the tunnel but asked to connect again before it had exited, the new
+        // target state is respected.
+        if self.target_state == TargetState::Secured && !exit_cause.is_user_initiated() {
+            self.apply_reconnect_decision(&exit_cause);
+        }
         self.current_relay = None;
         self.tunnel_endpoint = None;
         self.tunnel_metadata = None;
@@ -385,6 +417,44 @@ impl Daemon {
         self.set_state(TunnelState::NotRunning)
     }
 
+    /// Decides if and when to reconnect after the tunnel exited, while the target state is still
+    /// secured.
+    fn apply_reconnect_decision(&mut self, exit_cause: &TunnelExitCause) {
+        let attempt = self.reconnect_attempt;
+        self.reconnect_attempt = attempt.saturating_add(1);
+        match tunnel_exit_cause::reconnect_decision(exit_cause, attempt) {
+            ReconnectDecision::Immediate => debug!("Reconnecting immediately"),
+            ReconnectDecision::Backoff(delay) => {
+                info!("Reconnecting in {} seconds", delay.as_secs());
+                self.reconnect_delay_pending = true;
+                self.reconnect_delay_id = self.reconnect_delay_id.wrapping_add(1);
+                let delay_id = self.reconnect_delay_id;
+                let delay_tx = self.tx.clone();
+                thread::spawn(move || {
+                    thread::sleep(delay);
+                    let _ = delay_tx.send(DaemonEvent::ReconnectDelayElapsed(delay_id));
+                });
+            }
+            ReconnectDecision::Abort => {
+                info!("Not reconnecting after: {}", exit_cause);
+                self.target_state = TargetState::Unsecured;
+            }
+        }
+    }
+
+    /// Figures out why the tunnel exited by itself, primarily by looking at the tunnel log.
+    fn classify_tunnel_exit(&self, result: &tunnel::Result<()>) -> TunnelExitCause {
+        let cause_from_log = self.tunnel_log
+            .as_ref()
+            .and_then(|path| read_log_file(path).ok())
+            .and_then(|log| TunnelExitCause::from_openvpn_log(&log));
+        match (cause_from_log, result) {
+            (Some(exit_cause), _) => exit_cause,
+            (None, &Err(ref error)) => TunnelExitCause::Other(error.to_string()),
+            (None, &Ok(())) => TunnelExitCause::Other("The tunnel process exited".to_owned()),
+        }
+    }
+
     fn handle_tunnel_kill_result(&mut self, result: io::Result<()>) -> Result<()> {
         result.chain_err(|| "Error while trying to close tunnel")
     }
@@ -402,7 +472,13 @@ impl Daemon {
             UpdateRelaySettings(tx, update) => self.on_update_relay_settings(tx, update),
             SetAllowLan(tx, allow_lan) => self.on_set_allow_lan(tx, allow_lan),
             GetAllowLan(tx) => Ok(self.on_get_allow_lan(tx)),
+            SetBlockWhenDisconnected(tx, block_when_disconnected) => {
+                self.on_set_block_when_disconnected(tx, block_when_disconnected)
+            }
+            GetBlockWhenDisconnected(tx) => Ok(self.on_get_block_when_disconnected(tx)),
             SetOpenVpnMssfix(tx, mssfix_arg) => self.on_set_openvpn_mssfix(tx, mssfix_arg),
+            SetWireguardMtu(tx, mtu) => self.on_set_wireguard_mtu(tx, mtu),
+            SetWireguardKeepalive(tx, keepalive) => self.on_set_wireguard_keepalive(tx, keepalive),
             GetTunnelOptions(tx) => self.on_get_tunnel_options(tx),
             GetRelaySettings(tx) => Ok(self.on_get_relay_settings(tx)),
             GetVersionInfo(tx) => Ok(self.on_get_version_info(tx)),
@@ -428,11 +504,12 @@ impl Daemon {
         if let Some(ref relay) = self.current_relay {
             let location = relay.location.as_ref().cloned().unwrap();
             let geo_ip_location = GeoIpLocation {
-                ip: IpAddr::V4(relay.ipv4_addr_exit),
+                ip: Some(IpAddr::V4(relay.ipv4_addr_exit)),
                 country: location.country,
                 city: Some(location.city),
                 latitude: location.latitude,
                 longitude: location.longitude,
+                hostname: Some(relay.hostname.clone()),
                 mullvad_exit_ip: true,
             };
             Self::oneshot_send(tx, geo_ip_location, "current location");
@@ -448,14 +525,33 @@ impl Daemon {
         }
     }
 
+    /// Answers with the expiry of the given account. The expiry is only fetched from the API if
+    /// it was not already fetched within `ACCOUNT_EXPIRY_CACHE_TTL`.
     fn on_get_account_data(
         &mut self,
         tx: OneshotSender<BoxFuture<AccountData, mullvad_rpc::Error>>,
         account_token: AccountToken,
     ) {
+        let cached_expiry = match self.account_expiry {
+            Some((ref cached_account, ref expiry)) if *cached_account == account_token => {
+                expiry.fresh_value(Utc::now(), *ACCOUNT_EXPIRY_CACHE_TTL)
+            }
+            _ => None,
+        };
+        if let Some(expiry) = cached_expiry {
+            debug!("Using cached account expiry");
+            let account_data = future::ok(AccountData { expiry });
+            return Self::oneshot_send(tx, Box::new(account_data), "account data");
+        }
+
+        let daemon_tx = self.tx.clone();
         let rpc_call = self.accounts_proxy
-            .get_expiry(account_token)
-            .map(|expiry| AccountData { expiry });
+            .get_expiry(account_token.clone())
+            .map(move |expiry| {
+                let cache = ExpiryCache::new(expiry, Utc::now());
+                let _ = daemon_tx.send(DaemonEvent::AccountExpiryFetched(account_token, cache));
+                AccountData { expiry }
+            });
         Self::oneshot_send(tx, Box::new(rpc_call), "account data")
     }
 
@@ -471,7 +567,7 @@ impl Daemon {
     fn on_set_account(
         &mut self,
         tx: OneshotSender<()>,
-        account_token: Option<String>,
+        account_token: Option<AccountToken>,
     ) -> Result<()> {
         let save_result = self.settings.set_account_token(account_token);
 
@@ -482,7 +578,7 @@ impl Daemon {
                     self.state == TunnelState::Connecting || self.state == TunnelState::Connected;
                 if account_changed && tunnel_needs_restart {
                     info!("Initiating tunnel restart because the account token changed");
-                    self.kill_tunnel()?;
+                    self.restart_tunnel()?;
                 }
             }
             Err(e) => error!("{}", e.display_chain()),
@@ -513,7 +609,7 @@ impl Daemon {
         Self::oneshot_send(tx, current_version, "get_current_version response");
     }
 
-    fn on_get_account(&self, tx: OneshotSender<Option<String>>) {
+    fn on_get_account(&self, tx: OneshotSender<Option<AccountToken>>) {
         Self::oneshot_send(tx, self.settings.get_account_token(), "current account")
     }
 
@@ -533,7 +629,7 @@ impl Daemon {
 
                 if changed && tunnel_needs_restart {
                     info!("Initiating tunnel restart because the relay settings changed");
-                    self.kill_tunnel()?;
+                    self.restart_tunnel()?;
                 }
             }
             Err(e) => error!("{}", e.display_chain()),
@@ -550,8 +646,12 @@ impl Daemon {
         let save_result = self.settings.set_allow_lan(allow_lan);
         match save_result.chain_err(|| "Unable to save settings") {
             Ok(settings_changed) => {
-                if settings_changed && self.target_state == TargetState::Secured {
-                    self.set_security_policy()?;
+                if settings_changed {
+                    if self.target_state == TargetState::Secured {
+                        self.set_security_policy()?;
+                    } else if self.state == TunnelState::NotRunning {
+                        self.set_disconnected_policy()?;
+                    }
                 }
                 Self::oneshot_send(tx, (), "set_allow_lan response");
             }
@@ -564,6 +664,34 @@ impl Daemon {
         Self::oneshot_send(tx, self.settings.get_allow_lan(), "allow lan")
     }
 
+    fn on_set_block_when_disconnected(
+        &mut self,
+        tx: OneshotSender<()>,
+        block_when_disconnected: bool,
+    ) -> Result<()> {
+        let save_result = self.settings.set_block_when_disconnected(block_when_disconnected);
+        match save_result.chain_err(|| "Unable to save settings") {
+            Ok(settings_changed) => {
+                if settings_changed && self.target_state == TargetState::Unsecured
+                    && self.state == TunnelState::NotRunning
+                {
+                    self.set_disconnected_policy()?;
+                }
+                Self::oneshot_send(tx, (), "set_block_when_disconnected response");
+            }
+            Err(e) => error!("{}", e.display_chain()),
+        }
+        Ok(())
+    }
+
+    fn on_get_block_when_disconnected(&self, tx: OneshotSender<bool>) {
+        Self::oneshot_send(
+            tx,
+            self.settings.get_block_when_disconnected(),
+            "block when disconnected",
+        )
+    }
+
     fn on_set_openvpn_mssfix(
         &mut self,
         tx: OneshotSender<()>,
@@ -577,6 +705,28 @@ impl Daemon {
         Ok(())
     }
 
+    fn on_set_wireguard_mtu(&mut self, tx: OneshotSender<()>, mtu: Option<u16>) -> Result<()> {
+        let save_result = self.settings.set_wireguard_mtu(mtu);
+        match save_result.chain_err(|| "Unable to save settings") {
+            Ok(_) => Self::oneshot_send(tx, (), "set_wireguard_mtu response"),
+            Err(e) => error!("{}", e.display_chain()),
+        };
+        Ok(())
+    }
+
+    fn on_set_wireguard_keepalive(
+        &mut self,
+        tx: OneshotSender<()>,
+        keepalive: Option<u16>,
+    ) -> Result<()> {
+        let save_result = self.settings.set_wireguard_keepalive(keepalive);
+        match save_result.chain_err(|| "Unable to save settings") {
+            Ok(_) => Self::oneshot_send(tx, (), "set_wireguard_keepalive response"),
+            Err(e) => error!("{}", e.display_chain()),
+        };
+        Ok(())
+    }
+
     fn on_get_tunnel_options(&self, tx: OneshotSender<TunnelOptions>) -> Result<()> {
         let tunnel_options = self.settings.get_tunnel_options().clone();
         Self::oneshot_send(tx, tunnel_options, "get_tunnel_options response");
@@ -602,22 +752,29 @@ impl Daemon {
         self.set_target_state(TargetState::Unsecured)
     }
 
-    /// Update the state of the client. If it changed, notify the subscribers and trigger
-    /// appropriate actions.
-    fn set_state(&mut self, new_state: TunnelState) -> Result<()> {
-        if new_state != self.state {
-            debug!("State {:?} => {:?}", self.state, new_state);
-            self.state = new_state;
-            self.broadcast_state();
-            self.verify_state_consistency()?;
+    fn handle_reconnect_delay_elapsed(&mut self, delay_id: u32) -> Result<()> {
+        if self.reconnect_delay_pending && delay_id == self.reconnect_delay_id {
+            self.reconnect_delay_pending = false;
             self.apply_target_state()
         } else {
-            // Calling set_state with the same state we already have is an error. Should try to
-            // mitigate this possibility completely with a better state machine later.
-            Err(ErrorKind::InvalidState.into())
+            Ok(())
         }
     }
 
+    /// Update the state of the client. If it changed, notify the subscribers and trigger
+    /// appropriate actions.
+    fn set_state(&mut self, new_state: TunnelState) -> Result<()> {
+        ensure!(
+            self.state.can_transition_to(&new_state),
+            ErrorKind::InvalidStateTransition(self.state, new_state)
+        );
+        debug!("State {:?} => {:?}", self.state, new_state);
+        self.state = new_state;
+        self.broadcast_state();
+        self.verify_state_consistency()?;
+        self.apply_target_state()
+    }
+
     fn broadcast_state(&mut self) {
         let new_daemon_state = DaemonState {
             state: self.state.as_security_state(),
@@ -651,6 +808,8 @@ impl Daemon {
         if new_state != self.target_state {
             debug!("Target state {:?} => {:?}", self.target_state, new_state);
             self.target_state = new_state;
+            self.reconnect_attempt = 0;
+            self.reconnect_delay_pending = false;
             self.broadcast_state();
             self.apply_target_state()
         } else {
@@ -660,6 +819,10 @@ impl Daemon {
 
     fn apply_target_state(&mut self) -> Result<()> {
         match (self.target_state, self.state) {
+            (TargetState::Secured, TunnelState::NotRunning) if self.reconnect_delay_pending => {
+                debug!("Waiting for the reconnect delay before starting the tunnel");
+                Ok(())
+            }
             (TargetState::Secured, TunnelState::NotRunning) => {
                 debug!("Triggering tunnel start");
                 if let Err(e) = self.start_tunnel().chain_err(|| "Failed to start tunnel") {
@@ -671,7 +834,7 @@ impl Daemon {
                 }
                 Ok(())
             }
-            (TargetState::Unsecured, TunnelState::NotRunning) => self.reset_security_policy(),
+            (TargetState::Unsecured, TunnelState::NotRunning) => self.set_disconnected_policy(),
             (TargetState::Unsecured, TunnelState::Connecting)
             | (TargetState::Unsecured, TunnelState::Connected) => self.kill_tunnel(),
             (..) => Ok(()),
@@ -686,6 +849,9 @@ impl Daemon {
 
         match self.settings.get_relay_settings() {
             RelaySettings::CustomTunnelEndpoint(custom_relay) => {
+                if custom_relay.proxy.is_some() {
+                    warn!("Proxies are not supported yet, connecting to the custom relay directly");
+                }
                 let tunnel_endpoint = custom_relay
                     .to_tunnel_endpoint()
                     .chain_err(|| ErrorKind::NoRelay)?;
@@ -697,6 +863,14 @@ impl Daemon {
                     .chain_err(|| ErrorKind::NoRelay)?;
                 self.tunnel_endpoint = Some(tunnel_endpoint);
                 self.current_relay = Some(relay);
+                if self.settings.get_bridge_state().use_bridge(self.reconnect_attempt) {
+                    let bridge_settings = self.settings.get_bridge_settings();
+                    if self.relay_selector.get_bridge(bridge_settings).is_some() {
+                        warn!("Bridges are not supported yet, connecting to the relay directly");
+                    } else {
+                        warn!("No bridge matches the bridge settings");
+                    }
+                }
             }
         }
 
@@ -709,7 +883,7 @@ impl Daemon {
         self.prepare_tunnel_log_file()?;
 
         let tunnel_monitor =
-            self.spawn_tunnel_monitor(self.tunnel_endpoint.unwrap(), &account_token)?;
+            self.spawn_tunnel_monitor(self.tunnel_endpoint.unwrap(), account_token.as_str())?;
         self.tunnel_close_handle = Some(tunnel_monitor.close_handle());
         self.spawn_tunnel_monitor_wait_thread(tunnel_monitor);
 
@@ -752,6 +926,9 @@ impl Daemon {
         };
 
         let tunnel_options = self.settings.get_tunnel_options();
+        if tunnel_options.openvpn.proxy.is_some() {
+            warn!("Proxies are not supported yet, ignoring the OpenVPN proxy setting");
+        }
         TunnelMonitor::new(
             tunnel_endpoint,
             &tunnel_options,
@@ -775,11 +952,22 @@ impl Daemon {
         });
     }
 
+    /// Closes the tunnel so it is started again with the current settings. The restart is not a
+    /// failure, so the tunnel log of the old tunnel is not used to decide how to reconnect.
+    fn restart_tunnel(&mut self) -> Result<()> {
+        self.tunnel_exit_cause = Some(TunnelExitCause::Restart);
+        self.reconnect_attempt = 0;
+        self.kill_tunnel()
+    }
+
     fn kill_tunnel(&mut self) -> Result<()> {
         ensure!(
             self.state == TunnelState::Connecting || self.state == TunnelState::Connected,
             ErrorKind::InvalidState
         );
+        if self.target_state == TargetState::Unsecured {
+            self.tunnel_exit_cause = Some(TunnelExitCause::KilledByUser);
+        }
         let close_handle = self.tunnel_close_handle.take().unwrap();
         self.set_state(TunnelState::Exiting)?;
         let result_tx = self.tx.clone();
@@ -802,14 +990,42 @@ impl Daemon {
             (Some(relay), None) => SecurityPolicy::Connecting {
                 relay_endpoint: relay.to_endpoint(),
                 allow_lan: self.settings.get_allow_lan(),
+                allow_ipv6: self.settings.get_tunnel_options().enable_ipv6,
+                exclusions: vec![],
             },
             (Some(relay), Some(tunnel_metadata)) => SecurityPolicy::Connected {
                 relay_endpoint: relay.to_endpoint(),
                 tunnel: tunnel_metadata.clone(),
+                dns_servers: tunnel_metadata.gateways(),
                 allow_lan: self.settings.get_allow_lan(),
+                allow_ipv6: self.settings.get_tunnel_options().enable_ipv6,
+                keep_system_nameservers: self.settings
+                    .get_tunnel_options()
+                    .keep_system_nameservers,
+                exclusions: vec![],
             },
             _ => bail!(ErrorKind::InvalidState),
         };
+        self.apply_security_policy(policy)
+    }
+
+    /// Blocks all traffic if the user wants that while disconnected, otherwise lifts the security
+    /// policy. The blocking rules are left in place when the daemon exits, so nothing leaks
+    /// until it is started again.
+    fn set_disconnected_policy(&mut self) -> Result<()> {
+        if self.settings.get_block_when_disconnected() {
+            let policy = SecurityPolicy::Blocked {
+                allow_lan: self.settings.get_allow_lan(),
+                allow_ipv6: self.settings.get_tunnel_options().enable_ipv6,
+                exclusions: vec![],
+            };
+            self.apply_security_policy(policy)
+        } else {
+            self.reset_security_policy()
+        }
+    }
+
+    fn apply_security_policy(&mut self, policy: SecurityPolicy) -> Result<()> {
         debug!("Set security policy: {:?}", policy);
         self.firewall
             .apply_policy(policy)
@@ -853,6 +1069,10 @@ fn run() -> Result<()> {
         .chain_err(|| "Unable to initialize logger")?;
     log_version();
 
+    if config.restore_dns {
+        return restore_dns();
+    }
+
     if !running_as_admin() {
         warn!("Running daemon as a non-administrator user, clients might refuse to connect");
     }
@@ -872,6 +1092,20 @@ fn run() -> Result<()> {
     Ok(())
 }
 
+#[cfg(target_os = "linux")]
+fn restore_dns() -> Result<()> {
+    ensure!(
+        !rpc_uniqueness_check::is_another_instance_running(),
+        ErrorKind::DaemonIsAlreadyRunning
+    );
+    talpid_core::firewall::restore_dns_if_needed().chain_err(|| "Unable to restore DNS")
+}
+
+#[cfg(not(target_os = "linux"))]
+fn restore_dns() -> Result<()> {
+    bail!("Restoring DNS is only supported on Linux")
+}
+
 fn log_version() {
     info!(
         "Starting {} - {} {}",
@@ -902,6 +1136,12 @@ fn get_cache_dir() -> Result<PathBuf> {
         .chain_err(|| ErrorKind::NoCacheDir)
 }
 
+fn read_log_file(path: &Path) -> io::Result<String> {
+    let mut log = String::new();
+    fs::File::open(path)?.read_to_string(&mut log)?;
+    Ok(log)
+}
+
 #[cfg(unix)]
 fn running_as_admin() -> bool {
     let uid = unsafe { libc::getuid() };
@@ -82,6 +82,15 @@ build_rpc_trait! {
         #[rpc(meta, name = "get_allow_lan")]
         fn get_allow_lan(&self, Self::Metadata) -> BoxFuture<bool, Error>;
 
+        /// Set if the client should block all traffic while disconnected, and not only while
+        /// connecting.
+        #[rpc(meta, name = "set_block_when_disconnected")]
+        fn set_block_when_disconnected(&self, Self::Metadata, bool) -> BoxFuture<(), Error>;
+
+        /// Get if the client blocks all traffic while disconnected.
+        #[rpc(meta, name = "get_block_when_disconnected")]
+        fn get_block_when_disconnected(&self, Self::Metadata) -> BoxFuture<bool, Error>;
+
         /// Set if the client should automatically establish a tunnel on start or not.
         #[rpc(meta, name = "set_autoconnect")]
         fn set_autoconnect(&self, Self::Metadata, bool) -> BoxFuture<(), Error>;
@@ -121,6 +130,14 @@ build_rpc_trait! {
         #[rpc(meta, name = "set_openvpn_mssfix")]
         fn set_openvpn_mssfix(&self, Self::Metadata, Option<u16>) -> BoxFuture<(), Error>;
 
+        /// Sets the MTU of WireGuard tunnels
+        #[rpc(meta, name = "set_wireguard_mtu")]
+        fn set_wireguard_mtu(&self, Self::Metadata, Option<u16>) -> BoxFuture<(), Error>;
+
+        /// Sets how often WireGuard tunnels send keepalive packets, in seconds
+        #[rpc(meta, name = "set_wireguard_keepalive")]
+        fn set_wireguard_keepalive(&self, Self::Metadata, Option<u16>) -> BoxFuture<(), Error>;
+
         /// Gets tunnel specific options
         #[rpc(meta, name = "get_tunnel_options")]
         fn get_tunnel_options(&self, Self::Metadata) -> BoxFuture<TunnelOptions, Error>;
@@ -183,8 +200,16 @@ pub enum TunnelCommand {
     SetAllowLan(OneshotSender<()>, bool),
     /// Request the current allow LAN setting.
     GetAllowLan(OneshotSender<bool>),
+    /// Setting if all traffic should be blocked while disconnected.
+    SetBlockWhenDisconnected(OneshotSender<()>, bool),
+    /// Request the current block when disconnected setting.
+    GetBlockWhenDisconnected(OneshotSender<bool>),
     /// Set the mssfix argument for OpenVPN
     SetOpenVpnMssfix(OneshotSender<()>, Option<u16>),
+    /// Set the MTU of WireGuard tunnels
+    SetWireguardMtu(OneshotSender<()>, Option<u16>),
+    /// Set the keepalive interval of WireGuard tunnels
+    SetWireguardKeepalive(OneshotSender<()>, Option<u16>),
     /// Get the mssfix argument for OpenVPN
     GetTunnelOptions(OneshotSender<TunnelOptions>),
     /// Get information about the currently running and latest app versions
@@ -506,6 +531,29 @@ impl<T: From<TunnelCommand> + 'static + Send> ManagementInterfaceApi for Managem
         Box::new(future)
     }
 
+    fn set_block_when_disconnected(
+        &self,
+        meta: Self::Metadata,
+        block_when_disconnected: bool,
+    ) -> BoxFuture<(), Error> {
+        trace!("set_block_when_disconnected");
+        try_future!(self.check_auth(&meta));
+        let (tx, rx) = sync::oneshot::channel();
+        let message = TunnelCommand::SetBlockWhenDisconnected(tx, block_when_disconnected);
+        let future = self.send_command_to_daemon(message)
+            .and_then(|_| rx.map_err(|_| Error::internal_error()));
+        Box::new(future)
+    }
+
+    fn get_block_when_disconnected(&self, meta: Self::Metadata) -> BoxFuture<bool, Error> {
+        trace!("get_block_when_disconnected");
+        try_future!(self.check_auth(&meta));
+        let (tx, rx) = sync::oneshot::channel();
+        let future = self.send_command_to_daemon(TunnelCommand::GetBlockWhenDisconnected(tx))
+            .and_then(|_| rx.map_err(|_| Error::internal_error()));
+        Box::new(future)
+    }
+
     fn set_autoconnect(&self, meta: Self::Metadata, _autoconnect: bool) -> BoxFuture<(), Error> {
         trace!("set_autoconnect");
         try_future!(self.check_auth(&meta));
@@ -595,6 +643,31 @@ impl<T: From<TunnelCommand> + 'static + Send> ManagementInterfaceApi for Managem
         Box::new(future)
     }
 
+    fn set_wireguard_mtu(&self, meta: Self::Metadata, mtu: Option<u16>) -> BoxFuture<(), Error> {
+        trace!("set_wireguard_mtu");
+        try_future!(self.check_auth(&meta));
+        let (tx, rx) = sync::oneshot::channel();
+        let future = self.send_command_to_daemon(TunnelCommand::SetWireguardMtu(tx, mtu))
+            .and_then(|_| rx.map_err(|_| Error::internal_error()));
+
+        Box::new(future)
+    }
+
+    fn set_wireguard_keepalive(
+        &self,
+        meta: Self::Metadata,
+        keepalive: Option<u16>,
+    ) -> BoxFuture<(), Error> {
+        trace!("set_wireguard_keepalive");
+        try_future!(self.check_auth(&meta));
+        let (tx, rx) = sync::oneshot::channel();
+        let future =
+            self.send_command_to_daemon(TunnelCommand::SetWireguardKeepalive(tx, keepalive))
+                .and_then(|_| rx.map_err(|_| Error::internal_error()));
+
+        Box::new(future)
+    }
+
     fn get_tunnel_options(&self, meta: Self::Metadata) -> BoxFuture<TunnelOptions, Error> {
         trace!("get_tunnel_options");
         try_future!(self.check_auth(&meta));
@@ -4,26 +4,25 @@ use error_chain::ChainedError;
 use futures::Future;
 
 use mullvad_rpc::{HttpHandle, RelayListProxy};
-use mullvad_types::location::Location;
-use mullvad_types::relay_constraints::{Constraint, LocationConstraint, Match, OpenVpnConstraints,
-                                       RelayConstraints, TunnelConstraints};
-use mullvad_types::relay_list::{Relay, RelayList, RelayTunnels};
-
-use serde_json;
+use mullvad_types::location::{Continent, Location};
+use mullvad_types::relay_constraints::{BridgeSettings, Constraint, OpenVpnConstraints,
+                                       RelayConstraints, TunnelConstraints, TunnelProtocol};
+use mullvad_types::relay_list::{self, Relay, RelayList};
+use mullvad_types::ProxySettings;
 
 use talpid_types::net::{TransportProtocol, TunnelEndpoint, TunnelEndpointData};
 
-use std::fs::File;
-use std::io;
-use std::net::IpAddr;
 use std::path::{Path, PathBuf};
 use std::time::{self, Duration, SystemTime};
 
-use rand::distributions::{IndependentSample, Range};
 use rand::{self, Rng, ThreadRng};
 use tokio_timer::{TimeoutError, Timer};
 
 
+/// Relays reporting a higher load than this are only selected if no other relay matches.
+const MAX_PREFERRED_RELAY_LOAD: f32 = 0.9;
+
+
 error_chain! {
     errors {
         RelayCacheError { description("Error with relay cache on disk") }
@@ -46,12 +45,18 @@ pub struct RelaySelector {
     last_updated: SystemTime,
     rng: ThreadRng,
     rpc_client: RelayListProxy<HttpHandle>,
+    preferred_tunnel_protocol: TunnelProtocol,
 }
 
 impl RelaySelector {
     /// Returns a new `RelaySelector` backed by relays cached on disk. Use the `update` method
-    /// to refresh the relay list from the internet.
-    pub fn new(rpc_handle: HttpHandle, resource_dir: &Path) -> Self {
+    /// to refresh the relay list from the internet. `preferred_tunnel_protocol` is the tunnel
+    /// protocol to prefer when the constraints allow any tunnel protocol.
+    pub fn new(
+        rpc_handle: HttpHandle,
+        resource_dir: &Path,
+        preferred_tunnel_protocol: TunnelProtocol,
+    ) -> Self {
         let (last_updated, relay_list) = match Self::read_cached_relays(resource_dir) {
             Ok(value) => value,
             Err(error) => {
@@ -72,6 +77,7 @@ impl RelaySelector {
             last_updated,
             rng: rand::thread_rng(),
             rpc_client: RelayListProxy::new(rpc_handle),
+            preferred_tunnel_protocol,
         }
     }
 
@@ -93,8 +99,11 @@ impl RelaySelector {
         &mut self,
         constraints: &RelayConstraints,
     ) -> Result<(Relay, TunnelEndpoint)> {
-        // Highest priority preference. Where we prefer OpenVPN using UDP. But without changing
-        // any constraints that are explicitly specified.
+        if constraints.use_multihop {
+            warn!("Multihop is not supported by the tunnel yet, connecting to the exit relay only");
+        }
+        // Highest priority preference. Where we prefer OpenVPN using UDP, or the preferred tunnel
+        // protocol in general. But without changing any constraints that are explicitly specified.
         let tunnel_constraints1 = match constraints.tunnel {
             Constraint::Any => TunnelConstraints::OpenVpn(OpenVpnConstraints {
                 port: Constraint::Any,
@@ -116,6 +125,15 @@ impl RelaySelector {
         let relay_constraints1 = RelayConstraints {
             location: constraints.location.clone(),
             tunnel: Constraint::Only(tunnel_constraints1),
+            providers: constraints.providers.clone(),
+            ownership: constraints.ownership,
+            port: constraints.port,
+            tunnel_protocol: constraints
+                .tunnel_protocol
+                .or_preferred(self.preferred_tunnel_protocol),
+            entry_location: constraints.entry_location.clone(),
+            use_multihop: constraints.use_multihop,
+            ipv6_only: constraints.ipv6_only,
         };
 
         if let Some((relay, endpoint)) = self.get_tunnel_endpoint_internal(&relay_constraints1) {
@@ -129,123 +147,73 @@ impl RelaySelector {
         }
     }
 
-    /// Returns a random relay endpoint if any is matching the given constraints.
+    /// Returns a random relay endpoint if any is matching the given constraints. Overloaded relays
+    /// are avoided unless they are the only ones matching.
     fn get_tunnel_endpoint_internal(
         &mut self,
         constraints: &RelayConstraints,
     ) -> Option<(Relay, TunnelEndpoint)> {
-        let matching_relays: Vec<Relay> = self.relays
+        let mut matching_relays: Vec<Relay> = self.relays
             .iter()
-            .filter_map(|relay| Self::matching_relay(relay, constraints))
+            .filter_map(|relay| constraints.matching_relay(relay))
             .collect();
+        if matching_relays
+            .iter()
+            .any(|relay| relay.is_available(MAX_PREFERRED_RELAY_LOAD))
+        {
+            matching_relays.retain(|relay| relay.is_available(MAX_PREFERRED_RELAY_LOAD));
+        } else if !matching_relays.is_empty() {
+            warn!("All relays matching the constraints are overloaded");
+        }
 
         self.pick_random_relay(&matching_relays)
             .and_then(|selected_relay| {
-                info!(
-                    "Selected relay {} at {}",
-                    selected_relay.hostname, selected_relay.ipv4_addr_in
-                );
-                self.get_random_tunnel(&selected_relay.tunnels)
-                    .map(|tunnel_parameters| {
-                        let endpoint = TunnelEndpoint {
-                            address: IpAddr::V4(selected_relay.ipv4_addr_in),
-                            tunnel: tunnel_parameters,
-                        };
-                        (selected_relay.clone(), endpoint)
-                    })
+                match selected_relay.location {
+                    Some(ref location) => info!(
+                        "Selected relay {} in {} at {}",
+                        selected_relay.hostname, location, selected_relay.ipv4_addr_in
+                    ),
+                    None => info!(
+                        "Selected relay {} at {}",
+                        selected_relay.hostname, selected_relay.ipv4_addr_in
+                    ),
+                }
+                self.get_random_endpoint(&selected_relay.matching_endpoints(constraints))
+                    .map(|endpoint| (selected_relay.clone(), endpoint))
             })
     }
 
-    /// Takes a `Relay` and a corresponding `RelayConstraints` and returns a new `Relay` if the
-    /// given relay matches the constraints.
-    fn matching_relay(relay: &Relay, constraints: &RelayConstraints) -> Option<Relay> {
-        let matches_location = match constraints.location {
-            Constraint::Any => true,
-            Constraint::Only(LocationConstraint::Country(ref country)) => {
-                relay
-                    .location
-                    .as_ref()
-                    .map_or(false, |loc| loc.country_code == *country)
-                    && relay.include_in_country
-            }
-            Constraint::Only(LocationConstraint::City(ref country, ref city)) => {
-                relay.location.as_ref().map_or(false, |loc| {
-                    loc.country_code == *country && loc.city_code == *city
-                })
-            }
-        };
-        if !matches_location {
-            return None;
-        }
-        let relay = match constraints.tunnel {
-            Constraint::Any => relay.clone(),
-            Constraint::Only(ref tunnel_constraints) => {
-                let mut relay = relay.clone();
-                relay.tunnels = Self::matching_tunnels(&relay.tunnels, tunnel_constraints);
-                relay
-            }
-        };
-        if relay.tunnels.openvpn.is_empty() {
-            None
-        } else {
-            Some(relay)
-        }
-    }
-
-    /// Takes a `RelayTunnels` object which in turn is a collection of tunnel configurations for
-    /// a given relay. Then returns a new `RelayTunnels` instance with only the entries that
-    /// matches the given `TunnelConstraints`.
-    fn matching_tunnels(
-        tunnels: &RelayTunnels,
-        tunnel_constraints: &TunnelConstraints,
-    ) -> RelayTunnels {
-        RelayTunnels {
-            openvpn: tunnels
-                .openvpn
-                .iter()
-                .filter(|endpoint| tunnel_constraints.matches(*endpoint))
-                .cloned()
-                .collect(),
-            wireguard: tunnels
-                .wireguard
-                .iter()
-                .filter(|endpoint| tunnel_constraints.matches(*endpoint))
-                .cloned()
-                .collect(),
-        }
-    }
-
     /// Pick a random relay from the given slice. Will return `None` if the given slice is empty
     /// or all relays in it has zero weight.
     fn pick_random_relay<'a>(&mut self, relays: &'a [Relay]) -> Option<&'a Relay> {
-        let total_weight: u64 = relays.iter().map(|relay| relay.weight).sum();
         debug!(
             "Selecting among {} relays with combined weight {}",
             relays.len(),
-            total_weight
+            relays.iter().map(|relay| relay.weight).sum::<u64>()
         );
-        if total_weight == 0 {
-            None
-        } else {
-            // Pick a random number in the range 0 - total_weight. This choses the relay.
-            let mut i: u64 = Range::new(0, total_weight + 1).ind_sample(&mut self.rng);
-            Some(
-                relays
-                    .iter()
-                    .find(|relay| {
-                        i = i.saturating_sub(relay.weight);
-                        i == 0
-                    })
-                    .unwrap(),
-            )
-        }
+        relay_list::pick_weighted_relay(relays, &mut self.rng)
+    }
+
+    /// Picks a bridge matching the given settings.
+    pub fn get_bridge(&mut self, settings: &BridgeSettings) -> Option<ProxySettings> {
+        relay_list::select_bridge(&self.relays, settings, &mut self.rng)
     }
 
-    fn get_random_tunnel(&mut self, tunnels: &RelayTunnels) -> Option<TunnelEndpointData> {
-        self.rng
-            .choose(&tunnels.openvpn)
+    /// Picks a random tunnel endpoint, preferring OpenVPN endpoints over WireGuard ones.
+    fn get_random_endpoint(&mut self, endpoints: &[TunnelEndpoint]) -> Option<TunnelEndpoint> {
+        let openvpn_endpoints: Vec<TunnelEndpoint> = endpoints
+            .iter()
+            .filter(|endpoint| match endpoint.tunnel {
+                TunnelEndpointData::OpenVpn(_) => true,
+                TunnelEndpointData::Wireguard(_) => false,
+            })
             .cloned()
-            .map(|openvpn_endpoint| TunnelEndpointData::OpenVpn(openvpn_endpoint))
+            .collect();
+        if openvpn_endpoints.is_empty() {
+            self.rng.choose(endpoints).cloned()
+        } else {
+            self.rng.choose(&openvpn_endpoints).cloned()
+        }
     }
 
     /// Downloads the latest relay list and caches it. This operation is blocking.
@@ -254,7 +222,8 @@ impl RelaySelector {
         let download_future = self.rpc_client
             .relay_list()
             .map_err(|e| Error::with_chain(e, ErrorKind::DownloadError));
-        let relay_list = Timer::default().timeout(download_future, timeout).wait()?;
+        let mut relay_list = Timer::default().timeout(download_future, timeout).wait()?;
+        relay_list.deduplicate();
         if let Err(e) = Self::cache_relays(&relay_list) {
             error!("Unable to save relays to cache: {}", e.display_chain());
         }
@@ -272,8 +241,9 @@ impl RelaySelector {
         for country in &mut relay_list.countries {
             let country_name = country.name.clone();
             let country_code = country.code.clone();
+            let continent = Continent::from_country_code(country_code.as_str());
             for city in &mut country.cities {
-                city.has_active_relays = !city.relays.is_empty();
+                city.has_active_relays = city.relays.iter().any(|relay| relay.active);
                 let city_name = city.name.clone();
                 let city_code = city.code.clone();
                 let latitude = city.latitude;
@@ -286,6 +256,7 @@ impl RelaySelector {
                         city_code: city_code.clone(),
                         latitude,
                         longitude,
+                        continent,
                     });
                     relay
                 }));
@@ -296,8 +267,9 @@ impl RelaySelector {
 
     /// Write a `RelayList` to the cache file.
     fn cache_relays(relays: &RelayList) -> Result<()> {
-        let file = File::create(Self::get_cache_path()?).chain_err(|| ErrorKind::RelayCacheError)?;
-        serde_json::to_writer_pretty(file, relays).chain_err(|| ErrorKind::SerializationError)
+        relays
+            .save(&Self::get_cache_path()?)
+            .chain_err(|| ErrorKind::RelayCacheError)
     }
 
     /// Try to read the relays, first from cache and if that fails from the `resource_dir`.
@@ -318,19 +290,20 @@ impl RelaySelector {
             "Trying to read relays cache from {}",
             path.as_ref().to_string_lossy()
         );
-        let (last_modified, file) =
-            Self::read_file(path.as_ref()).chain_err(|| ErrorKind::RelayCacheError)?;
-        let relay_list = serde_json::from_reader(io::BufReader::new(file))
-            .chain_err(|| ErrorKind::SerializationError)?;
+        let last_modified = path.as_ref()
+            .metadata()
+            .and_then(|metadata| metadata.modified())
+            .chain_err(|| ErrorKind::RelayCacheError)?;
+        let relay_list = RelayList::load(path.as_ref()).map_err(|error| {
+            let kind = match *error.kind() {
+                relay_list::ErrorKind::ParseError(_) => ErrorKind::SerializationError,
+                _ => ErrorKind::RelayCacheError,
+            };
+            Error::with_chain(error, kind)
+        })?;
         Ok((last_modified, relay_list))
     }
 
-    fn read_file(path: &Path) -> io::Result<(SystemTime, File)> {
-        let file = File::open(path)?;
-        let last_modified = file.metadata()?.modified()?;
-        Ok((last_modified, file))
-    }
-
     fn get_cache_path() -> Result<PathBuf> {
         let dir = app_dirs::app_root(app_dirs::AppDataType::UserCache, &::APP_INFO)
             .chain_err(|| ErrorKind::RelayCacheError)?;
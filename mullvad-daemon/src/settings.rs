@@ -2,8 +2,10 @@ extern crate serde_json;
 
 use app_dirs;
 
-use mullvad_types::relay_constraints::{Constraint, LocationConstraint, RelayConstraints,
-                                       RelaySettings, RelaySettingsUpdate};
+use mullvad_types::account::AccountToken;
+use mullvad_types::relay_constraints::{BridgeSettings, BridgeState, Constraint,
+                                       LocationConstraint, RelayConstraints, RelaySettings,
+                                       RelaySettingsUpdate};
 use talpid_types::net::TunnelOptions;
 
 use std::fs::File;
@@ -26,6 +28,13 @@ error_chain! {
         ParseError {
             description("Malformed settings")
         }
+        InvalidTunnelOptions(problems: String) {
+            description("Invalid tunnel options")
+            display("Invalid tunnel options: {}", problems)
+        }
+        NearestLocationNotSupported {
+            description("Connecting to the nearest country is not supported yet")
+        }
     }
 }
 
@@ -34,10 +43,16 @@ static SETTINGS_FILE: &str = "settings.json";
 #[derive(Debug, Clone, Deserialize, Serialize)]
 #[serde(default)]
 pub struct Settings {
-    account_token: Option<String>,
+    account_token: Option<AccountToken>,
     relay_settings: RelaySettings,
+    /// When to send the tunnel traffic through a bridge.
+    bridge_state: BridgeState,
+    /// Where to get the bridge from, when one is used.
+    bridge_settings: BridgeSettings,
     /// If the app should allow communication with private (LAN) networks.
     allow_lan: bool,
+    /// If all traffic should be blocked while disconnected, and not only while connecting.
+    block_when_disconnected: bool,
     /// Options that should be applied to tunnels of a specific type regardless of where the relays
     /// might be located.
     tunnel_options: TunnelOptions,
@@ -47,11 +62,15 @@ impl Default for Settings {
     fn default() -> Self {
         Settings {
             account_token: None,
-            relay_settings: RelaySettings::Normal(RelayConstraints {
-                location: Constraint::Only(LocationConstraint::Country("se".to_owned())),
-                tunnel: Constraint::Any,
-            }),
+            relay_settings: RelaySettings::Normal(
+                RelayConstraints::builder()
+                    .location(LocationConstraint::Country("se".parse().unwrap()))
+                    .build(),
+            ),
+            bridge_state: BridgeState::Off,
+            bridge_settings: BridgeSettings::default(),
             allow_lan: false,
+            block_when_disconnected: false,
             tunnel_options: TunnelOptions::default(),
         }
     }
@@ -97,14 +116,14 @@ impl Settings {
         serde_json::from_reader(file).chain_err(|| ErrorKind::ParseError)
     }
 
-    pub fn get_account_token(&self) -> Option<String> {
+    pub fn get_account_token(&self) -> Option<AccountToken> {
         self.account_token.clone()
     }
 
     /// Changes account number to the one given. Also saves the new settings to disk.
     /// The boolean in the Result indicates if the account token changed or not
-    pub fn set_account_token(&mut self, mut account_token: Option<String>) -> Result<bool> {
-        if account_token.as_ref().map(String::len) == Some(0) {
+    pub fn set_account_token(&mut self, mut account_token: Option<AccountToken>) -> Result<bool> {
+        if account_token.as_ref().map_or(false, AccountToken::is_empty) {
             debug!("Setting empty account token is treated as unsetting it");
             account_token = None;
         }
@@ -127,21 +146,41 @@ impl Settings {
         self.relay_settings.clone()
     }
 
+    /// Applies the given update to the relay settings and saves them to disk if they changed.
+    /// The boolean in the Result indicates if the change can affect which relay is selected.
+    ///
+    /// Updates setting a location to `LocationConstraint::Nearest` are rejected, since the relay
+    /// selector does not resolve it into a country yet and would find no relay to connect to.
     pub fn update_relay_settings(&mut self, update: RelaySettingsUpdate) -> Result<bool> {
         let new_settings = self.relay_settings.merge(update);
+        if let RelaySettings::Normal(ref constraints) = new_settings {
+            let nearest = Constraint::Only(LocationConstraint::Nearest);
+            if constraints.location == nearest || constraints.entry_location == nearest {
+                bail!(ErrorKind::NearestLocationNotSupported);
+            }
+        }
         if self.relay_settings != new_settings {
             debug!(
                 "changing relay settings from {:?} to {:?}",
                 self.relay_settings, new_settings
             );
 
+            let relay_selection_changed = new_settings.differs_from(&self.relay_settings);
             self.relay_settings = new_settings;
-            self.save().map(|_| true)
+            self.save().map(|_| relay_selection_changed)
         } else {
             Ok(false)
         }
     }
 
+    pub fn get_bridge_state(&self) -> BridgeState {
+        self.bridge_state
+    }
+
+    pub fn get_bridge_settings(&self) -> &BridgeSettings {
+        &self.bridge_settings
+    }
+
     pub fn get_allow_lan(&self) -> bool {
         self.allow_lan
     }
@@ -155,9 +194,51 @@ impl Settings {
         }
     }
 
+    pub fn get_block_when_disconnected(&self) -> bool {
+        self.block_when_disconnected
+    }
+
+    pub fn set_block_when_disconnected(&mut self, block_when_disconnected: bool) -> Result<bool> {
+        if block_when_disconnected != self.block_when_disconnected {
+            self.block_when_disconnected = block_when_disconnected;
+            self.save().map(|_| true)
+        } else {
+            Ok(false)
+        }
+    }
+
+    /// Changes the OpenVPN mssfix option, if it is within `MSSFIX_RANGE`. Also saves the new
+    /// settings to disk.
     pub fn set_openvpn_mssfix(&mut self, openvpn_mssfix: Option<u16>) -> Result<bool> {
         if self.tunnel_options.openvpn.mssfix != openvpn_mssfix {
-            self.tunnel_options.openvpn.mssfix = openvpn_mssfix;
+            let mut tunnel_options = self.tunnel_options.clone();
+            tunnel_options.openvpn.mssfix = openvpn_mssfix;
+            Self::validate_tunnel_options(&tunnel_options)?;
+            self.tunnel_options = tunnel_options;
+            self.save().map(|_| true)
+        } else {
+            Ok(false)
+        }
+    }
+
+    /// Changes the MTU of WireGuard tunnels, if it is within `WIREGUARD_MTU_RANGE`. Also saves the
+    /// new settings to disk.
+    pub fn set_wireguard_mtu(&mut self, mtu: Option<u16>) -> Result<bool> {
+        if self.tunnel_options.wireguard.mtu != mtu {
+            let mut tunnel_options = self.tunnel_options.clone();
+            tunnel_options.wireguard.mtu = mtu;
+            Self::validate_tunnel_options(&tunnel_options)?;
+            self.tunnel_options = tunnel_options;
+            self.save().map(|_| true)
+        } else {
+            Ok(false)
+        }
+    }
+
+    /// Changes the keepalive interval of WireGuard tunnels. Also saves the new settings to disk.
+    pub fn set_wireguard_keepalive(&mut self, keepalive: Option<u16>) -> Result<bool> {
+        if self.tunnel_options.wireguard.keepalive != keepalive {
+            self.tunnel_options.wireguard.keepalive = keepalive;
             self.save().map(|_| true)
         } else {
             Ok(false)
@@ -167,4 +248,12 @@ impl Settings {
     pub fn get_tunnel_options(&self) -> &TunnelOptions {
         &self.tunnel_options
     }
+
+    /// Checks the tunnel options before they are stored, describing every problem in the error.
+    fn validate_tunnel_options(tunnel_options: &TunnelOptions) -> Result<()> {
+        tunnel_options.validate().map_err(|errors| {
+            let problems: Vec<String> = errors.iter().map(ToString::to_string).collect();
+            ErrorKind::InvalidTunnelOptions(problems.join("; ")).into()
+        })
+    }
 }
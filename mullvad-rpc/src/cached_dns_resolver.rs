@@ -1,8 +1,8 @@
-use std::fs::File;
+use std::fs::{self, File};
 use std::io::{self, Read, Write};
-use std::net::{IpAddr, ToSocketAddrs};
+use std::net::{IpAddr, SocketAddr, TcpStream, ToSocketAddrs};
 use std::path::{Path, PathBuf};
-use std::sync::mpsc;
+use std::sync::{mpsc, Arc, Mutex};
 use std::thread;
 use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
@@ -12,6 +12,8 @@ use error_chain::ChainedError;
 static DNS_TIMEOUT: Duration = Duration::from_secs(2);
 static MAX_CACHE_AGE: Duration = Duration::from_secs(3600);
 static EXPIRED_CACHE_TIMESTAMP: SystemTime = UNIX_EPOCH;
+/// The host cache files without a hostname were written for.
+static LEGACY_CACHE_HOSTNAME: &str = "api.mullvad.net";
 
 error_chain! {
     errors {
@@ -29,6 +31,15 @@ error_chain! {
             description("Address loaded from file is invalid")
         }
 
+        InvalidCacheFile {
+            description("Cache file is not in a valid format")
+        }
+
+        HostnameMismatch(cached: String, requested: String) {
+            description("Cached address belongs to a different host")
+            display("Cached address belongs to \"{}\", not \"{}\"", cached, requested)
+        }
+
         ResolveFailure(host: String) {
             description("Failed to resolve IP address for host")
             display("Failed to resolve IP address for host: {}", host)
@@ -80,14 +91,30 @@ impl DnsResolver for SystemDnsResolver {
 
 pub struct CachedDnsResolver<R: DnsResolver = SystemDnsResolver> {
     hostname: String,
-    dns_resolver: R,
-    cache_file: PathBuf,
-    cached_address: IpAddr,
-    last_updated: SystemTime,
+    dns_resolver: Arc<Mutex<R>>,
+    cache: Arc<Mutex<AddressCache>>,
+    fallback_address: IpAddr,
+    reachability_check: Option<ReachabilityCheck>,
+}
+
+/// Parameters for verifying that an address accepts TCP connections before using it.
+#[derive(Debug, Clone, Copy)]
+struct ReachabilityCheck {
+    port: u16,
+    timeout: Duration,
+}
+
+impl ReachabilityCheck {
+    fn is_reachable(&self, address: IpAddr) -> bool {
+        TcpStream::connect_timeout(&SocketAddr::new(address, self.port), self.timeout).is_ok()
+    }
 }
 
 impl CachedDnsResolver<SystemDnsResolver> {
-    pub fn new(hostname: String, cache_file: PathBuf, fallback_address: IpAddr) -> Self {
+    /// Creates a resolver for `hostname` that caches the resolved address in `cache_file`. The
+    /// directory containing the file is created when an address is first stored, so it can be
+    /// any writable location.
+    pub fn with_paths(hostname: String, cache_file: PathBuf, fallback_address: IpAddr) -> Self {
         Self::with_dns_resolver(SystemDnsResolver, hostname, cache_file, fallback_address)
     }
 }
@@ -99,43 +126,158 @@ impl<R: DnsResolver> CachedDnsResolver<R> {
         cache_file: PathBuf,
         fallback_address: IpAddr,
     ) -> Self {
-        let (cached_address, last_updated) =
-            Self::load_initial_cached_address(&cache_file, fallback_address);
+        let cache = AddressCache::load(cache_file, hostname.clone(), fallback_address);
 
         CachedDnsResolver {
             hostname,
-            dns_resolver,
-            cache_file,
-            cached_address,
-            last_updated,
+            dns_resolver: Arc::new(Mutex::new(dns_resolver)),
+            cache: Arc::new(Mutex::new(cache)),
+            fallback_address,
+            reachability_check: None,
         }
     }
 
+    /// Makes `resolve` verify that the address accepts TCP connections on `port` before
+    /// returning it. An unreachable address triggers a fresh DNS lookup, and if that doesn't
+    /// yield a reachable address the fallback address is used. The fallback address is then
+    /// cached like a resolved address, so later calls don't wait for the unreachable address
+    /// again. This adds up to `timeout` of latency to every `resolve` call, so it is off by
+    /// default.
+    pub fn with_reachability_check(mut self, port: u16, timeout: Duration) -> Self {
+        self.reachability_check = Some(ReachabilityCheck { port, timeout });
+        self
+    }
+
     pub fn resolve(&mut self) -> IpAddr {
-        if let Ok(cache_age) = self.last_updated.elapsed() {
-            if cache_age > MAX_CACHE_AGE {
-                self.resolve_into_cache();
-            }
-        } else {
-            warn!("System time changed, assuming cached IP address has expired");
-            self.resolve_into_cache();
+        let is_expired = self.cache.lock().unwrap().is_expired();
+        if is_expired {
+            resolve_into_cache(&self.hostname, &self.dns_resolver, &self.cache);
         }
 
-        self.cached_address
+        let address = self.cache.lock().unwrap().address;
+        match self.reachability_check {
+            Some(check) if !check.is_reachable(address) => {
+                self.resolve_after_unreachable(address, check, is_expired)
+            }
+            _ => address,
+        }
     }
 
-    fn load_initial_cached_address(
-        cache_file: &Path,
-        fallback_address: IpAddr,
-    ) -> (IpAddr, SystemTime) {
-        match Self::load_from_file(cache_file) {
-            Ok(previously_cached_address) => match Self::read_file_modification_time(cache_file) {
-                Ok(last_updated) => (previously_cached_address, last_updated),
-                Err(error) => {
-                    warn!("Failed to read modification time of file: {}", error);
-                    (previously_cached_address, EXPIRED_CACHE_TIMESTAMP)
+    /// Looks for a reachable address when the cached one is unreachable. The hostname is only
+    /// looked up again if `just_resolved` is false, since a lookup that was just done would give
+    /// the same answer.
+    fn resolve_after_unreachable(
+        &mut self,
+        unreachable_address: IpAddr,
+        check: ReachabilityCheck,
+        just_resolved: bool,
+    ) -> IpAddr {
+        warn!(
+            "Cached IP address {} for {} is unreachable",
+            unreachable_address, self.hostname
+        );
+        if !just_resolved {
+            let result = self.dns_resolver.lock().unwrap().resolve(&self.hostname);
+            match result {
+                Ok(address) if address != unreachable_address && check.is_reachable(address) => {
+                    self.cache.lock().unwrap().update(address);
+                    return address;
                 }
-            },
+                _ => (),
+            }
+        }
+        info!(
+            "Unable to find a reachable address for {}, using fallback",
+            self.hostname
+        );
+        self.cache.lock().unwrap().update(self.fallback_address);
+        self.fallback_address
+    }
+}
+
+impl<R: DnsResolver + Send + 'static> CachedDnsResolver<R> {
+    /// Spawns a thread that re-resolves the hostname every `interval` and updates the cache, so
+    /// `resolve` can return a warm value without blocking on DNS. The refresher runs until the
+    /// returned handle is stopped or dropped.
+    pub fn spawn_background_refresh(&self, interval: Duration) -> BackgroundRefreshHandle {
+        let (stop_tx, stop_rx) = mpsc::channel();
+        let hostname = self.hostname.clone();
+        let dns_resolver = self.dns_resolver.clone();
+        let cache = self.cache.clone();
+
+        let thread = thread::spawn(move || {
+            while let Err(mpsc::RecvTimeoutError::Timeout) = stop_rx.recv_timeout(interval) {
+                trace!("Refreshing cached IP address for {}", hostname);
+                resolve_into_cache(&hostname, &dns_resolver, &cache);
+            }
+            trace!("Background DNS cache refresh stopped");
+        });
+
+        BackgroundRefreshHandle {
+            stop_tx,
+            thread: Some(thread),
+        }
+    }
+}
+
+/// Handle to a background cache refresher spawned with
+/// `CachedDnsResolver::spawn_background_refresh`. Dropping the handle stops the refresher.
+pub struct BackgroundRefreshHandle {
+    stop_tx: mpsc::Sender<()>,
+    thread: Option<thread::JoinHandle<()>>,
+}
+
+impl BackgroundRefreshHandle {
+    /// Stops the refresher and waits for an ongoing refresh to complete.
+    pub fn stop(mut self) {
+        self.stop_and_join();
+    }
+
+    fn stop_and_join(&mut self) {
+        let _ = self.stop_tx.send(());
+        if let Some(thread) = self.thread.take() {
+            if thread.join().is_err() {
+                error!("Background DNS cache refresh thread panicked");
+            }
+        }
+    }
+}
+
+impl Drop for BackgroundRefreshHandle {
+    fn drop(&mut self) {
+        self.stop_and_join();
+    }
+}
+
+/// Resolves `hostname` and stores the result in `cache`. The DNS lookup is done without holding
+/// the cache lock, so concurrent readers are only blocked while the cache file is written.
+fn resolve_into_cache<R: DnsResolver>(
+    hostname: &str,
+    dns_resolver: &Mutex<R>,
+    cache: &Mutex<AddressCache>,
+) {
+    let result = dns_resolver.lock().unwrap().resolve(hostname);
+    if let Ok(address) = result {
+        cache.lock().unwrap().update(address);
+    }
+}
+
+/// The last resolved address and the file it is persisted in.
+///
+/// The file contains a single line with the hostname, the address and the time of resolution in
+/// seconds since the Unix epoch, separated by tabs. The hostname is used to detect when the file
+/// was written for another host than the one being resolved.
+struct AddressCache {
+    file: PathBuf,
+    hostname: String,
+    address: IpAddr,
+    last_updated: SystemTime,
+}
+
+impl AddressCache {
+    fn load(file: PathBuf, hostname: String, fallback_address: IpAddr) -> Self {
+        let (address, last_updated) = match Self::load_from_file(&file, &hostname) {
+            Ok(previously_cached) => previously_cached,
             Err(error) => {
                 info!(
                     "Failed to load previously cached IP address, using fallback: {}",
@@ -144,19 +286,77 @@ impl<R: DnsResolver> CachedDnsResolver<R> {
 
                 (fallback_address, EXPIRED_CACHE_TIMESTAMP)
             }
+        };
+
+        AddressCache {
+            file,
+            hostname,
+            address,
+            last_updated,
         }
     }
 
-    fn load_from_file(file_path: &Path) -> Result<IpAddr> {
+    fn is_expired(&self) -> bool {
+        if let Ok(cache_age) = self.last_updated.elapsed() {
+            cache_age > MAX_CACHE_AGE
+        } else {
+            warn!("System time changed, assuming cached IP address has expired");
+            true
+        }
+    }
+
+    fn update(&mut self, address: IpAddr) {
+        self.address = address;
+        self.last_updated = SystemTime::now();
+
+        if let Err(error) = self.update_cache_file() {
+            warn!("Failed to update cache file with new IP address: {}", error);
+        }
+    }
+
+    fn load_from_file(file_path: &Path, hostname: &str) -> Result<(IpAddr, SystemTime)> {
         let mut file = File::open(file_path)?;
-        let mut address = String::new();
+        let mut contents = String::new();
 
-        file.read_to_string(&mut address)?;
+        file.read_to_string(&mut contents)?;
 
-        address
-            .trim()
-            .parse()
-            .chain_err(|| ErrorKind::InvalidAddress)
+        let fields: Vec<&str> = contents.trim().split('\t').collect();
+        match fields.len() {
+            1 => Self::load_legacy_record(file_path, fields[0], hostname),
+            3 => {
+                ensure!(
+                    fields[0] == hostname,
+                    ErrorKind::HostnameMismatch(fields[0].to_owned(), hostname.to_owned())
+                );
+                let address = fields[1].parse().chain_err(|| ErrorKind::InvalidAddress)?;
+                let resolved_at: u64 = fields[2]
+                    .parse()
+                    .chain_err(|| ErrorKind::InvalidCacheFile)?;
+                Ok((address, UNIX_EPOCH + Duration::from_secs(resolved_at)))
+            }
+            _ => bail!(ErrorKind::InvalidCacheFile),
+        }
+    }
+
+    /// Older versions only stored the address, and only ever for `LEGACY_CACHE_HOSTNAME`. The
+    /// time of resolution is then taken from the modification time of the file.
+    fn load_legacy_record(
+        file_path: &Path,
+        address: &str,
+        hostname: &str,
+    ) -> Result<(IpAddr, SystemTime)> {
+        ensure!(
+            hostname == LEGACY_CACHE_HOSTNAME,
+            ErrorKind::HostnameMismatch(LEGACY_CACHE_HOSTNAME.to_owned(), hostname.to_owned())
+        );
+        let address = address.parse().chain_err(|| ErrorKind::InvalidAddress)?;
+        match Self::read_file_modification_time(file_path) {
+            Ok(last_updated) => Ok((address, last_updated)),
+            Err(error) => {
+                warn!("Failed to read modification time of file: {}", error);
+                Ok((address, EXPIRED_CACHE_TIMESTAMP))
+            }
+        }
     }
 
     fn read_file_modification_time(cache_file: &Path) -> io::Result<SystemTime> {
@@ -165,21 +365,21 @@ impl<R: DnsResolver> CachedDnsResolver<R> {
             .and_then(|metadata| metadata.modified())
     }
 
-    fn resolve_into_cache(&mut self) {
-        if let Ok(address) = self.dns_resolver.resolve(&self.hostname) {
-            self.cached_address = address;
-            self.last_updated = SystemTime::now();
-
-            if let Err(error) = self.update_cache_file() {
-                warn!("Failed to update cache file with new IP address: {}", error);
-            }
+    fn update_cache_file(&self) -> io::Result<()> {
+        if let Some(cache_dir) = self.file.parent() {
+            fs::create_dir_all(cache_dir)?;
         }
-    }
+        let resolved_at = self.last_updated
+            .duration_since(UNIX_EPOCH)
+            .map(|since_epoch| since_epoch.as_secs())
+            .unwrap_or(0);
+        let mut cache_file = File::create(&self.file)?;
 
-    fn update_cache_file(&mut self) -> io::Result<()> {
-        let mut cache_file = File::create(&self.cache_file)?;
-
-        writeln!(cache_file, "{}", self.cached_address)
+        writeln!(
+            cache_file,
+            "{}\t{}\t{}",
+            self.hostname, self.address, resolved_at
+        )
     }
 }
 
@@ -190,8 +390,10 @@ mod tests {
 
     use std::fs::{self, File};
     use std::io::{Read, Write};
-    use std::sync::atomic::{AtomicBool, Ordering};
-    use std::sync::Arc;
+    use std::net::TcpListener;
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+    use std::sync::{Arc, Mutex};
+    use std::time::Instant;
 
     use self::filetime::FileTime;
     use self::tempdir::TempDir;
@@ -220,9 +422,7 @@ mod tests {
         let mock_address = "192.168.1.206".parse().unwrap();
         let mock_resolver = MockDnsResolver::with_address(mock_address);
 
-        let cache_file_path = write_address(&cache_dir, cached_address);
-
-        make_file_old(&cache_file_path);
+        write_old_address(&cache_dir, cached_address);
 
         let mut cache = create_cached_dns_resolver(mock_resolver, &cache_dir, None);
         let address = cache.resolve();
@@ -237,9 +437,7 @@ mod tests {
         let mock_resolver = MockDnsResolver::that_fails();
         let cached_address = "127.0.0.1".parse().unwrap();
 
-        let cache_file_path = write_address(&cache_dir, cached_address);
-
-        make_file_old(&cache_file_path);
+        write_old_address(&cache_dir, cached_address);
 
         let mut cache = create_cached_dns_resolver(mock_resolver, &cache_dir, None);
         let address = cache.resolve();
@@ -262,15 +460,18 @@ mod tests {
 
     #[test]
     fn resolves_even_if_impossible_to_store_in_cache() {
-        let (temp_dir, cache_dir) = create_test_dirs();
+        let (_temp_dir, cache_dir) = create_test_dirs();
         let mock_address = "192.168.1.206".parse().unwrap();
         let mock_resolver = MockDnsResolver::with_address(mock_address);
 
-        let mut cache = create_cached_dns_resolver(mock_resolver, &cache_dir, None);
+        // A file where the cache directory should be can not be replaced by a directory.
+        let blocked_cache_dir = cache_dir.join("blocked");
+        File::create(&blocked_cache_dir).unwrap();
 
-        ::std::mem::drop(temp_dir);
+        let mut cache = create_cached_dns_resolver(mock_resolver, &blocked_cache_dir, None);
 
         assert_eq!(cache.resolve(), mock_address);
+        assert!(blocked_cache_dir.is_file());
     }
 
     #[test]
@@ -319,6 +520,229 @@ mod tests {
         assert_eq!(address, fallback_address);
     }
 
+    #[test]
+    fn creates_missing_cache_dir_with_custom_file_name() {
+        let (_temp_dir, cache_dir) = create_test_dirs();
+        let cache_file = cache_dir.join("state").join("mullvad-api.ip");
+        let mock_address = "192.168.1.206".parse().unwrap();
+        let mock_resolver = MockDnsResolver::with_address(mock_address);
+
+        let mut cache = CachedDnsResolver::with_dns_resolver(
+            mock_resolver,
+            "dummy.host".to_owned(),
+            cache_file.clone(),
+            IpAddr::from([10, 0, 109, 91]),
+        );
+
+        assert_eq!(cache.resolve(), mock_address);
+        assert_eq!(get_cached_record(&cache_file)[1], mock_address.to_string());
+    }
+
+    #[test]
+    fn background_refresh_updates_cached_address() {
+        let (_temp_dir, cache_dir) = create_test_dirs();
+        let cached_address = "127.0.0.1".parse().unwrap();
+        let new_address = "192.168.1.206".parse().unwrap();
+        let mock_resolver = MockDnsResolver::that_fails();
+        let mock_address = mock_resolver.address_handle();
+
+        write_address(&cache_dir, cached_address);
+
+        let mut cache = create_cached_dns_resolver(mock_resolver, &cache_dir, None);
+        let refresh_handle = cache.spawn_background_refresh(Duration::from_millis(10));
+
+        assert_eq!(cache.resolve(), cached_address);
+
+        *mock_address.lock().unwrap() = Some(new_address);
+
+        let start = Instant::now();
+        while cache.resolve() != new_address {
+            assert!(start.elapsed() < Duration::from_secs(5));
+            thread::sleep(Duration::from_millis(10));
+        }
+        refresh_handle.stop();
+
+        assert_eq!(get_cached_address(&cache_dir), new_address.to_string());
+    }
+
+    #[test]
+    fn unreachable_cached_address_leads_to_fallback_address_usage() {
+        let (_temp_dir, cache_dir) = create_test_dirs();
+        let fallback_address = "192.168.1.31".parse().unwrap();
+        let mock_resolver = MockDnsResolver::that_fails();
+        let mock_resolver_was_called = mock_resolver.was_called_handle();
+        let closed_port = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+
+        write_address(&cache_dir, "127.0.0.1".parse().unwrap());
+
+        let mut cache =
+            create_cached_dns_resolver(mock_resolver, &cache_dir, Some(fallback_address))
+                .with_reachability_check(closed_port, Duration::from_millis(500));
+        let address = cache.resolve();
+
+        assert!(mock_resolver_was_called.load(Ordering::Acquire));
+        assert_eq!(address, fallback_address);
+    }
+
+    #[test]
+    fn fallback_address_is_cached_after_unreachable_address() {
+        let (_temp_dir, cache_dir) = create_test_dirs();
+        let fallback_address = "127.0.0.1".parse().unwrap();
+        let mock_resolver = MockDnsResolver::that_fails();
+        let mock_resolver_calls = mock_resolver.call_count_handle();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let open_port = listener.local_addr().unwrap().port();
+
+        // Nothing listens on 127.0.0.2, so the cached address is unreachable.
+        write_address(&cache_dir, "127.0.0.2".parse().unwrap());
+
+        let mut cache =
+            create_cached_dns_resolver(mock_resolver, &cache_dir, Some(fallback_address))
+                .with_reachability_check(open_port, Duration::from_millis(500));
+
+        assert_eq!(cache.resolve(), fallback_address);
+        assert_eq!(mock_resolver_calls.load(Ordering::Acquire), 1);
+        assert_eq!(get_cached_address(&cache_dir), fallback_address.to_string());
+
+        assert_eq!(cache.resolve(), fallback_address);
+        assert_eq!(mock_resolver_calls.load(Ordering::Acquire), 1);
+    }
+
+    #[test]
+    fn expired_unreachable_address_is_only_resolved_once() {
+        let (_temp_dir, cache_dir) = create_test_dirs();
+        let fallback_address = "192.168.1.31".parse().unwrap();
+        let unreachable_address = "127.0.0.2".parse().unwrap();
+        let mock_resolver = MockDnsResolver::with_address(unreachable_address);
+        let mock_resolver_calls = mock_resolver.call_count_handle();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let open_port = listener.local_addr().unwrap().port();
+
+        write_old_address(&cache_dir, unreachable_address);
+
+        let mut cache =
+            create_cached_dns_resolver(mock_resolver, &cache_dir, Some(fallback_address))
+                .with_reachability_check(open_port, Duration::from_millis(500));
+
+        assert_eq!(cache.resolve(), fallback_address);
+        assert_eq!(mock_resolver_calls.load(Ordering::Acquire), 1);
+    }
+
+    #[test]
+    fn reachable_cached_address_is_used() {
+        let (_temp_dir, cache_dir) = create_test_dirs();
+        let cached_address = "127.0.0.1".parse().unwrap();
+        let mock_resolver = MockDnsResolver::that_fails();
+        let mock_resolver_was_called = mock_resolver.was_called_handle();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let open_port = listener.local_addr().unwrap().port();
+
+        write_address(&cache_dir, cached_address);
+
+        let mut cache = create_cached_dns_resolver(mock_resolver, &cache_dir, None)
+            .with_reachability_check(open_port, Duration::from_millis(500));
+        let address = cache.resolve();
+
+        assert!(!mock_resolver_was_called.load(Ordering::Acquire));
+        assert_eq!(address, cached_address);
+    }
+
+    #[test]
+    fn stores_hostname_and_resolution_time() {
+        let (_temp_dir, cache_dir) = create_test_dirs();
+        let mock_address: IpAddr = "192.168.1.206".parse().unwrap();
+        let mock_resolver = MockDnsResolver::with_address(mock_address);
+
+        let mut cache = create_cached_dns_resolver(mock_resolver, &cache_dir, None);
+        cache.resolve();
+
+        let record = get_cached_record(&cache_dir.join("api_ip_address.txt"));
+        let resolved_at: u64 = record[2].parse().unwrap();
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+        assert_eq!(record[0], "dummy.host");
+        assert_eq!(record[1], mock_address.to_string());
+        assert!(now.as_secs() - resolved_at < 60);
+    }
+
+    #[test]
+    fn cache_for_other_host_is_ignored() {
+        let (_temp_dir, cache_dir) = create_test_dirs();
+        let mock_address = "192.168.1.206".parse().unwrap();
+        let mock_resolver = MockDnsResolver::with_address(mock_address);
+        let mock_resolver_was_called = mock_resolver.was_called_handle();
+
+        write_address_for_host(&cache_dir, "other.host", "127.0.0.1".parse().unwrap());
+
+        let mut cache = create_cached_dns_resolver(mock_resolver, &cache_dir, None);
+        let address = cache.resolve();
+
+        assert!(mock_resolver_was_called.load(Ordering::Acquire));
+        assert_eq!(address, mock_address);
+    }
+
+    #[test]
+    fn legacy_cache_file_is_used_for_legacy_host() {
+        let (_temp_dir, cache_dir) = create_test_dirs();
+        let mock_resolver = MockDnsResolver::with_address("192.168.1.206".parse().unwrap());
+        let mock_resolver_was_called = mock_resolver.was_called_handle();
+        let cached_address = "127.0.0.1".parse().unwrap();
+
+        write_legacy_address(&cache_dir, cached_address);
+
+        let mut cache = create_cached_dns_resolver_for_host(
+            mock_resolver,
+            &cache_dir,
+            LEGACY_CACHE_HOSTNAME,
+            None,
+        );
+        let address = cache.resolve();
+
+        assert!(!mock_resolver_was_called.load(Ordering::Acquire));
+        assert_eq!(address, cached_address);
+    }
+
+    #[test]
+    fn old_legacy_cache_file_is_migrated() {
+        let (_temp_dir, cache_dir) = create_test_dirs();
+        let mock_address = "192.168.1.206".parse().unwrap();
+        let mock_resolver = MockDnsResolver::with_address(mock_address);
+
+        let cache_file_path = write_legacy_address(&cache_dir, "127.0.0.1".parse().unwrap());
+
+        make_file_old(&cache_file_path);
+
+        let mut cache = create_cached_dns_resolver_for_host(
+            mock_resolver,
+            &cache_dir,
+            LEGACY_CACHE_HOSTNAME,
+            None,
+        );
+        let address = cache.resolve();
+
+        let record = get_cached_record(&cache_file_path);
+        assert_eq!(address, mock_address);
+        assert_eq!(record[0], LEGACY_CACHE_HOSTNAME);
+        assert_eq!(record[1], mock_address.to_string());
+    }
+
+    #[test]
+    fn legacy_cache_file_is_ignored_for_other_hosts() {
+        let (_temp_dir, cache_dir) = create_test_dirs();
+        let fallback_address = "192.168.1.31".parse().unwrap();
+        let mock_resolver = MockDnsResolver::that_fails();
+
+        write_legacy_address(&cache_dir, "127.0.0.1".parse().unwrap());
+
+        let mut cache =
+            create_cached_dns_resolver(mock_resolver, &cache_dir, Some(fallback_address));
+
+        assert_eq!(cache.resolve(), fallback_address);
+    }
+
     fn create_test_dirs() -> (TempDir, PathBuf) {
         let temp_dir = TempDir::new("ip-cache-test").unwrap();
         let cache_dir = temp_dir.path().join("cache");
@@ -329,19 +753,34 @@ mod tests {
     }
 
     fn write_invalid_address(dir: &Path) -> PathBuf {
-        let file_path = dir.join("api_ip_address.txt");
-        let mut file = File::create(&file_path).unwrap();
+        write_cache_file(dir, "dummy.host\t400.30.12.9\t1520000000")
+    }
 
-        writeln!(file, "400.30.12.9").unwrap();
+    fn write_address(dir: &Path, address: IpAddr) -> PathBuf {
+        write_address_for_host(dir, "dummy.host", address)
+    }
 
-        file_path
+    fn write_address_for_host(dir: &Path, hostname: &str, address: IpAddr) -> PathBuf {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+        write_cache_file(
+            dir,
+            &format!("{}\t{}\t{}", hostname, address, now.as_secs()),
+        )
     }
 
-    fn write_address(dir: &Path, address: IpAddr) -> PathBuf {
+    fn write_old_address(dir: &Path, address: IpAddr) -> PathBuf {
+        write_cache_file(dir, &format!("dummy.host\t{}\t100000", address))
+    }
+
+    fn write_legacy_address(dir: &Path, address: IpAddr) -> PathBuf {
+        write_cache_file(dir, &address.to_string())
+    }
+
+    fn write_cache_file(dir: &Path, contents: &str) -> PathBuf {
         let file_path = dir.join("api_ip_address.txt");
         let mut file = File::create(&file_path).unwrap();
 
-        writeln!(file, "{}", address).unwrap();
+        writeln!(file, "{}", contents).unwrap();
 
         file_path
     }
@@ -355,16 +794,22 @@ mod tests {
     }
 
     fn get_cached_address(cache_dir: &Path) -> String {
-        let cache_file_path = cache_dir.join("api_ip_address.txt");
+        get_cached_record(&cache_dir.join("api_ip_address.txt"))[1].clone()
+    }
 
+    fn get_cached_record(cache_file_path: &Path) -> Vec<String> {
         assert!(cache_file_path.exists());
 
         let mut cache_file = File::open(cache_file_path).unwrap();
-        let mut cached_address = String::new();
+        let mut cached_record = String::new();
 
-        cache_file.read_to_string(&mut cached_address).unwrap();
+        cache_file.read_to_string(&mut cached_record).unwrap();
 
-        cached_address.trim().to_string()
+        cached_record
+            .trim()
+            .split('\t')
+            .map(|field| field.to_owned())
+            .collect()
     }
 
     fn create_cached_dns_resolver(
@@ -372,7 +817,21 @@ mod tests {
         cache_dir: &Path,
         fallback_address: Option<IpAddr>,
     ) -> CachedDnsResolver<MockDnsResolver> {
-        let hostname = "dummy.host".to_owned();
+        create_cached_dns_resolver_for_host(
+            mock_resolver,
+            cache_dir,
+            "dummy.host",
+            fallback_address,
+        )
+    }
+
+    fn create_cached_dns_resolver_for_host(
+        mock_resolver: MockDnsResolver,
+        cache_dir: &Path,
+        hostname: &str,
+        fallback_address: Option<IpAddr>,
+    ) -> CachedDnsResolver<MockDnsResolver> {
+        let hostname = hostname.to_owned();
         let filename = "api_ip_address.txt";
         let cache_file = cache_dir.join(filename);
         let fallback_address = fallback_address.unwrap_or(IpAddr::from([10, 0, 109, 91]));
@@ -381,34 +840,48 @@ mod tests {
     }
 
     struct MockDnsResolver {
-        address: Option<IpAddr>,
+        address: Arc<Mutex<Option<IpAddr>>>,
         called: Arc<AtomicBool>,
+        calls: Arc<AtomicUsize>,
     }
 
     impl MockDnsResolver {
         pub fn with_address(address: IpAddr) -> Self {
             MockDnsResolver {
-                address: Some(address),
+                address: Arc::new(Mutex::new(Some(address))),
                 called: Arc::new(AtomicBool::new(false)),
+                calls: Arc::new(AtomicUsize::new(0)),
             }
         }
 
         pub fn that_fails() -> Self {
             MockDnsResolver {
-                address: None,
+                address: Arc::new(Mutex::new(None)),
                 called: Arc::new(AtomicBool::new(false)),
+                calls: Arc::new(AtomicUsize::new(0)),
             }
         }
 
         pub fn was_called_handle(&self) -> Arc<AtomicBool> {
             self.called.clone()
         }
+
+        pub fn call_count_handle(&self) -> Arc<AtomicUsize> {
+            self.calls.clone()
+        }
+
+        pub fn address_handle(&self) -> Arc<Mutex<Option<IpAddr>>> {
+            self.address.clone()
+        }
     }
 
     impl DnsResolver for MockDnsResolver {
         fn resolve(&mut self, host: &str) -> Result<IpAddr> {
             self.called.store(true, Ordering::Release);
+            self.calls.fetch_add(1, Ordering::AcqRel);
             self.address
+                .lock()
+                .unwrap()
                 .ok_or_else(|| ErrorKind::ResolveFailure(host.to_owned()).into())
         }
     }
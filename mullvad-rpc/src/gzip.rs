@@ -0,0 +1,294 @@
+use flate2::read::GzDecoder;
+use futures::Future;
+use hyper::StatusCode;
+use jsonrpc_client_core::{self, Transport};
+use jsonrpc_client_http;
+
+use rest;
+
+use std::io::{self, Read};
+use std::time::Duration;
+
+
+/// The two magic bytes every gzip stream starts with, as specified in RFC 1952.
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+
+/// A `Transport` wrapper that transparently inflates gzip encoded response bodies before they
+/// reach the JSON-RPC layer. Bodies that are not gzip encoded are passed through untouched, so
+/// servers that ignore the `Accept-Encoding` header keep working.
+///
+/// Requests the server answers with 429 Too Many Requests fail with a `RateLimited` error, so
+/// callers can back off. See `rate_limit`.
+#[derive(Debug, Clone)]
+pub struct GzipTransport<T: Transport> {
+    inner: T,
+}
+
+impl<T: Transport> GzipTransport<T> {
+    /// Wraps the given transport.
+    pub fn new(inner: T) -> Self {
+        GzipTransport { inner }
+    }
+}
+
+impl<T: Transport> Transport for GzipTransport<T>
+where
+    T::Error: HttpStatusError,
+{
+    type Future = Box<Future<Item = Vec<u8>, Error = Self::Error> + Send>;
+    type Error = rest::Error;
+
+    fn get_next_id(&mut self) -> u64 {
+        self.inner.get_next_id()
+    }
+
+    fn send(&self, json_data: Vec<u8>) -> Self::Future {
+        Box::new(
+            self.inner
+                .send(json_data)
+                .map_err(check_rate_limit)
+                .map(maybe_decompress),
+        )
+    }
+}
+
+/// An error from a transport that fails requests the server answers with an HTTP error status.
+pub trait HttpStatusError: ::std::error::Error + Send + 'static {
+    /// Returns the status code of the response, if that is what made the request fail.
+    fn http_status(&self) -> Option<StatusCode>;
+}
+
+impl HttpStatusError for jsonrpc_client_http::Error {
+    fn http_status(&self) -> Option<StatusCode> {
+        match *self.kind() {
+            jsonrpc_client_http::ErrorKind::HttpError(status) => Some(status),
+            _ => None,
+        }
+    }
+}
+
+/// Turns a 429 Too Many Requests from the wrapped transport into a `RateLimited` error. The
+/// wrapped transport does not give access to the response headers, so the `Retry-After` header
+/// can not be read and the default delay is used.
+fn check_rate_limit<E: HttpStatusError>(error: E) -> rest::Error {
+    match error.http_status() {
+        Some(StatusCode::TooManyRequests) => {
+            let retry_after = Duration::from_secs(rest::DEFAULT_RETRY_AFTER_SECS);
+            warn!(
+                "Rate limited by server, retry after {} seconds",
+                retry_after.as_secs()
+            );
+            rest::Error::with_chain(error, rest::ErrorKind::RateLimited(retry_after))
+        }
+        _ => rest::Error::with_chain(error, rest::ErrorKind::TransportError),
+    }
+}
+
+/// Returns how long to wait before calling the API again if `error` is from a call the server
+/// rate limited.
+pub fn rate_limit(error: &jsonrpc_client_core::Error) -> Option<Duration> {
+    let transport_error = error
+        .1
+        .next_error
+        .as_ref()
+        .and_then(|cause| cause.downcast_ref::<rest::Error>());
+    match transport_error.map(|error| error.kind()) {
+        Some(&rest::ErrorKind::RateLimited(retry_after)) => Some(retry_after),
+        _ => None,
+    }
+}
+
+/// Inflates the given body if it is gzip encoded. If the body is not gzip encoded, or the
+/// decompression fails, the body is returned as is and left to the JSON parser to judge.
+fn maybe_decompress(body: Vec<u8>) -> Vec<u8> {
+    if !body.starts_with(&GZIP_MAGIC) {
+        return body;
+    }
+    match decompress(&body) {
+        Ok(decompressed) => {
+            trace!(
+                "Inflated gzip response from {} to {} bytes",
+                body.len(),
+                decompressed.len()
+            );
+            decompressed
+        }
+        Err(error) => {
+            warn!("Unable to inflate gzip encoded response: {}", error);
+            body
+        }
+    }
+}
+
+fn decompress(body: &[u8]) -> io::Result<Vec<u8>> {
+    let mut decompressed = Vec::new();
+    GzDecoder::new(body).read_to_end(&mut decompressed)?;
+    Ok(decompressed)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+    use jsonrpc_client_http::HttpTransport;
+    use serde_json;
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+    use std::sync::mpsc;
+    use std::thread;
+
+    use mullvad_types::relay_list::RelayList;
+    use RelayListProxy;
+
+    static RELAY_LIST: &str = r#"{"countries": [{
+        "name": "Sweden",
+        "code": "se",
+        "cities": [{
+            "name": "Gothenburg",
+            "code": "got",
+            "latitude": 57.70887,
+            "longitude": 11.97456,
+            "relays": [{
+                "hostname": "se2-wireguard",
+                "ipv4_addr_in": "185.213.154.131",
+                "ipv4_addr_exit": "185.213.154.132",
+                "include_in_country": true,
+                "weight": 100,
+                "tunnels": {"openvpn": [{"port": 1194, "protocol": "udp"}]}
+            }]
+        }]
+    }]}"#;
+
+    /// How the mock API server answers the request.
+    enum Answer {
+        Plain,
+        Gzip,
+        /// Answer with the given status line and no body.
+        Status(&'static str),
+    }
+
+    /// Starts an HTTP server answering a single JSON-RPC request with the relay list. Returns the
+    /// URI to send the request to, and a receiver getting the head of the request once answered.
+    fn serve_relay_list(answer: Answer) -> (String, mpsc::Receiver<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let uri = format!("http://{}/rpc/", listener.local_addr().unwrap());
+        let (head_tx, head_rx) = mpsc::channel();
+        thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream);
+            let head = read_head(&mut reader);
+            let content_length: usize = head.lines()
+                .find(|line| line.to_lowercase().starts_with("content-length:"))
+                .map(|line| line["content-length:".len()..].trim().parse().unwrap())
+                .expect("No Content-Length in request");
+            let mut body = vec![0; content_length];
+            reader.read_exact(&mut body).unwrap();
+
+            let request: serde_json::Value = serde_json::from_slice(&body).unwrap();
+            let response = format!(
+                r#"{{"jsonrpc": "2.0", "id": {}, "result": {}}}"#,
+                request["id"], RELAY_LIST
+            );
+            let (status, encoding, body) = match answer {
+                Answer::Plain => ("200 OK", "", response.into_bytes()),
+                Answer::Gzip => (
+                    "200 OK",
+                    "Content-Encoding: gzip\r\n",
+                    compress(response.as_bytes()),
+                ),
+                Answer::Status(status) => (status, "", Vec::new()),
+            };
+            let mut stream = reader.into_inner();
+            write!(
+                stream,
+                "HTTP/1.1 {}\r\n{}Content-Length: {}\r\nConnection: close\r\n\r\n",
+                status,
+                encoding,
+                body.len()
+            ).unwrap();
+            stream.write_all(&body).unwrap();
+            head_tx.send(head).unwrap();
+        });
+        (uri, head_rx)
+    }
+
+    /// Reads the request line and headers of a request, up to the empty line ending them.
+    fn read_head<R: BufRead>(reader: &mut R) -> String {
+        let mut head = String::new();
+        loop {
+            let mut line = String::new();
+            reader.read_line(&mut line).unwrap();
+            if line == "\r\n" || line.is_empty() {
+                return head;
+            }
+            head.push_str(&line);
+        }
+    }
+
+    fn compress(data: &[u8]) -> Vec<u8> {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    /// Fetches the relay list from `uri` over a handle set up just like the ones for the API.
+    fn fetch_relay_list(uri: &str) -> ::std::result::Result<RelayList, ::Error> {
+        let handle = HttpTransport::new().unwrap().handle(uri).unwrap();
+        RelayListProxy::new(::create_api_handle(handle))
+            .relay_list()
+            .call()
+    }
+
+    #[test]
+    fn requests_and_inflates_gzip_encoded_relay_list() {
+        let (uri, head_rx) = serve_relay_list(Answer::Gzip);
+        let relay_list = fetch_relay_list(&uri).unwrap();
+
+        assert_eq!(relay_list.countries.len(), 1);
+        assert_eq!(
+            relay_list.countries[0].cities[0].relays[0].hostname,
+            "se2-wireguard"
+        );
+        let head = head_rx.recv().unwrap().to_lowercase();
+        assert!(head.contains("accept-encoding: gzip\r\n"), "{}", head);
+    }
+
+    #[test]
+    fn passes_through_uncompressed_relay_list() {
+        let (uri, head_rx) = serve_relay_list(Answer::Plain);
+        let relay_list = fetch_relay_list(&uri).unwrap();
+
+        assert_eq!(relay_list.countries[0].code, "se");
+        let head = head_rx.recv().unwrap().to_lowercase();
+        assert!(head.contains("accept-encoding: gzip\r\n"), "{}", head);
+    }
+
+    #[test]
+    fn too_many_requests_is_rate_limited() {
+        let (uri, _head_rx) = serve_relay_list(Answer::Status("429 Too Many Requests"));
+        let error = fetch_relay_list(&uri).unwrap_err();
+
+        assert_eq!(
+            rate_limit(&error),
+            Some(Duration::from_secs(rest::DEFAULT_RETRY_AFTER_SECS))
+        );
+    }
+
+    #[test]
+    fn other_status_codes_are_not_rate_limited() {
+        let (uri, _head_rx) = serve_relay_list(Answer::Status("500 Internal Server Error"));
+        let error = fetch_relay_list(&uri).unwrap_err();
+
+        assert_eq!(rate_limit(&error), None);
+    }
+
+    #[test]
+    fn invalid_gzip_body_is_passed_through() {
+        let body = vec![0x1f, 0x8b, 0x00, 0x42];
+        assert_eq!(maybe_decompress(body.clone()), body);
+    }
+}
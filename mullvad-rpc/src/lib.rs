@@ -9,6 +9,7 @@
 extern crate chrono;
 #[macro_use]
 extern crate error_chain;
+extern crate flate2;
 extern crate futures;
 extern crate hyper;
 extern crate hyper_tls;
@@ -25,12 +26,12 @@ extern crate mullvad_types;
 
 use chrono::offset::Utc;
 use chrono::DateTime;
-use jsonrpc_client_http::header::Host;
+use jsonrpc_client_http::header::{qitem, AcceptEncoding, Encoding, Host};
 use jsonrpc_client_http::HttpTransport;
 use tokio_core::reactor::Handle;
 
 pub use jsonrpc_client_core::{Error, ErrorKind};
-pub use jsonrpc_client_http::{Error as HttpError, HttpHandle};
+pub use jsonrpc_client_http::Error as HttpError;
 
 use mullvad_types::account::AccountToken;
 use mullvad_types::relay_list::RelayList;
@@ -38,15 +39,28 @@ use mullvad_types::version;
 
 use std::collections::HashMap;
 use std::net::IpAddr;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 
 pub mod event_loop;
 pub mod rest;
 
+mod gzip;
+pub use gzip::{rate_limit, GzipTransport};
+
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
+
 mod cached_dns_resolver;
 use cached_dns_resolver::CachedDnsResolver;
+pub use cached_dns_resolver::BackgroundRefreshHandle;
 
 static MASTER_API_HOST: &str = "api.mullvad.net";
+static API_IP_CACHE_FILENAME: &str = "api_ip_address.txt";
+
+/// The transport used to talk to the Mullvad API. Responses are gzip compressed by the server
+/// when possible and inflated by the `GzipTransport` layer.
+pub type HttpHandle = GzipTransport<jsonrpc_client_http::HttpHandle>;
 
 
 /// A type that helps with the creation of RPC connections.
@@ -62,19 +76,46 @@ impl MullvadRpcFactory {
         }
     }
 
-    /// Create a new `MullvadRpcFactory` using the specified cache directory.
+    /// Create a new `MullvadRpcFactory` using the specified cache directory. The API address is
+    /// cached in a file named `api_ip_address.txt` in that directory.
     pub fn with_cache_dir(cache_dir: &Path) -> Self {
+        Self::with_cache_file(cache_dir.join(API_IP_CACHE_FILENAME))
+    }
+
+    /// Create a new `MullvadRpcFactory` caching the API address in the specified file. The
+    /// directory containing the file is created if it doesn't exist.
+    pub fn with_cache_file(cache_file: PathBuf) -> Self {
         let hostname = MASTER_API_HOST.to_owned();
-        let cache_file = cache_dir.join("api_ip_address.txt");
         let fallback_address = IpAddr::from([193, 138, 219, 46]);
 
-        let cached_dns_resolver = CachedDnsResolver::new(hostname, cache_file, fallback_address);
+        let cached_dns_resolver =
+            CachedDnsResolver::with_paths(hostname, cache_file, fallback_address);
 
         MullvadRpcFactory {
             address_cache: Some(cached_dns_resolver),
         }
     }
 
+    /// Makes the address cache verify that the cached API address accepts HTTPS connections
+    /// before using it, resolving anew or using the fallback address otherwise. Only has an
+    /// effect on factories using an address cache.
+    pub fn with_reachability_check(mut self, timeout: Duration) -> Self {
+        self.address_cache = self.address_cache
+            .map(|address_cache| address_cache.with_reachability_check(443, timeout));
+        self
+    }
+
+    /// Keeps the cached API address warm by re-resolving it every `interval` on a background
+    /// thread. Returns `None` if this factory does not use an address cache.
+    pub fn spawn_address_cache_refresh(
+        &self,
+        interval: Duration,
+    ) -> Option<BackgroundRefreshHandle> {
+        self.address_cache
+            .as_ref()
+            .map(|address_cache| address_cache.spawn_background_refresh(interval))
+    }
+
     /// Spawns a tokio core on a new thread and returns a `HttpHandle` running on that core.
     pub fn new_connection(&mut self) -> Result<HttpHandle, HttpError> {
         self.setup_connection(HttpTransport::new()?)
@@ -89,11 +130,8 @@ impl MullvadRpcFactory {
     }
 
     fn setup_connection(&mut self, transport: HttpTransport) -> Result<HttpHandle, HttpError> {
-        let mut handle = transport.handle(&self.api_uri())?;
-
-        handle.set_header(Host::new(MASTER_API_HOST, None));
-
-        Ok(handle)
+        let handle = transport.handle(&self.api_uri())?;
+        Ok(create_api_handle(handle))
     }
 
     fn api_uri(&mut self) -> String {
@@ -107,6 +145,15 @@ impl MullvadRpcFactory {
     }
 }
 
+/// Sets the headers every request to the API needs, asking for gzip compressed responses, and
+/// wraps the handle so such responses are inflated.
+fn create_api_handle(mut handle: jsonrpc_client_http::HttpHandle) -> HttpHandle {
+    handle.set_header(Host::new(MASTER_API_HOST, None));
+    handle.set_header(AcceptEncoding(vec![qitem(Encoding::Gzip)]));
+
+    GzipTransport::new(handle)
+}
+
 jsonrpc_client!(pub struct AccountsProxy {
     pub fn get_expiry(&mut self, account_token: AccountToken) -> RpcRequest<DateTime<Utc>>;
 });
@@ -3,12 +3,18 @@ use futures::{future, Future, Stream};
 
 use hyper;
 use hyper::client::Client;
+use hyper::header::RetryAfter;
 use hyper::{Request, StatusCode, Uri};
 use hyper_tls::HttpsConnector;
 use native_tls;
 
+use std::time::{Duration, SystemTime};
+
 use tokio_core::reactor::Handle;
 
+/// How long to back off when the server rate limits us without saying for how long.
+pub const DEFAULT_RETRY_AFTER_SECS: u64 = 60;
+
 
 error_chain! {
     errors {
@@ -17,6 +23,16 @@ error_chain! {
             description("Http error. Server did not return 200 OK")
             display("Http error. Status code {}", http_code)
         }
+        /// When the server responds with 429 Too Many Requests. Contains the time the server
+        /// asked us to wait before making another request.
+        RateLimited(retry_after: Duration) {
+            description("Rate limited by the server")
+            display("Rate limited by the server. Retry after {} seconds", retry_after.as_secs())
+        }
+        /// When the JSON-RPC transport fails for any other reason than being rate limited.
+        TransportError {
+            description("Error in the JSON-RPC transport")
+        }
     }
     foreign_links {
         Tls(native_tls::Error);
@@ -46,13 +62,7 @@ fn create_request_processing_future<CC: hyper::client::Connect>(
         client
             .request(request)
             .from_err()
-            .and_then(|response: hyper::Response| {
-                if response.status() == hyper::StatusCode::Ok {
-                    future::ok(response)
-                } else {
-                    future::err(ErrorKind::HttpError(response.status()).into())
-                }
-            })
+            .and_then(|response: hyper::Response| future::result(check_status(response)))
             .and_then(|response: hyper::Response| response.body().concat2().from_err())
             .map(|response_chunk| response_chunk.to_vec())
             .then(move |response_result| {
@@ -65,6 +75,92 @@ fn create_request_processing_future<CC: hyper::client::Connect>(
     Box::new(f) as Box<Future<Item = (), Error = ()>>
 }
 
+/// Returns the response if it has a 200 OK status code, otherwise an error describing the status.
+fn check_status(response: hyper::Response) -> Result<hyper::Response> {
+    match response.status() {
+        StatusCode::Ok => Ok(response),
+        StatusCode::TooManyRequests => {
+            let retry_after = get_retry_after(&response)
+                .unwrap_or(Duration::from_secs(DEFAULT_RETRY_AFTER_SECS));
+            warn!(
+                "Rate limited by server, retry after {} seconds",
+                retry_after.as_secs()
+            );
+            Err(ErrorKind::RateLimited(retry_after).into())
+        }
+        status => Err(ErrorKind::HttpError(status).into()),
+    }
+}
+
+/// Parses the `Retry-After` header of a response. The header can either contain a delay in
+/// seconds or an HTTP-date. A date in the past is treated as no delay.
+fn get_retry_after(response: &hyper::Response) -> Option<Duration> {
+    response
+        .headers()
+        .get::<RetryAfter>()
+        .map(|retry_after| match *retry_after {
+            RetryAfter::Delay(delay) => delay,
+            RetryAfter::DateTime(date) => SystemTime::from(date)
+                .duration_since(SystemTime::now())
+                .unwrap_or(Duration::from_secs(0)),
+        })
+}
+
 pub fn create_get_request(uri: Uri) -> Request {
     Request::new(hyper::Method::Get, uri)
 }
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hyper::header::HttpDate;
+
+    fn rate_limited_response() -> hyper::Response {
+        hyper::Response::new().with_status(StatusCode::TooManyRequests)
+    }
+
+    fn get_rate_limit(result: Result<hyper::Response>) -> Duration {
+        match result {
+            Err(Error(ErrorKind::RateLimited(retry_after), _)) => retry_after,
+            other => panic!("Expected a rate limit error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn parses_retry_after_seconds() {
+        let response =
+            rate_limited_response().with_header(RetryAfter::Delay(Duration::from_secs(120)));
+        assert_eq!(
+            get_rate_limit(check_status(response)),
+            Duration::from_secs(120)
+        );
+    }
+
+    #[test]
+    fn parses_retry_after_date() {
+        let date = SystemTime::now() + Duration::from_secs(3600);
+        let response =
+            rate_limited_response().with_header(RetryAfter::DateTime(HttpDate::from(date)));
+        let retry_after = get_rate_limit(check_status(response));
+        assert!(retry_after > Duration::from_secs(3500));
+        assert!(retry_after <= Duration::from_secs(3600));
+    }
+
+    #[test]
+    fn uses_default_without_retry_after() {
+        assert_eq!(
+            get_rate_limit(check_status(rate_limited_response())),
+            Duration::from_secs(DEFAULT_RETRY_AFTER_SECS)
+        );
+    }
+
+    #[test]
+    fn other_status_codes_are_http_errors() {
+        let response = hyper::Response::new().with_status(StatusCode::InternalServerError);
+        match check_status(response) {
+            Err(Error(ErrorKind::HttpError(StatusCode::InternalServerError), _)) => (),
+            other => panic!("Expected an http error, got {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,157 @@
+//! Helpers for testing code using the RPC proxies without network access.
+//!
+//! A `MockTransport` answers JSON-RPC requests with canned responses configured per method name.
+//! Any proxy generated by `jsonrpc_client!` can be constructed over it:
+//!
+//! ```
+//! extern crate mullvad_rpc;
+//! extern crate serde_json;
+//!
+//! use mullvad_rpc::testing::MockTransport;
+//! use mullvad_rpc::AppVersionProxy;
+//!
+//! # fn main() {
+//! let transport = MockTransport::new()
+//!     .with_result("is_app_version_supported", serde_json::Value::Bool(true))
+//!     .with_error("latest_app_version", -32000, "Internal error");
+//! let mut proxy = AppVersionProxy::new(transport);
+//!
+//! assert!(proxy.is_app_version_supported(&"2018.1".to_owned()).call().unwrap());
+//! assert!(proxy.latest_app_version().call().is_err());
+//! # }
+//! ```
+
+use futures::future;
+use jsonrpc_client_core::Transport;
+use serde_json::{self, Map, Value};
+
+use std::collections::HashMap;
+use std::io;
+
+
+/// A canned response for a method.
+#[derive(Debug, Clone)]
+pub enum MockResponse {
+    /// Respond with a successful result.
+    Result(Value),
+    /// Respond with a JSON-RPC error object.
+    Error { code: i64, message: String },
+}
+
+/// A `Transport` that never touches the network. Each request is answered with the response
+/// registered for its method name. Requests for unregistered methods get a "Method not found"
+/// error, just like a real JSON-RPC server would respond.
+#[derive(Debug, Clone, Default)]
+pub struct MockTransport {
+    responses: HashMap<String, MockResponse>,
+    next_id: u64,
+}
+
+impl MockTransport {
+    /// Creates a new `MockTransport` without any registered responses.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers `result` as the successful response to calls to `method`.
+    pub fn with_result<S: Into<String>>(mut self, method: S, result: Value) -> Self {
+        self.responses
+            .insert(method.into(), MockResponse::Result(result));
+        self
+    }
+
+    /// Registers a JSON-RPC error as the response to calls to `method`.
+    pub fn with_error<S: Into<String>, M: Into<String>>(
+        mut self,
+        method: S,
+        code: i64,
+        message: M,
+    ) -> Self {
+        self.responses.insert(
+            method.into(),
+            MockResponse::Error {
+                code,
+                message: message.into(),
+            },
+        );
+        self
+    }
+
+    fn respond(&self, json_data: &[u8]) -> io::Result<Vec<u8>> {
+        let request: Value = serde_json::from_slice(json_data)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        let method = request["method"].as_str().unwrap_or("");
+
+        let mut response = Map::new();
+        response.insert("jsonrpc".to_owned(), Value::from("2.0"));
+        response.insert("id".to_owned(), request["id"].clone());
+        match self.responses.get(method) {
+            Some(&MockResponse::Result(ref result)) => {
+                response.insert("result".to_owned(), result.clone());
+            }
+            Some(&MockResponse::Error { code, ref message }) => {
+                response.insert("error".to_owned(), error_object(code, message));
+            }
+            None => {
+                response.insert("error".to_owned(), error_object(-32601, "Method not found"));
+            }
+        }
+        serde_json::to_vec(&response).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+}
+
+impl Transport for MockTransport {
+    type Future = future::FutureResult<Vec<u8>, io::Error>;
+    type Error = io::Error;
+
+    fn get_next_id(&mut self) -> u64 {
+        self.next_id += 1;
+        self.next_id
+    }
+
+    fn send(&self, json_data: Vec<u8>) -> Self::Future {
+        future::result(self.respond(&json_data))
+    }
+}
+
+fn error_object(code: i64, message: &str) -> Value {
+    let mut error = Map::new();
+    error.insert("code".to_owned(), Value::from(code));
+    error.insert("message".to_owned(), Value::from(message));
+    Value::Object(error)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{DateTime, TimeZone, Utc};
+
+    use AccountsProxy;
+
+    #[test]
+    fn get_expiry_through_mock() {
+        let transport =
+            MockTransport::new().with_result("get_expiry", Value::from("2018-03-01T12:00:00Z"));
+        let mut proxy = AccountsProxy::new(transport);
+
+        let expiry: DateTime<Utc> = proxy.get_expiry("1234".to_owned()).call().unwrap();
+
+        assert_eq!(expiry, Utc.ymd(2018, 3, 1).and_hms(12, 0, 0));
+    }
+
+    #[test]
+    fn get_expiry_error_through_mock() {
+        let transport = MockTransport::new().with_error("get_expiry", -200, "Invalid account");
+        let mut proxy = AccountsProxy::new(transport);
+
+        assert!(proxy.get_expiry("1234".to_owned()).call().is_err());
+    }
+
+    #[test]
+    fn unknown_method_is_an_error() {
+        let mut proxy = AccountsProxy::new(MockTransport::new());
+
+        assert!(proxy.get_expiry("1234".to_owned()).call().is_err());
+    }
+}
@@ -1,9 +1,343 @@
 use chrono::offset::Utc;
-use chrono::DateTime;
+use chrono::{DateTime, Duration};
 
-pub type AccountToken = String;
+use std::fmt;
+
+
+/// The number of characters at the end of an account token that are shown in redacted form.
+const UNREDACTED_SUFFIX_LEN: usize = 4;
+
+
+/// The account number identifying a Mullvad account. Anyone knowing it can use the account, so it
+/// must never end up in logs or problem reports. The `Debug` output is therefore redacted, and
+/// there is no `Display` implementation. Use `as_str` where the full value is needed.
+#[derive(Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub struct AccountToken(String);
+
+impl AccountToken {
+    pub fn new(token: String) -> Self {
+        AccountToken(token)
+    }
+
+    /// Returns the full, unredacted, account token.
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// Returns the full, unredacted, account token.
+    pub fn into_string(self) -> String {
+        self.0
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Returns the token with all but the last four characters masked out, like `****1234`.
+    /// Tokens too short to hide anything are masked out completely.
+    pub fn redacted(&self) -> String {
+        let len = self.0.chars().count();
+        if len <= UNREDACTED_SUFFIX_LEN {
+            return "****".to_owned();
+        }
+        let suffix: String = self.0.chars().skip(len - UNREDACTED_SUFFIX_LEN).collect();
+        format!("****{}", suffix)
+    }
+}
+
+impl From<String> for AccountToken {
+    fn from(token: String) -> Self {
+        AccountToken(token)
+    }
+}
+
+impl fmt::Debug for AccountToken {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "AccountToken({})", self.redacted())
+    }
+}
 
 #[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
 pub struct AccountData {
     pub expiry: DateTime<Utc>,
 }
+
+impl AccountData {
+    /// Returns the number of whole days left until the account expires, as seen from `now`.
+    ///
+    /// An account expiring later the same day has zero days remaining. Once the account has
+    /// expired the result is always negative, counting the started days since the expiry. So an
+    /// account that expired an hour ago has `-1` days remaining.
+    pub fn days_remaining(&self, now: DateTime<Utc>) -> i64 {
+        let remaining = self.expiry.signed_duration_since(now);
+        if self.is_expired(now) {
+            let overdue = -remaining;
+            let days = overdue.num_days();
+            if overdue > Duration::days(days) {
+                -days - 1
+            } else {
+                -days
+            }
+        } else {
+            remaining.num_days()
+        }
+    }
+
+    /// Returns true if the account has expired at the time `now`.
+    pub fn is_expired(&self, now: DateTime<Utc>) -> bool {
+        self.expiry <= now
+    }
+}
+
+/// An account expiry fetched from the API, together with the time it was fetched. Used to avoid
+/// asking the API for the expiry every time a client polls for it.
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub struct ExpiryCache {
+    pub value: DateTime<Utc>,
+    pub fetched_at: DateTime<Utc>,
+}
+
+impl ExpiryCache {
+    pub fn new(value: DateTime<Utc>, fetched_at: DateTime<Utc>) -> Self {
+        ExpiryCache { value, fetched_at }
+    }
+
+    /// Returns true if the value was fetched less than `ttl` before `now`. A value fetched after
+    /// `now`, which happens if the system clock is turned back, is never fresh.
+    pub fn is_fresh(&self, now: DateTime<Utc>, ttl: Duration) -> bool {
+        self.fetched_at <= now && now.signed_duration_since(self.fetched_at) < ttl
+    }
+
+    /// Returns the cached expiry if it is fresh, see `is_fresh`. `None` means a new expiry has to
+    /// be fetched from the API.
+    pub fn fresh_value(&self, now: DateTime<Utc>, ttl: Duration) -> Option<DateTime<Utc>> {
+        if self.is_fresh(now, ttl) {
+            Some(self.value)
+        } else {
+            None
+        }
+    }
+}
+
+/// Formats the time until, or since, `expiry` relative to `now` as a short English phrase, like
+/// "expires in 23 days" or "expired 2 days ago". The output does not depend on the locale or
+/// time zone of the system, so it is stable across machines.
+pub fn format_expiry(expiry: DateTime<Utc>, now: DateTime<Utc>) -> String {
+    if expiry == now {
+        return "expires now".to_owned();
+    }
+    let expired = expiry < now;
+    let distance = if expired {
+        now.signed_duration_since(expiry)
+    } else {
+        expiry.signed_duration_since(now)
+    };
+
+    let amount = if distance < Duration::days(1) {
+        "less than a day".to_owned()
+    } else if distance > Duration::days(365) {
+        "more than a year".to_owned()
+    } else {
+        match distance.num_days() {
+            1 => "1 day".to_owned(),
+            days => format!("{} days", days),
+        }
+    };
+
+    if expired {
+        format!("expired {} ago", amount)
+    } else {
+        format!("expires in {}", amount)
+    }
+}
+
+/// Formats the date of `expiry` in ISO-8601 format, like "2018-03-24". The date is given in UTC.
+pub fn format_expiry_date(expiry: DateTime<Utc>) -> String {
+    expiry.format("%Y-%m-%d").to_string()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use serde_json;
+
+    fn account_expiring_at(expiry: DateTime<Utc>) -> AccountData {
+        AccountData { expiry }
+    }
+
+    #[test]
+    fn debug_output_is_redacted() {
+        let token = AccountToken::new("1234567890123456".to_owned());
+
+        assert_eq!(token.redacted(), "****3456");
+        assert_eq!(format!("{:?}", token), "AccountToken(****3456)");
+        assert_eq!(
+            format!("{:?}", Some(&token)),
+            "Some(AccountToken(****3456))"
+        );
+        assert!(!format!("{:#?}", token).contains("1234"));
+    }
+
+    #[test]
+    fn short_tokens_are_fully_redacted() {
+        assert_eq!(AccountToken::new("1234".to_owned()).redacted(), "****");
+        assert_eq!(AccountToken::new(String::new()).redacted(), "****");
+    }
+
+    #[test]
+    fn full_token_is_available_explicitly() {
+        let token = AccountToken::new("1234567890123456".to_owned());
+
+        assert_eq!(token.as_str(), "1234567890123456");
+        assert_eq!(token.clone().into_string(), "1234567890123456");
+    }
+
+    #[test]
+    fn token_serializes_as_plain_string() {
+        let token: AccountToken = serde_json::from_str(r#""1234567890123456""#).unwrap();
+
+        assert_eq!(token.as_str(), "1234567890123456");
+        assert_eq!(
+            serde_json::to_string(&token).unwrap(),
+            r#""1234567890123456""#
+        );
+    }
+
+    #[test]
+    fn expiry_cache_freshness() {
+        let fetched_at = Utc.ymd(2018, 3, 1).and_hms(12, 0, 0);
+        let cache = ExpiryCache::new(Utc.ymd(2018, 3, 24).and_hms(18, 0, 0), fetched_at);
+        let ttl = Duration::minutes(5);
+
+        assert!(cache.is_fresh(fetched_at, ttl));
+        assert!(cache.is_fresh(fetched_at + Duration::seconds(299), ttl));
+        assert_eq!(
+            cache.fresh_value(fetched_at + Duration::minutes(1), ttl),
+            Some(cache.value)
+        );
+
+        assert!(!cache.is_fresh(fetched_at + ttl, ttl));
+        assert!(!cache.is_fresh(fetched_at + Duration::days(1), ttl));
+        assert!(!cache.is_fresh(fetched_at - Duration::seconds(1), ttl));
+        assert_eq!(cache.fresh_value(fetched_at + ttl, ttl), None);
+    }
+
+    #[test]
+    fn expiry_cache_serde_round_trip() {
+        let cache = ExpiryCache::new(
+            Utc.ymd(2018, 3, 24).and_hms(18, 0, 0),
+            Utc.ymd(2018, 3, 1).and_hms(12, 0, 0),
+        );
+
+        let json = serde_json::to_string(&cache).unwrap();
+        assert_eq!(
+            json,
+            r#"{"value":"2018-03-24T18:00:00Z","fetched_at":"2018-03-01T12:00:00Z"}"#
+        );
+        assert_eq!(serde_json::from_str::<ExpiryCache>(&json).unwrap(), cache);
+    }
+
+    #[test]
+    fn future_expiry() {
+        let now = Utc.ymd(2018, 3, 1).and_hms(12, 0, 0);
+        let account = account_expiring_at(Utc.ymd(2018, 3, 24).and_hms(18, 0, 0));
+
+        assert_eq!(account.days_remaining(now), 23);
+        assert!(!account.is_expired(now));
+    }
+
+    #[test]
+    fn past_expiry() {
+        let now = Utc.ymd(2018, 3, 1).and_hms(12, 0, 0);
+
+        let account = account_expiring_at(Utc.ymd(2018, 2, 27).and_hms(12, 0, 0));
+        assert_eq!(account.days_remaining(now), -2);
+        assert!(account.is_expired(now));
+
+        let account = account_expiring_at(Utc.ymd(2018, 2, 27).and_hms(11, 0, 0));
+        assert_eq!(account.days_remaining(now), -3);
+    }
+
+    #[test]
+    fn same_day_expiry() {
+        let now = Utc.ymd(2018, 3, 1).and_hms(12, 0, 0);
+
+        let account = account_expiring_at(Utc.ymd(2018, 3, 1).and_hms(18, 0, 0));
+        assert_eq!(account.days_remaining(now), 0);
+        assert!(!account.is_expired(now));
+
+        let account = account_expiring_at(Utc.ymd(2018, 3, 1).and_hms(6, 0, 0));
+        assert_eq!(account.days_remaining(now), -1);
+        assert!(account.is_expired(now));
+
+        let account = account_expiring_at(now);
+        assert_eq!(account.days_remaining(now), 0);
+        assert!(account.is_expired(now));
+    }
+
+    #[test]
+    fn format_expiry_exactly_now() {
+        let now = Utc.ymd(2018, 3, 1).and_hms(12, 0, 0);
+
+        assert_eq!(format_expiry(now, now), "expires now");
+    }
+
+    #[test]
+    fn format_expiry_less_than_a_day() {
+        let now = Utc.ymd(2018, 3, 1).and_hms(12, 0, 0);
+
+        assert_eq!(
+            format_expiry(Utc.ymd(2018, 3, 2).and_hms(11, 59, 59), now),
+            "expires in less than a day"
+        );
+        assert_eq!(
+            format_expiry(Utc.ymd(2018, 3, 1).and_hms(11, 0, 0), now),
+            "expired less than a day ago"
+        );
+    }
+
+    #[test]
+    fn format_expiry_days() {
+        let now = Utc.ymd(2018, 3, 1).and_hms(12, 0, 0);
+
+        assert_eq!(
+            format_expiry(Utc.ymd(2018, 3, 2).and_hms(12, 0, 0), now),
+            "expires in 1 day"
+        );
+        assert_eq!(
+            format_expiry(Utc.ymd(2018, 3, 24).and_hms(18, 0, 0), now),
+            "expires in 23 days"
+        );
+        assert_eq!(
+            format_expiry(Utc.ymd(2018, 2, 27).and_hms(10, 0, 0), now),
+            "expired 2 days ago"
+        );
+    }
+
+    #[test]
+    fn format_expiry_more_than_a_year() {
+        let now = Utc.ymd(2018, 3, 1).and_hms(12, 0, 0);
+
+        assert_eq!(
+            format_expiry(Utc.ymd(2019, 3, 1).and_hms(12, 0, 0), now),
+            "expires in 365 days"
+        );
+        assert_eq!(
+            format_expiry(Utc.ymd(2019, 6, 1).and_hms(12, 0, 0), now),
+            "expires in more than a year"
+        );
+        assert_eq!(
+            format_expiry(Utc.ymd(2016, 3, 1).and_hms(12, 0, 0), now),
+            "expired more than a year ago"
+        );
+    }
+
+    #[test]
+    fn format_expiry_date_is_iso_8601() {
+        let expiry = Utc.ymd(2018, 3, 4).and_hms(23, 59, 0);
+
+        assert_eq!(format_expiry_date(expiry), "2018-03-04");
+    }
+}
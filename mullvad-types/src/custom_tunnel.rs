@@ -1,12 +1,70 @@
-use std::net::{IpAddr, ToSocketAddrs};
+use base64;
+use serde::de::{self, Deserialize, Deserializer};
+use serde::{Serialize, Serializer};
 
-use talpid_types::net::{TunnelEndpoint, TunnelEndpointData};
+use std::fmt;
+use std::io::{BufRead, BufReader, Read};
+use std::net::{IpAddr, Ipv6Addr, SocketAddr, ToSocketAddrs};
+use std::str::FromStr;
+
+use talpid_types::net::{InvalidProxySettings, OpenVpnEndpointData, TransportProtocol,
+                        TunnelEndpoint, TunnelEndpointData, WireguardEndpointData};
+
+pub use talpid_types::net::ProxySettings;
 
 error_chain!{
     errors {
         InvalidHost(host: String) {
             display("Invalid host: {}", host)
         }
+        EmptyHost {
+            description("No host given")
+        }
+        InvalidPort(port: u16) {
+            description("Invalid port")
+            display("Invalid port: {}", port)
+        }
+        UnsupportedTunnelProtocol {
+            description("Custom tunnels using this tunnel protocol are not supported")
+        }
+        MissingScheme {
+            description("Missing tunnel type, like \"openvpn://\"")
+        }
+        UnknownScheme(scheme: String) {
+            description("Unknown tunnel type")
+            display("Unknown tunnel type: {}", scheme)
+        }
+        MissingPort {
+            description("Missing port")
+        }
+        UnparsablePort(port: String) {
+            description("Unable to parse port")
+            display("Unable to parse port: {}", port)
+        }
+        InvalidTransportProtocol(protocol: String) {
+            description("Invalid transport protocol")
+            display("Invalid transport protocol: {}", protocol)
+        }
+        ConfigReadError {
+            description("Unable to read OpenVPN config")
+        }
+        MissingRemote {
+            description("No remote directive in the OpenVPN config")
+        }
+        InvalidPublicKey(key: String) {
+            description("Invalid WireGuard public key")
+            display("Invalid WireGuard public key: {}", key)
+        }
+        MissingTunnelAddress {
+            description("No tunnel address given")
+        }
+        InvalidProxyAddress(address: String) {
+            description("Invalid proxy address")
+            display("Invalid proxy address: {}", address)
+        }
+        IncompleteProxyAuth {
+            description("Both a username and a password must be given for proxy authentication")
+        }
     }
 }
 
@@ -15,9 +73,48 @@ error_chain!{
 pub struct CustomTunnelEndpoint {
     pub host: String,
     pub tunnel: TunnelEndpointData,
+    /// A proxy to tunnel the traffic to the endpoint through.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub proxy: Option<ProxySettings>,
 }
 
 impl CustomTunnelEndpoint {
+    /// Creates a new `CustomTunnelEndpoint` from user input, making sure it is valid.
+    pub fn new(host: String, tunnel: TunnelEndpointData) -> Result<Self> {
+        let endpoint = CustomTunnelEndpoint {
+            host,
+            tunnel,
+            proxy: None,
+        };
+        endpoint.validate()?;
+        Ok(endpoint)
+    }
+
+    /// Returns this endpoint, tunneled through the given proxy, if the proxy settings are valid.
+    pub fn with_proxy(mut self, proxy: ProxySettings) -> Result<Self> {
+        validate_proxy(&proxy)?;
+        self.proxy = Some(proxy);
+        Ok(self)
+    }
+
+    /// Checks that the host is an IP or a hostname that resolves, that the port is not zero and
+    /// that the tunnel protocol is supported. Also validates the proxy settings, if any.
+    pub fn validate(&self) -> Result<()> {
+        if let Some(ref proxy) = self.proxy {
+            validate_proxy(proxy)?;
+        }
+        if self.host.trim().is_empty() {
+            bail!(ErrorKind::EmptyHost);
+        }
+        if self.tunnel.port() == 0 {
+            bail!(ErrorKind::InvalidPort(0));
+        }
+        if let TunnelEndpointData::Wireguard(_) = self.tunnel {
+            bail!(ErrorKind::UnsupportedTunnelProtocol);
+        }
+        resolve_to_ip(&self.host).map(|_| ())
+    }
+
     pub fn to_tunnel_endpoint(&self) -> Result<TunnelEndpoint> {
         Ok(TunnelEndpoint {
             address: resolve_to_ip(&self.host)?,
@@ -26,6 +123,283 @@ impl CustomTunnelEndpoint {
     }
 }
 
+/// Checks that the proxy address is a valid socket address and that the authentication, if
+/// given, has both a username and a password.
+pub fn validate_proxy(proxy: &ProxySettings) -> Result<()> {
+    proxy.validate().map_err(|error| match error {
+        InvalidProxySettings::InvalidAddress(address) => {
+            ErrorKind::InvalidProxyAddress(address).into()
+        }
+        InvalidProxySettings::IncompleteAuth => ErrorKind::IncompleteProxyAuth.into(),
+    })
+}
+
+/// A custom WireGuard endpoint. Unlike OpenVPN, WireGuard needs the public key of the peer and
+/// the addresses of the tunnel interface to connect, so these endpoints can't be described by a
+/// `CustomTunnelEndpoint`.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct CustomWireguardEndpoint {
+    pub peer_public_key: WireguardPublicKey,
+    pub endpoint: SocketAddr,
+    /// The addresses to assign to the tunnel interface.
+    pub addresses: Vec<IpAddr>,
+}
+
+impl CustomWireguardEndpoint {
+    /// Checks that the endpoint port is not zero and that at least one tunnel address is given.
+    /// The public key is always valid, since it is checked when created.
+    pub fn validate(&self) -> Result<()> {
+        if self.endpoint.port() == 0 {
+            bail!(ErrorKind::InvalidPort(0));
+        }
+        if self.addresses.is_empty() {
+            bail!(ErrorKind::MissingTunnelAddress);
+        }
+        Ok(())
+    }
+}
+
+/// A WireGuard public key. Serialized as a base64 string, the format used by the `wg` tool.
+#[derive(Clone, Copy, Eq, PartialEq, Hash)]
+pub struct WireguardPublicKey([u8; 32]);
+
+impl WireguardPublicKey {
+    pub fn as_bytes(&self) -> &[u8; 32] {
+        &self.0
+    }
+}
+
+impl FromStr for WireguardPublicKey {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let bytes =
+            base64::decode(s).chain_err(|| ErrorKind::InvalidPublicKey(s.to_owned()))?;
+        if bytes.len() != 32 {
+            bail!(ErrorKind::InvalidPublicKey(s.to_owned()));
+        }
+        let mut key = [0u8; 32];
+        key.copy_from_slice(&bytes);
+        Ok(WireguardPublicKey(key))
+    }
+}
+
+impl fmt::Display for WireguardPublicKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&base64::encode(&self.0))
+    }
+}
+
+impl fmt::Debug for WireguardPublicKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "WireguardPublicKey({})", self)
+    }
+}
+
+impl Serialize for WireguardPublicKey {
+    fn serialize<S: Serializer>(&self, serializer: S) -> ::std::result::Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+impl<'de> Deserialize<'de> for WireguardPublicKey {
+    fn deserialize<D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> ::std::result::Result<Self, D::Error> {
+        let key = String::deserialize(deserializer)?;
+        key.parse().map_err(|_| {
+            de::Error::invalid_value(de::Unexpected::Str(&key), &"a base64 encoded 32 byte key")
+        })
+    }
+}
+
+/// Parses connection strings on the form `<tunnel>://<host>:<port>[/<protocol>]`. The tunnel is
+/// either `openvpn` or `wireguard` and the protocol `udp` or `tcp`, defaulting to `udp`. IPv6
+/// hosts must be enclosed in brackets, like `openvpn://[2001:db8::1]:1194/tcp`. WireGuard only
+/// supports UDP. Proxy settings can't be expressed in connection strings.
+impl FromStr for CustomTunnelEndpoint {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (scheme, rest) = match s.find("://") {
+            Some(index) => (&s[..index], &s[index + 3..]),
+            None => bail!(ErrorKind::MissingScheme),
+        };
+        let (authority, protocol) = match rest.find('/') {
+            Some(index) => (&rest[..index], Some(&rest[index + 1..])),
+            None => (rest, None),
+        };
+        let (host, port) = split_host_and_port(authority)?;
+        if host.is_empty() {
+            bail!(ErrorKind::EmptyHost);
+        }
+        let port = parse_port(port)?;
+        let protocol = match protocol {
+            None | Some("udp") => TransportProtocol::Udp,
+            Some("tcp") => TransportProtocol::Tcp,
+            Some(protocol) => bail!(ErrorKind::InvalidTransportProtocol(protocol.to_owned())),
+        };
+
+        let tunnel = match scheme {
+            "openvpn" => TunnelEndpointData::OpenVpn(OpenVpnEndpointData { port, protocol }),
+            "wireguard" if protocol == TransportProtocol::Udp => {
+                TunnelEndpointData::Wireguard(WireguardEndpointData { port })
+            }
+            "wireguard" => bail!(ErrorKind::InvalidTransportProtocol("tcp".to_owned())),
+            "" => bail!(ErrorKind::MissingScheme),
+            scheme => bail!(ErrorKind::UnknownScheme(scheme.to_owned())),
+        };
+        Ok(CustomTunnelEndpoint {
+            host: host.to_owned(),
+            tunnel,
+            proxy: None,
+        })
+    }
+}
+
+/// Formats the endpoint as a connection string that can be parsed back with `from_str`.
+impl fmt::Display for CustomTunnelEndpoint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let scheme = match self.tunnel {
+            TunnelEndpointData::OpenVpn(_) => "openvpn",
+            TunnelEndpointData::Wireguard(_) => "wireguard",
+        };
+        if self.host.contains(':') {
+            write!(f, "{}://[{}]:{}", scheme, self.host, self.tunnel.port())?;
+        } else {
+            write!(f, "{}://{}:{}", scheme, self.host, self.tunnel.port())?;
+        }
+        match self.tunnel {
+            TunnelEndpointData::OpenVpn(OpenVpnEndpointData {
+                protocol: TransportProtocol::Udp,
+                ..
+            }) => write!(f, "/udp"),
+            TunnelEndpointData::OpenVpn(OpenVpnEndpointData {
+                protocol: TransportProtocol::Tcp,
+                ..
+            }) => write!(f, "/tcp"),
+            TunnelEndpointData::Wireguard(_) => Ok(()),
+        }
+    }
+}
+
+/// The port OpenVPN uses when the config does not specify any.
+const OPENVPN_DEFAULT_PORT: u16 = 1194;
+
+/// Creates a `CustomTunnelEndpoint` from an OpenVPN config file, like a `.ovpn` file. Only the
+/// `remote`, `proto`, `port` and `rport` directives are used, the daemon supplies everything else
+/// itself. A port or protocol given on the `remote` line takes precedence over the `port` and
+/// `proto` directives. If the config has several `remote` directives the first one is used.
+pub fn from_openvpn_config<R: Read>(reader: R) -> Result<CustomTunnelEndpoint> {
+    let mut remote: Option<(String, Option<String>, Option<String>)> = None;
+    let mut default_port = None;
+    let mut default_protocol = None;
+    let mut inline_block_end: Option<String> = None;
+
+    for line in BufReader::new(reader).lines() {
+        let line = line.chain_err(|| ErrorKind::ConfigReadError)?;
+        let line = line.trim();
+
+        // Skip embedded files, like certificates in <ca></ca> blocks.
+        if let Some(end_tag) = inline_block_end.take() {
+            if line != end_tag {
+                inline_block_end = Some(end_tag);
+            }
+            continue;
+        }
+        if line.starts_with('<') && line.ends_with('>') && !line.starts_with("</") {
+            inline_block_end = Some(format!("</{}", &line[1..]));
+            continue;
+        }
+
+        let mut words = line.split_whitespace();
+        match words.next() {
+            Some("remote") => {
+                let host = words
+                    .next()
+                    .ok_or_else(|| ErrorKind::InvalidHost(String::new()))?;
+                let port = words.next().map(str::to_owned);
+                let protocol = words.next().map(str::to_owned);
+                if remote.is_none() {
+                    remote = Some((host.to_owned(), port, protocol));
+                } else {
+                    warn!("Ignoring additional remote {} in OpenVPN config", host);
+                }
+            }
+            Some("port") | Some("rport") => {
+                default_port = words.next().map(str::to_owned);
+            }
+            Some("proto") => {
+                default_protocol = words.next().map(str::to_owned);
+            }
+            _ => (),
+        }
+    }
+
+    let (host, port, protocol) = remote.ok_or(ErrorKind::MissingRemote)?;
+    let port = match port.or(default_port) {
+        Some(port) => parse_port(&port)?,
+        None => OPENVPN_DEFAULT_PORT,
+    };
+    let protocol = match protocol.or(default_protocol) {
+        Some(protocol) => parse_openvpn_protocol(&protocol)?,
+        None => TransportProtocol::Udp,
+    };
+    Ok(CustomTunnelEndpoint {
+        host,
+        tunnel: TunnelEndpointData::OpenVpn(OpenVpnEndpointData { port, protocol }),
+        proxy: None,
+    })
+}
+
+/// Parses the OpenVPN `proto` values, which can be suffixed with the IP version and client role.
+fn parse_openvpn_protocol(protocol: &str) -> Result<TransportProtocol> {
+    match protocol {
+        "udp" | "udp4" | "udp6" => Ok(TransportProtocol::Udp),
+        "tcp" | "tcp4" | "tcp6" | "tcp-client" | "tcp4-client" | "tcp6-client" => {
+            Ok(TransportProtocol::Tcp)
+        }
+        _ => bail!(ErrorKind::InvalidTransportProtocol(protocol.to_owned())),
+    }
+}
+
+/// Splits `host:port` or `[ipv6]:port` into the host and the port.
+fn split_host_and_port(authority: &str) -> Result<(&str, &str)> {
+    if authority.starts_with('[') {
+        let end = authority
+            .find(']')
+            .ok_or_else(|| ErrorKind::InvalidHost(authority.to_owned()))?;
+        let host = &authority[1..end];
+        if host.parse::<Ipv6Addr>().is_err() {
+            bail!(ErrorKind::InvalidHost(host.to_owned()));
+        }
+        match &authority[end + 1..] {
+            "" => bail!(ErrorKind::MissingPort),
+            port if port.starts_with(':') => Ok((host, &port[1..])),
+            _ => bail!(ErrorKind::InvalidHost(authority.to_owned())),
+        }
+    } else {
+        match authority.find(':') {
+            Some(index) if authority[index + 1..].contains(':') => {
+                bail!(ErrorKind::InvalidHost(authority.to_owned()))
+            }
+            Some(index) => Ok((&authority[..index], &authority[index + 1..])),
+            None => bail!(ErrorKind::MissingPort),
+        }
+    }
+}
+
+fn parse_port(port: &str) -> Result<u16> {
+    if port.is_empty() {
+        bail!(ErrorKind::MissingPort);
+    }
+    match port.parse() {
+        Ok(0) => bail!(ErrorKind::InvalidPort(0)),
+        Ok(port) => Ok(port),
+        Err(_) => bail!(ErrorKind::UnparsablePort(port.to_owned())),
+    }
+}
+
 /// Does a DNS lookup if the host isn't an IP.
 /// Returns the first IPv4 address if one exists, otherwise the first IPv6 address.
 /// Rust only provides means to resolve a socket addr, not just a host, for some reason. So
@@ -44,3 +418,435 @@ fn resolve_to_ip(host: &str) -> Result<IpAddr> {
         })
         .ok_or(ErrorKind::InvalidHost(host.to_owned()).into())
 }
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use relay_constraints::RelaySettings;
+    use serde_json;
+
+    fn openvpn(port: u16) -> TunnelEndpointData {
+        TunnelEndpointData::OpenVpn(OpenVpnEndpointData {
+            port,
+            protocol: TransportProtocol::Udp,
+        })
+    }
+
+    #[test]
+    fn empty_host_is_invalid() {
+        let result = CustomTunnelEndpoint::new(" ".to_owned(), openvpn(1194));
+
+        match result {
+            Err(Error(ErrorKind::EmptyHost, _)) => (),
+            result => panic!("Expected an empty host error, got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn port_zero_is_invalid() {
+        let result = CustomTunnelEndpoint::new("10.0.0.1".to_owned(), openvpn(0));
+
+        match result {
+            Err(Error(ErrorKind::InvalidPort(0), _)) => (),
+            result => panic!("Expected an invalid port error, got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn wireguard_is_unsupported() {
+        let tunnel = TunnelEndpointData::Wireguard(WireguardEndpointData { port: 51820 });
+
+        match CustomTunnelEndpoint::new("10.0.0.1".to_owned(), tunnel) {
+            Err(Error(ErrorKind::UnsupportedTunnelProtocol, _)) => (),
+            result => panic!("Expected an unsupported protocol error, got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn valid_endpoint() {
+        let endpoint = CustomTunnelEndpoint::new("10.0.0.1".to_owned(), openvpn(1194)).unwrap();
+
+        assert_eq!(
+            endpoint.to_tunnel_endpoint().unwrap().address,
+            "10.0.0.1".parse::<IpAddr>().unwrap()
+        );
+    }
+
+    fn parse_error(s: &str) -> ErrorKind {
+        match CustomTunnelEndpoint::from_str(s) {
+            Ok(endpoint) => panic!("Expected {} to be invalid, got {:?}", s, endpoint),
+            Err(Error(kind, _)) => kind,
+        }
+    }
+
+    #[test]
+    fn parse_openvpn_connection_string() {
+        let endpoint: CustomTunnelEndpoint = "openvpn://198.51.100.1:1194/udp".parse().unwrap();
+        assert_eq!(endpoint.host, "198.51.100.1");
+        assert_eq!(endpoint.tunnel, openvpn(1194));
+
+        let endpoint: CustomTunnelEndpoint = "openvpn://vpn.example.com:443/tcp".parse().unwrap();
+        assert_eq!(endpoint.host, "vpn.example.com");
+        assert_eq!(
+            endpoint.tunnel,
+            TunnelEndpointData::OpenVpn(OpenVpnEndpointData {
+                port: 443,
+                protocol: TransportProtocol::Tcp,
+            })
+        );
+
+        let endpoint: CustomTunnelEndpoint = "openvpn://[2001:db8::1]:1194".parse().unwrap();
+        assert_eq!(endpoint.host, "2001:db8::1");
+        assert_eq!(endpoint.tunnel, openvpn(1194));
+    }
+
+    #[test]
+    fn parse_wireguard_connection_string() {
+        let endpoint: CustomTunnelEndpoint = "wireguard://198.51.100.1:51820".parse().unwrap();
+        assert_eq!(endpoint.host, "198.51.100.1");
+        assert_eq!(
+            endpoint.tunnel,
+            TunnelEndpointData::Wireguard(WireguardEndpointData { port: 51820 })
+        );
+    }
+
+    #[test]
+    fn connection_string_round_trip() {
+        for s in &[
+            "openvpn://198.51.100.1:1194/udp",
+            "openvpn://vpn.example.com:443/tcp",
+            "openvpn://[2001:db8::1]:1194/udp",
+            "wireguard://198.51.100.1:51820",
+            "wireguard://[2001:db8::1]:51820",
+        ] {
+            let endpoint: CustomTunnelEndpoint = s.parse().unwrap();
+            assert_eq!(endpoint.to_string(), *s);
+        }
+    }
+
+    #[test]
+    fn malformed_connection_strings() {
+        match parse_error("198.51.100.1:1194/udp") {
+            ErrorKind::MissingScheme => (),
+            kind => panic!("Unexpected error: {:?}", kind),
+        }
+        match parse_error("://198.51.100.1:1194") {
+            ErrorKind::MissingScheme => (),
+            kind => panic!("Unexpected error: {:?}", kind),
+        }
+        match parse_error("ipsec://198.51.100.1:1194") {
+            ErrorKind::UnknownScheme(ref scheme) if scheme == "ipsec" => (),
+            kind => panic!("Unexpected error: {:?}", kind),
+        }
+        match parse_error("openvpn://198.51.100.1/udp") {
+            ErrorKind::MissingPort => (),
+            kind => panic!("Unexpected error: {:?}", kind),
+        }
+        match parse_error("openvpn://198.51.100.1:/udp") {
+            ErrorKind::MissingPort => (),
+            kind => panic!("Unexpected error: {:?}", kind),
+        }
+        match parse_error("openvpn://[2001:db8::1]/udp") {
+            ErrorKind::MissingPort => (),
+            kind => panic!("Unexpected error: {:?}", kind),
+        }
+        match parse_error("openvpn://198.51.100.1:http") {
+            ErrorKind::UnparsablePort(ref port) if port == "http" => (),
+            kind => panic!("Unexpected error: {:?}", kind),
+        }
+        match parse_error("openvpn://198.51.100.1:0") {
+            ErrorKind::InvalidPort(0) => (),
+            kind => panic!("Unexpected error: {:?}", kind),
+        }
+        match parse_error("openvpn://:1194") {
+            ErrorKind::EmptyHost => (),
+            kind => panic!("Unexpected error: {:?}", kind),
+        }
+        match parse_error("openvpn://2001:db8::1:1194") {
+            ErrorKind::InvalidHost(_) => (),
+            kind => panic!("Unexpected error: {:?}", kind),
+        }
+        match parse_error("openvpn://198.51.100.1:1194/sctp") {
+            ErrorKind::InvalidTransportProtocol(ref protocol) if protocol == "sctp" => (),
+            kind => panic!("Unexpected error: {:?}", kind),
+        }
+        match parse_error("wireguard://198.51.100.1:51820/tcp") {
+            ErrorKind::InvalidTransportProtocol(_) => (),
+            kind => panic!("Unexpected error: {:?}", kind),
+        }
+    }
+
+    #[test]
+    fn minimal_openvpn_config() {
+        let config = "client\nremote 198.51.100.1\n";
+
+        let endpoint = from_openvpn_config(config.as_bytes()).unwrap();
+
+        assert_eq!(endpoint.host, "198.51.100.1");
+        assert_eq!(endpoint.tunnel, openvpn(1194));
+    }
+
+    #[test]
+    fn openvpn_config_with_directives() {
+        let config = r#"
+# Example config
+client
+dev tun
+proto tcp-client
+port 1300
+remote vpn.example.com
+cipher AES-256-CBC
+<ca>
+-----BEGIN CERTIFICATE-----
+remote 10.0.0.1 1
+-----END CERTIFICATE-----
+</ca>
+"#;
+
+        let endpoint = from_openvpn_config(config.as_bytes()).unwrap();
+
+        assert_eq!(endpoint.host, "vpn.example.com");
+        assert_eq!(
+            endpoint.tunnel,
+            TunnelEndpointData::OpenVpn(OpenVpnEndpointData {
+                port: 1300,
+                protocol: TransportProtocol::Tcp,
+            })
+        );
+    }
+
+    #[test]
+    fn multi_remote_openvpn_config_uses_first_remote() {
+        let config = r#"
+client
+proto udp
+remote 198.51.100.1 443 tcp
+remote 198.51.100.2 1194
+remote-random
+"#;
+
+        let endpoint = from_openvpn_config(config.as_bytes()).unwrap();
+
+        assert_eq!(endpoint.host, "198.51.100.1");
+        assert_eq!(
+            endpoint.tunnel,
+            TunnelEndpointData::OpenVpn(OpenVpnEndpointData {
+                port: 443,
+                protocol: TransportProtocol::Tcp,
+            })
+        );
+    }
+
+    #[test]
+    fn openvpn_config_without_remote() {
+        match from_openvpn_config("client\nproto udp\n".as_bytes()) {
+            Err(Error(ErrorKind::MissingRemote, _)) => (),
+            result => panic!("Expected a missing remote error, got {:?}", result),
+        }
+    }
+
+    static VALID_KEY: &str = "AAECAwQFBgcICQoLDA0ODxAREhMUFRYXGBkaGxwdHh8=";
+
+    #[test]
+    fn valid_wireguard_public_key() {
+        let key: WireguardPublicKey = VALID_KEY.parse().unwrap();
+
+        assert_eq!(key.as_bytes()[31], 31);
+        assert_eq!(key.to_string(), VALID_KEY);
+    }
+
+    #[test]
+    fn invalid_wireguard_public_keys() {
+        for key in &[
+            "",
+            "not base64!",
+            "AAECAwQFBgcICQoLDA0ODxAREhMUFRYXGBkaGxwdHg==",
+            "AAECAwQFBgcICQoLDA0ODxAREhMUFRYXGBkaGxwdHh8gIQ==",
+        ] {
+            match key.parse::<WireguardPublicKey>() {
+                Err(Error(ErrorKind::InvalidPublicKey(_), _)) => (),
+                result => panic!("Expected {:?} to be invalid, got {:?}", key, result),
+            }
+        }
+    }
+
+    #[test]
+    fn wireguard_endpoint_serde_round_trip() {
+        let endpoint = CustomWireguardEndpoint {
+            peer_public_key: VALID_KEY.parse().unwrap(),
+            endpoint: "198.51.100.1:51820".parse().unwrap(),
+            addresses: vec!["10.64.0.2".parse().unwrap(), "fc00::2".parse().unwrap()],
+        };
+        endpoint.validate().unwrap();
+
+        let json = serde_json::to_string(&endpoint).unwrap();
+        assert!(json.contains(VALID_KEY));
+        let deserialized: CustomWireguardEndpoint = serde_json::from_str(&json).unwrap();
+        assert_eq!(deserialized, endpoint);
+    }
+
+    #[test]
+    fn wireguard_endpoint_with_invalid_key_is_not_deserialized() {
+        let json = r#"{
+            "peer_public_key": "AAECAwQFBgcICQoLDA0ODxAREhMUFRYXGBkaGxwdHg==",
+            "endpoint": "198.51.100.1:51820",
+            "addresses": ["10.64.0.2"]
+        }"#;
+
+        assert!(serde_json::from_str::<CustomWireguardEndpoint>(json).is_err());
+    }
+
+    #[test]
+    fn wireguard_endpoint_needs_address() {
+        let endpoint = CustomWireguardEndpoint {
+            peer_public_key: VALID_KEY.parse().unwrap(),
+            endpoint: "198.51.100.1:51820".parse().unwrap(),
+            addresses: vec![],
+        };
+
+        match endpoint.validate() {
+            Err(Error(ErrorKind::MissingTunnelAddress, _)) => (),
+            result => panic!("Expected a missing address error, got {:?}", result),
+        }
+    }
+
+    fn socks5(address: &str, auth: Option<(&str, &str)>) -> ProxySettings {
+        ProxySettings::Socks5 {
+            address: address.to_owned(),
+            auth: auth.map(|(username, password)| (username.to_owned(), password.to_owned())),
+        }
+    }
+
+    #[test]
+    fn socks5_proxy_without_credentials() {
+        let endpoint = CustomTunnelEndpoint::new("10.0.0.1".to_owned(), openvpn(1194))
+            .unwrap()
+            .with_proxy(socks5("127.0.0.1:1080", None))
+            .unwrap();
+        endpoint.validate().unwrap();
+
+        let json = serde_json::to_string(&endpoint).unwrap();
+        let deserialized: CustomTunnelEndpoint = serde_json::from_str(&json).unwrap();
+        assert_eq!(deserialized, endpoint);
+    }
+
+    #[test]
+    fn socks5_proxy_with_credentials() {
+        let proxy = socks5("[::1]:1080", Some(("user", "secret")));
+        proxy.validate().unwrap();
+
+        let json = serde_json::to_string(&proxy).unwrap();
+        assert_eq!(
+            json,
+            r#"{"socks5":{"address":"[::1]:1080","auth":["user","secret"]}}"#
+        );
+        assert_eq!(serde_json::from_str::<ProxySettings>(&json).unwrap(), proxy);
+    }
+
+    #[test]
+    fn invalid_socks5_proxies() {
+        match validate_proxy(&socks5("localhost", None)) {
+            Err(Error(ErrorKind::InvalidProxyAddress(_), _)) => (),
+            result => panic!("Expected an invalid address error, got {:?}", result),
+        }
+        match validate_proxy(&socks5("127.0.0.1:1080", Some(("user", "")))) {
+            Err(Error(ErrorKind::IncompleteProxyAuth, _)) => (),
+            result => panic!("Expected an incomplete auth error, got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn endpoint_without_proxy_field_is_deserialized() {
+        let json = r#"{
+            "host": "10.0.0.1",
+            "tunnel": {"openvpn": {"port": 1194, "protocol": "udp"}}
+        }"#;
+
+        let endpoint: CustomTunnelEndpoint = serde_json::from_str(json).unwrap();
+        assert_eq!(endpoint.proxy, None);
+        assert!(!serde_json::to_string(&endpoint).unwrap().contains("proxy"));
+    }
+
+    /// Checks that `value` is serialized as `golden`, and that `golden` is deserialized back into
+    /// `value`. These are the formats sent between the daemon and its clients, so changing them
+    /// breaks clients that are not updated at the same time.
+    fn assert_golden<T>(value: &T, golden: serde_json::Value)
+    where
+        T: Serialize + de::DeserializeOwned + PartialEq + fmt::Debug,
+    {
+        assert_eq!(serde_json::to_value(value).unwrap(), golden);
+        assert_eq!(serde_json::from_value::<T>(golden).unwrap(), *value);
+    }
+
+    #[test]
+    fn openvpn_endpoint_format() {
+        let endpoint = CustomTunnelEndpoint::new("10.0.0.1".to_owned(), openvpn(1194)).unwrap();
+        assert_golden(
+            &endpoint,
+            json!({
+                "host": "10.0.0.1",
+                "tunnel": {"openvpn": {"port": 1194, "protocol": "udp"}}
+            }),
+        );
+
+        let endpoint = endpoint
+            .with_proxy(socks5("127.0.0.1:1080", Some(("user", "secret"))))
+            .unwrap();
+        assert_golden(
+            &endpoint,
+            json!({
+                "host": "10.0.0.1",
+                "tunnel": {"openvpn": {"port": 1194, "protocol": "udp"}},
+                "proxy": {"socks5": {"address": "127.0.0.1:1080", "auth": ["user", "secret"]}}
+            }),
+        );
+    }
+
+    #[test]
+    fn wireguard_endpoint_format() {
+        let endpoint = CustomTunnelEndpoint {
+            host: "wg.example.com".to_owned(),
+            tunnel: TunnelEndpointData::Wireguard(WireguardEndpointData { port: 51820 }),
+            proxy: Some(socks5("[::1]:1080", None)),
+        };
+        assert_golden(
+            &endpoint,
+            json!({
+                "host": "wg.example.com",
+                "tunnel": {"wireguard": {"port": 51820}},
+                "proxy": {"socks5": {"address": "[::1]:1080", "auth": null}}
+            }),
+        );
+
+        let endpoint = CustomWireguardEndpoint {
+            peer_public_key: VALID_KEY.parse().unwrap(),
+            endpoint: "198.51.100.1:51820".parse().unwrap(),
+            addresses: vec!["10.64.0.2".parse().unwrap(), "fc00::2".parse().unwrap()],
+        };
+        assert_golden(
+            &endpoint,
+            json!({
+                "peer_public_key": VALID_KEY,
+                "endpoint": "198.51.100.1:51820",
+                "addresses": ["10.64.0.2", "fc00::2"]
+            }),
+        );
+    }
+
+    #[test]
+    fn custom_relay_settings_are_stable() {
+        let endpoint = CustomTunnelEndpoint::new("10.0.0.1".to_owned(), openvpn(1194))
+            .unwrap()
+            .with_proxy(socks5("127.0.0.1:1080", None))
+            .unwrap();
+        let settings = RelaySettings::CustomTunnelEndpoint(endpoint);
+
+        let json = serde_json::to_string(&settings).unwrap();
+        let deserialized: RelaySettings = serde_json::from_str(&json).unwrap();
+        assert_eq!(deserialized, settings);
+        assert_eq!(serde_json::to_string(&deserialized).unwrap(), json);
+        let value: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(value["custom_tunnel_endpoint"]["host"], json!("10.0.0.1"));
+    }
+}
@@ -6,10 +6,14 @@
 //! GNU General Public License as published by the Free Software Foundation, either version 3 of
 //! the License, or (at your option) any later version.
 
+extern crate base64;
 extern crate chrono;
+extern crate rand;
 extern crate serde;
 #[macro_use]
 extern crate serde_derive;
+#[macro_use]
+extern crate serde_json;
 
 extern crate talpid_types;
 
@@ -21,9 +25,11 @@ extern crate error_chain;
 
 pub mod account;
 pub mod location;
+pub mod problem_report;
 pub mod relay_constraints;
 pub mod relay_list;
 pub mod states;
+pub mod tunnel_exit_cause;
 pub mod version;
 
 mod custom_tunnel;
@@ -1,9 +1,16 @@
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use std::error::Error;
+use std::fmt;
 use std::net::IpAddr;
+use std::str::FromStr;
+
+pub type Hostname = String;
 
-pub type CountryCode = String;
-pub type CityCode = String;
+/// Mean radius of the earth, in kilometers.
+const EARTH_RADIUS_KM: f64 = 6371.0;
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize)]
 pub struct Location {
     pub country: String,
     pub country_code: CountryCode,
@@ -11,14 +18,654 @@ pub struct Location {
     pub city_code: CityCode,
     pub latitude: f64,
     pub longitude: f64,
+    pub continent: Option<Continent>,
+}
+
+/// Mirror of `Location` used for deserialization. Locations serialized before the continent was
+/// added lack that field, so it is looked up from the country code if missing.
+#[derive(Deserialize)]
+struct SerializedLocation {
+    country: String,
+    country_code: CountryCode,
+    city: String,
+    city_code: CityCode,
+    latitude: f64,
+    longitude: f64,
+    #[serde(default)]
+    continent: Option<Continent>,
+}
+
+impl<'de> Deserialize<'de> for Location {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let location = SerializedLocation::deserialize(deserializer)?;
+        let continent = location
+            .continent
+            .or_else(|| Continent::from_country_code(location.country_code.as_str()));
+        Ok(Location {
+            country: location.country,
+            country_code: location.country_code,
+            city: location.city,
+            city_code: location.city_code,
+            latitude: location.latitude,
+            longitude: location.longitude,
+            continent,
+        })
+    }
+}
+
+impl Location {
+    /// Returns the coordinates of this location.
+    pub fn coordinates(&self) -> Coordinates {
+        Coordinates {
+            latitude: self.latitude,
+            longitude: self.longitude,
+        }
+    }
+
+    /// Returns the great-circle distance to `other`, in kilometers.
+    pub fn distance_to(&self, other: &Location) -> f64 {
+        self.coordinates().distance_to(&other.coordinates())
+    }
+
+    /// Returns the location in the compact form used in relay hostnames, like "se-got". Only the
+    /// country code is returned if the city is not known.
+    pub fn short(&self) -> String {
+        if self.has_city() {
+            format!("{}-{}", self.country_code, self.city_code)
+        } else {
+            self.country_code.to_string()
+        }
+    }
+
+    fn has_city(&self) -> bool {
+        !self.city.trim().is_empty()
+    }
+}
+
+impl fmt::Display for Location {
+    /// Formats the location like "Gothenburg, Sweden (se)", or "Sweden (se)" if the city is not
+    /// known.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.has_city() {
+            write!(f, "{}, ", self.city)?;
+        }
+        write!(f, "{} ({})", self.country, self.country_code)
+    }
+}
+
+/// A two letter ISO 3166-1 country code. The code is stored in lowercase, so codes compare equal
+/// regardless of the case they were given in.
+#[derive(Debug, Clone, Eq, PartialEq, Hash, PartialOrd, Ord)]
+pub struct CountryCode(String);
+
+impl CountryCode {
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// Returns the English name of the country, for all countries Mullvad has relays in. The code
+    /// itself is returned for other countries.
+    pub fn name(&self) -> &str {
+        match self.0.as_str() {
+            "al" => "Albania",
+            "at" => "Austria",
+            "au" => "Australia",
+            "be" => "Belgium",
+            "bg" => "Bulgaria",
+            "br" => "Brazil",
+            "ca" => "Canada",
+            "ch" => "Switzerland",
+            "cz" => "Czech Republic",
+            "de" => "Germany",
+            "dk" => "Denmark",
+            "ee" => "Estonia",
+            "es" => "Spain",
+            "fi" => "Finland",
+            "fr" => "France",
+            "gb" => "UK",
+            "gr" => "Greece",
+            "hk" => "Hong Kong",
+            "hu" => "Hungary",
+            "ie" => "Ireland",
+            "il" => "Israel",
+            "it" => "Italy",
+            "jp" => "Japan",
+            "lu" => "Luxembourg",
+            "lv" => "Latvia",
+            "md" => "Moldova",
+            "nl" => "Netherlands",
+            "no" => "Norway",
+            "nz" => "New Zealand",
+            "pl" => "Poland",
+            "pt" => "Portugal",
+            "ro" => "Romania",
+            "rs" => "Serbia",
+            "se" => "Sweden",
+            "sg" => "Singapore",
+            "us" => "USA",
+            _ => &self.0,
+        }
+    }
+}
+
+impl FromStr for CountryCode {
+    type Err = InvalidCodeError;
+
+    /// Parses a country code, ignoring case and surrounding whitespace.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        parse_code(s, 2).map(CountryCode)
+    }
+}
+
+/// A three letter code identifying a city within a country. Stored in lowercase, just like
+/// `CountryCode`.
+#[derive(Debug, Clone, Eq, PartialEq, Hash, PartialOrd, Ord)]
+pub struct CityCode(String);
+
+impl CityCode {
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// Returns the English name of the city in the given country, for all cities Mullvad has
+    /// relays in. City codes are only unique within a country, "hel" is both Helsinki in Finland
+    /// and Helsingborg in Sweden. The code itself is returned for other cities.
+    pub fn name(&self, country: &CountryCode) -> &str {
+        match (country.as_str(), self.0.as_str()) {
+            ("al", "tia") => "Tirana",
+            ("at", "vie") => "Vienna",
+            ("au", "bne") => "Brisbane",
+            ("au", "mel") => "Melbourne",
+            ("au", "per") => "Perth",
+            ("au", "syd") => "Sydney",
+            ("be", "bru") => "Brussels",
+            ("bg", "sof") => "Sofia",
+            ("br", "sao") => "Sao Paulo",
+            ("ca", "mtr") => "Montreal",
+            ("ca", "tor") => "Toronto",
+            ("ca", "van") => "Vancouver",
+            ("ch", "zrh") => "Zurich",
+            ("cz", "prg") => "Prague",
+            ("de", "ber") => "Berlin",
+            ("de", "dus") => "Dusseldorf",
+            ("de", "fra") => "Frankfurt",
+            ("dk", "cph") => "Copenhagen",
+            ("ee", "tll") => "Tallinn",
+            ("es", "mad") => "Madrid",
+            ("fi", "hel") => "Helsinki",
+            ("fr", "par") => "Paris",
+            ("gb", "lon") => "London",
+            ("gb", "mnc") => "Manchester",
+            ("gr", "ath") => "Athens",
+            ("hk", "hkg") => "Hong Kong",
+            ("hu", "bud") => "Budapest",
+            ("ie", "dub") => "Dublin",
+            ("il", "tlv") => "Tel Aviv",
+            ("it", "mil") => "Milan",
+            ("jp", "tyo") => "Tokyo",
+            ("lu", "lux") => "Luxembourg",
+            ("lv", "rix") => "Riga",
+            ("md", "chi") => "Chisinau",
+            ("nl", "ams") => "Amsterdam",
+            ("no", "osl") => "Oslo",
+            ("nz", "akl") => "Auckland",
+            ("pl", "waw") => "Warsaw",
+            ("pt", "lis") => "Lisbon",
+            ("ro", "buh") => "Bucharest",
+            ("rs", "beg") => "Belgrade",
+            ("se", "got") => "Gothenburg",
+            ("se", "hel") => "Helsingborg",
+            ("se", "mma") => "Malmo",
+            ("se", "sto") => "Stockholm",
+            ("sg", "sin") => "Singapore",
+            ("us", "atl") => "Atlanta, GA",
+            ("us", "chi") => "Chicago, IL",
+            ("us", "dal") => "Dallas, TX",
+            ("us", "den") => "Denver, CO",
+            ("us", "lax") => "Los Angeles, CA",
+            ("us", "mia") => "Miami, FL",
+            ("us", "nyc") => "New York, NY",
+            ("us", "phx") => "Phoenix, AZ",
+            ("us", "sea") => "Seattle, WA",
+            ("us", "sjc") => "San Jose, CA",
+            ("us", "slc") => "Salt Lake City, UT",
+            ("us", "was") => "Washington DC",
+            _ => &self.0,
+        }
+    }
+}
+
+impl FromStr for CityCode {
+    type Err = InvalidCodeError;
+
+    /// Parses a city code, ignoring case and surrounding whitespace.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        parse_code(s, 3).map(CityCode)
+    }
+}
+
+fn parse_code(s: &str, length: usize) -> Result<String, InvalidCodeError> {
+    let code = normalize_code(s);
+    if code.len() == length && code.bytes().all(|b| b.is_ascii_lowercase()) {
+        Ok(code)
+    } else {
+        Err(InvalidCodeError(s.to_owned()))
+    }
+}
+
+fn normalize_code(s: &str) -> String {
+    s.trim().to_lowercase()
+}
+
+/// Error returned when parsing a string that is not a valid country or city code.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct InvalidCodeError(pub String);
+
+impl fmt::Display for InvalidCodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: \"{}\"", self.description(), self.0)
+    }
+}
+
+impl Error for InvalidCodeError {
+    fn description(&self) -> &str {
+        "Invalid location code"
+    }
+}
+
+impl fmt::Display for CountryCode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl fmt::Display for CityCode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl PartialEq<str> for CountryCode {
+    fn eq(&self, other: &str) -> bool {
+        self.0.eq_ignore_ascii_case(other)
+    }
+}
+
+impl<'a> PartialEq<&'a str> for CountryCode {
+    fn eq(&self, other: &&'a str) -> bool {
+        self.0.eq_ignore_ascii_case(other)
+    }
+}
+
+impl PartialEq<str> for CityCode {
+    fn eq(&self, other: &str) -> bool {
+        self.0.eq_ignore_ascii_case(other)
+    }
+}
+
+impl<'a> PartialEq<&'a str> for CityCode {
+    fn eq(&self, other: &&'a str) -> bool {
+        self.0.eq_ignore_ascii_case(other)
+    }
+}
+
+impl Serialize for CountryCode {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.0)
+    }
+}
+
+impl<'de> Deserialize<'de> for CountryCode {
+    /// Accepts any code, only normalizing it like `from_str` does. Codes come from the relay list
+    /// and the settings, and one unexpected code from the API must not make the whole relay list
+    /// unreadable. Codes given by the user are validated when parsed instead.
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let code = String::deserialize(deserializer)?;
+        Ok(CountryCode(normalize_code(&code)))
+    }
+}
+
+impl Serialize for CityCode {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.0)
+    }
+}
+
+impl<'de> Deserialize<'de> for CityCode {
+    /// Accepts any code, just like deserializing a `CountryCode`.
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let code = String::deserialize(deserializer)?;
+        Ok(CityCode(normalize_code(&code)))
+    }
+}
+
+/// A point on the earth, given in decimal degrees.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Coordinates {
+    pub latitude: f64,
+    pub longitude: f64,
+}
+
+impl Coordinates {
+    /// Returns true if the latitude and longitude are numbers within their valid ranges.
+    pub fn is_valid(&self) -> bool {
+        self.latitude >= -90.0 && self.latitude <= 90.0 && self.longitude >= -180.0
+            && self.longitude <= 180.0
+    }
+
+    /// Returns the great-circle distance to `other`, in kilometers, computed with the haversine
+    /// formula.
+    pub fn distance_to(&self, other: &Coordinates) -> f64 {
+        let delta_latitude = (other.latitude - self.latitude).to_radians();
+        let delta_longitude = (other.longitude - self.longitude).to_radians();
+
+        let a = (delta_latitude / 2.0).sin().powi(2)
+            + self.latitude.to_radians().cos() * other.latitude.to_radians().cos()
+                * (delta_longitude / 2.0).sin().powi(2);
+        // Rounding errors can push `a` slightly outside of [0, 1] for identical or antipodal
+        // points, which would make the square roots below return NaN.
+        let a = a.max(0.0).min(1.0);
+
+        2.0 * EARTH_RADIUS_KM * a.sqrt().atan2((1.0 - a).sqrt())
+    }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Continent {
+    Africa,
+    Antarctica,
+    Asia,
+    Europe,
+    NorthAmerica,
+    Oceania,
+    SouthAmerica,
+}
+
+impl Continent {
+    /// Returns the continent the country with the given two letter ISO 3166-1 code belongs to.
+    /// Countries spanning several continents are mapped to the one holding their capital.
+    pub fn from_country_code(country_code: &str) -> Option<Continent> {
+        use self::Continent::*;
+        let continent = match country_code.to_lowercase().as_str() {
+            "ao" | "bf" | "bi" | "bj" | "bw" | "cd" | "cf" | "cg" | "ci" | "cm" | "cv" | "dj"
+            | "dz" | "eg" | "eh" | "er" | "et" | "ga" | "gh" | "gm" | "gn" | "gq" | "gw"
+            | "ke" | "km" | "lr" | "ls" | "ly" | "ma" | "mg" | "ml" | "mr" | "mu" | "mw"
+            | "mz" | "na" | "ne" | "ng" | "re" | "rw" | "sc" | "sd" | "sh" | "sl" | "sn"
+            | "so" | "ss" | "st" | "sz" | "td" | "tg" | "tn" | "tz" | "ug" | "yt" | "za"
+            | "zm" | "zw" => Africa,
+            "aq" | "bv" | "gs" | "hm" | "tf" => Antarctica,
+            "ae" | "af" | "am" | "az" | "bd" | "bh" | "bn" | "bt" | "cc" | "cn" | "cx" | "cy"
+            | "ge" | "hk" | "id" | "il" | "in" | "io" | "iq" | "ir" | "jo" | "jp" | "kg"
+            | "kh" | "kp" | "kr" | "kw" | "kz" | "la" | "lb" | "lk" | "mm" | "mn" | "mo"
+            | "mv" | "my" | "np" | "om" | "ph" | "pk" | "ps" | "qa" | "sa" | "sg" | "sy"
+            | "th" | "tj" | "tl" | "tm" | "tr" | "tw" | "uz" | "vn" | "ye" => Asia,
+            "ad" | "al" | "at" | "ax" | "ba" | "be" | "bg" | "by" | "ch" | "cz" | "de" | "dk"
+            | "ee" | "es" | "fi" | "fo" | "fr" | "gb" | "gg" | "gi" | "gr" | "hr" | "hu"
+            | "ie" | "im" | "is" | "it" | "je" | "li" | "lt" | "lu" | "lv" | "mc" | "md"
+            | "me" | "mk" | "mt" | "nl" | "no" | "pl" | "pt" | "ro" | "rs" | "ru" | "se"
+            | "si" | "sj" | "sk" | "sm" | "ua" | "va" | "xk" => Europe,
+            "ag" | "ai" | "aw" | "bb" | "bl" | "bm" | "bq" | "bs" | "bz" | "ca" | "cr" | "cu"
+            | "cw" | "dm" | "do" | "gd" | "gl" | "gp" | "gt" | "hn" | "ht" | "jm" | "kn"
+            | "ky" | "lc" | "mf" | "mq" | "ms" | "mx" | "ni" | "pa" | "pm" | "pr" | "sv"
+            | "sx" | "tc" | "tt" | "um" | "us" | "vc" | "vg" | "vi" => NorthAmerica,
+            "as" | "au" | "ck" | "fj" | "fm" | "gu" | "ki" | "mh" | "mp" | "nc" | "nf" | "nr"
+            | "nu" | "nz" | "pf" | "pg" | "pn" | "pw" | "sb" | "tk" | "to" | "tv" | "vu"
+            | "wf" | "ws" => Oceania,
+            "ar" | "bo" | "br" | "cl" | "co" | "ec" | "fk" | "gf" | "gy" | "pe" | "py" | "sr"
+            | "uy" | "ve" => SouthAmerica,
+            _ => return None,
+        };
+        Some(continent)
+    }
+}
+
+/// The apparent location of the device, as seen from the internet. Also used as the format of the
+/// response from the GeoIP lookup service, which may leave out fields it doesn't know.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct GeoIpLocation {
-    pub ip: IpAddr,
+    #[serde(default)]
+    pub ip: Option<IpAddr>,
     pub country: String,
+    #[serde(default)]
     pub city: Option<String>,
     pub latitude: f64,
     pub longitude: f64,
+    /// The hostname of the Mullvad relay the traffic exits through, if any.
+    #[serde(rename = "mullvad_exit_ip_hostname", default)]
+    pub hostname: Option<String>,
+    /// If the traffic exits through a Mullvad relay.
+    #[serde(default)]
     pub mullvad_exit_ip: bool,
 }
+
+impl GeoIpLocation {
+    /// Returns the coordinates of this location.
+    pub fn coordinates(&self) -> Coordinates {
+        Coordinates {
+            latitude: self.latitude,
+            longitude: self.longitude,
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json;
+
+    fn location(latitude: f64, longitude: f64) -> Location {
+        Location {
+            country: String::new(),
+            country_code: "se".parse().unwrap(),
+            city: String::new(),
+            city_code: "got".parse().unwrap(),
+            latitude,
+            longitude,
+            continent: None,
+        }
+    }
+
+    fn assert_distance(from: &Location, to: &Location, expected: f64) {
+        let distance = from.distance_to(to);
+        assert!(
+            (distance - expected).abs() < 5.0,
+            "Expected a distance of {} km, got {} km",
+            expected,
+            distance
+        );
+    }
+
+    fn named_location(country: &str, city: &str) -> Location {
+        Location {
+            country: country.to_owned(),
+            city: city.to_owned(),
+            ..location(57.7089, 11.9746)
+        }
+    }
+
+    #[test]
+    fn display_full_location() {
+        let gothenburg = named_location("Sweden", "Gothenburg");
+
+        assert_eq!(gothenburg.to_string(), "Gothenburg, Sweden (se)");
+        assert_eq!(gothenburg.short(), "se-got");
+    }
+
+    #[test]
+    fn display_country_only_location() {
+        let sweden = named_location("Sweden", "");
+
+        assert_eq!(sweden.to_string(), "Sweden (se)");
+        assert_eq!(sweden.short(), "se");
+        assert_eq!(named_location("Sweden", "  ").to_string(), "Sweden (se)");
+    }
+
+    #[test]
+    fn distance_between_known_cities() {
+        let stockholm = location(59.3293, 18.0686);
+        let gothenburg = location(57.7089, 11.9746);
+        let london = location(51.5074, -0.1278);
+        let paris = location(48.8566, 2.3522);
+        let new_york = location(40.7128, -74.0060);
+        let los_angeles = location(34.0522, -118.2437);
+
+        assert_distance(&stockholm, &gothenburg, 398.0);
+        assert_distance(&london, &paris, 344.0);
+        assert_distance(&new_york, &los_angeles, 3936.0);
+        assert_distance(&los_angeles, &new_york, 3936.0);
+    }
+
+    #[test]
+    fn distance_to_same_point_is_zero() {
+        let gothenburg = location(57.7089, 11.9746);
+
+        assert_eq!(gothenburg.distance_to(&gothenburg), 0.0);
+    }
+
+    #[test]
+    fn distance_to_antipode_is_half_circumference() {
+        let a = Coordinates {
+            latitude: 0.0,
+            longitude: 0.0,
+        };
+        let b = Coordinates {
+            latitude: 0.0,
+            longitude: 180.0,
+        };
+
+        let distance = a.distance_to(&b);
+        assert!(!distance.is_nan());
+        assert!((distance - 20015.0).abs() < 5.0);
+    }
+
+    #[test]
+    fn country_code_to_continent() {
+        assert_eq!(Continent::from_country_code("se"), Some(Continent::Europe));
+        assert_eq!(Continent::from_country_code("US"), Some(Continent::NorthAmerica));
+        assert_eq!(Continent::from_country_code("br"), Some(Continent::SouthAmerica));
+        assert_eq!(Continent::from_country_code("sg"), Some(Continent::Asia));
+        assert_eq!(Continent::from_country_code("au"), Some(Continent::Oceania));
+        assert_eq!(Continent::from_country_code("za"), Some(Continent::Africa));
+        assert_eq!(Continent::from_country_code("zz"), None);
+    }
+
+    #[test]
+    fn codes_ignore_case_and_whitespace() {
+        let country: CountryCode = " SE ".parse().unwrap();
+        assert_eq!(country, "se".parse::<CountryCode>().unwrap());
+        assert_eq!(country, "Se");
+        assert_eq!(country.to_string(), "se");
+
+        let city: CityCode = "GOT".parse().unwrap();
+        assert_eq!(city, "got".parse::<CityCode>().unwrap());
+        assert_eq!(city.to_string(), "got");
+    }
+
+    #[test]
+    fn invalid_codes_are_rejected() {
+        for code in &["", "s", "swe", "s1", "å", "se-"] {
+            assert!(code.parse::<CountryCode>().is_err(), "{}", code);
+        }
+        for code in &["", "go", "gote", "g0t", "gö"] {
+            assert!(code.parse::<CityCode>().is_err(), "{}", code);
+        }
+    }
+
+    #[test]
+    fn deserialized_codes_are_not_validated() {
+        let country: CountryCode = serde_json::from_str(r#"" SE ""#).unwrap();
+        assert_eq!(country.as_str(), "se");
+        let country: CountryCode = serde_json::from_str(r#""Sweden""#).unwrap();
+        assert_eq!(country.as_str(), "sweden");
+        let city: CityCode = serde_json::from_str(r#""got-1""#).unwrap();
+        assert_eq!(city.as_str(), "got-1");
+    }
+
+    #[test]
+    fn code_names() {
+        let country = |code: &str| code.parse::<CountryCode>().unwrap();
+        let city = |code: &str| code.parse::<CityCode>().unwrap();
+
+        assert_eq!(country("SE").name(), "Sweden");
+        assert_eq!(country("de").name(), "Germany");
+        assert_eq!(country("us").name(), "USA");
+        assert_eq!(country("xx").name(), "xx");
+
+        assert_eq!(city("got").name(&country("se")), "Gothenburg");
+        assert_eq!(city("hel").name(&country("se")), "Helsingborg");
+        assert_eq!(city("hel").name(&country("fi")), "Helsinki");
+        assert_eq!(city("nyc").name(&country("us")), "New York, NY");
+        assert_eq!(city("got").name(&country("fi")), "got");
+        assert_eq!(city("xyz").name(&country("xx")), "xyz");
+    }
+
+    #[test]
+    fn location_serde_round_trip() {
+        let mut original = location(57.7089, 11.9746);
+        original.country_code = "SE".parse().unwrap();
+        original.continent = Some(Continent::Europe);
+
+        let json = serde_json::to_string(&original).unwrap();
+        assert!(json.contains(r#""continent":"europe""#));
+
+        let location: Location = serde_json::from_str(&json).unwrap();
+        assert_eq!(location.country_code, "se");
+        assert_eq!(location.continent, Some(Continent::Europe));
+    }
+
+    #[test]
+    fn missing_continent_is_looked_up() {
+        let json = r#"{
+            "country": "Japan",
+            "country_code": "jp",
+            "city": "Tokyo",
+            "city_code": "tyo",
+            "latitude": 35.685,
+            "longitude": 139.751389
+        }"#;
+
+        let location: Location = serde_json::from_str(json).unwrap();
+        assert_eq!(location.continent, Some(Continent::Asia));
+    }
+
+    #[test]
+    fn deserialize_geoip_response() {
+        let json = r#"{
+            "ip": "198.51.100.7",
+            "country": "Sweden",
+            "city": "Gothenburg",
+            "longitude": 11.9667,
+            "latitude": 57.7,
+            "mullvad_exit_ip": true,
+            "mullvad_exit_ip_hostname": "se-got-001",
+            "organization": "31173 Services AB"
+        }"#;
+
+        let location: GeoIpLocation = serde_json::from_str(json).unwrap();
+        assert_eq!(
+            location,
+            GeoIpLocation {
+                ip: Some("198.51.100.7".parse().unwrap()),
+                country: "Sweden".to_owned(),
+                city: Some("Gothenburg".to_owned()),
+                latitude: 57.7,
+                longitude: 11.9667,
+                hostname: Some("se-got-001".to_owned()),
+                mullvad_exit_ip: true,
+            }
+        );
+    }
+
+    #[test]
+    fn deserialize_partial_geoip_response() {
+        let json = r#"{"country": "Sweden", "latitude": 62.0, "longitude": 15.0}"#;
+
+        let location: GeoIpLocation = serde_json::from_str(json).unwrap();
+        assert_eq!(location.ip, None);
+        assert_eq!(location.city, None);
+        assert_eq!(location.hostname, None);
+        assert!(!location.mullvad_exit_ip);
+    }
+}
@@ -0,0 +1,69 @@
+use std::collections::HashMap;
+
+
+/// System information attached to a problem report. Only holds values that are safe to send to
+/// support. The account token in particular is never included, only whether one is set.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct ProblemReportMetadata {
+    /// The name of the operating system, like "Linux" or "macOS".
+    pub os: String,
+    /// The version of the operating system, or distribution on Linux.
+    pub os_version: String,
+    /// The version of the app sending the report.
+    pub app_version: String,
+    /// Whether the reporter has an account token set in the app, or `None` if that is not known.
+    pub account_present: Option<bool>,
+}
+
+impl ProblemReportMetadata {
+    /// Returns the metadata as the key value pairs expected by the problem report API. Values
+    /// that are not known are left out rather than guessed.
+    pub fn into_map(self) -> HashMap<String, String> {
+        let mut map = HashMap::new();
+        map.insert("os".to_owned(), self.os);
+        map.insert("os-version".to_owned(), self.os_version);
+        map.insert("mullvad-daemon-version".to_owned(), self.app_version);
+        if let Some(account_present) = self.account_present {
+            map.insert("account-present".to_owned(), account_present.to_string());
+        }
+        map
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn into_map_uses_api_keys() {
+        let metadata = ProblemReportMetadata {
+            os: "Linux".to_owned(),
+            os_version: "Ubuntu 18.04 LTS".to_owned(),
+            app_version: "2018.2-beta1".to_owned(),
+            account_present: Some(true),
+        };
+
+        let mut expected = HashMap::new();
+        expected.insert("os".to_owned(), "Linux".to_owned());
+        expected.insert("os-version".to_owned(), "Ubuntu 18.04 LTS".to_owned());
+        expected.insert(
+            "mullvad-daemon-version".to_owned(),
+            "2018.2-beta1".to_owned(),
+        );
+        expected.insert("account-present".to_owned(), "true".to_owned());
+        assert_eq!(metadata.clone().into_map(), expected);
+
+        let metadata = ProblemReportMetadata {
+            account_present: Some(false),
+            ..metadata
+        };
+        assert_eq!(metadata.clone().into_map()["account-present"], "false");
+
+        let metadata = ProblemReportMetadata {
+            account_present: None,
+            ..metadata
+        };
+        assert!(!metadata.into_map().contains_key("account-present"));
+    }
+}
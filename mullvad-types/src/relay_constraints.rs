@@ -1,7 +1,11 @@
-use location::{CityCode, CountryCode};
-use CustomTunnelEndpoint;
+use location::{CityCode, CountryCode, Hostname};
+use relay_list::{Provider, Relay};
+use {CustomTunnelEndpoint, ProxySettings};
+
+use serde::{Deserialize, Deserializer};
 
 use std::fmt;
+use std::str::FromStr;
 
 use talpid_types::net::{OpenVpnEndpointData, TransportProtocol, WireguardEndpointData};
 
@@ -34,6 +38,29 @@ impl<T: fmt::Debug + Clone + Eq + PartialEq> Default for Constraint<T> {
 
 impl<T: Copy + fmt::Debug + Clone + Eq + PartialEq> Copy for Constraint<T> {}
 
+impl<T: fmt::Display + fmt::Debug + Clone + Eq + PartialEq> fmt::Display for Constraint<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Constraint::Any => f.write_str("any"),
+            Constraint::Only(ref value) => fmt::Display::fmt(value, f),
+        }
+    }
+}
+
+/// Parses "any", in any case, as `Constraint::Any`. Everything else is parsed as a `T` and
+/// wrapped in `Constraint::Only`.
+impl<T: FromStr + fmt::Debug + Clone + Eq + PartialEq> FromStr for Constraint<T> {
+    type Err = T::Err;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.eq_ignore_ascii_case("any") {
+            Ok(Constraint::Any)
+        } else {
+            T::from_str(s).map(Constraint::Only)
+        }
+    }
+}
+
 impl<T: fmt::Debug + Clone + Eq + PartialEq> Match<T> for Constraint<T> {
     fn matches(&self, other: &T) -> bool {
         match *self {
@@ -43,6 +70,33 @@ impl<T: fmt::Debug + Clone + Eq + PartialEq> Match<T> for Constraint<T> {
     }
 }
 
+/// Deserializes a constraint on a list of values, for use with `#[serde(deserialize_with)]`.
+/// Duplicate values are dropped, keeping the first occurrence. `Only` an empty list is
+/// deserialized as `Any`, since a constraint allowing no value at all is meaningless.
+pub fn deserialize_vec_constraint<'de, D, T>(
+    deserializer: D,
+) -> Result<Constraint<Vec<T>>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de> + fmt::Debug + Clone + Eq + PartialEq,
+{
+    let values = match Constraint::<Vec<T>>::deserialize(deserializer)? {
+        Constraint::Any => return Ok(Constraint::Any),
+        Constraint::Only(values) => values,
+    };
+    let mut unique_values = Vec::with_capacity(values.len());
+    for value in values {
+        if !unique_values.contains(&value) {
+            unique_values.push(value);
+        }
+    }
+    if unique_values.is_empty() {
+        Ok(Constraint::Any)
+    } else {
+        Ok(Constraint::Only(unique_values))
+    }
+}
+
 #[derive(Debug, Clone, Eq, PartialEq, Deserialize, Serialize)]
 #[serde(rename_all = "snake_case")]
 pub enum RelaySettings {
@@ -70,6 +124,17 @@ impl RelaySettings {
             }),
         }
     }
+
+    /// Returns true if connecting with these settings could select another relay than connecting
+    /// with `other`. See `RelayConstraints::differs_from`.
+    pub fn differs_from(&self, other: &RelaySettings) -> bool {
+        match (self, other) {
+            (&RelaySettings::Normal(ref constraints), &RelaySettings::Normal(ref other)) => {
+                constraints.differs_from(other)
+            }
+            _ => self != other,
+        }
+    }
 }
 
 #[derive(Debug, Default, Clone, Eq, PartialEq, Deserialize, Serialize)]
@@ -77,13 +142,309 @@ impl RelaySettings {
 pub struct RelayConstraints {
     pub location: Constraint<LocationConstraint>,
     pub tunnel: Constraint<TunnelConstraints>,
+    #[serde(default, deserialize_with = "deserialize_vec_constraint")]
+    pub providers: Constraint<Vec<Provider>>,
+    #[serde(default)]
+    pub ownership: Ownership,
+    #[serde(default)]
+    pub port: Constraint<TransportPort>,
+    #[serde(default)]
+    pub tunnel_protocol: TunnelProtocol,
+    /// Where to select the entry relay from when `use_multihop` is enabled.
+    #[serde(default)]
+    pub entry_location: Constraint<LocationConstraint>,
+    /// Route traffic through an entry relay before the exit relay selected by `location`.
+    #[serde(default)]
+    pub use_multihop: bool,
+    /// Only select relays accepting IPv6 connections, and connect to them over IPv6. For clients
+    /// without IPv4 connectivity.
+    #[serde(default)]
+    pub ipv6_only: bool,
 }
 
 impl RelayConstraints {
+    /// Returns a builder for constraints that match any relay until restricted.
+    pub fn builder() -> RelayConstraintsBuilder {
+        RelayConstraintsBuilder::new()
+    }
+
     pub fn merge(&self, update: RelayConstraintsUpdate) -> Self {
+        let mut constraints = self.clone();
+        constraints.apply(update);
+        constraints
+    }
+
+    /// Overwrites the constraints that are set in `update` and leaves the rest untouched.
+    pub fn apply(&mut self, update: RelayConstraintsUpdate) {
+        if let Some(location) = update.location {
+            self.location = location;
+        }
+        if let Some(tunnel) = update.tunnel {
+            self.tunnel = tunnel;
+        }
+        if let Some(providers) = update.providers {
+            self.providers = providers;
+        }
+        if let Some(ownership) = update.ownership {
+            self.ownership = ownership;
+        }
+        if let Some(port) = update.port {
+            self.port = port;
+        }
+        if let Some(tunnel_protocol) = update.tunnel_protocol {
+            self.tunnel_protocol = tunnel_protocol;
+        }
+        if let Some(entry_location) = update.entry_location {
+            self.entry_location = entry_location;
+        }
+        if let Some(use_multihop) = update.use_multihop {
+            self.use_multihop = use_multihop;
+        }
+        if let Some(ipv6_only) = update.ipv6_only {
+            self.ipv6_only = ipv6_only;
+        }
+    }
+
+    /// Returns true if these constraints can match other relays than `other` does. Unlike `!=`,
+    /// this ignores differences that do not affect relay selection: the order of the providers,
+    /// an empty provider list versus no provider constraint, and the entry location when
+    /// multihop is disabled.
+    pub fn differs_from(&self, other: &RelayConstraints) -> bool {
+        self.normalized() != other.normalized()
+    }
+
+    fn normalized(&self) -> RelayConstraints {
+        let mut constraints = self.clone();
+        if let Constraint::Only(ref mut providers) = constraints.providers {
+            providers.sort();
+            providers.dedup();
+        }
+        if constraints.providers == Constraint::Only(vec![]) {
+            constraints.providers = Constraint::Any;
+        }
+        if !constraints.use_multihop {
+            constraints.entry_location = Constraint::Any;
+        }
+        constraints
+    }
+
+    /// Returns the constraints an entry relay must satisfy. These are the same as for the exit
+    /// relay, except that the location is given by `entry_location`.
+    pub fn entry_constraints(&self) -> RelayConstraints {
         RelayConstraints {
-            location: update.location.unwrap_or_else(|| self.location.clone()),
-            tunnel: update.tunnel.unwrap_or_else(|| self.tunnel.clone()),
+            location: self.entry_location.clone(),
+            ..self.clone()
+        }
+    }
+
+    /// Takes a `Relay` and returns a new `Relay`, with only the tunnel endpoints matching these
+    /// constraints, if the given relay matches the constraints. See the `Match` implementation
+    /// for which relays match.
+    pub fn matching_relay(&self, relay: &Relay) -> Option<Relay> {
+        if !self.matches(relay) {
+            return None;
+        }
+        let mut relay = relay.clone();
+        if let Constraint::Only(ref tunnel_constraints) = self.tunnel {
+            relay.tunnels = relay.tunnels.matching(tunnel_constraints);
+        }
+        relay.tunnels = relay.tunnels.matching(&self.port);
+        Some(relay)
+    }
+}
+
+/// Matches relays satisfying all the constraints. That is relays in the location, hosted by one of
+/// the providers and with the right ownership, that have at least one endpoint allowed by the
+/// tunnel, port and tunnel protocol constraints. With `ipv6_only`, the relay must also accept IPv6
+/// connections. The location of the relay must be set for it to match a location constraint.
+/// Relays that can't be selected in the constrained location never match, see
+/// `Relay::is_selectable`. The weight of the relay is not considered.
+impl Match<Relay> for RelayConstraints {
+    fn matches(&self, relay: &Relay) -> bool {
+        self.location.matches(relay) && self.providers.matches(relay)
+            && self.ownership.matches(relay) && !relay.matching_endpoints(self).is_empty()
+    }
+}
+
+/// A builder of `RelayConstraints`, for callers that only care about a few of the constraints.
+/// Every constraint that is not set matches any relay, and multihop is disabled.
+#[derive(Debug, Default, Clone)]
+pub struct RelayConstraintsBuilder {
+    constraints: RelayConstraints,
+}
+
+impl RelayConstraintsBuilder {
+    pub fn new() -> Self {
+        RelayConstraintsBuilder::default()
+    }
+
+    /// Restricts where the exit relay is located.
+    pub fn location(&mut self, location: LocationConstraint) -> &mut Self {
+        self.constraints.location = Constraint::Only(location);
+        self
+    }
+
+    /// Restricts the tunnel endpoints of the relay.
+    pub fn tunnel(&mut self, tunnel: TunnelConstraints) -> &mut Self {
+        self.constraints.tunnel = Constraint::Only(tunnel);
+        self
+    }
+
+    /// Only selects relays hosted by one of the given providers.
+    pub fn providers(&mut self, providers: Vec<Provider>) -> &mut Self {
+        self.constraints.providers = Constraint::Only(providers);
+        self
+    }
+
+    pub fn ownership(&mut self, ownership: Ownership) -> &mut Self {
+        self.constraints.ownership = ownership;
+        self
+    }
+
+    /// Restricts the transport protocol and port of the tunnel endpoint.
+    pub fn port(&mut self, port: TransportPort) -> &mut Self {
+        self.constraints.port = Constraint::Only(port);
+        self
+    }
+
+    pub fn tunnel_protocol(&mut self, tunnel_protocol: TunnelProtocol) -> &mut Self {
+        self.constraints.tunnel_protocol = tunnel_protocol;
+        self
+    }
+
+    /// Enables multihop, with the entry relay located at `entry_location`.
+    pub fn multihop(&mut self, entry_location: Constraint<LocationConstraint>) -> &mut Self {
+        self.constraints.entry_location = entry_location;
+        self.constraints.use_multihop = true;
+        self
+    }
+
+    /// Only selects relays that can be connected to over IPv6.
+    pub fn ipv6_only(&mut self, ipv6_only: bool) -> &mut Self {
+        self.constraints.ipv6_only = ipv6_only;
+        self
+    }
+
+    pub fn build(&self) -> RelayConstraints {
+        self.constraints.clone()
+    }
+}
+
+/// Matches relays hosted by any of the allowed providers. An empty list of providers is treated
+/// the same as `Constraint::Any`, since it would otherwise exclude every relay.
+impl Match<Relay> for Constraint<Vec<Provider>> {
+    fn matches(&self, relay: &Relay) -> bool {
+        match *self {
+            Constraint::Only(ref providers) if !providers.is_empty() => {
+                providers.contains(&relay.provider)
+            }
+            _ => true,
+        }
+    }
+}
+
+/// Restricts relay selection based on who owns the relay hardware.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Ownership {
+    /// Only relays running on servers owned by Mullvad.
+    MullvadOwned,
+    /// Only relays running on servers rented from the hosting provider.
+    Rented,
+    Any,
+}
+
+impl Default for Ownership {
+    fn default() -> Self {
+        Ownership::Any
+    }
+}
+
+impl Match<Relay> for Ownership {
+    fn matches(&self, relay: &Relay) -> bool {
+        match *self {
+            Ownership::MullvadOwned => relay.owned,
+            Ownership::Rented => !relay.owned,
+            Ownership::Any => true,
+        }
+    }
+}
+
+/// A transport protocol, optionally restricted to a single port, that the tunnel endpoint must
+/// use. WireGuard endpoints are always considered to use UDP.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Deserialize, Serialize)]
+pub struct TransportPort {
+    pub protocol: TransportProtocol,
+    #[serde(default)]
+    pub port: Constraint<u16>,
+}
+
+impl Match<OpenVpnEndpointData> for TransportPort {
+    fn matches(&self, endpoint: &OpenVpnEndpointData) -> bool {
+        self.protocol == endpoint.protocol && self.port.matches(&endpoint.port)
+    }
+}
+
+impl Match<WireguardEndpointData> for TransportPort {
+    fn matches(&self, endpoint: &WireguardEndpointData) -> bool {
+        self.protocol == TransportProtocol::Udp && self.port.matches(&endpoint.port)
+    }
+}
+
+impl Match<OpenVpnEndpointData> for Constraint<TransportPort> {
+    fn matches(&self, endpoint: &OpenVpnEndpointData) -> bool {
+        match *self {
+            Constraint::Any => true,
+            Constraint::Only(ref transport_port) => transport_port.matches(endpoint),
+        }
+    }
+}
+
+impl Match<WireguardEndpointData> for Constraint<TransportPort> {
+    fn matches(&self, endpoint: &WireguardEndpointData) -> bool {
+        match *self {
+            Constraint::Any => true,
+            Constraint::Only(ref transport_port) => transport_port.matches(endpoint),
+        }
+    }
+}
+
+/// The type of tunnel a relay must support to be selected.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash, Deserialize, Serialize)]
+pub enum TunnelProtocol {
+    #[serde(rename = "openvpn")]
+    OpenVpn,
+    #[serde(rename = "wireguard")]
+    Wireguard,
+    #[serde(rename = "any")]
+    Any,
+}
+
+impl TunnelProtocol {
+    /// Returns `default` if this is `TunnelProtocol::Any`, otherwise returns `self`.
+    pub fn or_preferred(self, default: TunnelProtocol) -> TunnelProtocol {
+        match self {
+            TunnelProtocol::Any => default,
+            protocol => protocol,
+        }
+    }
+}
+
+impl Default for TunnelProtocol {
+    fn default() -> Self {
+        TunnelProtocol::Any
+    }
+}
+
+/// Matches relays advertising at least one endpoint for the tunnel protocol.
+impl Match<Relay> for TunnelProtocol {
+    fn matches(&self, relay: &Relay) -> bool {
+        let has_openvpn = !relay.tunnels.openvpn.is_empty();
+        let has_wireguard = !relay.tunnels.wireguard.is_empty();
+        match *self {
+            TunnelProtocol::OpenVpn => has_openvpn,
+            TunnelProtocol::Wireguard => has_wireguard,
+            TunnelProtocol::Any => has_openvpn || has_wireguard,
         }
     }
 }
@@ -96,6 +457,34 @@ pub enum LocationConstraint {
     Country(CountryCode),
     /// A city is composed of a country code and a city code.
     City(CountryCode, CityCode),
+    /// A single relay is identified by its hostname, together with the codes of the country and
+    /// city it is located in.
+    Hostname(CountryCode, CityCode, Hostname),
+    /// The country with the relay closest to the user. Must be turned into a country with
+    /// `relay_list::resolve_nearest` before selecting a relay, since it matches no relay by itself.
+    /// The daemon does not do that yet, so it refuses to store this in its settings.
+    Nearest,
+}
+
+impl Match<Relay> for LocationConstraint {
+    fn matches(&self, relay: &Relay) -> bool {
+        match *self {
+            LocationConstraint::Country(ref country) => {
+                relay
+                    .location
+                    .as_ref()
+                    .map_or(false, |loc| loc.country_code == *country)
+                    && relay.include_in_country
+            }
+            LocationConstraint::City(ref country, ref city) => {
+                relay.location.as_ref().map_or(false, |loc| {
+                    loc.country_code == *country && loc.city_code == *city
+                })
+            }
+            LocationConstraint::Hostname(_, _, ref hostname) => relay.hostname == *hostname,
+            LocationConstraint::Nearest => false,
+        }
+    }
 }
 
 
@@ -149,6 +538,84 @@ impl Match<WireguardEndpointData> for WireguardConstraints {
 }
 
 
+/// Decides when the tunnel traffic is sent through a bridge, to get past networks blocking
+/// traffic to the relays.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BridgeState {
+    /// Use a bridge only when connecting directly to the relay keeps failing.
+    Auto,
+    /// Always use a bridge.
+    On,
+    /// Never use a bridge.
+    Off,
+}
+
+impl Default for BridgeState {
+    fn default() -> Self {
+        BridgeState::Off
+    }
+}
+
+impl BridgeState {
+    /// Returns true if connection attempt number `attempt`, counting from zero, should go through
+    /// a bridge. In `Auto`, the first two attempts connect directly and every other attempt after
+    /// that uses a bridge, so a broken bridge can't keep the tunnel from ever coming up.
+    pub fn use_bridge(&self, attempt: u32) -> bool {
+        match *self {
+            BridgeState::Auto => attempt >= 2 && attempt % 2 == 0,
+            BridgeState::On => true,
+            BridgeState::Off => false,
+        }
+    }
+}
+
+/// Where to get a bridge from.
+#[derive(Debug, Clone, Eq, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BridgeSettings {
+    /// Use a bridge running on one of the relays matching the constraints.
+    Normal(BridgeConstraints),
+    /// Use a proxy configured by the user.
+    Custom(ProxySettings),
+}
+
+impl Default for BridgeSettings {
+    fn default() -> Self {
+        BridgeSettings::Normal(BridgeConstraints::default())
+    }
+}
+
+/// Matches the relays that can be selected under this location constraint. Inactive relays never
+/// match, and relays excluded from their country only match when the location is constrained to
+/// their city or hostname. See `Relay::is_selectable`.
+impl Match<Relay> for Constraint<LocationConstraint> {
+    fn matches(&self, relay: &Relay) -> bool {
+        match *self {
+            Constraint::Any => relay.is_selectable(),
+            Constraint::Only(ref location) => relay.active && location.matches(relay),
+        }
+    }
+}
+
+/// Constraints for selecting the relay to use as a bridge.
+#[derive(Debug, Default, Clone, Eq, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct BridgeConstraints {
+    pub location: Constraint<LocationConstraint>,
+    #[serde(deserialize_with = "deserialize_vec_constraint")]
+    pub providers: Constraint<Vec<Provider>>,
+    pub ownership: Ownership,
+}
+
+impl Match<Relay> for BridgeConstraints {
+    fn matches(&self, relay: &Relay) -> bool {
+        !relay.bridges.is_empty() && self.location.matches(relay) && self.providers.matches(relay)
+            && self.ownership.matches(relay)
+    }
+}
+
+
 #[derive(Debug, Deserialize, Serialize)]
 #[serde(rename_all = "snake_case")]
 pub enum RelaySettingsUpdate {
@@ -161,4 +628,592 @@ pub enum RelaySettingsUpdate {
 pub struct RelayConstraintsUpdate {
     pub location: Option<Constraint<LocationConstraint>>,
     pub tunnel: Option<Constraint<TunnelConstraints>>,
+    pub providers: Option<Constraint<Vec<Provider>>>,
+    pub ownership: Option<Ownership>,
+    pub port: Option<Constraint<TransportPort>>,
+    pub tunnel_protocol: Option<TunnelProtocol>,
+    pub entry_location: Option<Constraint<LocationConstraint>>,
+    pub use_multihop: Option<bool>,
+    pub ipv6_only: Option<bool>,
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json;
+    use location::Location;
+    use relay_list::{BridgeEndpointData, RelayTunnels};
+
+    #[test]
+    fn display_constraint() {
+        assert_eq!(Constraint::<u16>::Any.to_string(), "any");
+        assert_eq!(Constraint::Only(1194u16).to_string(), "1194");
+        assert_eq!(Constraint::Only(TransportProtocol::Tcp).to_string(), "TCP");
+    }
+
+    #[test]
+    fn parse_constraint() {
+        assert_eq!("any".parse::<Constraint<u16>>(), Ok(Constraint::Any));
+        assert_eq!("ANY".parse::<Constraint<u16>>(), Ok(Constraint::Any));
+        assert_eq!("443".parse::<Constraint<u16>>(), Ok(Constraint::Only(443)));
+        assert_eq!(
+            "udp".parse::<Constraint<TransportProtocol>>(),
+            Ok(Constraint::Only(TransportProtocol::Udp))
+        );
+        assert!("anything".parse::<Constraint<u16>>().is_err());
+        assert!("65536".parse::<Constraint<u16>>().is_err());
+    }
+
+    fn relay_from(provider: &str) -> Relay {
+        relay(provider, false)
+    }
+
+    fn relay(provider: &str, owned: bool) -> Relay {
+        Relay {
+            hostname: "se1".to_owned(),
+            ipv4_addr_in: "10.0.0.1".parse().unwrap(),
+            ipv4_addr_exit: "10.0.0.2".parse().unwrap(),
+            ipv6_addr_in: None,
+            include_in_country: true,
+            weight: 100,
+            provider: Provider(provider.to_owned()),
+            owned,
+            active: true,
+            tunnels: RelayTunnels::default(),
+            bridges: vec![],
+            load: None,
+            location: None,
+        }
+    }
+
+    #[test]
+    fn provider_allow_list() {
+        let constraint = Constraint::Only(vec![
+            Provider("31173".to_owned()),
+            Provider("M247".to_owned()),
+        ]);
+
+        assert!(constraint.matches(&relay_from("31173")));
+        assert!(constraint.matches(&relay_from("M247")));
+        assert!(!constraint.matches(&relay_from("DataPacket")));
+        assert!(!constraint.matches(&relay_from("")));
+    }
+
+    #[test]
+    fn any_or_empty_provider_constraint_matches_all() {
+        let any: Constraint<Vec<Provider>> = Constraint::Any;
+        let empty: Constraint<Vec<Provider>> = Constraint::Only(vec![]);
+
+        for relay in &[relay_from("31173"), relay_from("")] {
+            assert!(any.matches(relay));
+            assert!(empty.matches(relay));
+        }
+    }
+
+    #[test]
+    fn missing_providers_deserialize_as_any() {
+        let json = r#"{"location": "any", "tunnel": "any"}"#;
+        let constraints: RelayConstraints = serde_json::from_str(json).unwrap();
+
+        assert_eq!(constraints.providers, Constraint::Any);
+        assert_eq!(constraints.ownership, Ownership::Any);
+        assert_eq!(constraints.port, Constraint::Any);
+        assert_eq!(constraints.tunnel_protocol, TunnelProtocol::Any);
+        assert_eq!(constraints.entry_location, Constraint::Any);
+        assert!(!constraints.use_multihop);
+    }
+
+    #[test]
+    fn duplicate_providers_are_dropped_on_deserialize() {
+        let json = r#"{
+            "location": "any",
+            "tunnel": "any",
+            "providers": {"only": ["31173", "M247", "31173"]}
+        }"#;
+        let constraints: RelayConstraints = serde_json::from_str(json).unwrap();
+        assert_eq!(
+            constraints.providers,
+            Constraint::Only(vec![Provider("31173".to_owned()), Provider("M247".to_owned())])
+        );
+
+        let json = r#"{"providers": {"only": ["M247", "M247"]}}"#;
+        let constraints: BridgeConstraints = serde_json::from_str(json).unwrap();
+        assert_eq!(
+            constraints.providers,
+            Constraint::Only(vec![Provider("M247".to_owned())])
+        );
+    }
+
+    #[test]
+    fn empty_providers_deserialize_as_any() {
+        let json = r#"{"location": "any", "tunnel": "any", "providers": {"only": []}}"#;
+        let constraints: RelayConstraints = serde_json::from_str(json).unwrap();
+        assert_eq!(constraints.providers, Constraint::Any);
+
+        let json = r#"{"providers": {"only": []}}"#;
+        let constraints: BridgeConstraints = serde_json::from_str(json).unwrap();
+        assert_eq!(constraints.providers, Constraint::Any);
+
+        let json = r#"{"providers": "any"}"#;
+        let constraints: BridgeConstraints = serde_json::from_str(json).unwrap();
+        assert_eq!(constraints.providers, Constraint::Any);
+    }
+
+    #[test]
+    fn ownership_constraint() {
+        let relays = [relay("31173", true), relay("M247", false), relay("31173", false)];
+        let matching = |ownership: Ownership| -> Vec<bool> {
+            relays.iter().map(|relay| ownership.matches(relay)).collect()
+        };
+
+        assert_eq!(matching(Ownership::MullvadOwned), [true, false, false]);
+        assert_eq!(matching(Ownership::Rented), [false, true, true]);
+        assert_eq!(matching(Ownership::Any), [true, true, true]);
+    }
+
+    #[test]
+    fn tcp_443_endpoint_is_selected() {
+        let tunnels = RelayTunnels {
+            openvpn: vec![
+                OpenVpnEndpointData {
+                    port: 1194,
+                    protocol: TransportProtocol::Udp,
+                },
+                OpenVpnEndpointData {
+                    port: 443,
+                    protocol: TransportProtocol::Udp,
+                },
+                OpenVpnEndpointData {
+                    port: 80,
+                    protocol: TransportProtocol::Tcp,
+                },
+                OpenVpnEndpointData {
+                    port: 443,
+                    protocol: TransportProtocol::Tcp,
+                },
+            ],
+            wireguard: vec![WireguardEndpointData { port: 443 }],
+        };
+        let constraint = Constraint::Only(TransportPort {
+            protocol: TransportProtocol::Tcp,
+            port: Constraint::Only(443),
+        });
+
+        let matching = tunnels.matching(&constraint);
+        assert_eq!(
+            matching.openvpn,
+            [OpenVpnEndpointData {
+                port: 443,
+                protocol: TransportProtocol::Tcp,
+            }]
+        );
+        assert!(matching.wireguard.is_empty());
+    }
+
+    #[test]
+    fn unsupported_port_matches_no_endpoint() {
+        let tunnels = RelayTunnels {
+            openvpn: vec![OpenVpnEndpointData {
+                port: 1194,
+                protocol: TransportProtocol::Udp,
+            }],
+            wireguard: vec![WireguardEndpointData { port: 51820 }],
+        };
+        let any_udp_port = Constraint::Only(TransportPort {
+            protocol: TransportProtocol::Udp,
+            port: Constraint::Any,
+        });
+        let udp_53 = Constraint::Only(TransportPort {
+            protocol: TransportProtocol::Udp,
+            port: Constraint::Only(53),
+        });
+
+        assert_eq!(tunnels.matching(&any_udp_port).openvpn.len(), 1);
+        assert_eq!(tunnels.matching(&any_udp_port).wireguard.len(), 1);
+        assert!(tunnels.matching(&udp_53).openvpn.is_empty());
+        assert!(tunnels.matching(&udp_53).wireguard.is_empty());
+    }
+
+    fn relay_with_tunnels(hostname: &str, openvpn: bool, wireguard: bool) -> Relay {
+        let mut relay = relay_from("31173");
+        relay.hostname = hostname.to_owned();
+        if openvpn {
+            relay.tunnels.openvpn.push(OpenVpnEndpointData {
+                port: 1194,
+                protocol: TransportProtocol::Udp,
+            });
+        }
+        if wireguard {
+            relay
+                .tunnels
+                .wireguard
+                .push(WireguardEndpointData { port: 51820 });
+        }
+        relay
+    }
+
+    #[test]
+    fn tunnel_protocol_constraint() {
+        let relays = [
+            relay_with_tunnels("openvpn", true, false),
+            relay_with_tunnels("wireguard", false, true),
+            relay_with_tunnels("both", true, true),
+            relay_with_tunnels("none", false, false),
+        ];
+        let matching = |protocol: TunnelProtocol| -> Vec<&str> {
+            relays
+                .iter()
+                .filter(|relay| protocol.matches(*relay))
+                .map(|relay| relay.hostname.as_str())
+                .collect()
+        };
+
+        assert_eq!(matching(TunnelProtocol::OpenVpn), ["openvpn", "both"]);
+        assert_eq!(matching(TunnelProtocol::Wireguard), ["wireguard", "both"]);
+        assert_eq!(matching(TunnelProtocol::Any), ["openvpn", "wireguard", "both"]);
+    }
+
+    fn gothenburg_relay() -> Relay {
+        let mut relay = relay_with_tunnels("se-got-001", true, true);
+        relay.tunnels.openvpn.push(OpenVpnEndpointData {
+            port: 443,
+            protocol: TransportProtocol::Tcp,
+        });
+        relay.location = Some(Location {
+            country: "Sweden".to_owned(),
+            country_code: "se".parse().unwrap(),
+            city: "Gothenburg".to_owned(),
+            city_code: "got".parse().unwrap(),
+            latitude: 57.7,
+            longitude: 11.97,
+            continent: None,
+        });
+        relay
+    }
+
+    #[test]
+    fn relay_constraints_match_each_dimension() {
+        let relay = gothenburg_relay();
+        let matches = |builder: &mut RelayConstraintsBuilder| builder.build().matches(&relay);
+        let country = |code: &str| LocationConstraint::Country(code.parse().unwrap());
+        let city = |country: &str, city: &str| {
+            LocationConstraint::City(country.parse().unwrap(), city.parse().unwrap())
+        };
+        let port = |protocol, port| TransportPort {
+            protocol,
+            port: Constraint::Only(port),
+        };
+        let provider = |name: &str| vec![Provider(name.to_owned())];
+
+        assert!(matches(&mut RelayConstraints::builder()));
+
+        assert!(matches(RelayConstraints::builder().location(country("se"))));
+        assert!(matches(RelayConstraints::builder().location(city("se", "got"))));
+        assert!(!matches(RelayConstraints::builder().location(country("de"))));
+        assert!(!matches(RelayConstraints::builder().location(city("se", "sto"))));
+        assert!(!matches(RelayConstraints::builder().location(LocationConstraint::Nearest)));
+
+        assert!(matches(RelayConstraints::builder().tunnel_protocol(TunnelProtocol::OpenVpn)));
+        assert!(matches(RelayConstraints::builder().tunnel_protocol(TunnelProtocol::Wireguard)));
+
+        let wireguard_port = |port| TunnelConstraints::Wireguard(WireguardConstraints { port });
+        assert!(matches(RelayConstraints::builder().tunnel(wireguard_port(Constraint::Any))));
+        assert!(!matches(RelayConstraints::builder().tunnel(wireguard_port(Constraint::Only(53)))));
+
+        assert!(matches(RelayConstraints::builder().port(port(TransportProtocol::Tcp, 443))));
+        assert!(matches(RelayConstraints::builder().port(port(TransportProtocol::Udp, 51820))));
+        assert!(!matches(RelayConstraints::builder().port(port(TransportProtocol::Udp, 443))));
+        assert!(!matches(RelayConstraints::builder().port(port(TransportProtocol::Tcp, 80))));
+
+        assert!(matches(RelayConstraints::builder().providers(provider("31173"))));
+        assert!(!matches(RelayConstraints::builder().providers(provider("M247"))));
+
+        assert!(matches(RelayConstraints::builder().ownership(Ownership::Rented)));
+        assert!(!matches(RelayConstraints::builder().ownership(Ownership::MullvadOwned)));
+    }
+
+    #[test]
+    fn relay_constraints_match_combined() {
+        let relay = gothenburg_relay();
+        let mut builder = RelayConstraints::builder();
+        builder
+            .location(LocationConstraint::Country("se".parse().unwrap()))
+            .providers(vec![Provider("31173".to_owned())])
+            .ownership(Ownership::Rented)
+            .tunnel_protocol(TunnelProtocol::OpenVpn)
+            .port(TransportPort {
+                protocol: TransportProtocol::Tcp,
+                port: Constraint::Only(443),
+            });
+        assert!(builder.build().matches(&relay));
+
+        // Each dimension is satisfied on its own, but there is no WireGuard endpoint on TCP 443.
+        builder.tunnel_protocol(TunnelProtocol::Wireguard);
+        assert!(!builder.build().matches(&relay));
+        builder.tunnel_protocol(TunnelProtocol::Any);
+        assert!(builder.build().matches(&relay));
+
+        builder.ownership(Ownership::MullvadOwned);
+        assert!(!builder.build().matches(&relay));
+        builder.ownership(Ownership::Any);
+
+        let mut inactive = relay.clone();
+        inactive.active = false;
+        assert!(!builder.build().matches(&inactive));
+        let mut excluded = relay.clone();
+        excluded.include_in_country = false;
+        assert!(!builder.build().matches(&excluded));
+        assert!(builder.build().matching_relay(&relay).is_some());
+        assert!(builder.build().matching_relay(&inactive).is_none());
+    }
+
+    #[test]
+    fn any_tunnel_protocol_uses_preferred_default() {
+        let default = TunnelProtocol::Wireguard;
+
+        assert_eq!(TunnelProtocol::Any.or_preferred(default), default);
+        assert_eq!(
+            TunnelProtocol::OpenVpn.or_preferred(default),
+            TunnelProtocol::OpenVpn
+        );
+    }
+
+    #[test]
+    fn bridge_state_per_attempt() {
+        let use_bridge = |state: BridgeState| -> Vec<bool> {
+            (0..7).map(|attempt| state.use_bridge(attempt)).collect()
+        };
+
+        assert_eq!(use_bridge(BridgeState::Off), [false; 7]);
+        assert_eq!(use_bridge(BridgeState::On), [true; 7]);
+        assert_eq!(
+            use_bridge(BridgeState::Auto),
+            [false, false, true, false, true, false, true]
+        );
+    }
+
+    #[test]
+    fn bridge_constraints_require_bridge_endpoints() {
+        let mut bridge = relay("31173", true);
+        bridge.bridges.push(BridgeEndpointData { port: 1080 });
+        let plain_relay = relay("31173", true);
+        let constraints = BridgeConstraints {
+            ownership: Ownership::MullvadOwned,
+            ..BridgeConstraints::default()
+        };
+
+        assert!(constraints.matches(&bridge));
+        assert!(!constraints.matches(&plain_relay));
+        bridge.owned = false;
+        assert!(!constraints.matches(&bridge));
+    }
+
+    #[test]
+    fn missing_bridge_settings_deserialize_as_defaults() {
+        let settings: BridgeSettings = serde_json::from_str(r#"{"normal": {}}"#).unwrap();
+        assert_eq!(settings, BridgeSettings::default());
+        assert_eq!(BridgeState::default(), BridgeState::Off);
+    }
+
+    #[test]
+    fn apply_partial_update() {
+        let mut constraints = RelayConstraints {
+            location: Constraint::Only(LocationConstraint::Country("se".parse().unwrap())),
+            tunnel: Constraint::Only(TunnelConstraints::OpenVpn(OpenVpnConstraints {
+                port: Constraint::Only(1194),
+                protocol: Constraint::Only(TransportProtocol::Udp),
+            })),
+            providers: Constraint::Only(vec![Provider("31173".to_owned())]),
+            ownership: Ownership::MullvadOwned,
+            port: Constraint::Any,
+            tunnel_protocol: TunnelProtocol::OpenVpn,
+            entry_location: Constraint::Only(LocationConstraint::Country("de".parse().unwrap())),
+            use_multihop: true,
+            ipv6_only: false,
+        };
+        let original = constraints.clone();
+
+        constraints.apply(RelayConstraintsUpdate {
+            location: Some(Constraint::Only(LocationConstraint::City(
+                "de".parse().unwrap(),
+                "fra".parse().unwrap(),
+            ))),
+            ..RelayConstraintsUpdate::default()
+        });
+        assert_eq!(
+            constraints.location,
+            Constraint::Only(LocationConstraint::City(
+                "de".parse().unwrap(),
+                "fra".parse().unwrap(),
+            ))
+        );
+        assert_eq!(constraints.tunnel, original.tunnel);
+        assert_eq!(constraints.providers, original.providers);
+        assert_eq!(constraints.ownership, original.ownership);
+        assert_eq!(constraints.port, original.port);
+        assert_eq!(constraints.tunnel_protocol, original.tunnel_protocol);
+        assert_eq!(constraints.entry_location, original.entry_location);
+        assert_eq!(constraints.use_multihop, original.use_multihop);
+        assert_eq!(constraints.ipv6_only, original.ipv6_only);
+
+        constraints.apply(RelayConstraintsUpdate {
+            providers: Some(Constraint::Any),
+            tunnel_protocol: Some(TunnelProtocol::Any),
+            ..RelayConstraintsUpdate::default()
+        });
+        assert_eq!(constraints.providers, Constraint::Any);
+        assert_eq!(constraints.tunnel_protocol, TunnelProtocol::Any);
+        assert_eq!(constraints.tunnel, original.tunnel);
+        assert_eq!(constraints.ownership, original.ownership);
+    }
+
+    #[test]
+    fn empty_update_changes_nothing() {
+        let constraints = RelayConstraints {
+            location: Constraint::Only(LocationConstraint::Country("se".parse().unwrap())),
+            ..RelayConstraints::default()
+        };
+        assert_eq!(
+            constraints.merge(RelayConstraintsUpdate::default()),
+            constraints
+        );
+    }
+
+    #[test]
+    fn equal_constraints_do_not_differ() {
+        let constraints = RelayConstraints {
+            location: Constraint::Only(LocationConstraint::Country("se".parse().unwrap())),
+            providers: Constraint::Only(vec![
+                Provider("31173".to_owned()),
+                Provider("DataPacket".to_owned()),
+            ]),
+            ..RelayConstraints::default()
+        };
+        assert!(!constraints.differs_from(&constraints.clone()));
+
+        let reordered_providers = RelayConstraints {
+            providers: Constraint::Only(vec![
+                Provider("DataPacket".to_owned()),
+                Provider("31173".to_owned()),
+            ]),
+            ..constraints.clone()
+        };
+        assert!(!constraints.differs_from(&reordered_providers));
+
+        let unused_entry_location = RelayConstraints {
+            entry_location: Constraint::Only(LocationConstraint::Country("de".parse().unwrap())),
+            ..constraints.clone()
+        };
+        assert_ne!(constraints, unused_entry_location);
+        assert!(!constraints.differs_from(&unused_entry_location));
+
+        let no_providers = RelayConstraints {
+            providers: Constraint::Only(vec![]),
+            ..RelayConstraints::default()
+        };
+        assert!(!no_providers.differs_from(&RelayConstraints::default()));
+    }
+
+    #[test]
+    fn differing_constraints() {
+        let constraints = RelayConstraints {
+            location: Constraint::Only(LocationConstraint::Country("se".parse().unwrap())),
+            ..RelayConstraints::default()
+        };
+        let changes = [
+            RelayConstraints {
+                location: Constraint::Any,
+                ..constraints.clone()
+            },
+            RelayConstraints {
+                location: Constraint::Only(LocationConstraint::City(
+                    "se".parse().unwrap(),
+                    "got".parse().unwrap(),
+                )),
+                ..constraints.clone()
+            },
+            RelayConstraints {
+                providers: Constraint::Only(vec![Provider("31173".to_owned())]),
+                ..constraints.clone()
+            },
+            RelayConstraints {
+                ownership: Ownership::MullvadOwned,
+                ..constraints.clone()
+            },
+            RelayConstraints {
+                use_multihop: true,
+                ..constraints.clone()
+            },
+        ];
+        for changed in changes.iter() {
+            assert!(constraints.differs_from(changed), "{:?}", changed);
+            assert!(changed.differs_from(&constraints), "{:?}", changed);
+        }
+
+        let multihop = RelayConstraints {
+            use_multihop: true,
+            ..constraints.clone()
+        };
+        let other_entry = RelayConstraints {
+            entry_location: Constraint::Only(LocationConstraint::Country("de".parse().unwrap())),
+            ..multihop.clone()
+        };
+        assert!(multihop.differs_from(&other_entry));
+    }
+
+    #[test]
+    fn builder_defaults_to_any() {
+        let constraints = RelayConstraints::builder()
+            .location(LocationConstraint::Country("se".parse().unwrap()))
+            .build();
+
+        assert_eq!(
+            constraints.location,
+            Constraint::Only(LocationConstraint::Country("se".parse().unwrap()))
+        );
+        assert_eq!(constraints.tunnel, Constraint::Any);
+        assert_eq!(constraints.providers, Constraint::Any);
+        assert_eq!(constraints.ownership, Ownership::Any);
+        assert_eq!(constraints.port, Constraint::Any);
+        assert_eq!(constraints.tunnel_protocol, TunnelProtocol::Any);
+        assert_eq!(constraints.entry_location, Constraint::Any);
+        assert!(!constraints.use_multihop);
+    }
+
+    #[test]
+    fn builder_sets_constraints() {
+        let constraints = RelayConstraints::builder()
+            .ownership(Ownership::MullvadOwned)
+            .tunnel_protocol(TunnelProtocol::Wireguard)
+            .multihop(Constraint::Only(LocationConstraint::Country(
+                "de".parse().unwrap(),
+            )))
+            .build();
+
+        assert_eq!(constraints.location, Constraint::Any);
+        assert_eq!(constraints.ownership, Ownership::MullvadOwned);
+        assert_eq!(constraints.tunnel_protocol, TunnelProtocol::Wireguard);
+        assert_eq!(
+            constraints.entry_location,
+            Constraint::Only(LocationConstraint::Country("de".parse().unwrap()))
+        );
+        assert!(constraints.use_multihop);
+    }
+
+    #[test]
+    fn missing_owned_field_means_rented() {
+        let json = r#"{
+            "hostname": "se1",
+            "ipv4_addr_in": "10.0.0.1",
+            "ipv4_addr_exit": "10.0.0.2",
+            "include_in_country": true,
+            "weight": 100,
+            "tunnels": {}
+        }"#;
+        let relay: Relay = serde_json::from_str(json).unwrap();
+
+        assert!(!relay.owned);
+        assert!(Ownership::Rented.matches(&relay));
+    }
 }
@@ -1,19 +1,697 @@
-use location::{CityCode, CountryCode, Location};
+use location::{CityCode, Continent, Coordinates, CountryCode, Hostname, Location};
+use relay_constraints::{BridgeSettings, Constraint, LocationConstraint, Match, RelayConstraints,
+                        TunnelProtocol};
+use ProxySettings;
 
-use std::net::Ipv4Addr;
+use chrono::{DateTime, TimeZone, Utc};
+use rand::Rng;
+use serde_json;
 
-use talpid_types::net::{OpenVpnEndpointData, WireguardEndpointData};
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
+use std::error;
+use std::ffi::OsString;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Write};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use talpid_types::net::{OpenVpnEndpointData, TunnelEndpoint, TunnelEndpointData,
+                        WireguardEndpointData};
+
+
+error_chain! {
+    errors {
+        ReadError(path: PathBuf) {
+            description("Unable to read relay list")
+            display("Unable to read relay list from {}", path.display())
+        }
+        ParseError(path: PathBuf) {
+            description("Unable to parse relay list")
+            display("Unable to parse relay list in {}", path.display())
+        }
+        WriteError(path: PathBuf) {
+            description("Unable to write relay list")
+            display("Unable to write relay list to {}", path.display())
+        }
+        UnknownCountry(country: String) {
+            description("No such country in the relay list")
+            display("No country named or with the code \"{}\" in the relay list", country)
+        }
+        UnknownCity(country: CountryCode, city: String) {
+            description("No such city in the relay list")
+            display("No city named or with the code \"{}\" in country {}", city, country)
+        }
+        UnknownHostname(hostname: String) {
+            description("No such relay in the relay list")
+            display("No relay with the hostname \"{}\" in the given location", hostname)
+        }
+        UnsupportedVersion(version: u64) {
+            description("Unsupported relay list format version")
+            display("Unsupported relay list format version: {}", version)
+        }
+        MalformedRelayList {
+            description("The relay list does not follow the expected format")
+        }
+    }
+}
+
+
+/// The version of the format relay lists are saved in. Saved relay lists without a version are
+/// version 0, which is from before relays had a provider and an ownership.
+pub const RELAY_LIST_VERSION: u64 = 1;
 
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct RelayList {
     pub countries: Vec<RelayListCountry>,
+    /// When this relay list was last saved to disk. Relay lists that were never saved have the
+    /// timestamp of the unix epoch.
+    #[serde(default = "unix_epoch")]
+    pub last_updated: DateTime<Utc>,
+}
+
+/// Used for serializing a `RelayList` with a new `last_updated` without cloning it.
+#[derive(Serialize)]
+struct SavedRelayList<'a> {
+    version: u64,
+    countries: &'a [RelayListCountry],
+    last_updated: DateTime<Utc>,
+}
+
+fn unix_epoch() -> DateTime<Utc> {
+    Utc.timestamp(0, 0)
 }
 
 impl RelayList {
     pub fn empty() -> Self {
         Self {
             countries: Vec::new(),
+            last_updated: unix_epoch(),
+        }
+    }
+
+    /// Reads a relay list stored as JSON at the given path. Relay lists saved in an older format
+    /// are migrated to the current one, and duplicated relays are removed.
+    pub fn load(path: &Path) -> Result<RelayList> {
+        let file = File::open(path).chain_err(|| ErrorKind::ReadError(path.to_owned()))?;
+        let relay_list = serde_json::from_reader(BufReader::new(file))
+            .chain_err(|| ErrorKind::ParseError(path.to_owned()))?;
+        let mut relay_list =
+            migrate(relay_list).chain_err(|| ErrorKind::ParseError(path.to_owned()))?;
+        relay_list.deduplicate();
+        Ok(relay_list)
+    }
+
+    /// Stores this relay list as JSON at the given path, with `last_updated` set to the current
+    /// time. The list is first written to a temporary file next to `path`, which is then renamed
+    /// to `path`, so a failed write never leaves a truncated relay list behind.
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let mut temp_file_name = path.file_name().map(OsString::from).unwrap_or_default();
+        temp_file_name.push(".tmp");
+        let temp_path = path.with_file_name(temp_file_name);
+
+        let saved_relay_list = SavedRelayList {
+            version: RELAY_LIST_VERSION,
+            countries: &self.countries,
+            last_updated: Utc::now(),
+        };
+        Self::write_json(&temp_path, &saved_relay_list)
+            .and_then(|_| fs::rename(&temp_path, path))
+            .chain_err(|| ErrorKind::WriteError(path.to_owned()))
+    }
+
+    fn write_json(path: &Path, relay_list: &SavedRelayList) -> io::Result<()> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        serde_json::to_writer_pretty(&mut writer, relay_list)?;
+        writer.flush()?;
+        writer.get_ref().sync_all()
+    }
+
+    /// Returns all relays matching the given constraints, with their location set and with only
+    /// the tunnel endpoints matching the constraints.
+    pub fn matching_relays(&self, constraints: &RelayConstraints) -> Vec<Relay> {
+        self.located_relays()
+            .iter()
+            .filter_map(|relay| constraints.matching_relay(relay))
+            .collect()
+    }
+
+    /// Returns all relays with their location set.
+    fn located_relays(&self) -> Vec<Relay> {
+        let mut relays = Vec::new();
+        for country in &self.countries {
+            for city in &country.cities {
+                let location = city.location(country);
+                for relay in &city.relays {
+                    let mut relay = relay.clone();
+                    relay.location = Some(location.clone());
+                    relays.push(relay);
+                }
+            }
+        }
+        relays
+    }
+
+    /// Picks a random relay matching the given constraints. The probability of a relay being
+    /// picked is proportional to its weight. Returns `None` if no relay with a non-zero weight
+    /// matches the constraints. See `select_result` for why no relay matched.
+    pub fn select<R: Rng>(&self, constraints: &RelayConstraints, rng: &mut R) -> Option<Relay> {
+        self.select_result(constraints, rng).ok()
+    }
+
+    /// Picks a relay like `select` does. If no relay can be picked, the error tells which
+    /// constraint ruled out the last relays. The constraints are applied in the order location,
+    /// providers, ownership and finally the tunnel, port and tunnel protocol constraints.
+    pub fn select_result<R: Rng>(
+        &self,
+        constraints: &RelayConstraints,
+        rng: &mut R,
+    ) -> ::std::result::Result<Relay, NoRelayReason> {
+        let mut relays = self.located_relays();
+        retain_or(&mut relays, NoRelayReason::NoMatchingLocation, |relay| {
+            constraints.location.matches(relay)
+        })?;
+        retain_or(&mut relays, NoRelayReason::NoMatchingProvider, |relay| {
+            constraints.providers.matches(relay)
+        })?;
+        retain_or(&mut relays, NoRelayReason::NoMatchingOwnership, |relay| {
+            constraints.ownership.matches(relay)
+        })?;
+        let relays: Vec<Relay> = relays
+            .iter()
+            .filter_map(|relay| constraints.matching_relay(relay))
+            .collect();
+        if relays.is_empty() {
+            return Err(NoRelayReason::NoMatchingProtocol);
+        }
+        pick_weighted_relay(&relays, rng)
+            .cloned()
+            .ok_or(NoRelayReason::AllFiltered)
+    }
+
+    /// Picks a relay like `select` does, together with one of its endpoints matching the
+    /// constraints.
+    pub fn select_with_endpoint<R: Rng>(
+        &self,
+        constraints: &RelayConstraints,
+        rng: &mut R,
+    ) -> Option<(Relay, TunnelEndpoint)> {
+        let relays = self.matching_relays(constraints);
+        let relay = pick_weighted_relay(&relays, rng)?;
+        let endpoint = *rng.choose(&relay.matching_endpoints(constraints))?;
+        Some((relay.clone(), endpoint))
+    }
+
+    /// Picks the relay with the lowest latency among the relays matching the given constraints,
+    /// using the latencies measured by the caller. Ties are broken by picking the relay with the
+    /// lowest hostname. If none of the matching relays has a latency sample, a relay is picked by
+    /// weight like `select` does. Relays with zero weight are never picked.
+    pub fn select_by_latency<R: Rng>(
+        &self,
+        constraints: &RelayConstraints,
+        latencies: &HashMap<Hostname, Duration>,
+        rng: &mut R,
+    ) -> Option<Relay> {
+        let relays: Vec<Relay> = self.matching_relays(constraints)
+            .into_iter()
+            .filter(|relay| relay.weight > 0)
+            .collect();
+        let fastest_relay = relays
+            .iter()
+            .filter_map(|relay| {
+                latencies
+                    .get(&relay.hostname)
+                    .map(|latency| (*latency, &relay.hostname, relay))
+            })
+            .min_by(|&(latency_a, hostname_a, _), &(latency_b, hostname_b, _)| {
+                latency_a
+                    .cmp(&latency_b)
+                    .then_with(|| hostname_a.cmp(hostname_b))
+            })
+            .map(|(_, _, relay)| relay);
+        match fastest_relay {
+            Some(relay) => Some(relay.clone()),
+            None => pick_weighted_relay(&relays, rng).cloned(),
+        }
+    }
+
+    /// Picks an exit relay like `select` does. If multihop is enabled in the constraints, an entry
+    /// relay matching `RelayConstraints::entry_constraints` is picked as well. The entry and exit
+    /// relays are never the same relay. Returns `None` if no such pair of relays exists.
+    pub fn select_entry_and_exit<R: Rng>(
+        &self,
+        constraints: &RelayConstraints,
+        rng: &mut R,
+    ) -> Option<(Option<Relay>, Relay)> {
+        if !constraints.use_multihop {
+            return self.select(constraints, rng).map(|exit| (None, exit));
+        }
+        let entries = self.matching_relays(&constraints.entry_constraints());
+        let other_entries = |relay: &Relay| -> Vec<Relay> {
+            entries
+                .iter()
+                .filter(|entry| entry.hostname != relay.hostname && entry.weight > 0)
+                .cloned()
+                .collect()
+        };
+        // Only consider exit relays that leave at least one other relay to enter through.
+        let exits: Vec<Relay> = self.matching_relays(constraints)
+            .into_iter()
+            .filter(|exit| !other_entries(exit).is_empty())
+            .collect();
+        let exit = pick_weighted_relay(&exits, rng)?.clone();
+        let entry = pick_weighted_relay(&other_entries(&exit), rng)?.clone();
+        Some((Some(entry), exit))
+    }
+
+    /// Picks a bridge to send the tunnel traffic through according to the given settings. See
+    /// `select_bridge`.
+    pub fn select_bridge<R: Rng>(
+        &self,
+        settings: &BridgeSettings,
+        rng: &mut R,
+    ) -> Option<ProxySettings> {
+        select_bridge(&self.located_relays(), settings, rng)
+    }
+
+    /// Returns the `n` relays closest to `to` that match the given constraints, sorted by
+    /// great-circle distance with the closest relay first. Relays without valid coordinates are
+    /// left out.
+    pub fn nearest(
+        &self,
+        to: &Coordinates,
+        constraints: &RelayConstraints,
+        n: usize,
+    ) -> Vec<Relay> {
+        let mut relays_by_distance: Vec<(f64, Relay)> = self.matching_relays(constraints)
+            .into_iter()
+            .filter_map(|relay| {
+                let coordinates = relay.location.as_ref().map(Location::coordinates);
+                match coordinates {
+                    Some(coordinates) if coordinates.is_valid() => {
+                        Some((coordinates.distance_to(to), relay))
+                    }
+                    _ => {
+                        warn!("Relay {} has no valid coordinates, ignoring it", relay.hostname);
+                        None
+                    }
+                }
+            })
+            .collect();
+        relays_by_distance.sort_by(|&(a, _), &(b, _)| {
+            a.partial_cmp(&b).unwrap_or(Ordering::Equal)
+        });
+        relays_by_distance
+            .into_iter()
+            .take(n)
+            .map(|(_, relay)| relay)
+            .collect()
+    }
+
+    /// Returns the tunnel protocols supported by at least one selectable relay in the given
+    /// location. `TunnelProtocol::Any` is never included. If no relay at all is in the location,
+    /// the protocols of the whole list are returned instead.
+    pub fn protocols_available(&self, location: &LocationConstraint) -> HashSet<TunnelProtocol> {
+        let relays = self.located_relays();
+        let location = if relays.iter().any(|relay| location.matches(relay)) {
+            Constraint::Only(location.clone())
+        } else {
+            Constraint::Any
+        };
+
+        let mut protocols = HashSet::new();
+        for relay in relays.iter().filter(|relay| location.matches(*relay)) {
+            for &protocol in [TunnelProtocol::OpenVpn, TunnelProtocol::Wireguard].iter() {
+                if protocol.matches(relay) {
+                    protocols.insert(protocol);
+                }
+            }
+        }
+        protocols
+    }
+
+    /// Turns a location given by a user into a `LocationConstraint` with the canonical codes from
+    /// this list. Countries and cities can be given either by code or by name, ignoring case. A
+    /// hostname must belong to a relay in the given country, and city if one is given.
+    pub fn location_constraint(
+        &self,
+        country: &str,
+        city: Option<&str>,
+        hostname: Option<&str>,
+    ) -> Result<LocationConstraint> {
+        let country = self.countries
+            .iter()
+            .find(|c| c.code == country.trim() || c.name.eq_ignore_ascii_case(country.trim()))
+            .ok_or_else(|| ErrorKind::UnknownCountry(country.to_owned()))?;
+        let cities: Vec<&RelayListCity> = match city {
+            Some(city) => {
+                let city = country
+                    .cities
+                    .iter()
+                    .find(|c| {
+                        c.code == city.trim() || c.name.eq_ignore_ascii_case(city.trim())
+                    })
+                    .ok_or_else(|| ErrorKind::UnknownCity(country.code.clone(), city.to_owned()))?;
+                vec![city]
+            }
+            None => country.cities.iter().collect(),
+        };
+        match (hostname, city) {
+            (Some(hostname), _) => cities
+                .iter()
+                .filter_map(|city| {
+                    city.relays
+                        .iter()
+                        .find(|relay| relay.hostname.eq_ignore_ascii_case(hostname))
+                        .map(|relay| {
+                            LocationConstraint::Hostname(
+                                country.code.clone(),
+                                city.code.clone(),
+                                relay.hostname.clone(),
+                            )
+                        })
+                })
+                .next()
+                .ok_or_else(|| ErrorKind::UnknownHostname(hostname.to_owned()).into()),
+            (None, Some(_)) => Ok(LocationConstraint::City(
+                country.code.clone(),
+                cities[0].code.clone(),
+            )),
+            (None, None) => Ok(LocationConstraint::Country(country.code.clone())),
+        }
+    }
+
+    /// Returns the relay with the given hostname, ignoring case.
+    pub fn find_by_hostname(&self, hostname: &str) -> Option<&Relay> {
+        self.relays().find(|relay| relay.hostname.eq_ignore_ascii_case(hostname))
+    }
+
+    /// Returns all relays with a hostname starting with `prefix`, ignoring case.
+    pub fn find_by_hostname_prefix(&self, prefix: &str) -> Vec<&Relay> {
+        let prefix = prefix.as_bytes();
+        self.relays()
+            .filter(|relay| {
+                let hostname = relay.hostname.as_bytes();
+                hostname.len() >= prefix.len()
+                    && hostname[..prefix.len()].eq_ignore_ascii_case(prefix)
+            })
+            .collect()
+    }
+
+    /// Overlays the countries, cities and relays of `other` onto this list. `other` is treated as
+    /// a partial update:
+    ///
+    /// * Relays are identified by hostname, ignoring case. A relay in `other` replaces the relay
+    ///   with the same hostname in this list, even if it has moved to another city. Relays not in
+    ///   this list are added.
+    /// * Countries are identified by country code and cities by city code within their country.
+    ///   Their names and coordinates are taken from `other`.
+    /// * Countries, cities and relays only present in this list are kept.
+    ///
+    /// To replace the whole list with a complete relay list, assign it instead of merging.
+    pub fn merge(&mut self, other: RelayList) {
+        {
+            let updated_hostnames: HashSet<String> = other
+                .relays()
+                .map(|relay| hostname_key(&relay.hostname))
+                .collect();
+            for city in self.countries
+                .iter_mut()
+                .flat_map(|country| country.cities.iter_mut())
+            {
+                city.relays
+                    .retain(|relay| !updated_hostnames.contains(&hostname_key(&relay.hostname)));
+            }
+        }
+
+        for other_country in other.countries {
+            let index = self.countries
+                .iter()
+                .position(|country| country.code == other_country.code);
+            match index {
+                Some(index) => self.countries[index].merge(other_country),
+                None => self.countries.push(other_country),
+            }
+        }
+    }
+
+    /// Removes relays with the same hostname, ignoring case, as a relay later in the list. A relay
+    /// listed more than once would otherwise be picked more often than its weight says.
+    pub fn deduplicate(&mut self) {
+        let mut last_positions = HashMap::new();
+        for (country_index, country) in self.countries.iter().enumerate() {
+            for (city_index, city) in country.cities.iter().enumerate() {
+                for (relay_index, relay) in city.relays.iter().enumerate() {
+                    last_positions.insert(
+                        hostname_key(&relay.hostname),
+                        (country_index, city_index, relay_index),
+                    );
+                }
+            }
+        }
+
+        for (country_index, country) in self.countries.iter_mut().enumerate() {
+            for (city_index, city) in country.cities.iter_mut().enumerate() {
+                let mut relay_index = 0;
+                city.relays.retain(|relay| {
+                    let position = (country_index, city_index, relay_index);
+                    relay_index += 1;
+                    let is_last = last_positions[&hostname_key(&relay.hostname)] == position;
+                    if !is_last {
+                        warn!("Dropping duplicate of relay {} from relay list", relay.hostname);
+                    }
+                    is_last
+                });
+            }
+        }
+    }
+
+    /// Returns a copy of this list without the relays that are more loaded than `max_load`. Relays
+    /// that do not report their load are kept. Countries and cities are kept even if all their
+    /// relays are removed.
+    pub fn filter_available(&self, max_load: f32) -> RelayList {
+        let mut relay_list = self.clone();
+        for city in relay_list
+            .countries
+            .iter_mut()
+            .flat_map(|country| country.cities.iter_mut())
+        {
+            city.relays.retain(|relay| relay.is_available(max_load));
+            city.has_active_relays = city.relays.iter().any(|relay| relay.active);
+        }
+        relay_list
+    }
+
+    /// Returns the number of active relays in the list. Active means selectable here, that is
+    /// both active and included in their country, so the count matches the sum of
+    /// `relays_per_country`. See `Relay::is_selectable`.
+    pub fn active_relay_count(&self) -> usize {
+        self.relays().filter(|relay| relay.is_selectable()).count()
+    }
+
+    /// Returns the number of countries having at least one selectable relay.
+    pub fn country_count(&self) -> usize {
+        self.relays_per_country().len()
+    }
+
+    /// Returns the number of selectable relays, for each country having at least one such relay.
+    /// See `Relay::is_selectable`.
+    pub fn relays_per_country(&self) -> HashMap<CountryCode, usize> {
+        let mut counts = HashMap::new();
+        for country in &self.countries {
+            let count = country
+                .cities
+                .iter()
+                .flat_map(|city| city.relays.iter())
+                .filter(|relay| relay.is_selectable())
+                .count();
+            if count > 0 {
+                *counts.entry(country.code.clone()).or_insert(0) += count;
+            }
+        }
+        counts
+    }
+
+    /// Returns an iterator over all relays in all cities of all countries.
+    fn relays<'a>(&'a self) -> Box<Iterator<Item = &'a Relay> + 'a> {
+        Box::new(
+            self.countries
+                .iter()
+                .flat_map(|country| country.cities.iter())
+                .flat_map(|city| city.relays.iter()),
+        )
+    }
+}
+
+/// Keeps only the relays matching `predicate`. Returns `reason` if no relay is left.
+fn retain_or<F>(
+    relays: &mut Vec<Relay>,
+    reason: NoRelayReason,
+    predicate: F,
+) -> ::std::result::Result<(), NoRelayReason>
+where
+    F: FnMut(&Relay) -> bool,
+{
+    relays.retain(predicate);
+    if relays.is_empty() {
+        Err(reason)
+    } else {
+        Ok(())
+    }
+}
+
+/// Why no relay could be selected. Tells the user which constraint to relax.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub enum NoRelayReason {
+    /// No selectable relay is in the constrained location.
+    NoMatchingLocation,
+    /// No relay in the location is hosted by any of the allowed providers.
+    NoMatchingProvider,
+    /// No relay in the location, from the allowed providers, has the required ownership.
+    NoMatchingOwnership,
+    /// No relay matching the other constraints has an endpoint allowed by the tunnel, port and
+    /// tunnel protocol constraints.
+    NoMatchingProtocol,
+    /// Relays match all constraints, but all of them have zero weight and are never picked.
+    AllFiltered,
+}
+
+impl fmt::Display for NoRelayReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::NoRelayReason::*;
+        let reason = match *self {
+            NoMatchingLocation => "No relays in the selected location",
+            NoMatchingProvider => "No relays from the selected providers in the location",
+            NoMatchingOwnership => "No relays with the selected ownership in the location",
+            NoMatchingProtocol => "No relays with the selected tunnel protocol and port",
+            AllFiltered => "All relays matching the constraints are disabled",
+        };
+        f.write_str(reason)
+    }
+}
+
+impl error::Error for NoRelayReason {
+    fn description(&self) -> &str {
+        "No relay matching the constraints"
+    }
+}
+
+/// Upgrades a relay list saved in any earlier format version to the current one, filling in
+/// defaults for the fields that did not exist in the older format, and deserializes it.
+pub fn migrate(mut relay_list: serde_json::Value) -> Result<RelayList> {
+    let version = match relay_list.get("version") {
+        None => 0,
+        Some(version) => version.as_u64().ok_or(ErrorKind::MalformedRelayList)?,
+    };
+    if version > RELAY_LIST_VERSION {
+        bail!(ErrorKind::UnsupportedVersion(version));
+    }
+    if version == 0 {
+        migrate_v0(&mut relay_list)?;
+    }
+    serde_json::from_value(relay_list).chain_err(|| ErrorKind::MalformedRelayList)
+}
+
+/// Adds the relay fields introduced in version 1 to a version 0 relay list. Relays from before
+/// these fields were added were all active and rented from an unnamed provider.
+fn migrate_v0(relay_list: &mut serde_json::Value) -> Result<()> {
+    let countries = relay_list
+        .get_mut("countries")
+        .and_then(serde_json::Value::as_array_mut)
+        .ok_or(ErrorKind::MalformedRelayList)?;
+    for country in countries {
+        let cities = match country
+            .get_mut("cities")
+            .and_then(serde_json::Value::as_array_mut)
+        {
+            Some(cities) => cities,
+            None => continue,
+        };
+        for city in cities {
+            let relays = match city
+                .get_mut("relays")
+                .and_then(serde_json::Value::as_array_mut)
+            {
+                Some(relays) => relays,
+                None => continue,
+            };
+            for relay in relays {
+                let relay = relay.as_object_mut().ok_or(ErrorKind::MalformedRelayList)?;
+                let defaults = [
+                    ("provider", json!("")),
+                    ("owned", json!(false)),
+                    ("active", json!(true)),
+                    ("tunnels", json!({})),
+                ];
+                for &(key, ref value) in defaults.iter() {
+                    if !relay.contains_key(key) {
+                        relay.insert(key.to_owned(), value.clone());
+                    }
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Resolves `LocationConstraint::Nearest` into the country of the selectable relay closest to
+/// `from`. If no relay in the list has valid coordinates, `LocationConstraint::Nearest` is
+/// returned as it is, and will not match any relay.
+pub fn resolve_nearest(relay_list: &RelayList, from: &Coordinates) -> LocationConstraint {
+    relay_list
+        .nearest(from, &RelayConstraints::default(), 1)
+        .into_iter()
+        .next()
+        .and_then(|relay| relay.location)
+        .map_or(LocationConstraint::Nearest, |location| {
+            LocationConstraint::Country(location.country_code)
+        })
+}
+
+/// Picks a random relay from the given slice, with a probability proportional to its weight.
+/// Returns `None` if the slice is empty or all relays in it have zero weight.
+pub fn pick_weighted_relay<'a, R: Rng>(relays: &'a [Relay], rng: &mut R) -> Option<&'a Relay> {
+    let total_weight: u64 = relays.iter().map(|relay| relay.weight).sum();
+    if total_weight == 0 {
+        return None;
+    }
+    let mut i = rng.gen_range(0, total_weight);
+    relays.iter().find(|relay| {
+        if i < relay.weight {
+            true
+        } else {
+            i -= relay.weight;
+            false
+        }
+    })
+}
+
+/// Picks a bridge among the given relays, which must have their location set to match location
+/// constraints. A relay bridge is picked with a probability proportional to the relay weight.
+/// Custom bridges are returned as they are. Returns `None` if no relay matches the constraints.
+pub fn select_bridge<R: Rng>(
+    relays: &[Relay],
+    settings: &BridgeSettings,
+    rng: &mut R,
+) -> Option<ProxySettings> {
+    match *settings {
+        BridgeSettings::Custom(ref proxy) => Some(proxy.clone()),
+        BridgeSettings::Normal(ref constraints) => {
+            let bridges: Vec<Relay> = relays
+                .iter()
+                .filter(|relay| constraints.matches(*relay))
+                .cloned()
+                .collect();
+            let relay = pick_weighted_relay(&bridges, rng)?;
+            let endpoint = rng.choose(&relay.bridges)?;
+            let address = SocketAddr::new(IpAddr::V4(relay.ipv4_addr_in), endpoint.port);
+            Some(ProxySettings::Socks5 {
+                address: address.to_string(),
+                auth: None,
+            })
         }
     }
 }
@@ -25,6 +703,27 @@ pub struct RelayListCountry {
     pub cities: Vec<RelayListCity>,
 }
 
+/// Returns what identifies a relay with the given hostname. Hostnames are not case sensitive, so
+/// relays whose hostnames only differ in case are the same relay.
+fn hostname_key(hostname: &str) -> String {
+    hostname.to_lowercase()
+}
+
+impl RelayListCountry {
+    fn merge(&mut self, other: RelayListCountry) {
+        self.name = other.name;
+        for other_city in other.cities {
+            let index = self.cities
+                .iter()
+                .position(|city| city.code == other_city.code);
+            match index {
+                Some(index) => self.cities[index].merge(other_city),
+                None => self.cities.push(other_city),
+            }
+        }
+    }
+}
+
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct RelayListCity {
     pub name: String,
@@ -37,21 +736,1509 @@ pub struct RelayListCity {
     pub relays: Vec<Relay>,
 }
 
+impl RelayListCity {
+    /// Takes the name and coordinates of `other`, and adds its relays. They replace the relays
+    /// with the same hostname, ignoring case.
+    fn merge(&mut self, other: RelayListCity) {
+        self.name = other.name;
+        self.latitude = other.latitude;
+        self.longitude = other.longitude;
+        for other_relay in other.relays {
+            let key = hostname_key(&other_relay.hostname);
+            self.relays
+                .retain(|relay| hostname_key(&relay.hostname) != key);
+            self.relays.push(other_relay);
+        }
+    }
+
+    /// Returns the location of this city, which is in the given country.
+    pub fn location(&self, country: &RelayListCountry) -> Location {
+        Location {
+            country: country.name.clone(),
+            country_code: country.code.clone(),
+            city: self.name.clone(),
+            city_code: self.code.clone(),
+            latitude: self.latitude,
+            longitude: self.longitude,
+            continent: Continent::from_country_code(country.code.as_str()),
+        }
+    }
+}
+
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct Relay {
     pub hostname: String,
     pub ipv4_addr_in: Ipv4Addr,
     pub ipv4_addr_exit: Ipv4Addr,
+    /// The address to connect to the relay on over IPv6. `None` for relays only accepting IPv4
+    /// connections, which is assumed for relay lists without this information.
+    #[serde(default)]
+    pub ipv6_addr_in: Option<Ipv6Addr>,
     pub include_in_country: bool,
     pub weight: u64,
+    #[serde(default)]
+    pub provider: Provider,
+    /// If the relay hardware is owned by Mullvad, as opposed to rented from the provider.
+    /// Relay lists without this information are assumed to describe rented relays.
+    #[serde(default)]
+    pub owned: bool,
+    /// If the relay is currently in service. Inactive relays are never selected. Relay lists
+    /// without this information are assumed to only contain active relays.
+    #[serde(default = "default_active")]
+    pub active: bool,
     pub tunnels: RelayTunnels,
+    /// The bridges running on this relay. Relay lists without this information are assumed to
+    /// have no bridges.
+    #[serde(default)]
+    pub bridges: Vec<BridgeEndpointData>,
+    /// The fraction of the relay capacity currently in use, from 0.0 for an idle relay to 1.0 for
+    /// a relay running at full capacity. `None` if the relay list does not report the load.
+    #[serde(default)]
+    pub load: Option<f32>,
     #[serde(skip)]
     pub location: Option<Location>,
 }
 
+impl Relay {
+    /// Returns all endpoints of this relay satisfying the tunnel, port and tunnel protocol
+    /// constraints. The other constraints, like the location, are not checked. The endpoints are
+    /// on the IPv4 address of the relay, or on its IPv6 address if the constraints are
+    /// `ipv6_only`, in which case relays without an IPv6 address have no endpoints.
+    pub fn matching_endpoints(&self, constraints: &RelayConstraints) -> Vec<TunnelEndpoint> {
+        let address = if constraints.ipv6_only {
+            match self.ipv6_addr_in {
+                Some(ipv6_addr_in) => IpAddr::V6(ipv6_addr_in),
+                None => return vec![],
+            }
+        } else {
+            IpAddr::V4(self.ipv4_addr_in)
+        };
+        let mut tunnels = self.tunnels.matching(&constraints.port);
+        if let Constraint::Only(ref tunnel_constraints) = constraints.tunnel {
+            tunnels = tunnels.matching(tunnel_constraints);
+        }
+        let openvpn = tunnels.openvpn.into_iter().map(TunnelEndpointData::OpenVpn);
+        let wireguard = tunnels
+            .wireguard
+            .into_iter()
+            .map(TunnelEndpointData::Wireguard);
+        let tunnels: Vec<TunnelEndpointData> = match constraints.tunnel_protocol {
+            TunnelProtocol::OpenVpn => openvpn.collect(),
+            TunnelProtocol::Wireguard => wireguard.collect(),
+            TunnelProtocol::Any => openvpn.chain(wireguard).collect(),
+        };
+        tunnels
+            .into_iter()
+            .map(|tunnel| TunnelEndpoint { address, tunnel })
+            .collect()
+    }
+
+    /// Returns true if the relay can be selected without constraining the location to its city or
+    /// to the relay itself. Inactive relays are never selected. Active relays that are not
+    /// included in their country are only selected when the location is constrained to their
+    /// city or hostname, so they are not selected when constraining to their country or when the
+    /// location is not constrained at all.
+    pub fn is_selectable(&self) -> bool {
+        self.active && self.include_in_country
+    }
+
+    /// Returns true unless the relay reports a load above `max_load`. Relays without load data
+    /// are assumed to be available.
+    pub fn is_available(&self, max_load: f32) -> bool {
+        self.load.map_or(true, |load| load <= max_load)
+    }
+}
+
+fn default_active() -> bool {
+    true
+}
+
+/// The name of the company hosting a relay. Relays from lists that predate this field have an
+/// empty provider name.
+#[derive(Debug, Default, Clone, Eq, PartialEq, Hash, PartialOrd, Ord, Deserialize, Serialize)]
+pub struct Provider(pub String);
+
+/// A SOCKS5 proxy running on a relay, that can be used as a bridge to the relays.
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Deserialize, Serialize)]
+pub struct BridgeEndpointData {
+    pub port: u16,
+}
+
 #[derive(Debug, Default, Clone, Deserialize, Serialize)]
 #[serde(default)]
 pub struct RelayTunnels {
     pub openvpn: Vec<OpenVpnEndpointData>,
     pub wireguard: Vec<WireguardEndpointData>,
 }
+
+impl RelayTunnels {
+    /// Returns a new `RelayTunnels` with only the endpoints matching the given constraint.
+    pub fn matching<M>(&self, constraint: &M) -> RelayTunnels
+    where
+        M: Match<OpenVpnEndpointData> + Match<WireguardEndpointData>,
+    {
+        RelayTunnels {
+            openvpn: self.openvpn
+                .iter()
+                .filter(|endpoint| constraint.matches(*endpoint))
+                .cloned()
+                .collect(),
+            wireguard: self.wireguard
+                .iter()
+                .filter(|endpoint| constraint.matches(*endpoint))
+                .cloned()
+                .collect(),
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    extern crate tempdir;
+
+    use super::*;
+    use relay_constraints::{BridgeConstraints, Ownership, RelayConstraintsBuilder, TransportPort};
+
+    use self::tempdir::TempDir;
+    use rand::{SeedableRng, XorShiftRng};
+    use serde_json;
+    use std::io::Write;
+    use talpid_types::net::TransportProtocol;
+
+    fn relay(hostname: &str, weight: u64) -> Relay {
+        Relay {
+            hostname: hostname.to_owned(),
+            ipv4_addr_in: "10.0.0.1".parse().unwrap(),
+            ipv4_addr_exit: "10.0.0.2".parse().unwrap(),
+            ipv6_addr_in: None,
+            include_in_country: true,
+            weight,
+            provider: Provider::default(),
+            owned: false,
+            active: true,
+            tunnels: RelayTunnels {
+                openvpn: vec![OpenVpnEndpointData {
+                    port: 1194,
+                    protocol: TransportProtocol::Udp,
+                }],
+                wireguard: vec![],
+            },
+            bridges: vec![],
+            load: None,
+            location: None,
+        }
+    }
+
+    fn city(name: &str, code: &str, relays: Vec<Relay>) -> RelayListCity {
+        located_city(name, code, (0.0, 0.0), relays)
+    }
+
+    fn located_city(
+        name: &str,
+        code: &str,
+        (latitude, longitude): (f64, f64),
+        relays: Vec<Relay>,
+    ) -> RelayListCity {
+        RelayListCity {
+            name: name.to_owned(),
+            code: code.parse().unwrap(),
+            latitude,
+            longitude,
+            has_active_relays: !relays.is_empty(),
+            relays,
+        }
+    }
+
+    fn relay_list() -> RelayList {
+        RelayList {
+            last_updated: unix_epoch(),
+            countries: vec![
+                RelayListCountry {
+                    name: "Sweden".to_owned(),
+                    code: "se".parse().unwrap(),
+                    cities: vec![
+                        city(
+                            "Gothenburg",
+                            "got",
+                            vec![relay("se-got-001", 100), relay("se-got-002", 300)],
+                        ),
+                        city(
+                            "Stockholm",
+                            "sto",
+                            vec![relay("se-sto-001", 0), relay("se-sto-002", 600)],
+                        ),
+                    ],
+                },
+                RelayListCountry {
+                    name: "Germany".to_owned(),
+                    code: "de".parse().unwrap(),
+                    cities: vec![city("Frankfurt", "fra", vec![relay("de-fra-001", 1000)])],
+                },
+            ],
+        }
+    }
+
+    fn sweden() -> RelayConstraints {
+        RelayConstraints {
+            location: Constraint::Only(LocationConstraint::Country("se".parse().unwrap())),
+            ..RelayConstraints::default()
+        }
+    }
+
+    #[test]
+    fn select_result_tells_why_no_relay_matched() {
+        let relay_list = relay_list();
+        let mut rng = XorShiftRng::from_seed([1, 2, 3, 4]);
+        let mut select = |builder: &mut RelayConstraintsBuilder| {
+            relay_list
+                .select_result(&builder.build(), &mut rng)
+                .map(|relay| relay.hostname)
+        };
+        let country = |code: &str| LocationConstraint::Country(code.parse().unwrap());
+
+        assert_eq!(
+            select(RelayConstraints::builder().location(country("no"))),
+            Err(NoRelayReason::NoMatchingLocation)
+        );
+        assert_eq!(
+            select(RelayConstraints::builder().providers(vec![Provider("M247".to_owned())])),
+            Err(NoRelayReason::NoMatchingProvider)
+        );
+        assert_eq!(
+            select(RelayConstraints::builder().ownership(Ownership::MullvadOwned)),
+            Err(NoRelayReason::NoMatchingOwnership)
+        );
+        assert_eq!(
+            select(RelayConstraints::builder().tunnel_protocol(TunnelProtocol::Wireguard)),
+            Err(NoRelayReason::NoMatchingProtocol)
+        );
+        let zero_weight_relay = LocationConstraint::Hostname(
+            "se".parse().unwrap(),
+            "sto".parse().unwrap(),
+            "se-sto-001".to_owned(),
+        );
+        assert_eq!(
+            select(RelayConstraints::builder().location(zero_weight_relay)),
+            Err(NoRelayReason::AllFiltered)
+        );
+        assert_eq!(
+            select(RelayConstraints::builder().location(country("de"))),
+            Ok("de-fra-001".to_owned())
+        );
+    }
+
+    fn dual_stack(mut relay: Relay, ipv6_addr_in: &str) -> Relay {
+        relay.ipv6_addr_in = Some(ipv6_addr_in.parse().unwrap());
+        relay
+    }
+
+    #[test]
+    fn ipv6_only_selects_dual_stack_relays() {
+        let mut relay_list = relay_list();
+        relay_list.countries[0].cities[0].relays[1] =
+            dual_stack(relay("se-got-002", 300), "2001:db8::2");
+        relay_list.countries[1].cities[0].relays =
+            vec![dual_stack(relay("de-fra-001", 1000), "2001:db8::3")];
+        let mut rng = XorShiftRng::from_seed([1, 2, 3, 4]);
+        let ipv6_only = RelayConstraints::builder().ipv6_only(true).build();
+
+        assert_eq!(
+            hostnames(&relay_list.matching_relays(&ipv6_only)),
+            ["se-got-002", "de-fra-001"]
+        );
+        for _ in 0..100 {
+            let (relay, endpoint) = relay_list
+                .select_with_endpoint(&ipv6_only, &mut rng)
+                .unwrap();
+            assert_eq!(endpoint.address, IpAddr::V6(relay.ipv6_addr_in.unwrap()));
+        }
+
+        // Dual stack relays are connected to over IPv4 unless IPv6 is required.
+        let germany = RelayConstraints::builder()
+            .location(LocationConstraint::Country("de".parse().unwrap()))
+            .build();
+        let (_, endpoint) = relay_list
+            .select_with_endpoint(&germany, &mut rng)
+            .unwrap();
+        assert_eq!(endpoint.address, "10.0.0.1".parse::<IpAddr>().unwrap());
+        assert_eq!(relay_list.matching_relays(&RelayConstraints::default()).len(), 5);
+
+        let sweden_ipv6 = RelayConstraints {
+            ipv6_only: true,
+            ..sweden()
+        };
+        let stockholm_ipv6 = RelayConstraints {
+            location: Constraint::Only(LocationConstraint::City(
+                "se".parse().unwrap(),
+                "sto".parse().unwrap(),
+            )),
+            ..sweden_ipv6.clone()
+        };
+        assert_eq!(
+            relay_list.select(&sweden_ipv6, &mut rng).unwrap().hostname,
+            "se-got-002"
+        );
+        assert!(relay_list.select(&stockholm_ipv6, &mut rng).is_none());
+    }
+
+    #[test]
+    fn missing_ipv6_address_means_ipv4_only() {
+        let json = r#"{
+            "hostname": "se1",
+            "ipv4_addr_in": "10.0.0.1",
+            "ipv4_addr_exit": "10.0.0.2",
+            "include_in_country": true,
+            "weight": 100,
+            "tunnels": {"openvpn": [], "wireguard": []}
+        }"#;
+        let relay: Relay = serde_json::from_str(json).unwrap();
+        assert_eq!(relay.ipv6_addr_in, None);
+
+        let relay = dual_stack(relay, "2001:db8::1");
+        let json = serde_json::to_string(&relay).unwrap();
+        let deserialized: Relay = serde_json::from_str(&json).unwrap();
+        assert_eq!(deserialized.ipv6_addr_in, relay.ipv6_addr_in);
+    }
+
+    #[test]
+    fn selection_follows_weights() {
+        let relay_list = relay_list();
+        let constraints = sweden();
+        let mut rng = XorShiftRng::from_seed([1, 2, 3, 4]);
+        let draws = 10000;
+
+        let mut counts = ::std::collections::HashMap::new();
+        for _ in 0..draws {
+            let relay = relay_list.select(&constraints, &mut rng).unwrap();
+            *counts.entry(relay.hostname).or_insert(0) += 1;
+        }
+
+        let share = |hostname: &str| {
+            counts.get(hostname).cloned().unwrap_or(0) as f64 / draws as f64
+        };
+        assert!((share("se-got-001") - 0.1).abs() < 0.02);
+        assert!((share("se-got-002") - 0.3).abs() < 0.02);
+        assert!((share("se-sto-002") - 0.6).abs() < 0.02);
+        assert_eq!(share("se-sto-001"), 0.0);
+        assert_eq!(share("de-fra-001"), 0.0);
+    }
+
+    #[test]
+    fn selection_is_deterministic_with_seeded_rng() {
+        let relay_list = relay_list();
+        let constraints = RelayConstraints::default();
+        let mut rng1 = XorShiftRng::from_seed([5, 6, 7, 8]);
+        let mut rng2 = XorShiftRng::from_seed([5, 6, 7, 8]);
+
+        for _ in 0..100 {
+            assert_eq!(
+                relay_list.select(&constraints, &mut rng1).unwrap().hostname,
+                relay_list.select(&constraints, &mut rng2).unwrap().hostname
+            );
+        }
+    }
+
+    #[test]
+    fn selection_without_weight_fails() {
+        let relay_list = relay_list();
+        let constraints = RelayConstraints {
+            location: Constraint::Only(LocationConstraint::City(
+                "se".parse().unwrap(),
+                "sto".parse().unwrap(),
+            )),
+            ..RelayConstraints::default()
+        };
+        let mut rng = XorShiftRng::from_seed([1, 2, 3, 4]);
+
+        let selected = relay_list.select(&constraints, &mut rng).unwrap();
+        assert_eq!(selected.hostname, "se-sto-002");
+        assert_eq!(selected.location.unwrap().city, "Stockholm");
+
+        let relays = [relay("se-sto-001", 0)];
+        assert!(pick_weighted_relay(&relays, &mut rng).is_none());
+        assert!(pick_weighted_relay(&[], &mut rng).is_none());
+    }
+
+    fn hostnames(relays: &[Relay]) -> Vec<&str> {
+        relays.iter().map(|relay| relay.hostname.as_str()).collect()
+    }
+
+    #[test]
+    fn nearest_relays_are_sorted_by_distance() {
+        let relay_list = RelayList {
+            last_updated: unix_epoch(),
+            countries: vec![
+                RelayListCountry {
+                    name: "Sweden".to_owned(),
+                    code: "se".parse().unwrap(),
+                    cities: vec![
+                        located_city(
+                            "Gothenburg",
+                            "got",
+                            (57.70887, 11.97456),
+                            vec![relay("se-got-001", 100)],
+                        ),
+                        located_city(
+                            "Stockholm",
+                            "sto",
+                            (59.3289, 18.0649),
+                            vec![relay("se-sto-001", 100), relay("se-sto-002", 100)],
+                        ),
+                        located_city(
+                            "Malmö",
+                            "mma",
+                            (55.607075, 13.002716),
+                            vec![relay("se-mma-001", 100)],
+                        ),
+                    ],
+                },
+                RelayListCountry {
+                    name: "Germany".to_owned(),
+                    code: "de".parse().unwrap(),
+                    cities: vec![located_city(
+                        "Frankfurt",
+                        "fra",
+                        (50.110924, 8.682127),
+                        vec![relay("de-fra-001", 100)],
+                    )],
+                },
+            ],
+        };
+        let copenhagen = Coordinates {
+            latitude: 55.676098,
+            longitude: 12.568337,
+        };
+
+        let nearest = relay_list.nearest(&copenhagen, &RelayConstraints::default(), 3);
+        assert_eq!(hostnames(&nearest), ["se-mma-001", "se-got-001", "se-sto-001"]);
+
+        let nearest = relay_list.nearest(&copenhagen, &RelayConstraints::default(), 10);
+        assert_eq!(
+            hostnames(&nearest),
+            ["se-mma-001", "se-got-001", "se-sto-001", "se-sto-002", "de-fra-001"]
+        );
+
+        let nearest = relay_list.nearest(&copenhagen, &sweden(), 10);
+        assert_eq!(nearest.len(), 4);
+    }
+
+    #[test]
+    fn resolve_nearest_country() {
+        let relay_list = RelayList {
+            last_updated: unix_epoch(),
+            countries: vec![
+                RelayListCountry {
+                    name: "Sweden".to_owned(),
+                    code: "se".parse().unwrap(),
+                    cities: vec![
+                        located_city("Malmö", "mma", (55.6, 13.0), vec![relay("se-mma-001", 1)]),
+                    ],
+                },
+                RelayListCountry {
+                    name: "Finland".to_owned(),
+                    code: "fi".parse().unwrap(),
+                    cities: vec![located_city(
+                        "Helsinki",
+                        "hel",
+                        (60.2, 24.9),
+                        vec![excluded_from_country(relay("fi-hel-001", 1))],
+                    )],
+                },
+                RelayListCountry {
+                    name: "Germany".to_owned(),
+                    code: "de".parse().unwrap(),
+                    cities: vec![
+                        located_city("Frankfurt", "fra", (50.1, 8.7), vec![relay("de-fra-001", 1)]),
+                    ],
+                },
+            ],
+        };
+        let resolve = |latitude, longitude| {
+            resolve_nearest(
+                &relay_list,
+                &Coordinates {
+                    latitude,
+                    longitude,
+                },
+            )
+        };
+        let country = |code: &str| LocationConstraint::Country(code.parse().unwrap());
+
+        // Copenhagen and Munich
+        assert_eq!(resolve(55.7, 12.6), country("se"));
+        assert_eq!(resolve(48.1, 11.6), country("de"));
+        // Relays excluded from their country are never the nearest.
+        assert_eq!(resolve(60.2, 24.9), country("se"));
+        let copenhagen = Coordinates {
+            latitude: 55.7,
+            longitude: 12.6,
+        };
+        assert_eq!(
+            resolve_nearest(&RelayList::empty(), &copenhagen),
+            LocationConstraint::Nearest
+        );
+
+        let nearest = RelayConstraints::builder()
+            .location(LocationConstraint::Nearest)
+            .build();
+        assert!(relay_list.matching_relays(&nearest).is_empty());
+    }
+
+    #[test]
+    fn relays_without_valid_coordinates_are_excluded() {
+        let relay_list = RelayList {
+            last_updated: unix_epoch(),
+            countries: vec![RelayListCountry {
+                name: "Sweden".to_owned(),
+                code: "se".parse().unwrap(),
+                cities: vec![
+                    located_city("Nowhere", "now", (::std::f64::NAN, 0.0), vec![relay("a", 1)]),
+                    located_city("Gothenburg", "got", (57.7, 11.9), vec![relay("b", 1)]),
+                ],
+            }],
+        };
+        let origin = Coordinates {
+            latitude: 0.0,
+            longitude: 0.0,
+        };
+
+        let nearest = relay_list.nearest(&origin, &RelayConstraints::default(), 2);
+        assert_eq!(hostnames(&nearest), ["b"]);
+    }
+
+    #[test]
+    fn find_by_exact_hostname() {
+        let relay_list = relay_list();
+
+        assert_eq!(
+            relay_list.find_by_hostname("se-sto-002").unwrap().hostname,
+            "se-sto-002"
+        );
+        assert!(relay_list.find_by_hostname("se-sto-00").is_none());
+        assert!(relay_list.find_by_hostname("se-sto-0020").is_none());
+    }
+
+    #[test]
+    fn location_constraint_from_codes_and_names() {
+        let relay_list = relay_list();
+        let country = |country: &str| LocationConstraint::Country(country.parse().unwrap());
+        let city = |country: &str, city: &str| {
+            LocationConstraint::City(country.parse().unwrap(), city.parse().unwrap())
+        };
+
+        let locations = [
+            ("se", None, country("se")),
+            ("SE", None, country("se")),
+            ("sweden", None, country("se")),
+            ("Germany", None, country("de")),
+            ("se", Some("got"), city("se", "got")),
+            ("Sweden", Some("GOT"), city("se", "got")),
+            ("se", Some("gothenburg"), city("se", "got")),
+            ("SWEDEN", Some("Stockholm"), city("se", "sto")),
+        ];
+        for &(country, city, ref expected) in locations.iter() {
+            assert_eq!(
+                relay_list.location_constraint(country, city, None).unwrap(),
+                *expected
+            );
+        }
+    }
+
+    #[test]
+    fn location_constraint_with_hostname() {
+        let relay_list = relay_list();
+        let expected = LocationConstraint::Hostname(
+            "se".parse().unwrap(),
+            "sto".parse().unwrap(),
+            "se-sto-002".to_owned(),
+        );
+
+        assert_eq!(
+            relay_list
+                .location_constraint("Sweden", Some("sto"), Some("SE-STO-002"))
+                .unwrap(),
+            expected
+        );
+        assert_eq!(
+            relay_list
+                .location_constraint("se", None, Some("se-sto-002"))
+                .unwrap(),
+            expected
+        );
+    }
+
+    fn lookup_error(country: &str, city: Option<&str>, hostname: Option<&str>) -> ErrorKind {
+        match relay_list().location_constraint(country, city, hostname) {
+            Ok(location) => panic!("Expected lookup to fail, got {:?}", location),
+            Err(Error(kind, _)) => kind,
+        }
+    }
+
+    #[test]
+    fn unknown_locations_are_rejected() {
+        match lookup_error("no", None, None) {
+            ErrorKind::UnknownCountry(ref country) => assert_eq!(country, "no"),
+            kind => panic!("Unexpected error: {}", kind),
+        }
+        match lookup_error("sweden", Some("fra"), None) {
+            ErrorKind::UnknownCity(ref country, ref city) => {
+                assert_eq!((country.as_str(), city.as_str()), ("se", "fra"))
+            }
+            kind => panic!("Unexpected error: {}", kind),
+        }
+        match lookup_error("se", Some("got"), Some("se-sto-001")) {
+            ErrorKind::UnknownHostname(ref hostname) => assert_eq!(hostname, "se-sto-001"),
+            kind => panic!("Unexpected error: {}", kind),
+        }
+    }
+
+    #[test]
+    fn multihop_entry_and_exit_differ() {
+        let relay_list = relay_list();
+        let constraints = RelayConstraints {
+            location: Constraint::Only(LocationConstraint::Country("se".parse().unwrap())),
+            entry_location: Constraint::Only(LocationConstraint::Country("se".parse().unwrap())),
+            use_multihop: true,
+            ..RelayConstraints::default()
+        };
+        let mut rng = XorShiftRng::from_seed([1, 2, 3, 4]);
+
+        for _ in 0..100 {
+            let (entry, exit) = relay_list
+                .select_entry_and_exit(&constraints, &mut rng)
+                .unwrap();
+            let entry = entry.unwrap();
+            assert_ne!(entry.hostname, exit.hostname);
+            assert_eq!(entry.location.unwrap().country_code, "se");
+            assert_eq!(exit.location.unwrap().country_code, "se");
+        }
+    }
+
+    #[test]
+    fn multihop_requires_two_distinct_relays() {
+        let relay_list = relay_list();
+        let germany = Constraint::Only(LocationConstraint::Country("de".parse().unwrap()));
+        let mut constraints = RelayConstraints {
+            location: germany.clone(),
+            entry_location: germany,
+            use_multihop: true,
+            ..RelayConstraints::default()
+        };
+        let mut rng = XorShiftRng::from_seed([1, 2, 3, 4]);
+
+        assert!(
+            relay_list
+                .select_entry_and_exit(&constraints, &mut rng)
+                .is_none()
+        );
+
+        constraints.entry_location = Constraint::Any;
+        let (entry, exit) = relay_list
+            .select_entry_and_exit(&constraints, &mut rng)
+            .unwrap();
+        assert_eq!(exit.hostname, "de-fra-001");
+        assert!(entry.unwrap().hostname.starts_with("se-"));
+    }
+
+    #[test]
+    fn single_hop_ignores_entry_location() {
+        let relay_list = relay_list();
+        let constraints = RelayConstraints {
+            entry_location: Constraint::Only(LocationConstraint::Country("no".parse().unwrap())),
+            ..sweden()
+        };
+        let mut rng1 = XorShiftRng::from_seed([5, 6, 7, 8]);
+        let mut rng2 = XorShiftRng::from_seed([5, 6, 7, 8]);
+
+        for _ in 0..100 {
+            let (entry, exit) = relay_list
+                .select_entry_and_exit(&constraints, &mut rng1)
+                .unwrap();
+            assert!(entry.is_none());
+            assert_eq!(
+                exit.hostname,
+                relay_list.select(&sweden(), &mut rng2).unwrap().hostname
+            );
+        }
+    }
+
+    fn relay_list_with_bridge() -> RelayList {
+        let mut relay_list = relay_list();
+        relay_list.countries[0].cities[0].relays[1]
+            .bridges
+            .push(BridgeEndpointData { port: 1080 });
+        relay_list
+    }
+
+    #[test]
+    fn select_relay_bridge() {
+        let relay_list = relay_list_with_bridge();
+        let mut rng = XorShiftRng::from_seed([1, 2, 3, 4]);
+
+        let bridge = relay_list
+            .select_bridge(&BridgeSettings::default(), &mut rng)
+            .unwrap();
+        assert_eq!(
+            bridge,
+            ProxySettings::Socks5 {
+                address: "10.0.0.1:1080".to_owned(),
+                auth: None,
+            }
+        );
+    }
+
+    #[test]
+    fn no_bridge_outside_location() {
+        let relay_list = relay_list_with_bridge();
+        let mut rng = XorShiftRng::from_seed([1, 2, 3, 4]);
+        let settings = BridgeSettings::Normal(BridgeConstraints {
+            location: Constraint::Only(LocationConstraint::Country("de".parse().unwrap())),
+            ..BridgeConstraints::default()
+        });
+
+        assert_eq!(relay_list.select_bridge(&settings, &mut rng), None);
+    }
+
+    #[test]
+    fn custom_bridge_is_used_as_is() {
+        let proxy = ProxySettings::Socks5 {
+            address: "192.0.2.1:1080".to_owned(),
+            auth: Some(("user".to_owned(), "pass".to_owned())),
+        };
+        let mut rng = XorShiftRng::from_seed([1, 2, 3, 4]);
+
+        assert_eq!(
+            relay_list().select_bridge(&BridgeSettings::Custom(proxy.clone()), &mut rng),
+            Some(proxy)
+        );
+    }
+
+    fn with_load(mut relay: Relay, load: f32) -> Relay {
+        relay.load = Some(load);
+        relay
+    }
+
+    #[test]
+    fn filter_available_drops_overloaded_relays() {
+        let mut relay_list = relay_list();
+        relay_list.countries[0].cities[0].relays = vec![
+            with_load(relay("se-got-001", 100), 0.0),
+            with_load(relay("se-got-002", 100), 0.5),
+            with_load(relay("se-got-003", 100), 0.8),
+            with_load(relay("se-got-004", 100), 0.95),
+            relay("se-got-005", 100),
+        ];
+        relay_list.countries[1].cities[0].relays = vec![with_load(relay("de-fra-001", 100), 1.0)];
+
+        let available = relay_list.filter_available(0.8);
+
+        assert_eq!(
+            city_hostnames(&available, "got"),
+            ["se-got-001", "se-got-002", "se-got-003", "se-got-005"]
+        );
+        assert!(city_hostnames(&available, "fra").is_empty());
+        assert!(!available.countries[1].cities[0].has_active_relays);
+        assert_eq!(city_hostnames(&relay_list, "got").len(), 5);
+    }
+
+    #[test]
+    fn missing_load_means_available() {
+        let json = r#"{
+            "hostname": "se1",
+            "ipv4_addr_in": "10.0.0.1",
+            "ipv4_addr_exit": "10.0.0.2",
+            "include_in_country": true,
+            "weight": 100,
+            "tunnels": {}
+        }"#;
+        let relay: Relay = serde_json::from_str(json).unwrap();
+
+        assert_eq!(relay.load, None);
+        assert!(relay.is_available(0.0));
+    }
+
+    fn mixed_relay() -> Relay {
+        let mut relay = relay("se-got-001", 100);
+        relay.tunnels = RelayTunnels {
+            openvpn: vec![
+                OpenVpnEndpointData {
+                    port: 1194,
+                    protocol: TransportProtocol::Udp,
+                },
+                OpenVpnEndpointData {
+                    port: 1195,
+                    protocol: TransportProtocol::Udp,
+                },
+                OpenVpnEndpointData {
+                    port: 443,
+                    protocol: TransportProtocol::Tcp,
+                },
+            ],
+            wireguard: vec![WireguardEndpointData { port: 51820 }],
+        };
+        relay
+    }
+
+    fn endpoint_strings(endpoints: &[TunnelEndpoint]) -> Vec<String> {
+        endpoints
+            .iter()
+            .map(|endpoint| {
+                let endpoint = endpoint.to_endpoint();
+                format!("{}/{}", endpoint.address, endpoint.protocol)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn matching_endpoints_follow_port_constraints() {
+        let relay = mixed_relay();
+        let transport_port = |protocol, port| Constraint::Only(TransportPort { protocol, port });
+        let cases = vec![
+            (
+                Constraint::Any,
+                vec![
+                    "10.0.0.1:1194/UDP",
+                    "10.0.0.1:1195/UDP",
+                    "10.0.0.1:443/TCP",
+                    "10.0.0.1:51820/UDP",
+                ],
+            ),
+            (
+                transport_port(TransportProtocol::Tcp, Constraint::Any),
+                vec!["10.0.0.1:443/TCP"],
+            ),
+            (
+                transport_port(TransportProtocol::Udp, Constraint::Any),
+                vec![
+                    "10.0.0.1:1194/UDP",
+                    "10.0.0.1:1195/UDP",
+                    "10.0.0.1:51820/UDP",
+                ],
+            ),
+            (
+                transport_port(TransportProtocol::Udp, Constraint::Only(1195)),
+                vec!["10.0.0.1:1195/UDP"],
+            ),
+            (
+                transport_port(TransportProtocol::Tcp, Constraint::Only(1194)),
+                vec![],
+            ),
+        ];
+        for (port, expected) in cases {
+            let constraints = RelayConstraints {
+                port,
+                ..RelayConstraints::default()
+            };
+            assert_eq!(
+                endpoint_strings(&relay.matching_endpoints(&constraints)),
+                expected,
+                "{:?}",
+                constraints.port
+            );
+        }
+    }
+
+    #[test]
+    fn matching_endpoints_follow_tunnel_protocol() {
+        let relay = mixed_relay();
+        let constraints = RelayConstraints {
+            tunnel_protocol: TunnelProtocol::Wireguard,
+            ..RelayConstraints::default()
+        };
+        assert_eq!(
+            endpoint_strings(&relay.matching_endpoints(&constraints)),
+            ["10.0.0.1:51820/UDP"]
+        );
+
+        let constraints = RelayConstraints {
+            tunnel_protocol: TunnelProtocol::OpenVpn,
+            port: Constraint::Only(TransportPort {
+                protocol: TransportProtocol::Udp,
+                port: Constraint::Only(51820),
+            }),
+            ..RelayConstraints::default()
+        };
+        assert!(relay.matching_endpoints(&constraints).is_empty());
+    }
+
+    #[test]
+    fn select_with_endpoint_skips_relays_without_matching_endpoints() {
+        let mut relay_list = relay_list();
+        relay_list.countries[0].cities[0].relays = vec![mixed_relay()];
+        let constraints = RelayConstraints {
+            port: Constraint::Only(TransportPort {
+                protocol: TransportProtocol::Tcp,
+                port: Constraint::Any,
+            }),
+            ..sweden()
+        };
+        let mut rng = XorShiftRng::from_seed([1, 2, 3, 4]);
+
+        for _ in 0..10 {
+            let (relay, endpoint) = relay_list
+                .select_with_endpoint(&constraints, &mut rng)
+                .unwrap();
+            assert_eq!(relay.hostname, "se-got-001");
+            assert_eq!(endpoint_strings(&[endpoint]), ["10.0.0.1:443/TCP"]);
+        }
+    }
+
+    #[test]
+    fn deduplicate_keeps_last_occurrence() {
+        let mut relay_list = relay_list();
+        relay_list.countries[0].cities[0]
+            .relays
+            .push(relay("se-got-001", 10));
+        relay_list.countries[1].cities[0]
+            .relays
+            .push(relay("SE-STO-002", 50));
+
+        relay_list.deduplicate();
+
+        assert_eq!(
+            city_hostnames(&relay_list, "got"),
+            ["se-got-002", "se-got-001"]
+        );
+        assert_eq!(city_hostnames(&relay_list, "sto"), ["se-sto-001"]);
+        assert_eq!(
+            city_hostnames(&relay_list, "fra"),
+            ["de-fra-001", "SE-STO-002"]
+        );
+        assert_eq!(weight_of(&relay_list, "se-got-001"), 10);
+        assert_eq!(weight_of(&relay_list, "se-sto-002"), 50);
+        assert_eq!(relay_list.active_relay_count(), 5);
+    }
+
+    #[test]
+    fn load_deduplicates_relays() {
+        let temp_dir = TempDir::new("relay-list-test").unwrap();
+        let path = temp_dir.path().join("relays.json");
+        let mut relay_list = relay_list();
+        relay_list.countries[1].cities[0]
+            .relays
+            .push(relay("de-fra-001", 10));
+        relay_list.save(&path).unwrap();
+
+        let loaded = RelayList::load(&path).unwrap();
+
+        assert_eq!(city_hostnames(&loaded, "fra"), ["de-fra-001"]);
+        assert_eq!(weight_of(&loaded, "de-fra-001"), 10);
+    }
+
+    fn wireguard_relay(hostname: &str) -> Relay {
+        let mut relay = relay(hostname, 100);
+        relay.tunnels = RelayTunnels {
+            openvpn: vec![],
+            wireguard: vec![WireguardEndpointData { port: 51820 }],
+        };
+        relay
+    }
+
+    #[test]
+    fn protocols_available_per_location() {
+        let mut relay_list = relay_list();
+        relay_list.countries[0].cities[1].relays = vec![
+            wireguard_relay("se-sto-001"),
+            inactive(relay("se-sto-002", 100)),
+        ];
+        relay_list.countries[1].cities[0].relays = vec![inactive(wireguard_relay("de-fra-001"))];
+        let protocols = |location: LocationConstraint| {
+            let mut protocols: Vec<_> = relay_list
+                .protocols_available(&location)
+                .into_iter()
+                .map(|protocol| format!("{:?}", protocol))
+                .collect();
+            protocols.sort();
+            protocols
+        };
+
+        assert_eq!(
+            protocols(LocationConstraint::Country("se".parse().unwrap())),
+            ["OpenVpn", "Wireguard"]
+        );
+        assert_eq!(
+            protocols(LocationConstraint::City(
+                "se".parse().unwrap(),
+                "got".parse().unwrap()
+            )),
+            ["OpenVpn"]
+        );
+        assert_eq!(
+            protocols(LocationConstraint::City(
+                "se".parse().unwrap(),
+                "sto".parse().unwrap()
+            )),
+            ["Wireguard"]
+        );
+        assert!(protocols(LocationConstraint::Country("de".parse().unwrap())).is_empty());
+        assert_eq!(
+            protocols(LocationConstraint::Country("no".parse().unwrap())),
+            ["OpenVpn", "Wireguard"]
+        );
+    }
+
+    fn latencies(samples: &[(&str, u64)]) -> HashMap<Hostname, Duration> {
+        samples
+            .iter()
+            .map(|&(hostname, millis)| (hostname.to_owned(), Duration::from_millis(millis)))
+            .collect()
+    }
+
+    #[test]
+    fn select_by_latency_picks_fastest_measured_relay() {
+        let relay_list = relay_list();
+        let mut rng = XorShiftRng::from_seed([1, 2, 3, 4]);
+        let select = |latencies: &HashMap<Hostname, Duration>, rng: &mut XorShiftRng| {
+            relay_list
+                .select_by_latency(&sweden(), latencies, rng)
+                .map(|relay| relay.hostname)
+        };
+
+        // Unmeasured relays and relays outside the constraints are ignored.
+        let samples = latencies(&[("se-got-002", 40), ("se-sto-002", 25), ("de-fra-001", 5)]);
+        for _ in 0..10 {
+            assert_eq!(select(&samples, &mut rng), Some("se-sto-002".to_owned()));
+        }
+        // Relays with zero weight are never picked, however fast they are.
+        let samples = latencies(&[("se-sto-001", 1), ("se-got-001", 30)]);
+        assert_eq!(select(&samples, &mut rng), Some("se-got-001".to_owned()));
+        // Equal latencies are broken by hostname.
+        let samples = latencies(&[("se-sto-002", 30), ("se-got-002", 30), ("se-got-001", 30)]);
+        assert_eq!(select(&samples, &mut rng), Some("se-got-001".to_owned()));
+    }
+
+    #[test]
+    fn select_by_latency_falls_back_to_weights() {
+        let relay_list = relay_list();
+        let mut rng = XorShiftRng::from_seed([1, 2, 3, 4]);
+        let samples = latencies(&[("de-fra-001", 5)]);
+
+        let mut selected = HashSet::new();
+        for _ in 0..100 {
+            let relay = relay_list
+                .select_by_latency(&sweden(), &samples, &mut rng)
+                .unwrap();
+            selected.insert(relay.hostname);
+        }
+        let mut selected: Vec<_> = selected.into_iter().collect();
+        selected.sort();
+        assert_eq!(selected, ["se-got-001", "se-got-002", "se-sto-002"]);
+    }
+
+    #[test]
+    fn find_by_hostname_ignores_case() {
+        let relay_list = relay_list();
+
+        assert_eq!(
+            relay_list.find_by_hostname("DE-Fra-001").unwrap().hostname,
+            "de-fra-001"
+        );
+    }
+
+    #[test]
+    fn find_by_hostname_prefix() {
+        let relay_list = relay_list();
+
+        assert_eq!(
+            hostnames_of(&relay_list.find_by_hostname_prefix("SE-GOT")),
+            ["se-got-001", "se-got-002"]
+        );
+        assert_eq!(relay_list.find_by_hostname_prefix("se-").len(), 4);
+        assert_eq!(relay_list.find_by_hostname_prefix("").len(), 5);
+        assert!(relay_list.find_by_hostname_prefix("no").is_empty());
+        assert!(
+            relay_list
+                .find_by_hostname_prefix("se-got-001-extra")
+                .is_empty()
+        );
+    }
+
+    fn hostnames_of<'a>(relays: &[&'a Relay]) -> Vec<&'a str> {
+        relays.iter().map(|relay| relay.hostname.as_str()).collect()
+    }
+
+    fn inactive(mut relay: Relay) -> Relay {
+        relay.active = false;
+        relay
+    }
+
+    fn excluded_from_country(mut relay: Relay) -> Relay {
+        relay.include_in_country = false;
+        relay
+    }
+
+    #[test]
+    fn unselectable_relays_are_never_selected() {
+        let mut relay_list = relay_list();
+        relay_list.countries[0].cities[0].relays.extend(vec![
+            inactive(relay("se-got-003", 1_000_000)),
+            excluded_from_country(relay("se-got-004", 1_000_000)),
+        ]);
+        let is_unselectable =
+            |relay: &Relay| relay.hostname == "se-got-003" || relay.hostname == "se-got-004";
+        let samples = latencies(&[("se-got-003", 1), ("se-got-004", 1)]);
+        let origin = Coordinates {
+            latitude: 0.0,
+            longitude: 0.0,
+        };
+        let mut rng = XorShiftRng::from_seed([1, 2, 3, 4]);
+
+        let mut multihop = RelayConstraints::builder();
+        multihop.multihop(Constraint::Any);
+        for constraints in &[RelayConstraints::default(), sweden(), multihop.build()] {
+            for _ in 0..100 {
+                let relay = relay_list.select(constraints, &mut rng).unwrap();
+                assert!(!is_unselectable(&relay));
+                let (relay, _) = relay_list
+                    .select_with_endpoint(constraints, &mut rng)
+                    .unwrap();
+                assert!(!is_unselectable(&relay));
+                let (entry, exit) = relay_list
+                    .select_entry_and_exit(constraints, &mut rng)
+                    .unwrap();
+                assert!(!is_unselectable(&exit));
+                assert!(!entry.as_ref().map_or(false, |entry| is_unselectable(entry)));
+            }
+            let relay = relay_list
+                .select_by_latency(constraints, &samples, &mut rng)
+                .unwrap();
+            assert!(!is_unselectable(&relay));
+            let nearest = relay_list.nearest(&origin, constraints, 10);
+            assert!(!nearest.is_empty());
+            assert!(!nearest.iter().any(|relay| is_unselectable(relay)));
+        }
+        assert_eq!(relay_list.active_relay_count(), 5);
+        assert_eq!(relay_list.relays_per_country()[&"se".parse().unwrap()], 4);
+    }
+
+    #[test]
+    fn excluded_relays_are_selected_in_their_city() {
+        let mut relay_list = relay_list();
+        relay_list.countries[0].cities[0].relays = vec![
+            inactive(relay("se-got-003", 100)),
+            excluded_from_country(relay("se-got-004", 100)),
+        ];
+        let constraints = RelayConstraints::builder()
+            .location(LocationConstraint::City(
+                "se".parse().unwrap(),
+                "got".parse().unwrap(),
+            ))
+            .build();
+        let mut rng = XorShiftRng::from_seed([1, 2, 3, 4]);
+
+        assert_eq!(
+            hostnames(&relay_list.matching_relays(&constraints)),
+            ["se-got-004"]
+        );
+        for _ in 0..10 {
+            let relay = relay_list.select(&constraints, &mut rng).unwrap();
+            assert_eq!(relay.hostname, "se-got-004");
+        }
+    }
+
+    #[test]
+    fn relay_counts() {
+        let mut relay_list = relay_list();
+        relay_list.countries.push(RelayListCountry {
+            name: "Norway".to_owned(),
+            code: "no".parse().unwrap(),
+            cities: vec![city("Oslo", "osl", vec![inactive(relay("no-osl-001", 100))])],
+        });
+        relay_list.countries.push(RelayListCountry {
+            name: "Denmark".to_owned(),
+            code: "dk".parse().unwrap(),
+            cities: vec![city(
+                "Copenhagen",
+                "cph",
+                vec![excluded_from_country(relay("dk-cph-001", 100))],
+            )],
+        });
+        relay_list.countries[0].cities[0]
+            .relays
+            .push(inactive(relay("se-got-003", 100)));
+
+        assert_eq!(relay_list.active_relay_count(), 5);
+        assert_eq!(relay_list.country_count(), 2);
+
+        let relays_per_country = relay_list.relays_per_country();
+        assert_eq!(relays_per_country.len(), 2);
+        assert_eq!(relays_per_country[&"se".parse().unwrap()], 4);
+        assert_eq!(relays_per_country[&"de".parse().unwrap()], 1);
+        assert_eq!(
+            relays_per_country.values().sum::<usize>(),
+            relay_list.active_relay_count()
+        );
+    }
+
+    #[test]
+    fn empty_relay_list_counts() {
+        let relay_list = RelayList::empty();
+
+        assert_eq!(relay_list.active_relay_count(), 0);
+        assert_eq!(relay_list.country_count(), 0);
+        assert!(relay_list.relays_per_country().is_empty());
+    }
+
+    #[test]
+    fn missing_active_field_means_active() {
+        let json = r#"{
+            "hostname": "se1",
+            "ipv4_addr_in": "10.0.0.1",
+            "ipv4_addr_exit": "10.0.0.2",
+            "include_in_country": true,
+            "weight": 100,
+            "tunnels": {}
+        }"#;
+        let relay: Relay = serde_json::from_str(json).unwrap();
+
+        assert!(relay.active);
+    }
+
+    fn weight_of(relay_list: &RelayList, hostname: &str) -> u64 {
+        relay_list.find_by_hostname(hostname).unwrap().weight
+    }
+
+    fn city_hostnames<'a>(relay_list: &'a RelayList, city_code: &str) -> Vec<&'a str> {
+        relay_list
+            .countries
+            .iter()
+            .flat_map(|country| country.cities.iter())
+            .filter(|city| city.code == city_code)
+            .flat_map(|city| city.relays.iter())
+            .map(|relay| relay.hostname.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn merge_overlapping_lists() {
+        let mut relay_list = relay_list();
+        let update = RelayList {
+            last_updated: unix_epoch(),
+            countries: vec![RelayListCountry {
+                name: "Sverige".to_owned(),
+                code: "se".parse().unwrap(),
+                cities: vec![
+                    city("Göteborg", "got", vec![relay("se-got-002", 50)]),
+                    city("Stockholm", "sto", vec![relay("se-got-001", 10)]),
+                ],
+            }],
+        };
+
+        relay_list.merge(update);
+
+        assert_eq!(relay_list.countries.len(), 2);
+        assert_eq!(relay_list.countries[0].name, "Sverige");
+        assert_eq!(relay_list.countries[0].cities[0].name, "Göteborg");
+        assert_eq!(city_hostnames(&relay_list, "got"), ["se-got-002"]);
+        assert_eq!(
+            city_hostnames(&relay_list, "sto"),
+            ["se-sto-001", "se-sto-002", "se-got-001"]
+        );
+        assert_eq!(weight_of(&relay_list, "se-got-001"), 10);
+        assert_eq!(weight_of(&relay_list, "se-got-002"), 50);
+        assert_eq!(weight_of(&relay_list, "de-fra-001"), 1000);
+    }
+
+    #[test]
+    fn merge_ignores_hostname_case() {
+        let mut relay_list = relay_list();
+        let update = RelayList {
+            last_updated: unix_epoch(),
+            countries: vec![RelayListCountry {
+                name: "Sweden".to_owned(),
+                code: "SE".parse().unwrap(),
+                cities: vec![
+                    city("Gothenburg", "GOT", vec![relay("SE-GOT-001", 10)]),
+                    city("Stockholm", "sto", vec![relay("Se-Sto-001", 20)]),
+                ],
+            }],
+        };
+
+        relay_list.merge(update);
+
+        assert_eq!(relay_list.countries.len(), 2);
+        assert_eq!(
+            city_hostnames(&relay_list, "got"),
+            ["se-got-002", "SE-GOT-001"]
+        );
+        assert_eq!(
+            city_hostnames(&relay_list, "sto"),
+            ["se-sto-002", "Se-Sto-001"]
+        );
+        assert_eq!(weight_of(&relay_list, "se-got-001"), 10);
+        assert_eq!(weight_of(&relay_list, "se-sto-001"), 20);
+    }
+
+    #[test]
+    fn merge_disjoint_lists() {
+        let mut relay_list = relay_list();
+        let update = RelayList {
+            last_updated: unix_epoch(),
+            countries: vec![RelayListCountry {
+                name: "Norway".to_owned(),
+                code: "no".parse().unwrap(),
+                cities: vec![city("Oslo", "osl", vec![relay("no-osl-001", 100)])],
+            }],
+        };
+
+        relay_list.merge(update);
+
+        assert_eq!(relay_list.countries.len(), 3);
+        assert_eq!(relay_list.active_relay_count(), 6);
+        assert!(relay_list.find_by_hostname("se-got-001").is_some());
+        assert!(relay_list.find_by_hostname("no-osl-001").is_some());
+    }
+
+    #[test]
+    fn merge_superset_list() {
+        let mut relay_list = relay_list();
+        let mut update = relay_list.clone();
+        for relay in update
+            .countries
+            .iter_mut()
+            .flat_map(|country| country.cities.iter_mut())
+            .flat_map(|city| city.relays.iter_mut())
+        {
+            relay.weight += 1;
+        }
+        update.countries[1]
+            .cities
+            .push(city("Berlin", "ber", vec![relay("de-ber-001", 100)]));
+
+        relay_list.merge(update.clone());
+
+        assert_eq!(relay_list.countries.len(), 2);
+        assert_eq!(relay_list.countries[1].cities.len(), 2);
+        assert_eq!(relay_list.active_relay_count(), 6);
+        for relay in update.relays() {
+            assert_eq!(weight_of(&relay_list, &relay.hostname), relay.weight);
+        }
+    }
+
+    #[test]
+    fn save_and_load_round_trip() {
+        let temp_dir = TempDir::new("relay-list-test").unwrap();
+        let path = temp_dir.path().join("relays.json");
+        let relay_list = relay_list();
+        let before_save = Utc::now();
+
+        relay_list.save(&path).unwrap();
+        let loaded = RelayList::load(&path).unwrap();
+
+        assert!(!temp_dir.path().join("relays.json.tmp").exists());
+        assert!(loaded.last_updated >= before_save - ::chrono::Duration::seconds(1));
+        assert_eq!(loaded.countries.len(), relay_list.countries.len());
+        for relay in relay_list.relays() {
+            let loaded_relay = loaded.find_by_hostname(&relay.hostname).unwrap();
+            assert_eq!(loaded_relay.weight, relay.weight);
+            assert_eq!(loaded_relay.tunnels.openvpn, relay.tunnels.openvpn);
+        }
+    }
+
+    #[test]
+    fn load_corrupt_file() {
+        let temp_dir = TempDir::new("relay-list-test").unwrap();
+        let path = temp_dir.path().join("relays.json");
+        File::create(&path)
+            .unwrap()
+            .write_all(br#"{"countries": [{"name": "Swe"#)
+            .unwrap();
+
+        match RelayList::load(&path) {
+            Err(Error(ErrorKind::ParseError(ref error_path), _)) => assert_eq!(*error_path, path),
+            result => panic!("Expected a parse error, got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn save_writes_current_version() {
+        let temp_dir = TempDir::new("relay-list-test").unwrap();
+        let path = temp_dir.path().join("relays.json");
+        relay_list().save(&path).unwrap();
+
+        let raw: serde_json::Value = serde_json::from_reader(File::open(&path).unwrap()).unwrap();
+        assert_eq!(raw["version"], json!(RELAY_LIST_VERSION));
+    }
+
+    #[test]
+    fn load_v0_relay_list() {
+        let temp_dir = TempDir::new("relay-list-test").unwrap();
+        let path = temp_dir.path().join("relays.json");
+        File::create(&path)
+            .unwrap()
+            .write_all(
+                br#"{
+                    "countries": [{
+                        "name": "Sweden",
+                        "code": "se",
+                        "cities": [{
+                            "name": "Gothenburg",
+                            "code": "got",
+                            "latitude": 57.7,
+                            "longitude": 11.97,
+                            "relays": [{
+                                "hostname": "se1",
+                                "ipv4_addr_in": "10.0.0.1",
+                                "ipv4_addr_exit": "10.0.0.2",
+                                "include_in_country": true,
+                                "weight": 100
+                            }]
+                        }]
+                    }]
+                }"#,
+            )
+            .unwrap();
+
+        let relay_list = RelayList::load(&path).unwrap();
+        let relay = relay_list.find_by_hostname("se1").unwrap();
+
+        assert_eq!(relay.provider, Provider::default());
+        assert!(!relay.owned);
+        assert!(relay.active);
+        assert!(relay.tunnels.openvpn.is_empty());
+        assert!(relay.bridges.is_empty());
+        assert_eq!(relay_list.last_updated.timestamp(), 0);
+    }
+
+    #[test]
+    fn migrate_rejects_unknown_versions() {
+        let future = json!({"version": RELAY_LIST_VERSION + 1, "countries": []});
+        match migrate(future) {
+            Err(Error(ErrorKind::UnsupportedVersion(version), _)) => {
+                assert_eq!(version, RELAY_LIST_VERSION + 1)
+            }
+            result => panic!("Expected an unsupported version error, got {:?}", result),
+        }
+        match migrate(json!({"version": "one", "countries": []})) {
+            Err(Error(ErrorKind::MalformedRelayList, _)) => (),
+            result => panic!("Expected a malformed relay list error, got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn unexpected_codes_do_not_fail_the_relay_list() {
+        let relay_list: RelayList = serde_json::from_value(json!({
+            "countries": [{
+                "name": "Kosovo",
+                "code": "XK-1",
+                "cities": [{
+                    "name": "Pristina",
+                    "code": "prn1",
+                    "latitude": 42.66,
+                    "longitude": 21.17
+                }]
+            }]
+        })).unwrap();
+
+        assert_eq!(relay_list.countries[0].code.as_str(), "xk-1");
+        assert_eq!(relay_list.countries[0].cities[0].code.as_str(), "prn1");
+    }
+
+    #[test]
+    fn missing_last_updated_is_unix_epoch() {
+        let relay_list: RelayList = serde_json::from_str(r#"{"countries": []}"#).unwrap();
+
+        assert_eq!(relay_list.last_updated.timestamp(), 0);
+    }
+}
@@ -1,9 +1,25 @@
+use std::fmt;
+
+
+/// The state reported to frontends over the management interface. The serialized form of this
+/// type and the enums in it is part of the IPC protocol and must not change, see the tests below.
 #[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
 pub struct DaemonState {
     pub state: SecurityState,
     pub target_state: TargetState,
 }
 
+impl fmt::Display for DaemonState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match (self.state, self.target_state) {
+            (SecurityState::Unsecured, TargetState::Unsecured) => f.write_str("Disconnected"),
+            (SecurityState::Unsecured, TargetState::Secured) => f.write_str("Connecting..."),
+            (SecurityState::Secured, TargetState::Unsecured) => f.write_str("Disconnecting..."),
+            (SecurityState::Secured, TargetState::Secured) => f.write_str("Connected"),
+        }
+    }
+}
+
 /// Security state of the computer.
 /// TODO(linus): There is a difference between lockdown(firewall) and tunnel functionality. The
 /// firewall can be set to prevent any leaks but the tunnel is not connected. Then we are secured,
@@ -17,6 +33,15 @@ pub enum SecurityState {
     Secured,
 }
 
+impl fmt::Display for SecurityState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            SecurityState::Unsecured => f.write_str("Unsecured"),
+            SecurityState::Secured => f.write_str("Secured"),
+        }
+    }
+}
+
 /// Represents the state the client strives towards.
 /// When in `Secured`, the client should keep the computer from leaking and try to
 /// establish a VPN tunnel if it is not up.
@@ -26,3 +51,235 @@ pub enum TargetState {
     Unsecured,
     Secured,
 }
+
+impl fmt::Display for TargetState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            TargetState::Unsecured => f.write_str("Unsecured"),
+            TargetState::Secured => f.write_str("Secured"),
+        }
+    }
+}
+
+/// Represents the internal state of the actual tunnel.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TunnelState {
+    /// No tunnel is running.
+    NotRunning,
+    /// The tunnel has been started, but it is not established/functional.
+    Connecting,
+    /// The tunnel is up and working.
+    Connected,
+    /// This state is active from when we manually trigger a tunnel kill until the tunnel wait
+    /// operation (TunnelExit) returned.
+    Exiting,
+}
+
+impl TunnelState {
+    pub fn as_security_state(&self) -> SecurityState {
+        use self::TunnelState::*;
+        match *self {
+            NotRunning | Connecting => SecurityState::Unsecured,
+            Connected | Exiting => SecurityState::Secured,
+        }
+    }
+
+    /// Returns true if traffic is currently protected by a working tunnel, which is only the case
+    /// in `Connected`.
+    ///
+    /// There is no blocked state yet, where traffic would be dropped rather than leaked while the
+    /// tunnel is down. `Exiting` is not counted as one, even though `as_security_state` reports it
+    /// as secured, since the tunnel is being torn down.
+    pub fn is_secured(&self) -> bool {
+        *self == TunnelState::Connected
+    }
+
+    /// Returns whether the tunnel is allowed to move directly from this state to `next`.
+    ///
+    /// A tunnel is always started from `NotRunning` and always ends up in `NotRunning` again. It
+    /// can drop back to `NotRunning` from any state where it is running, since the tunnel process
+    /// can die at any time. Staying in the same state is not a transition and is not allowed.
+    pub fn can_transition_to(&self, next: &TunnelState) -> bool {
+        use self::TunnelState::*;
+        match (*self, *next) {
+            (NotRunning, Connecting) => true,
+            (Connecting, Connected) | (Connecting, Exiting) | (Connecting, NotRunning) => true,
+            (Connected, Exiting) | (Connected, NotRunning) => true,
+            (Exiting, NotRunning) => true,
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for TunnelState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            TunnelState::NotRunning => f.write_str("Disconnected"),
+            TunnelState::Connecting => f.write_str("Connecting"),
+            TunnelState::Connected => f.write_str("Connected"),
+            TunnelState::Exiting => f.write_str("Disconnecting"),
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::TunnelState::*;
+    use super::{DaemonState, SecurityState, TargetState, TunnelState};
+    use serde_json;
+
+    #[test]
+    fn is_secured() {
+        assert!(!NotRunning.is_secured());
+        assert!(!Connecting.is_secured());
+        assert!(Connected.is_secured());
+        assert!(!Exiting.is_secured());
+    }
+
+    #[test]
+    fn display_tunnel_state() {
+        assert_eq!(NotRunning.to_string(), "Disconnected");
+        assert_eq!(Connecting.to_string(), "Connecting");
+        assert_eq!(Connected.to_string(), "Connected");
+        assert_eq!(Exiting.to_string(), "Disconnecting");
+    }
+
+    #[test]
+    fn display_target_and_security_state() {
+        assert_eq!(TargetState::Unsecured.to_string(), "Unsecured");
+        assert_eq!(TargetState::Secured.to_string(), "Secured");
+        assert_eq!(SecurityState::Unsecured.to_string(), "Unsecured");
+        assert_eq!(SecurityState::Secured.to_string(), "Secured");
+    }
+
+    #[test]
+    fn display_daemon_state() {
+        let states = [
+            (
+                SecurityState::Unsecured,
+                TargetState::Unsecured,
+                "Disconnected",
+            ),
+            (
+                SecurityState::Unsecured,
+                TargetState::Secured,
+                "Connecting...",
+            ),
+            (
+                SecurityState::Secured,
+                TargetState::Unsecured,
+                "Disconnecting...",
+            ),
+            (SecurityState::Secured, TargetState::Secured, "Connected"),
+        ];
+        for &(state, target_state, expected) in states.iter() {
+            let daemon_state = DaemonState {
+                state,
+                target_state,
+            };
+            assert_eq!(daemon_state.to_string(), expected);
+        }
+    }
+
+    #[test]
+    fn tunnel_state_transitions() {
+        let transitions = [
+            (NotRunning, NotRunning, false),
+            (NotRunning, Connecting, true),
+            (NotRunning, Connected, false),
+            (NotRunning, Exiting, false),
+            (Connecting, NotRunning, true),
+            (Connecting, Connecting, false),
+            (Connecting, Connected, true),
+            (Connecting, Exiting, true),
+            (Connected, NotRunning, true),
+            (Connected, Connecting, false),
+            (Connected, Connected, false),
+            (Connected, Exiting, true),
+            (Exiting, NotRunning, true),
+            (Exiting, Connecting, false),
+            (Exiting, Connected, false),
+            (Exiting, Exiting, false),
+        ];
+        for &(from, to, legal) in transitions.iter() {
+            assert_eq!(from.can_transition_to(&to), legal, "{:?} => {:?}", from, to);
+        }
+    }
+
+    #[test]
+    fn serialize_unit_states() {
+        assert_eq!(to_json(&SecurityState::Unsecured), r#""unsecured""#);
+        assert_eq!(to_json(&SecurityState::Secured), r#""secured""#);
+        assert_eq!(to_json(&TargetState::Unsecured), r#""unsecured""#);
+        assert_eq!(to_json(&TargetState::Secured), r#""secured""#);
+        assert_eq!(to_json(&NotRunning), r#""not_running""#);
+        assert_eq!(to_json(&Connecting), r#""connecting""#);
+        assert_eq!(to_json(&Connected), r#""connected""#);
+        assert_eq!(to_json(&Exiting), r#""exiting""#);
+    }
+
+    #[test]
+    fn serialize_daemon_state() {
+        let state = DaemonState {
+            state: SecurityState::Secured,
+            target_state: TargetState::Unsecured,
+        };
+        assert_eq!(
+            to_json(&state),
+            r#"{"state":"secured","target_state":"unsecured"}"#
+        );
+    }
+
+    #[test]
+    fn deserialize_known_good_json() {
+        let states: Vec<DaemonState> = serde_json::from_str(
+            r#"[
+                {"state": "unsecured", "target_state": "unsecured"},
+                {"state": "unsecured", "target_state": "secured"},
+                {"state": "secured", "target_state": "unsecured"},
+                {"state": "secured", "target_state": "secured"}
+            ]"#,
+        ).unwrap();
+        assert_eq!(
+            states,
+            vec![
+                DaemonState {
+                    state: SecurityState::Unsecured,
+                    target_state: TargetState::Unsecured,
+                },
+                DaemonState {
+                    state: SecurityState::Unsecured,
+                    target_state: TargetState::Secured,
+                },
+                DaemonState {
+                    state: SecurityState::Secured,
+                    target_state: TargetState::Unsecured,
+                },
+                DaemonState {
+                    state: SecurityState::Secured,
+                    target_state: TargetState::Secured,
+                },
+            ]
+        );
+
+        let tunnel_states: Vec<TunnelState> =
+            serde_json::from_str(r#"["not_running", "connecting", "connected", "exiting"]"#)
+                .unwrap();
+        assert_eq!(
+            tunnel_states,
+            vec![NotRunning, Connecting, Connected, Exiting]
+        );
+    }
+
+    #[test]
+    fn unknown_state_is_rejected() {
+        assert!(serde_json::from_str::<SecurityState>(r#""Secured""#).is_err());
+        assert!(serde_json::from_str::<TargetState>(r#""blocked""#).is_err());
+    }
+
+    fn to_json<T: ::serde::Serialize>(value: &T) -> String {
+        serde_json::to_string(value).unwrap()
+    }
+}
@@ -0,0 +1,353 @@
+use std::cmp;
+use std::fmt;
+use std::time::Duration;
+
+
+/// The delay before the first reconnection attempt that is not immediate. Doubled for every
+/// following attempt.
+const INITIAL_BACKOFF_SECS: u64 = 1;
+/// The longest delay between two reconnection attempts.
+const MAX_BACKOFF_SECS: u64 = 60;
+
+
+/// The reason a tunnel stopped running.
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TunnelExitCause {
+    /// The VPN server rejected the account credentials.
+    AuthFailed,
+    /// The tunnel did not come up, or stopped responding, within the allowed time.
+    Timeout,
+    /// The address of the VPN server could not be resolved.
+    DnsFailure,
+    /// The network the tunnel was running over changed or went away.
+    NetworkChanged,
+    /// The VPN server closed the connection, for example because it is restarting.
+    ServerDisconnected,
+    /// The tunnel was closed because the user asked to disconnect.
+    KilledByUser,
+    /// The tunnel was closed to be started again with settings the user changed, like the relay
+    /// settings or the account.
+    Restart,
+    /// The tunnel exited for some other reason, described by the contained message.
+    Other(String),
+}
+
+impl TunnelExitCause {
+    /// Tries to classify a single line of OpenVPN log output. Returns `None` if the line does not
+    /// describe a known reason for the tunnel to exit.
+    pub fn from_openvpn_log_line(line: &str) -> Option<Self> {
+        if line.contains("AUTH_FAILED") {
+            Some(TunnelExitCause::AuthFailed)
+        } else if line.contains("RESOLVE: Cannot resolve host address") {
+            Some(TunnelExitCause::DnsFailure)
+        } else if line.contains("Network is unreachable") {
+            Some(TunnelExitCause::NetworkChanged)
+        } else if line.contains("Received control message: 'RESTART'")
+            || line.contains("Connection reset")
+        {
+            Some(TunnelExitCause::ServerDisconnected)
+        } else if line.contains("Inactivity timeout")
+            || line.contains("TLS key negotiation failed to occur")
+            || line.contains("Connection timed out")
+        {
+            Some(TunnelExitCause::Timeout)
+        } else {
+            None
+        }
+    }
+
+    /// Classifies the OpenVPN log of a tunnel that has exited. The last line describing a known
+    /// reason wins, since that is the one closest to the exit.
+    pub fn from_openvpn_log(log: &str) -> Option<Self> {
+        log.lines().filter_map(Self::from_openvpn_log_line).last()
+    }
+
+    /// Returns true if the tunnel exited because the user asked for it, directly or by changing
+    /// settings. Such an exit is not a failure, so it must not count as a reconnection attempt or
+    /// lead to a backoff.
+    pub fn is_user_initiated(&self) -> bool {
+        match *self {
+            TunnelExitCause::KilledByUser | TunnelExitCause::Restart => true,
+            _ => false,
+        }
+    }
+
+    /// Returns an explanation of the exit that can be shown to the user as is.
+    pub fn user_message(&self) -> String {
+        match *self {
+            TunnelExitCause::AuthFailed => String::from(
+                "The server did not accept your account. Check that your account number is \
+                 correct and that the account has time left.",
+            ),
+            TunnelExitCause::Timeout => String::from(
+                "The server did not respond in time. Check your internet connection or try \
+                 another location.",
+            ),
+            TunnelExitCause::DnsFailure => String::from(
+                "The server address could not be looked up. Check your internet connection.",
+            ),
+            TunnelExitCause::NetworkChanged => String::from(
+                "Your network connection changed or was lost. The app reconnects once the \
+                 network is available again.",
+            ),
+            TunnelExitCause::ServerDisconnected => String::from(
+                "The server closed the connection. This happens when it restarts. The app \
+                 reconnects automatically.",
+            ),
+            TunnelExitCause::KilledByUser => String::from("You disconnected."),
+            TunnelExitCause::Restart => {
+                String::from("The connection was restarted to apply your new settings.")
+            }
+            TunnelExitCause::Other(ref message) => {
+                format!("The connection was lost unexpectedly: {}", message)
+            }
+        }
+    }
+}
+
+impl fmt::Display for TunnelExitCause {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            TunnelExitCause::AuthFailed => f.write_str("Authentication with the server failed"),
+            TunnelExitCause::Timeout => f.write_str("Timed out"),
+            TunnelExitCause::DnsFailure => f.write_str("Unable to resolve the server address"),
+            TunnelExitCause::NetworkChanged => f.write_str("The network changed"),
+            TunnelExitCause::ServerDisconnected => f.write_str("Disconnected by the server"),
+            TunnelExitCause::KilledByUser => f.write_str("Disconnected by the user"),
+            TunnelExitCause::Restart => f.write_str("Restarted to apply new settings"),
+            TunnelExitCause::Other(ref message) => f.write_str(message),
+        }
+    }
+}
+
+
+/// What the daemon should do after the tunnel has exited while it wants to be connected.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum ReconnectDecision {
+    /// Try to connect again right away.
+    Immediate,
+    /// Wait for the given amount of time before trying to connect again.
+    Backoff(Duration),
+    /// Stop trying to connect.
+    Abort,
+}
+
+/// Decides how to react to a tunnel exiting. `attempt` is the number of reconnection attempts
+/// made since the tunnel was last up, so it is zero the first time a working tunnel goes down.
+///
+/// A tunnel closed by the user is never reconnected, and neither is one rejected by the server
+/// since retrying with the same credentials would fail again. A tunnel restarted to apply new
+/// settings is always started again right away. Network problems are retried with
+/// an exponential backoff. Other failures, including the server closing the connection, are
+/// retried immediately once before backing off.
+pub fn reconnect_decision(cause: &TunnelExitCause, attempt: u32) -> ReconnectDecision {
+    match *cause {
+        TunnelExitCause::KilledByUser | TunnelExitCause::AuthFailed => ReconnectDecision::Abort,
+        TunnelExitCause::Restart => ReconnectDecision::Immediate,
+        TunnelExitCause::Timeout
+        | TunnelExitCause::DnsFailure
+        | TunnelExitCause::NetworkChanged => ReconnectDecision::Backoff(backoff_delay(attempt)),
+        TunnelExitCause::ServerDisconnected | TunnelExitCause::Other(_) => if attempt == 0 {
+            ReconnectDecision::Immediate
+        } else {
+            ReconnectDecision::Backoff(backoff_delay(attempt - 1))
+        },
+    }
+}
+
+/// Returns the delay to use before reconnection attempt number `attempt`, counting from zero.
+pub fn backoff_delay(attempt: u32) -> Duration {
+    let secs = if attempt >= 32 {
+        MAX_BACKOFF_SECS
+    } else {
+        cmp::min(INITIAL_BACKOFF_SECS << attempt, MAX_BACKOFF_SECS)
+    };
+    Duration::from_secs(secs)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json;
+    use std::collections::HashSet;
+
+    #[test]
+    fn classify_openvpn_log_lines() {
+        let lines = [
+            (
+                "Thu Mar  1 12:00:00 2018 AUTH: Received control message: AUTH_FAILED",
+                Some(TunnelExitCause::AuthFailed),
+            ),
+            (
+                "Thu Mar  1 12:00:00 2018 RESOLVE: Cannot resolve host address: se1.mullvad.net",
+                Some(TunnelExitCause::DnsFailure),
+            ),
+            (
+                "Thu Mar  1 12:00:00 2018 TLS Error: TLS key negotiation failed to occur within 60 \
+                 seconds (check your network connectivity)",
+                Some(TunnelExitCause::Timeout),
+            ),
+            (
+                "Thu Mar  1 12:00:00 2018 [se1] Inactivity timeout (--ping-restart), restarting",
+                Some(TunnelExitCause::Timeout),
+            ),
+            (
+                "Thu Mar  1 12:00:00 2018 write UDP: Network is unreachable (code=101)",
+                Some(TunnelExitCause::NetworkChanged),
+            ),
+            (
+                "Thu Mar  1 12:00:00 2018 Connection reset, restarting [0]",
+                Some(TunnelExitCause::ServerDisconnected),
+            ),
+            (
+                "Thu Mar  1 12:00:00 2018 Received control message: 'RESTART'",
+                Some(TunnelExitCause::ServerDisconnected),
+            ),
+            ("Thu Mar  1 12:00:00 2018 Initialization Sequence Completed", None),
+        ];
+        for &(line, ref expected) in lines.iter() {
+            assert_eq!(
+                TunnelExitCause::from_openvpn_log_line(line),
+                *expected,
+                "{}",
+                line
+            );
+        }
+    }
+
+    #[test]
+    fn last_known_cause_in_log_wins() {
+        let log = "RESOLVE: Cannot resolve host address: se1.mullvad.net:1194\n\
+                   Initialization Sequence Completed\n\
+                   AUTH: Received control message: AUTH_FAILED\n\
+                   SIGTERM[soft,auth-failure] received, process exiting\n";
+        assert_eq!(
+            TunnelExitCause::from_openvpn_log(log),
+            Some(TunnelExitCause::AuthFailed)
+        );
+        assert_eq!(
+            TunnelExitCause::from_openvpn_log("Peer Connection Initiated"),
+            None
+        );
+    }
+
+    #[test]
+    fn user_initiated_causes() {
+        assert!(TunnelExitCause::KilledByUser.is_user_initiated());
+        assert!(TunnelExitCause::Restart.is_user_initiated());
+        assert!(!TunnelExitCause::AuthFailed.is_user_initiated());
+        assert!(!TunnelExitCause::Timeout.is_user_initiated());
+        assert!(!TunnelExitCause::DnsFailure.is_user_initiated());
+        assert!(!TunnelExitCause::NetworkChanged.is_user_initiated());
+        assert!(!TunnelExitCause::ServerDisconnected.is_user_initiated());
+        assert!(!TunnelExitCause::Other("crashed".to_owned()).is_user_initiated());
+    }
+
+    #[test]
+    fn serialization() {
+        assert_eq!(
+            serde_json::to_string(&TunnelExitCause::KilledByUser).unwrap(),
+            r#""killed_by_user""#
+        );
+        assert_eq!(
+            serde_json::to_string(&TunnelExitCause::Other("crashed".to_owned())).unwrap(),
+            r#"{"other":"crashed"}"#
+        );
+        let cause: TunnelExitCause = serde_json::from_str(r#""dns_failure""#).unwrap();
+        assert_eq!(cause, TunnelExitCause::DnsFailure);
+
+        for cause in all_causes() {
+            let json = serde_json::to_string(&cause).unwrap();
+            let deserialized: TunnelExitCause = serde_json::from_str(&json).unwrap();
+            assert_eq!(deserialized, cause);
+        }
+    }
+
+    #[test]
+    fn user_messages() {
+        let auth_failed = TunnelExitCause::AuthFailed.user_message();
+        let network_changed = TunnelExitCause::NetworkChanged.user_message();
+        let server_disconnected = TunnelExitCause::ServerDisconnected.user_message();
+        assert!(auth_failed.contains("did not accept your account"));
+        assert!(network_changed.contains("network connection changed"));
+        assert!(server_disconnected.contains("server closed the connection"));
+        assert_eq!(
+            TunnelExitCause::Other("crashed".to_owned()).user_message(),
+            "The connection was lost unexpectedly: crashed"
+        );
+
+        let messages: HashSet<String> = all_causes()
+            .iter()
+            .map(TunnelExitCause::user_message)
+            .collect();
+        assert_eq!(messages.len(), all_causes().len());
+    }
+
+    fn all_causes() -> Vec<TunnelExitCause> {
+        vec![
+            TunnelExitCause::AuthFailed,
+            TunnelExitCause::Timeout,
+            TunnelExitCause::DnsFailure,
+            TunnelExitCause::NetworkChanged,
+            TunnelExitCause::ServerDisconnected,
+            TunnelExitCause::KilledByUser,
+            TunnelExitCause::Restart,
+            TunnelExitCause::Other("crashed".to_owned()),
+        ]
+    }
+
+    #[test]
+    fn backoff_schedule() {
+        let expected_secs = [1, 2, 4, 8, 16, 32, 60, 60];
+        for (attempt, &secs) in expected_secs.iter().enumerate() {
+            assert_eq!(backoff_delay(attempt as u32), Duration::from_secs(secs));
+        }
+        assert_eq!(backoff_delay(31), Duration::from_secs(MAX_BACKOFF_SECS));
+        assert_eq!(
+            backoff_delay(u32::max_value()),
+            Duration::from_secs(MAX_BACKOFF_SECS)
+        );
+    }
+
+    #[test]
+    fn reconnect_decisions() {
+        let other = TunnelExitCause::Other("crashed".to_owned());
+        let decisions = [
+            (TunnelExitCause::KilledByUser, 0, ReconnectDecision::Abort),
+            (TunnelExitCause::KilledByUser, 5, ReconnectDecision::Abort),
+            (TunnelExitCause::AuthFailed, 0, ReconnectDecision::Abort),
+            (TunnelExitCause::AuthFailed, 5, ReconnectDecision::Abort),
+            (TunnelExitCause::Restart, 0, ReconnectDecision::Immediate),
+            (TunnelExitCause::Restart, 5, ReconnectDecision::Immediate),
+            (TunnelExitCause::Timeout, 0, backoff_secs(1)),
+            (TunnelExitCause::Timeout, 3, backoff_secs(8)),
+            (TunnelExitCause::DnsFailure, 0, backoff_secs(1)),
+            (TunnelExitCause::DnsFailure, 10, backoff_secs(60)),
+            (TunnelExitCause::NetworkChanged, 2, backoff_secs(4)),
+            (
+                TunnelExitCause::ServerDisconnected,
+                0,
+                ReconnectDecision::Immediate,
+            ),
+            (TunnelExitCause::ServerDisconnected, 2, backoff_secs(2)),
+            (other.clone(), 0, ReconnectDecision::Immediate),
+            (other.clone(), 1, backoff_secs(1)),
+            (other, 4, backoff_secs(8)),
+        ];
+        for &(ref cause, attempt, expected) in decisions.iter() {
+            assert_eq!(
+                reconnect_decision(cause, attempt),
+                expected,
+                "{:?}, attempt {}",
+                cause,
+                attempt
+            );
+        }
+    }
+
+    fn backoff_secs(secs: u64) -> ReconnectDecision {
+        ReconnectDecision::Backoff(Duration::from_secs(secs))
+    }
+}
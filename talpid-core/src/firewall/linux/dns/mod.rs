@@ -0,0 +1,673 @@
+use error_chain::ChainedError;
+
+use std::fs::File;
+use std::io::Read;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::sync::{mpsc, Arc, Mutex};
+
+mod monitor;
+mod network_manager;
+mod resolvconf;
+mod resolved;
+mod static_file;
+
+use self::network_manager::NetworkManager;
+use self::resolvconf::Resolvconf;
+use self::resolved::SystemdResolved;
+use self::static_file::StaticFile;
+
+error_chain! {
+    errors {
+        SetDnsFailed(interface: String, servers: Vec<IpAddr>) {
+            description("Unable to set DNS")
+            display("Unable to set DNS for {} to [{}]", interface, format_servers(servers))
+        }
+        NoUsableNameservers(servers: Vec<IpAddr>) {
+            description("None of the nameservers can be used")
+            display("None of the nameservers [{}] can be used", format_servers(servers))
+        }
+        NoSuchInterface(interface: String) {
+            description("Unable to find the index of the network interface")
+            display("Unable to find the index of the network interface {}", interface)
+        }
+        BusCallFailed(method: &'static str) {
+            description("Error while calling systemd-resolved over D-Bus")
+            display("Error while calling {} on systemd-resolved over D-Bus", method)
+        }
+        NmcliFailed(stderr: String) {
+            description("Error while running nmcli")
+            display("nmcli failed: {}", stderr)
+        }
+        ResolvconfFailed(stderr: String) {
+            description("Error while running resolvconf")
+            display("resolvconf failed: {}", stderr)
+        }
+        ReadResolvConfFailed(path: PathBuf) {
+            description("Error while reading the resolv.conf file")
+            display("Error while reading {}", path.to_string_lossy())
+        }
+        WriteResolvConfFailed(path: PathBuf) {
+            description("Error while writing the resolv.conf file")
+            display("Error while writing {}", path.to_string_lossy())
+        }
+        BackupResolvConfFailed(path: PathBuf) {
+            description("Error while accessing the backup of the resolv.conf file")
+            display(
+                "Error while accessing the resolv.conf backup at {}",
+                path.to_string_lossy()
+            )
+        }
+        LockResolvConfFailed(path: PathBuf) {
+            description("Unable to lock the resolv.conf file")
+            display("Unable to take the lock at {}", path.to_string_lossy())
+        }
+        ResolvConfIsSymlink(path: PathBuf) {
+            description("The resolv.conf file is a symlink, so some other program manages it")
+            display(
+                "{} is a symlink, so some other program manages it",
+                path.to_string_lossy()
+            )
+        }
+    }
+}
+
+/// Where the resolver reads its configuration from, unless told otherwise.
+pub const DEFAULT_RESOLV_CONF_PATH: &str = "/etc/resolv.conf";
+
+
+/// Something that can point the DNS lookups of the system at the tunnel.
+pub trait DnsManager: Send {
+    /// Makes all DNS lookups go to the given servers over the given interface.
+    fn set_dns(&mut self, interface: &str, servers: &[IpAddr]) -> Result<()>;
+
+    /// Does the same as `set_dns`, but keeps the nameservers of the system after the given ones.
+    /// Backends that can not do that replace them like `set_dns` does.
+    fn prepend_dns(&mut self, interface: &str, servers: &[IpAddr]) -> Result<()> {
+        warn!("Unable to keep the system nameservers with this DNS backend, replacing them");
+        self.set_dns(interface, servers)
+    }
+
+    /// Removes the DNS configuration set with `set_dns`, restoring what was there before.
+    fn reset(&mut self) -> Result<()>;
+
+    /// Undoes DNS configuration left behind by an earlier run that never got to call `reset`,
+    /// like after a crash.
+    fn recover(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    /// Makes the manager report on `events` when other programs change the DNS configuration
+    /// it set. Managers that do not notice such changes ignore this.
+    fn set_events(&mut self, _events: DnsEvents) {}
+
+    /// Checks if the DNS configuration last set is still in effect, without changing anything.
+    /// Managers that can not look at the live configuration assume it is.
+    fn is_active(&self) -> bool {
+        true
+    }
+}
+
+
+/// Something that happened to the DNS configuration we set.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum DnsEvent {
+    /// DNS was pointed at the tunnel.
+    Applied,
+    /// The DNS configuration from before `Applied` was put back.
+    Reverted,
+    /// Another program changed the DNS configuration, so it no longer points at the tunnel.
+    ExternallyModified,
+    /// DNS was pointed at the tunnel again after being externally modified.
+    Restored,
+}
+
+/// Sends `DnsEvent`s to all subscribers. Subscribers that have gone away are forgotten.
+#[derive(Clone, Default)]
+pub struct DnsEvents {
+    subscribers: Arc<Mutex<Vec<mpsc::Sender<DnsEvent>>>>,
+}
+
+impl DnsEvents {
+    /// Returns a channel receiving every event sent from now on.
+    pub fn subscribe(&self) -> mpsc::Receiver<DnsEvent> {
+        let (tx, rx) = mpsc::channel();
+        self.subscribers.lock().unwrap().push(tx);
+        rx
+    }
+
+    /// Sends `event` to all subscribers.
+    pub fn send(&self, event: DnsEvent) {
+        self.subscribers
+            .lock()
+            .unwrap()
+            .retain(|subscriber| subscriber.send(event).is_ok());
+    }
+}
+
+
+/// Resets the DNS configuration of the wrapped manager when dropped, so DNS is restored also
+/// when its owner never gets to call `reset`, like after a panic or an early return. Resetting
+/// when there is nothing to reset does nothing, so calling `reset` before dropping is fine.
+///
+/// Also reports what happens to the DNS configuration to everyone subscribed.
+pub struct DnsGuard {
+    manager: Box<DnsManager>,
+    events: DnsEvents,
+    /// If our DNS configuration is set, so resetting it reverts something.
+    applied: bool,
+}
+
+impl DnsGuard {
+    pub fn new(mut manager: Box<DnsManager>) -> Self {
+        let events = DnsEvents::default();
+        manager.set_events(events.clone());
+        DnsGuard {
+            manager,
+            events,
+            applied: false,
+        }
+    }
+
+    /// Returns a channel on which everything that happens to the DNS configuration from now on
+    /// is reported.
+    pub fn subscribe(&self) -> mpsc::Receiver<DnsEvent> {
+        self.events.subscribe()
+    }
+
+    fn set_applied(&mut self) {
+        self.applied = true;
+        self.events.send(DnsEvent::Applied);
+    }
+}
+
+impl DnsManager for DnsGuard {
+    fn set_dns(&mut self, interface: &str, servers: &[IpAddr]) -> Result<()> {
+        self.manager.set_dns(interface, servers)?;
+        self.set_applied();
+        Ok(())
+    }
+
+    fn prepend_dns(&mut self, interface: &str, servers: &[IpAddr]) -> Result<()> {
+        self.manager.prepend_dns(interface, servers)?;
+        self.set_applied();
+        Ok(())
+    }
+
+    fn reset(&mut self) -> Result<()> {
+        self.manager.reset()?;
+        if self.applied {
+            self.applied = false;
+            self.events.send(DnsEvent::Reverted);
+        }
+        Ok(())
+    }
+
+    fn recover(&mut self) -> Result<()> {
+        self.manager.recover()
+    }
+
+    /// Only active if something was applied and has not been reset since.
+    fn is_active(&self) -> bool {
+        self.applied && self.manager.is_active()
+    }
+}
+
+impl Drop for DnsGuard {
+    fn drop(&mut self) {
+        if let Err(error) = self.reset() {
+            error!(
+                "Unable to restore DNS when shutting down: {}",
+                error.display_chain()
+            );
+        }
+    }
+}
+
+
+/// The programs that can be in charge of DNS on a Linux system.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum DnsManagerKind {
+    /// systemd-resolved is running and the resolv.conf points at one of its generated files.
+    SystemdResolved,
+    /// NetworkManager is running, writes the resolv.conf itself and `nmcli` is installed.
+    NetworkManager,
+    /// The resolv.conf is generated from the configuration fed to the `resolvconf` program.
+    Resolvconf,
+    /// Nothing manages the resolv.conf, so it can be edited directly.
+    StaticFile,
+}
+
+/// Figures out what is in charge of DNS on this system, judging by the resolv.conf at
+/// `resolv_conf`.
+pub fn detect_dns_manager(resolv_conf: &Path) -> DnsManagerKind {
+    detect_dns_manager_in(Path::new("/"), resolv_conf)
+}
+
+/// Returns a `DnsManager` working with the given kind of system DNS setup. When it edits a
+/// resolv.conf directly, it is the one at `resolv_conf`. It restores DNS when dropped.
+pub fn dns_manager(kind: DnsManagerKind, resolv_conf: &Path) -> DnsGuard {
+    DnsGuard::new(new_dns_manager(kind, resolv_conf))
+}
+
+/// Undoes DNS configuration left behind by a daemon that never got to reset it, like when it was
+/// killed. Does nothing if there is nothing to undo, so it is safe to run whenever the daemon is
+/// not running.
+pub fn restore_dns_if_needed(resolv_conf: &Path) -> Result<()> {
+    restore_dns_if_needed_in(Path::new("/"), resolv_conf)
+}
+
+/// Makes `manager` point DNS at the given servers over the given interface, leaving out the servers
+/// that can not be used and failing if that leaves none. With `keep_system_nameservers`, the
+/// nameservers of the system are kept after them. Errors say which servers were being set, to
+/// make reports actionable.
+pub fn set_dns(
+    manager: &mut DnsManager,
+    interface: &str,
+    servers: &[IpAddr],
+    keep_system_nameservers: bool,
+) -> Result<()> {
+    let usable_servers = usable_nameservers(servers);
+    // Setting no nameservers at all would leave the system without working DNS.
+    ensure!(
+        !usable_servers.is_empty(),
+        ErrorKind::NoUsableNameservers(servers.to_vec())
+    );
+    debug!(
+        "Setting DNS for {} to [{}]",
+        interface,
+        format_servers(&usable_servers)
+    );
+    let result = if keep_system_nameservers {
+        manager.prepend_dns(interface, &usable_servers)
+    } else {
+        manager.set_dns(interface, &usable_servers)
+    };
+    result.chain_err(|| ErrorKind::SetDnsFailed(interface.to_owned(), usable_servers.clone()))
+}
+
+/// Returns the servers that can be written to a resolv.conf. IPv6 link-local addresses only work
+/// together with a scope id, which resolv.conf can not express reliably, so they are left out.
+/// So are unspecified, loopback and multicast addresses, which can only end up here by mistake
+/// and would break DNS.
+fn usable_nameservers(servers: &[IpAddr]) -> Vec<IpAddr> {
+    servers
+        .iter()
+        .filter(|server| match **server {
+            _ if server.is_unspecified() || server.is_loopback() || server.is_multicast() => {
+                warn!("Not using invalid nameserver {}", server);
+                false
+            }
+            IpAddr::V6(ref address) if address.segments()[0] & 0xffc0 == 0xfe80 => {
+                warn!("Not using link-local nameserver {}", address);
+                false
+            }
+            _ => true,
+        })
+        .cloned()
+        .collect()
+}
+
+fn format_servers(servers: &[IpAddr]) -> String {
+    servers
+        .iter()
+        .map(|server| server.to_string())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+fn new_dns_manager(kind: DnsManagerKind, resolv_conf: &Path) -> Box<DnsManager> {
+    let resolv_conf = resolv_conf.to_path_buf();
+    match kind {
+        DnsManagerKind::SystemdResolved => Box::new(SystemdResolved::new()),
+        DnsManagerKind::Resolvconf => Box::new(Resolvconf::new()),
+        DnsManagerKind::NetworkManager => Box::new(NetworkManager::new(Box::new(
+            StaticFile::monitored(resolv_conf),
+        ))),
+        DnsManagerKind::StaticFile => Box::new(StaticFile::monitored(resolv_conf)),
+    }
+}
+
+/// Does the same as `restore_dns_if_needed`, but on a file system rooted at `root`.
+fn restore_dns_if_needed_in(root: &Path, resolv_conf: &Path) -> Result<()> {
+    new_dns_manager(detect_dns_manager_in(root, resolv_conf), resolv_conf).recover()
+}
+
+/// Does the same as `detect_dns_manager`, but on a file system rooted at `root`. The programs
+/// managing DNS are looked for under `root`, but `resolv_conf` is used as is.
+fn detect_dns_manager_in(root: &Path, resolv_conf: &Path) -> DnsManagerKind {
+    let link_target = resolv_conf
+        .read_link()
+        .map(|target| target.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let header = read_header(resolv_conf);
+
+    if link_target.contains("/run/systemd/resolve/") && root.join("run/systemd/resolve").is_dir() {
+        DnsManagerKind::SystemdResolved
+    } else if header.contains("NetworkManager")
+        && root.join("run/NetworkManager").is_dir()
+        && (root.join("bin/nmcli").exists() || root.join("usr/bin/nmcli").exists())
+    {
+        DnsManagerKind::NetworkManager
+    } else if (link_target.contains("/run/resolvconf/") || header.contains("resolvconf"))
+        && (root.join("sbin/resolvconf").exists() || root.join("usr/sbin/resolvconf").exists())
+    {
+        DnsManagerKind::Resolvconf
+    } else {
+        DnsManagerKind::StaticFile
+    }
+}
+
+/// Returns the leading comment lines of a resolv.conf file, where the programs generating it
+/// say who they are.
+fn read_header(path: &Path) -> String {
+    let mut contents = String::new();
+    if File::open(path)
+        .and_then(|mut file| file.read_to_string(&mut contents))
+        .is_err()
+    {
+        return String::new();
+    }
+    contents
+        .lines()
+        .take_while(|line| line.starts_with('#'))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::fs;
+    use std::io::Write;
+    use std::os::unix::fs::symlink;
+    use uuid::Uuid;
+
+    const RESOLV_CONF: &str = "etc/resolv.conf";
+
+    /// A directory standing in for the root of the file system, removed when dropped.
+    struct FakeRoot(PathBuf);
+
+    impl FakeRoot {
+        fn new() -> Self {
+            let root = FakeRoot(env::temp_dir().join(Uuid::new_v4().to_string()));
+            fs::create_dir_all(root.0.join("etc")).unwrap();
+            root
+        }
+
+        fn create_dir(&self, path: &str) {
+            fs::create_dir_all(self.0.join(path)).unwrap();
+        }
+
+        fn write(&self, path: &str, contents: &str) {
+            let path = self.0.join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            File::create(path)
+                .unwrap()
+                .write_all(contents.as_bytes())
+                .unwrap();
+        }
+
+        fn link_resolv_conf(&self, target: &str) {
+            symlink(target, self.0.join(RESOLV_CONF)).unwrap();
+        }
+
+        fn read(&self, path: &str) -> String {
+            let mut contents = String::new();
+            File::open(self.0.join(path))
+                .unwrap()
+                .read_to_string(&mut contents)
+                .unwrap();
+            contents
+        }
+
+        fn resolv_conf(&self) -> PathBuf {
+            self.0.join(RESOLV_CONF)
+        }
+
+        fn detect(&self) -> DnsManagerKind {
+            detect_dns_manager_in(&self.0, &self.resolv_conf())
+        }
+    }
+
+    impl Drop for FakeRoot {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    #[test]
+    fn link_local_nameservers_are_left_out() {
+        let servers: Vec<IpAddr> = ["10.8.0.1", "fe80::1", "fdda:d0d0:cafe:1194::1", "febf::1"]
+            .iter()
+            .map(|server| server.parse().unwrap())
+            .collect();
+        assert_eq!(usable_nameservers(&servers), vec![servers[0], servers[2]]);
+    }
+
+    #[test]
+    fn invalid_nameservers_are_left_out() {
+        let servers: Vec<IpAddr> = ["0.0.0.0", "127.0.0.1", "10.8.0.1", "224.0.0.1", "::", "::1"]
+            .iter()
+            .map(|server| server.parse().unwrap())
+            .collect();
+        assert_eq!(usable_nameservers(&servers), vec![servers[2]]);
+    }
+
+    #[test]
+    fn set_dns_fails_without_usable_nameservers() {
+        let servers: Vec<IpAddr> = ["0.0.0.0", "ff02::1"]
+            .iter()
+            .map(|server| server.parse().unwrap())
+            .collect();
+        let error = set_dns(&mut FailingManager, "tun0", &servers, false).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "None of the nameservers [0.0.0.0, ff02::1] can be used"
+        );
+    }
+
+    struct FailingManager;
+
+    impl DnsManager for FailingManager {
+        fn set_dns(&mut self, _interface: &str, _servers: &[IpAddr]) -> Result<()> {
+            let path = PathBuf::from("/etc/resolv.conf");
+            bail!(ErrorKind::WriteResolvConfFailed(path))
+        }
+
+        fn reset(&mut self) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn set_dns_error_includes_servers() {
+        let servers: Vec<IpAddr> = ["10.8.0.1", "fe80::1", "fdda:d0d0:cafe:1194::1"]
+            .iter()
+            .map(|server| server.parse().unwrap())
+            .collect();
+        let error = set_dns(&mut FailingManager, "tun0", &servers, false).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Unable to set DNS for tun0 to [10.8.0.1, fdda:d0d0:cafe:1194::1]"
+        );
+        let chain = error.iter().map(|e| e.to_string()).collect::<Vec<_>>();
+        assert_eq!(
+            chain,
+            vec![
+                "Unable to set DNS for tun0 to [10.8.0.1, fdda:d0d0:cafe:1194::1]".to_owned(),
+                "Error while writing /etc/resolv.conf".to_owned(),
+            ]
+        );
+    }
+
+    #[test]
+    fn detects_systemd_resolved() {
+        let root = FakeRoot::new();
+        root.link_resolv_conf("../run/systemd/resolve/stub-resolv.conf");
+        assert_eq!(root.detect(), DnsManagerKind::StaticFile);
+
+        root.create_dir("run/systemd/resolve");
+        assert_eq!(root.detect(), DnsManagerKind::SystemdResolved);
+    }
+
+    #[test]
+    fn detects_network_manager() {
+        let root = FakeRoot::new();
+        root.write(
+            RESOLV_CONF,
+            "# Generated by NetworkManager\nnameserver 192.168.1.1\n",
+        );
+        assert_eq!(root.detect(), DnsManagerKind::StaticFile);
+
+        root.create_dir("run/NetworkManager");
+        assert_eq!(root.detect(), DnsManagerKind::StaticFile);
+
+        root.write("usr/bin/nmcli", "");
+        assert_eq!(root.detect(), DnsManagerKind::NetworkManager);
+    }
+
+    #[test]
+    fn detects_resolvconf() {
+        let root = FakeRoot::new();
+        root.link_resolv_conf("/run/resolvconf/resolv.conf");
+        assert_eq!(root.detect(), DnsManagerKind::StaticFile);
+
+        root.write("sbin/resolvconf", "");
+        assert_eq!(root.detect(), DnsManagerKind::Resolvconf);
+
+        let root = FakeRoot::new();
+        root.write("usr/sbin/resolvconf", "");
+        root.write(
+            RESOLV_CONF,
+            "# Generated by resolvconf\nnameserver 192.168.1.1\n",
+        );
+        assert_eq!(root.detect(), DnsManagerKind::Resolvconf);
+    }
+
+    #[test]
+    fn falls_back_to_static_file() {
+        let root = FakeRoot::new();
+        assert_eq!(root.detect(), DnsManagerKind::StaticFile);
+
+        root.write(
+            RESOLV_CONF,
+            "nameserver 192.168.1.1\n# Generated by NetworkManager\n",
+        );
+        root.create_dir("run/NetworkManager");
+        root.create_dir("run/systemd/resolve");
+        assert_eq!(root.detect(), DnsManagerKind::StaticFile);
+    }
+
+    #[test]
+    fn restores_dns_left_behind() {
+        let root = FakeRoot::new();
+        let original = "nameserver 192.168.1.1\nsearch example.com\n";
+        root.write(
+            RESOLV_CONF,
+            "nameserver 10.8.0.1\nsearch example.com\n",
+        );
+        root.write("etc/resolv.conf.mullvad-backup", original);
+
+        restore_dns_if_needed_in(&root.0, &root.resolv_conf()).unwrap();
+        assert_eq!(root.read(RESOLV_CONF), original);
+        assert!(!root.0.join("etc/resolv.conf.mullvad-backup").exists());
+
+        // Running it again, when there is nothing to restore, changes nothing.
+        restore_dns_if_needed_in(&root.0, &root.resolv_conf()).unwrap();
+        assert_eq!(root.read(RESOLV_CONF), original);
+    }
+
+    #[test]
+    fn guard_restores_dns_when_dropped() {
+        let root = FakeRoot::new();
+        let original = "nameserver 192.168.1.1\n";
+        root.write(RESOLV_CONF, original);
+        let resolv_conf = root.resolv_conf();
+        let servers = ["10.8.0.1".parse().unwrap()];
+
+        let mut guard = DnsGuard::new(Box::new(StaticFile::new(resolv_conf.clone())));
+        guard.set_dns("tun0", &servers).unwrap();
+        assert!(root
+            .read(RESOLV_CONF)
+            .contains("nameserver 10.8.0.1\n"));
+        drop(guard);
+        assert_eq!(root.read(RESOLV_CONF), original);
+
+        // Resetting first and then dropping restores only once.
+        let mut guard = DnsGuard::new(Box::new(StaticFile::new(resolv_conf)));
+        guard.set_dns("tun0", &servers).unwrap();
+        guard.reset().unwrap();
+        root.write(RESOLV_CONF, "nameserver 192.168.1.2\n");
+        drop(guard);
+        assert_eq!(root.read(RESOLV_CONF), "nameserver 192.168.1.2\n");
+    }
+
+    #[test]
+    fn uses_the_given_resolv_conf() {
+        let root = FakeRoot::new();
+        let system_resolv_conf = "nameserver 192.168.1.1\n";
+        root.write(RESOLV_CONF, system_resolv_conf);
+        let original = "# Generated by NetworkManager\nnameserver 192.168.1.2\n";
+        root.write("run/resolv.conf", original);
+        let resolv_conf = root.0.join("run/resolv.conf");
+        root.create_dir("run/NetworkManager");
+        root.write("usr/bin/nmcli", "");
+        assert_eq!(
+            detect_dns_manager_in(&root.0, &resolv_conf),
+            DnsManagerKind::NetworkManager
+        );
+
+        let mut manager = DnsGuard::new(new_dns_manager(DnsManagerKind::StaticFile, &resolv_conf));
+        let events = manager.subscribe();
+        manager.set_dns("tun0", &["10.8.0.1".parse().unwrap()]).unwrap();
+        assert!(root
+            .read("run/resolv.conf")
+            .contains("nameserver 10.8.0.1\n"));
+
+        // The monitor watches the given file too.
+        root.write("run/resolv.conf", original);
+        let timeout = ::std::time::Duration::from_secs(5);
+        assert_eq!(events.recv_timeout(timeout), Ok(DnsEvent::Applied));
+        assert_eq!(events.recv_timeout(timeout), Ok(DnsEvent::ExternallyModified));
+        assert_eq!(events.recv_timeout(timeout), Ok(DnsEvent::Restored));
+        assert!(root
+            .read("run/resolv.conf")
+            .contains("nameserver 10.8.0.1\n"));
+
+        manager.reset().unwrap();
+        assert_eq!(root.read("run/resolv.conf"), original);
+        assert_eq!(root.read(RESOLV_CONF), system_resolv_conf);
+    }
+
+    #[test]
+    fn reports_dns_events() {
+        let root = FakeRoot::new();
+        root.write(RESOLV_CONF, "nameserver 192.168.1.1\n");
+        let resolv_conf = root.resolv_conf();
+        let mut guard = DnsGuard::new(Box::new(StaticFile::monitored(resolv_conf)));
+        let events = guard.subscribe();
+        let next_event = || {
+            events
+                .recv_timeout(::std::time::Duration::from_secs(5))
+                .expect("No DNS event reported")
+        };
+
+        guard.set_dns("tun0", &["10.8.0.1".parse().unwrap()]).unwrap();
+        assert_eq!(next_event(), DnsEvent::Applied);
+
+        root.write(RESOLV_CONF, "nameserver 192.168.1.1\n");
+        assert_eq!(next_event(), DnsEvent::ExternallyModified);
+        assert_eq!(next_event(), DnsEvent::Restored);
+        assert!(root
+            .read(RESOLV_CONF)
+            .contains("nameserver 10.8.0.1\n"));
+
+        guard.reset().unwrap();
+        assert_eq!(next_event(), DnsEvent::Reverted);
+        drop(guard);
+        assert!(events.try_recv().is_err());
+    }
+}
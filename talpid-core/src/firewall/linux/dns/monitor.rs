@@ -0,0 +1,244 @@
+extern crate libc;
+
+use std::ffi::{CString, OsStr, OsString};
+use std::io;
+use std::mem;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::io::RawFd;
+use std::path::Path;
+use std::ptr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+/// The events on the directory that mean a file in it was written, replaced or removed.
+const WATCH_MASK: u32 = libc::IN_CLOSE_WRITE | libc::IN_MOVED_TO | libc::IN_MOVED_FROM
+    | libc::IN_CREATE | libc::IN_DELETE;
+
+/// How long to wait before trying again when watching the directory fails.
+const RETRY_INTERVAL_MS: u64 = 1000;
+
+
+/// Calls a closure every time the watched file is written, replaced or removed, until dropped.
+/// A burst of changes, like another program writing the file in several steps, results in only
+/// one call, made once the file has been left alone for a while.
+///
+/// If the watch breaks, like when the directory is removed and created again, it is set up again
+/// and the closure is called, since the file might have changed in the meantime.
+pub struct DnsMonitor {
+    inotify: Arc<Inotify>,
+    stopped: Arc<AtomicBool>,
+}
+
+impl DnsMonitor {
+    /// Starts watching the file at `path`. The directory it is in must exist, but the file does
+    /// not have to.
+    pub fn spawn<F>(path: &Path, mut on_change: F) -> io::Result<Self>
+    where
+        F: FnMut() + Send + 'static,
+    {
+        let dir = path.parent().unwrap_or(Path::new("/"));
+        let file_name = path.file_name()
+            .map(OsStr::to_os_string)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "No file name"))?;
+        let dir_name = dir.to_string_lossy().into_owned();
+        let inotify = Arc::new(Inotify::watch(dir)?);
+        let stopped = Arc::new(AtomicBool::new(false));
+
+        let (change_tx, change_rx) = mpsc::channel();
+        let (debounced_tx, debounced_rx) = mpsc::channel();
+        ::mpsc::debounce(
+            change_rx,
+            debounced_tx,
+            Duration::from_millis(::mpsc::DEFAULT_DEBOUNCE_WINDOW_MS),
+        );
+
+        let watch_inotify = inotify.clone();
+        let watch_stopped = stopped.clone();
+        thread::spawn(move || {
+            while !watch_stopped.load(Ordering::SeqCst) {
+                match watch_inotify.read_events() {
+                    Ok(events) => {
+                        let watch_removed =
+                            events.iter().any(|&(mask, _)| mask & libc::IN_IGNORED != 0);
+                        if watch_removed && !watch_stopped.load(Ordering::SeqCst) {
+                            warn!("The watch on {} was removed, watching it again", dir_name);
+                            watch_inotify.rewatch(&watch_stopped);
+                            let _ = change_tx.send(());
+                        } else if events.iter().any(|&(mask, ref name)| {
+                            mask & libc::IN_Q_OVERFLOW != 0 || *name == file_name
+                        }) {
+                            let _ = change_tx.send(());
+                        }
+                    }
+                    Err(error) => {
+                        warn!("Unable to read file change events, retrying: {}", error);
+                        thread::sleep(Duration::from_millis(RETRY_INTERVAL_MS));
+                    }
+                }
+            }
+            trace!("DNS monitor thread exit");
+        });
+        let handler_stopped = stopped.clone();
+        thread::spawn(move || {
+            while let Ok(()) = debounced_rx.recv() {
+                if !handler_stopped.load(Ordering::SeqCst) {
+                    on_change();
+                }
+            }
+        });
+
+        Ok(DnsMonitor { inotify, stopped })
+    }
+}
+
+impl Drop for DnsMonitor {
+    fn drop(&mut self) {
+        self.stopped.store(true, Ordering::SeqCst);
+        // Removing the watch makes the kernel queue one last event, waking up the thread so it
+        // sees that it should stop.
+        self.inotify.unwatch();
+    }
+}
+
+
+/// An inotify instance watching one directory.
+struct Inotify {
+    fd: RawFd,
+    dir: CString,
+    watch: Mutex<libc::c_int>,
+}
+
+impl Inotify {
+    fn watch(dir: &Path) -> io::Result<Self> {
+        let dir = CString::new(dir.as_os_str().as_bytes())
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        let fd = unsafe { libc::inotify_init1(libc::IN_CLOEXEC) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let inotify = Inotify {
+            fd,
+            dir,
+            watch: Mutex::new(-1),
+        };
+        inotify.add_watch()?;
+        Ok(inotify)
+    }
+
+    fn add_watch(&self) -> io::Result<()> {
+        let watch = unsafe { libc::inotify_add_watch(self.fd, self.dir.as_ptr(), WATCH_MASK) };
+        if watch < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        *self.watch.lock().unwrap() = watch;
+        Ok(())
+    }
+
+    /// Watches the directory again after the kernel removed the watch, retrying until it works
+    /// or `stopped` is set. The directory is usually gone when that happens, and might come back.
+    fn rewatch(&self, stopped: &AtomicBool) {
+        while !stopped.load(Ordering::SeqCst) {
+            match self.add_watch() {
+                Ok(()) => return,
+                Err(error) => {
+                    trace!("Unable to watch {:?}, retrying: {}", self.dir, error);
+                    thread::sleep(Duration::from_millis(RETRY_INTERVAL_MS));
+                }
+            }
+        }
+    }
+
+    fn unwatch(&self) {
+        let watch = *self.watch.lock().unwrap();
+        unsafe { libc::inotify_rm_watch(self.fd, watch) };
+    }
+
+    /// Blocks until there are events, and returns the event masks and the names of the files
+    /// they are about.
+    fn read_events(&self) -> io::Result<Vec<(u32, OsString)>> {
+        let mut buffer = [0u8; 4096];
+        let len = unsafe {
+            libc::read(
+                self.fd,
+                buffer.as_mut_ptr() as *mut libc::c_void,
+                buffer.len(),
+            )
+        };
+        if len < 0 {
+            let error = io::Error::last_os_error();
+            return match error.kind() {
+                io::ErrorKind::Interrupted => Ok(Vec::new()),
+                _ => Err(error),
+            };
+        }
+
+        let len = len as usize;
+        let header_len = mem::size_of::<libc::inotify_event>();
+        let mut events = Vec::new();
+        let mut offset = 0;
+        while offset + header_len <= len {
+            let event = unsafe {
+                ptr::read_unaligned(buffer[offset..].as_ptr() as *const libc::inotify_event)
+            };
+            let name_start = offset + header_len;
+            let name_end = name_start + event.len as usize;
+            // The name is padded with nul bytes.
+            let name = buffer[name_start..name_end]
+                .split(|byte| *byte == 0)
+                .next()
+                .unwrap_or(&[]);
+            events.push((event.mask, OsStr::from_bytes(name).to_os_string()));
+            offset = name_end;
+        }
+        Ok(events)
+    }
+}
+
+impl Drop for Inotify {
+    fn drop(&mut self) {
+        unsafe { libc::close(self.fd) };
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::fs::{self, File};
+    use uuid::Uuid;
+
+    fn expect_change(changes: &mpsc::Receiver<()>) {
+        changes
+            .recv_timeout(Duration::from_millis(3 * RETRY_INTERVAL_MS))
+            .expect("No change reported");
+    }
+
+    #[test]
+    fn keeps_watching_after_the_directory_is_recreated() {
+        let dir = env::temp_dir().join(Uuid::new_v4().to_string());
+        let path = dir.join("resolv.conf");
+        fs::create_dir(&dir).unwrap();
+        File::create(&path).unwrap();
+
+        let (change_tx, change_rx) = mpsc::channel();
+        let monitor = DnsMonitor::spawn(&path, move || change_tx.send(()).unwrap()).unwrap();
+
+        fs::remove_file(&path).unwrap();
+        expect_change(&change_rx);
+        File::create(&path).unwrap();
+        expect_change(&change_rx);
+
+        fs::remove_dir_all(&dir).unwrap();
+        fs::create_dir(&dir).unwrap();
+        // Reported once watching again, since the file could have changed in between.
+        expect_change(&change_rx);
+        File::create(&path).unwrap();
+        expect_change(&change_rx);
+
+        drop(monitor);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,283 @@
+use duct;
+use error_chain::ChainedError;
+
+use std::net::IpAddr;
+
+use super::{DnsEvents, DnsManager, ErrorKind, Result, ResultExt};
+
+/// DNS priority making NetworkManager use the servers of the tunnel connection only. A negative
+/// priority excludes the servers of all connections with a higher priority value.
+const EXCLUSIVE_DNS_PRIORITY: &str = "-2147483647";
+
+
+/// A way of running the `nmcli` program.
+pub trait NmcliCommand: Send {
+    /// Runs `nmcli` with the given arguments.
+    fn run(&mut self, args: &[&str]) -> Result<()>;
+}
+
+/// Runs the `nmcli` program installed on the system, which talks to NetworkManager over D-Bus.
+pub struct SystemNmcli;
+
+impl NmcliCommand for SystemNmcli {
+    fn run(&mut self, args: &[&str]) -> Result<()> {
+        let output = duct::cmd("nmcli", args)
+            .stdout_null()
+            .stderr_capture()
+            .unchecked()
+            .run()
+            .chain_err(|| ErrorKind::NmcliFailed(String::from("Unable to start nmcli")))?;
+        if output.status.success() {
+            Ok(())
+        } else {
+            let stderr = String::from_utf8_lossy(&output.stderr).trim().to_owned();
+            bail!(ErrorKind::NmcliFailed(stderr));
+        }
+    }
+}
+
+
+/// Sets DNS on the connection NetworkManager has applied to the tunnel device. Only the applied
+/// connection is modified, never the saved connection profile, so reapplying the profile restores
+/// the previous settings. NetworkManager regenerates `/etc/resolv.conf` from its connections, so
+/// writing to the file directly would not stick.
+///
+/// Tunnel devices NetworkManager does not manage can not be configured this way, so for those
+/// DNS is set with `fallback` instead.
+pub struct NetworkManager<C: NmcliCommand = SystemNmcli> {
+    command: C,
+    /// The device whose applied connection we have modified, if any.
+    modified_device: Option<String>,
+    fallback: Box<DnsManager>,
+    using_fallback: bool,
+}
+
+impl NetworkManager<SystemNmcli> {
+    pub fn new(fallback: Box<DnsManager>) -> Self {
+        Self::with_command(SystemNmcli, fallback)
+    }
+}
+
+impl<C: NmcliCommand> NetworkManager<C> {
+    pub fn with_command(command: C, fallback: Box<DnsManager>) -> Self {
+        NetworkManager {
+            command,
+            modified_device: None,
+            fallback,
+            using_fallback: false,
+        }
+    }
+
+    fn modify_device(&mut self, interface: &str, servers: &[IpAddr]) -> Result<()> {
+        let args = modify_args(interface, servers);
+        self.command
+            .run(&args.iter().map(String::as_str).collect::<Vec<_>>())?;
+        self.modified_device = Some(interface.to_owned());
+        Ok(())
+    }
+}
+
+impl<C: NmcliCommand> DnsManager for NetworkManager<C> {
+    fn set_dns(&mut self, interface: &str, servers: &[IpAddr]) -> Result<()> {
+        if self.using_fallback {
+            return self.fallback.set_dns(interface, servers);
+        }
+        if self.modified_device.as_ref().map(String::as_str) != Some(interface) {
+            self.reset()?;
+        }
+        match self.modify_device(interface, servers) {
+            Ok(()) => Ok(()),
+            Err(error) => {
+                warn!(
+                    "Unable to set DNS through NetworkManager, editing resolv.conf instead: {}",
+                    error.display_chain()
+                );
+                self.using_fallback = true;
+                self.fallback.set_dns(interface, servers)
+            }
+        }
+    }
+
+    fn reset(&mut self) -> Result<()> {
+        let device_result = match self.modified_device.take() {
+            Some(device) => self.command.run(&["device", "reapply", &device]),
+            None => Ok(()),
+        };
+        let fallback_result = if self.using_fallback {
+            self.using_fallback = false;
+            self.fallback.reset()
+        } else {
+            Ok(())
+        };
+        device_result.and(fallback_result)
+    }
+
+    /// Changes to the applied connection go away together with the tunnel device, so only the
+    /// fallback can have left something behind.
+    fn recover(&mut self) -> Result<()> {
+        self.fallback.recover()
+    }
+
+    fn set_events(&mut self, events: DnsEvents) {
+        self.fallback.set_events(events)
+    }
+
+    fn is_active(&self) -> bool {
+        if self.using_fallback {
+            self.fallback.is_active()
+        } else {
+            self.modified_device.is_some()
+        }
+    }
+}
+
+/// The `nmcli` arguments making the applied connection of `interface` use only `servers`.
+fn modify_args(interface: &str, servers: &[IpAddr]) -> Vec<String> {
+    let mut args = vec![
+        "device".to_owned(),
+        "modify".to_owned(),
+        interface.to_owned(),
+    ];
+    let (v4_servers, v6_servers): (Vec<IpAddr>, Vec<IpAddr>) =
+        servers.iter().partition(|server| server.is_ipv4());
+    for &(family, ref family_servers) in &[("ipv4", v4_servers), ("ipv6", v6_servers)] {
+        if family_servers.is_empty() {
+            continue;
+        }
+        let dns = family_servers
+            .iter()
+            .map(|server| server.to_string())
+            .collect::<Vec<_>>()
+            .join(",");
+        args.extend(vec![
+            format!("{}.dns", family),
+            dns,
+            format!("{}.ignore-auto-dns", family),
+            "yes".to_owned(),
+            format!("{}.dns-priority", family),
+            EXCLUSIVE_DNS_PRIORITY.to_owned(),
+        ]);
+    }
+    args
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    #[derive(Default)]
+    struct MockCommand {
+        runs: Vec<Vec<String>>,
+        fail: bool,
+    }
+
+    impl<'a> NmcliCommand for &'a mut MockCommand {
+        fn run(&mut self, args: &[&str]) -> Result<()> {
+            self.runs.push(strings(args));
+            if self.fail {
+                bail!(ErrorKind::NmcliFailed(
+                    "Error: Device 'tun0' not found.".to_owned()
+                ));
+            }
+            Ok(())
+        }
+    }
+
+    /// Records the calls made to the fallback in a shared log.
+    struct MockFallback(Arc<Mutex<Vec<String>>>);
+
+    impl DnsManager for MockFallback {
+        fn set_dns(&mut self, interface: &str, _servers: &[IpAddr]) -> Result<()> {
+            self.0
+                .lock()
+                .unwrap()
+                .push(format!("set_dns {}", interface));
+            Ok(())
+        }
+
+        fn reset(&mut self) -> Result<()> {
+            self.0.lock().unwrap().push("reset".to_owned());
+            Ok(())
+        }
+    }
+
+    fn strings(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn modifies_and_reapplies_device() {
+        let mut command = MockCommand::default();
+        let fallback_log = Arc::new(Mutex::new(Vec::new()));
+        {
+            let fallback = Box::new(MockFallback(fallback_log.clone()));
+            let mut network_manager = NetworkManager::with_command(&mut command, fallback);
+            let servers = ["10.8.0.1".parse().unwrap(), "fd00::1".parse().unwrap()];
+            network_manager.set_dns("tun0", &servers).unwrap();
+            network_manager.set_dns("tun1", &servers[..1]).unwrap();
+            network_manager.reset().unwrap();
+            network_manager.reset().unwrap();
+        }
+        assert_eq!(
+            command.runs,
+            vec![
+                strings(&[
+                    "device",
+                    "modify",
+                    "tun0",
+                    "ipv4.dns",
+                    "10.8.0.1",
+                    "ipv4.ignore-auto-dns",
+                    "yes",
+                    "ipv4.dns-priority",
+                    "-2147483647",
+                    "ipv6.dns",
+                    "fd00::1",
+                    "ipv6.ignore-auto-dns",
+                    "yes",
+                    "ipv6.dns-priority",
+                    "-2147483647",
+                ]),
+                strings(&["device", "reapply", "tun0"]),
+                strings(&[
+                    "device",
+                    "modify",
+                    "tun1",
+                    "ipv4.dns",
+                    "10.8.0.1",
+                    "ipv4.ignore-auto-dns",
+                    "yes",
+                    "ipv4.dns-priority",
+                    "-2147483647",
+                ]),
+                strings(&["device", "reapply", "tun1"]),
+            ]
+        );
+        assert!(fallback_log.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn falls_back_for_unmanaged_devices() {
+        let mut command = MockCommand {
+            fail: true,
+            ..MockCommand::default()
+        };
+        let fallback_log = Arc::new(Mutex::new(Vec::new()));
+        {
+            let fallback = Box::new(MockFallback(fallback_log.clone()));
+            let mut network_manager = NetworkManager::with_command(&mut command, fallback);
+            let servers = ["10.8.0.1".parse().unwrap()];
+            network_manager.set_dns("tun0", &servers).unwrap();
+            network_manager.set_dns("tun0", &servers).unwrap();
+            network_manager.reset().unwrap();
+        }
+        // The device is never reapplied, since it was never modified.
+        assert_eq!(command.runs.len(), 1);
+        assert_eq!(
+            *fallback_log.lock().unwrap(),
+            vec!["set_dns tun0", "set_dns tun0", "reset"]
+        );
+    }
+}
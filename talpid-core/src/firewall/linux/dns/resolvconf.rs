@@ -0,0 +1,240 @@
+use duct;
+
+use std::fs;
+use std::io;
+use std::net::IpAddr;
+
+use super::{DnsManager, ErrorKind, Result, ResultExt};
+
+
+/// Suffix added to the interface name to form the name our configuration is recorded under.
+const RECORD_SUFFIX: &str = ".mullvad";
+
+/// Where the Debian `resolvconf` and openresolv keep the configuration they were fed, in one file
+/// per record.
+const RECORD_DIRS: &[&str] = &["/run/resolvconf/interface", "/run/resolvconf/interfaces"];
+
+
+/// A way of running the `resolvconf` program.
+pub trait ResolvconfCommand: Send {
+    /// Runs `resolvconf` with the given arguments, writing `input` to its stdin.
+    fn run(&mut self, args: &[&str], input: &str) -> Result<()>;
+
+    /// Returns the names of all records currently fed to `resolvconf`.
+    fn records(&mut self) -> Result<Vec<String>>;
+}
+
+/// Runs the `resolvconf` program installed on the system.
+pub struct SystemResolvconf;
+
+impl ResolvconfCommand for SystemResolvconf {
+    fn run(&mut self, args: &[&str], input: &str) -> Result<()> {
+        let output = duct::cmd("resolvconf", args)
+            .input(input)
+            .stdout_null()
+            .stderr_capture()
+            .unchecked()
+            .run()
+            .chain_err(|| {
+                ErrorKind::ResolvconfFailed(String::from("Unable to start resolvconf"))
+            })?;
+        if output.status.success() {
+            Ok(())
+        } else {
+            let stderr = String::from_utf8_lossy(&output.stderr).trim().to_owned();
+            bail!(ErrorKind::ResolvconfFailed(stderr));
+        }
+    }
+
+    fn records(&mut self) -> Result<Vec<String>> {
+        let mut records = Vec::new();
+        for dir in RECORD_DIRS {
+            let entries = match fs::read_dir(dir) {
+                Ok(entries) => entries,
+                Err(ref e) if e.kind() == io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(e).chain_err(|| list_records_failed(dir)),
+            };
+            for entry in entries {
+                let entry = entry.chain_err(|| list_records_failed(dir))?;
+                records.push(entry.file_name().to_string_lossy().into_owned());
+            }
+        }
+        Ok(records)
+    }
+}
+
+
+/// Sets DNS by feeding the configuration of the tunnel interface to the `resolvconf` program,
+/// which merges it with the configuration of the other interfaces into resolv.conf.
+pub struct Resolvconf<C: ResolvconfCommand = SystemResolvconf> {
+    command: C,
+    /// The name our configuration is recorded under in resolvconf, if we have added any.
+    record: Option<String>,
+}
+
+impl Resolvconf<SystemResolvconf> {
+    pub fn new() -> Self {
+        Self::with_command(SystemResolvconf)
+    }
+}
+
+impl<C: ResolvconfCommand> Resolvconf<C> {
+    pub fn with_command(command: C) -> Self {
+        Resolvconf {
+            command,
+            record: None,
+        }
+    }
+}
+
+impl<C: ResolvconfCommand> DnsManager for Resolvconf<C> {
+    fn set_dns(&mut self, interface: &str, servers: &[IpAddr]) -> Result<()> {
+        let record = format!("{}{}", interface, RECORD_SUFFIX);
+        if self.record.as_ref() != Some(&record) {
+            self.reset()?;
+        }
+        self.record = Some(record.clone());
+        self.command
+            .run(&["-a", &record], &interface_config(servers))
+    }
+
+    fn reset(&mut self) -> Result<()> {
+        match self.record.take() {
+            Some(record) => self.command.run(&["-d", &record], ""),
+            None => Ok(()),
+        }
+    }
+
+    /// Deletes every record named like ours, since a daemon that was killed never got to delete
+    /// the one it added.
+    fn recover(&mut self) -> Result<()> {
+        let mut leftovers: Vec<String> = self.command
+            .records()?
+            .into_iter()
+            .filter(|record| record.ends_with(RECORD_SUFFIX))
+            .collect();
+        leftovers.sort();
+        leftovers.dedup();
+        for record in leftovers {
+            info!(
+                "Removing resolvconf record {} left by an earlier run",
+                record
+            );
+            self.command.run(&["-d", &record], "")?;
+        }
+        Ok(())
+    }
+}
+
+fn list_records_failed(dir: &str) -> ErrorKind {
+    ErrorKind::ResolvconfFailed(format!("Unable to list the records in {}", dir))
+}
+
+fn interface_config(servers: &[IpAddr]) -> String {
+    servers
+        .iter()
+        .map(|server| format!("nameserver {}\n", server))
+        .collect()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Default)]
+    struct MockCommand {
+        runs: Vec<(Vec<String>, String)>,
+        records: Vec<String>,
+        fail_with: Option<String>,
+    }
+
+    impl<'a> ResolvconfCommand for &'a mut MockCommand {
+        fn run(&mut self, args: &[&str], input: &str) -> Result<()> {
+            self.runs.push(run(args, input));
+            match self.fail_with {
+                Some(ref stderr) => bail!(ErrorKind::ResolvconfFailed(stderr.clone())),
+                None => Ok(()),
+            }
+        }
+
+        fn records(&mut self) -> Result<Vec<String>> {
+            Ok(self.records.clone())
+        }
+    }
+
+    fn run(args: &[&str], input: &str) -> (Vec<String>, String) {
+        (
+            args.iter().map(|arg| arg.to_string()).collect(),
+            input.to_owned(),
+        )
+    }
+
+    #[test]
+    fn adds_and_deletes_mullvad_record() {
+        let mut command = MockCommand::default();
+        {
+            let mut resolvconf = Resolvconf::with_command(&mut command);
+            let servers = ["10.8.0.1".parse().unwrap(), "fd00::1".parse().unwrap()];
+            resolvconf.set_dns("tun0", &servers).unwrap();
+            resolvconf.set_dns("tun1", &servers[..1]).unwrap();
+            resolvconf.reset().unwrap();
+            resolvconf.reset().unwrap();
+        }
+        assert_eq!(
+            command.runs,
+            vec![
+                run(
+                    &["-a", "tun0.mullvad"],
+                    "nameserver 10.8.0.1\nnameserver fd00::1\n",
+                ),
+                run(&["-d", "tun0.mullvad"], ""),
+                run(&["-a", "tun1.mullvad"], "nameserver 10.8.0.1\n"),
+                run(&["-d", "tun1.mullvad"], ""),
+            ]
+        );
+    }
+
+    #[test]
+    fn failure_includes_stderr() {
+        let mut command = MockCommand {
+            fail_with: Some("resolvconf: Error: /etc/resolv.conf isn't a symlink".to_owned()),
+            ..MockCommand::default()
+        };
+        let mut resolvconf = Resolvconf::with_command(&mut command);
+        let error = resolvconf
+            .set_dns("tun0", &["10.8.0.1".parse().unwrap()])
+            .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "resolvconf failed: resolvconf: Error: /etc/resolv.conf isn't a symlink"
+        );
+    }
+    #[test]
+    fn recover_deletes_leftover_mullvad_records() {
+        let mut command = MockCommand {
+            records: vec![
+                "eth0.dhclient".to_owned(),
+                "tun0.mullvad".to_owned(),
+                "lo.dnsmasq".to_owned(),
+                "tun1.mullvad".to_owned(),
+            ],
+            ..MockCommand::default()
+        };
+        Resolvconf::with_command(&mut command).recover().unwrap();
+        assert_eq!(
+            command.runs,
+            vec![
+                run(&["-d", "tun0.mullvad"], ""),
+                run(&["-d", "tun1.mullvad"], ""),
+            ]
+        );
+
+        let mut command = MockCommand {
+            records: vec!["eth0.dhclient".to_owned()],
+            ..MockCommand::default()
+        };
+        Resolvconf::with_command(&mut command).recover().unwrap();
+        assert!(command.runs.is_empty());
+    }
+}
@@ -0,0 +1,262 @@
+use duct;
+
+use std::fs::File;
+use std::io::Read;
+use std::net::IpAddr;
+use std::path::Path;
+
+use super::{DnsManager, ErrorKind, Result, ResultExt};
+
+const RESOLVED_DESTINATION: &str = "org.freedesktop.resolve1";
+const RESOLVED_OBJECT_PATH: &str = "/org/freedesktop/resolve1";
+const RESOLVED_MANAGER_INTERFACE: &str = "org.freedesktop.resolve1.Manager";
+
+/// The address family values resolved expects, as defined by the Linux socket API.
+const AF_INET: u8 = 2;
+const AF_INET6: u8 = 10;
+
+/// Routing domain matching all names, so every lookup goes to the DNS servers of the link.
+const ROUTE_ALL_DOMAINS: &str = "~.";
+
+
+/// A method call on the systemd-resolved manager object, with arguments in the text format
+/// `busctl` takes.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct MethodCall {
+    pub method: &'static str,
+    pub signature: &'static str,
+    pub args: Vec<String>,
+}
+
+impl MethodCall {
+    /// Sets the DNS servers of the link with the given interface index.
+    fn set_link_dns(link: u32, servers: &[IpAddr]) -> Self {
+        let mut args = vec![link.to_string(), servers.len().to_string()];
+        for server in servers {
+            let (family, octets) = match *server {
+                IpAddr::V4(ref addr) => (AF_INET, addr.octets().to_vec()),
+                IpAddr::V6(ref addr) => (AF_INET6, addr.octets().to_vec()),
+            };
+            args.push(family.to_string());
+            args.push(octets.len().to_string());
+            args.extend(octets.iter().map(|octet| octet.to_string()));
+        }
+        MethodCall {
+            method: "SetLinkDNS",
+            signature: "ia(iay)",
+            args,
+        }
+    }
+
+    /// Makes the link with the given interface index handle lookups for all domains.
+    fn set_link_domains(link: u32) -> Self {
+        MethodCall {
+            method: "SetLinkDomains",
+            signature: "ia(sb)",
+            args: vec![
+                link.to_string(),
+                "1".to_owned(),
+                ROUTE_ALL_DOMAINS.to_owned(),
+                "true".to_owned(),
+            ],
+        }
+    }
+
+    /// Drops all DNS configuration set on the link with the given interface index.
+    fn revert_link(link: u32) -> Self {
+        MethodCall {
+            method: "RevertLink",
+            signature: "i",
+            args: vec![link.to_string()],
+        }
+    }
+}
+
+
+/// A way of sending method calls to systemd-resolved.
+pub trait ResolvedBus: Send {
+    fn call(&mut self, call: &MethodCall) -> Result<()>;
+}
+
+/// Talks to systemd-resolved over the system D-Bus using the `busctl` tool.
+pub struct BusCtl;
+
+impl ResolvedBus for BusCtl {
+    fn call(&mut self, call: &MethodCall) -> Result<()> {
+        let mut args = vec![
+            "call".to_owned(),
+            RESOLVED_DESTINATION.to_owned(),
+            RESOLVED_OBJECT_PATH.to_owned(),
+            RESOLVED_MANAGER_INTERFACE.to_owned(),
+            call.method.to_owned(),
+            call.signature.to_owned(),
+        ];
+        args.extend(call.args.iter().cloned());
+        duct::cmd("busctl", args)
+            .stdout_null()
+            .run()
+            .map(|_| ())
+            .chain_err(|| ErrorKind::BusCallFailed(call.method))
+    }
+}
+
+
+/// Sets the DNS servers of the tunnel interface through systemd-resolved. Systems running
+/// resolved manage `/etc/resolv.conf` themselves, so writing to it directly would not stick.
+pub struct SystemdResolved<B: ResolvedBus = BusCtl> {
+    bus: B,
+    /// The index of the link we have configured DNS on, if any. Tunnel interfaces are created
+    /// without any DNS configuration of their own, so reverting this link restores the previous
+    /// state.
+    configured_link: Option<u32>,
+}
+
+impl SystemdResolved<BusCtl> {
+    pub fn new() -> Self {
+        Self::with_bus(BusCtl)
+    }
+}
+
+impl<B: ResolvedBus> SystemdResolved<B> {
+    pub fn with_bus(bus: B) -> Self {
+        SystemdResolved {
+            bus,
+            configured_link: None,
+        }
+    }
+
+    fn set_link_dns(&mut self, link: u32, servers: &[IpAddr]) -> Result<()> {
+        if self.configured_link.map(|configured| configured != link) == Some(true) {
+            self.reset()?;
+        }
+        // Recorded before configuring anything so a partially applied configuration is reverted.
+        self.configured_link = Some(link);
+        self.bus.call(&MethodCall::set_link_dns(link, servers))?;
+        self.bus.call(&MethodCall::set_link_domains(link))
+    }
+}
+
+impl<B: ResolvedBus> DnsManager for SystemdResolved<B> {
+    fn set_dns(&mut self, interface: &str, servers: &[IpAddr]) -> Result<()> {
+        let link = interface_index(interface)?;
+        self.set_link_dns(link, servers)
+    }
+
+    fn reset(&mut self) -> Result<()> {
+        match self.configured_link.take() {
+            Some(link) => self.bus.call(&MethodCall::revert_link(link)),
+            None => Ok(()),
+        }
+    }
+}
+
+fn interface_index(interface: &str) -> Result<u32> {
+    let path = Path::new("/sys/class/net").join(interface).join("ifindex");
+    let mut index = String::new();
+    File::open(path)
+        .and_then(|mut file| file.read_to_string(&mut index))
+        .chain_err(|| ErrorKind::NoSuchInterface(interface.to_owned()))?;
+    index
+        .trim()
+        .parse()
+        .chain_err(|| ErrorKind::NoSuchInterface(interface.to_owned()))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::super::DnsManager;
+    use super::*;
+
+    #[derive(Default)]
+    struct MockBus {
+        calls: Vec<MethodCall>,
+    }
+
+    impl<'a> ResolvedBus for &'a mut MockBus {
+        fn call(&mut self, call: &MethodCall) -> Result<()> {
+            self.calls.push(call.clone());
+            Ok(())
+        }
+    }
+
+    fn strings(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn set_link_dns_message() {
+        let servers = ["10.8.0.1".parse().unwrap(), "fd00::1".parse().unwrap()];
+        let call = MethodCall::set_link_dns(7, &servers);
+        assert_eq!(call.method, "SetLinkDNS");
+        assert_eq!(call.signature, "ia(iay)");
+        assert_eq!(
+            call.args,
+            strings(&[
+                "7", "2", "2", "4", "10", "8", "0", "1", "10", "16", "253", "0", "0", "0", "0",
+                "0", "0", "0", "0", "0", "0", "0", "0", "0", "0", "1",
+            ])
+        );
+    }
+
+    #[test]
+    fn set_dns_routes_all_domains_and_reset_reverts() {
+        let mut bus = MockBus::default();
+        {
+            let mut resolved = SystemdResolved::with_bus(&mut bus);
+            resolved
+                .set_link_dns(7, &["10.8.0.1".parse().unwrap()])
+                .unwrap();
+            resolved.reset().unwrap();
+            resolved.reset().unwrap();
+        }
+        assert_eq!(
+            bus.calls,
+            vec![
+                MethodCall {
+                    method: "SetLinkDNS",
+                    signature: "ia(iay)",
+                    args: strings(&["7", "1", "2", "4", "10", "8", "0", "1"]),
+                },
+                MethodCall {
+                    method: "SetLinkDomains",
+                    signature: "ia(sb)",
+                    args: strings(&["7", "1", "~.", "true"]),
+                },
+                MethodCall {
+                    method: "RevertLink",
+                    signature: "i",
+                    args: strings(&["7"]),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn changing_link_reverts_the_old_one() {
+        let mut bus = MockBus::default();
+        {
+            let mut resolved = SystemdResolved::with_bus(&mut bus);
+            let servers = ["10.8.0.1".parse().unwrap()];
+            resolved.set_link_dns(7, &servers).unwrap();
+            resolved.set_link_dns(7, &servers).unwrap();
+            resolved.set_link_dns(9, &servers).unwrap();
+        }
+        let methods: Vec<(&str, &str)> = bus.calls
+            .iter()
+            .map(|call| (call.method, call.args[0].as_str()))
+            .collect();
+        assert_eq!(
+            methods,
+            vec![
+                ("SetLinkDNS", "7"),
+                ("SetLinkDomains", "7"),
+                ("SetLinkDNS", "7"),
+                ("SetLinkDomains", "7"),
+                ("RevertLink", "7"),
+                ("SetLinkDNS", "9"),
+                ("SetLinkDomains", "9"),
+            ]
+        );
+    }
+}
@@ -0,0 +1,825 @@
+extern crate libc;
+
+use error_chain::ChainedError;
+
+use std::ffi::CString;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::net::IpAddr;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::MetadataExt;
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use super::monitor::DnsMonitor;
+use super::{DnsEvent, DnsEvents, DnsManager, ErrorKind, Result, ResultExt};
+
+/// How long to wait for another process to finish writing the file before giving up.
+const LOCK_TIMEOUT_MS: u64 = 2000;
+const LOCK_RETRY_INTERVAL_MS: u64 = 10;
+
+/// The number of nameservers the resolver uses. Any more are ignored.
+const MAX_NAMESERVERS: usize = 3;
+
+/// The first line of every resolv.conf we write.
+const HEADER: &str = "# Generated by Mullvad VPN";
+
+
+/// Sets DNS by writing the resolv.conf file directly. Only suitable when no other program is
+/// managing the file. A resolv.conf that is a symlink belongs to some other program, so it is
+/// never written to.
+///
+/// The contents to restore are saved the first time our nameservers are set. If another program,
+/// like a DHCP client, rewrites the file while they are set, the backup is not refreshed, so
+/// `reset` puts back the file from before we connected and the newer changes are lost.
+pub struct StaticFile {
+    path: PathBuf,
+    /// Where the contents of the file from before we first wrote to it are saved, so they can be
+    /// restored by `recover` even if the daemon is restarted while connected.
+    backup_path: PathBuf,
+    /// Lock file held while reading and writing the file, so other programs respecting it can
+    /// not interleave their writes with ours.
+    lock_path: PathBuf,
+    /// The contents of the file before we first wrote to it.
+    backup: Option<Vec<u8>>,
+    /// The nameservers the file should have, or `None` if we are not managing the file at the
+    /// moment. Shared with the monitor, which puts them back if another program removes them.
+    servers: Arc<Mutex<Option<Vec<IpAddr>>>>,
+    /// If the file should be monitored while our nameservers are set.
+    monitored: bool,
+    monitor: Option<DnsMonitor>,
+    /// Where the monitor reports changes made by other programs.
+    events: Option<DnsEvents>,
+}
+
+impl StaticFile {
+    pub fn new(path: PathBuf) -> Self {
+        let backup_path = path.with_file_name(format!("{}.mullvad-backup", file_name(&path)));
+        let lock_path = path.with_file_name(format!(".{}.lock", file_name(&path)));
+        StaticFile {
+            path,
+            backup_path,
+            lock_path,
+            backup: None,
+            servers: Arc::new(Mutex::new(None)),
+            monitored: false,
+            monitor: None,
+            events: None,
+        }
+    }
+
+    /// Creates a `StaticFile` that also watches the file while our nameservers are set, and
+    /// writes them back if another program, like a DHCP client, replaces them.
+    pub fn monitored(path: PathBuf) -> Self {
+        StaticFile {
+            monitored: true,
+            ..Self::new(path)
+        }
+    }
+
+    fn start_monitor(&mut self) {
+        let path = self.path.clone();
+        let servers = self.servers.clone();
+        let events = self.events.clone();
+        let on_change = move || {
+            let file = StaticFile::new(path.clone());
+            if let Err(error) = file.restore_servers(&servers, events.as_ref()) {
+                error!("{}", error.display_chain());
+            }
+        };
+        match DnsMonitor::spawn(&self.path, on_change) {
+            Ok(monitor) => self.monitor = Some(monitor),
+            Err(error) => warn!(
+                "Unable to monitor {}, DNS might leak if it is changed: {}",
+                self.path.to_string_lossy(),
+                error
+            ),
+        }
+    }
+
+    /// Writes the given nameservers back to the file if it no longer uses them. Does nothing if
+    /// it does, so our own writes do not cause more writes.
+    fn restore_servers(
+        &self,
+        servers: &Mutex<Option<Vec<IpAddr>>>,
+        events: Option<&DnsEvents>,
+    ) -> Result<()> {
+        let _lock = self.lock()?;
+        let servers = match *servers.lock().unwrap() {
+            Some(ref servers) => servers.clone(),
+            None => return Ok(()),
+        };
+        let current = String::from_utf8_lossy(&self.read_current()?).into_owned();
+        if uses_nameservers(&current, &servers) {
+            return Ok(());
+        }
+        warn!(
+            "{} was changed by another program, setting the nameservers again",
+            self.path.to_string_lossy()
+        );
+        let send = |event| events.map(|events| events.send(event));
+        send(DnsEvent::ExternallyModified);
+        self.write(merge_ignoring_nameservers(&current, &servers).as_bytes())?;
+        send(DnsEvent::Restored);
+        Ok(())
+    }
+
+    fn lock(&self) -> Result<FileLock> {
+        FileLock::acquire(&self.lock_path, Duration::from_millis(LOCK_TIMEOUT_MS))
+            .chain_err(|| ErrorKind::LockResolvConfFailed(self.lock_path.clone()))
+    }
+
+    /// Returns the contents to restore on `reset`, saving them to the backup file first if this
+    /// is the first time we write to the file. If a backup is already saved, we are writing over
+    /// our own configuration from an earlier run, so the saved backup is kept and used.
+    fn take_backup(&self) -> Result<Vec<u8>> {
+        match read_file(&self.backup_path) {
+            Ok(backup) => Ok(backup),
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => {
+                let backup = self.read_current()?;
+                write_atomically(&self.backup_path, &backup)
+                    .chain_err(|| ErrorKind::BackupResolvConfFailed(self.backup_path.clone()))?;
+                Ok(backup)
+            }
+            Err(e) => {
+                Err(e).chain_err(|| ErrorKind::BackupResolvConfFailed(self.backup_path.clone()))
+            }
+        }
+    }
+
+    /// Reads the file as it is now. A missing file, common in minimal containers, is treated as
+    /// an empty one, since that is how the resolver treats it too.
+    fn read_current(&self) -> Result<Vec<u8>> {
+        match read_file(&self.path) {
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(Vec::new()),
+            result => result.chain_err(|| ErrorKind::ReadResolvConfFailed(self.path.clone())),
+        }
+    }
+
+    fn restore(&self, backup: &[u8]) -> Result<()> {
+        self.write(backup)?;
+        fs::remove_file(&self.backup_path)
+            .chain_err(|| ErrorKind::BackupResolvConfFailed(self.backup_path.clone()))
+    }
+
+    fn write(&self, contents: &[u8]) -> Result<()> {
+        self.ensure_not_symlink()?;
+        write_atomically(&self.path, contents)
+            .chain_err(|| ErrorKind::WriteResolvConfFailed(self.path.clone()))
+    }
+
+    fn set_nameservers(&mut self, servers: &[IpAddr], keep_system_nameservers: bool) -> Result<()> {
+        let _lock = self.lock()?;
+        self.ensure_not_symlink()?;
+        let backup = match self.backup.take() {
+            Some(backup) => backup,
+            None => self.take_backup()?,
+        };
+        let original = String::from_utf8_lossy(&backup).into_owned();
+        self.backup = Some(backup);
+        let servers = if keep_system_nameservers {
+            prepend_nameservers(servers, &original)
+        } else {
+            servers.to_vec()
+        };
+        self.write(merge_ignoring_nameservers(&original, &servers).as_bytes())?;
+        *self.servers.lock().unwrap() = Some(servers);
+        if self.monitored && self.monitor.is_none() {
+            self.start_monitor();
+        }
+        Ok(())
+    }
+
+    fn ensure_not_symlink(&self) -> Result<()> {
+        let is_symlink = fs::symlink_metadata(&self.path)
+            .map(|metadata| metadata.file_type().is_symlink())
+            .unwrap_or(false);
+        if is_symlink {
+            bail!(ErrorKind::ResolvConfIsSymlink(self.path.clone()));
+        }
+        Ok(())
+    }
+}
+
+impl DnsManager for StaticFile {
+    fn set_dns(&mut self, _interface: &str, servers: &[IpAddr]) -> Result<()> {
+        self.set_nameservers(servers, false)
+    }
+
+    fn prepend_dns(&mut self, _interface: &str, servers: &[IpAddr]) -> Result<()> {
+        self.set_nameservers(servers, true)
+    }
+
+    fn set_events(&mut self, events: DnsEvents) {
+        self.events = Some(events);
+    }
+
+    /// Looks at the file, since other programs might have replaced our nameservers.
+    fn is_active(&self) -> bool {
+        match *self.servers.lock().unwrap() {
+            Some(ref servers) => match self.read_current() {
+                Ok(contents) => uses_nameservers(&String::from_utf8_lossy(&contents), servers),
+                Err(_) => false,
+            },
+            None => false,
+        }
+    }
+
+    fn reset(&mut self) -> Result<()> {
+        self.monitor = None;
+        let _lock = self.lock()?;
+        *self.servers.lock().unwrap() = None;
+        match self.backup.take() {
+            Some(backup) => self.restore(&backup),
+            None => Ok(()),
+        }
+    }
+
+    fn recover(&mut self) -> Result<()> {
+        let _lock = self.lock()?;
+        match read_file(&self.backup_path) {
+            Ok(backup) => {
+                info!(
+                    "Restoring {} from backup left by an earlier run",
+                    self.path.to_string_lossy()
+                );
+                self.backup = None;
+                self.restore(&backup)
+            }
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+            Err(e) => {
+                Err(e).chain_err(|| ErrorKind::BackupResolvConfFailed(self.backup_path.clone()))
+            }
+        }
+    }
+}
+
+/// An exclusive advisory lock on a file, released when dropped.
+#[derive(Debug)]
+struct FileLock(File);
+
+impl FileLock {
+    /// Takes the lock on the file at `path`, creating the file if needed. Gives up after
+    /// `timeout`, so a stuck holder can not block us forever.
+    fn acquire(path: &Path, timeout: Duration) -> io::Result<Self> {
+        let file = OpenOptions::new().write(true).create(true).open(path)?;
+        let start = Instant::now();
+        loop {
+            if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } == 0 {
+                return Ok(FileLock(file));
+            }
+            let error = io::Error::last_os_error();
+            if error.kind() != io::ErrorKind::WouldBlock {
+                return Err(error);
+            }
+            if start.elapsed() >= timeout {
+                return Err(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    "Timed out waiting for the lock",
+                ));
+            }
+            thread::sleep(Duration::from_millis(LOCK_RETRY_INTERVAL_MS));
+        }
+    }
+}
+
+impl Drop for FileLock {
+    fn drop(&mut self) {
+        unsafe { libc::flock(self.0.as_raw_fd(), libc::LOCK_UN) };
+    }
+}
+
+fn read_file(path: &Path) -> io::Result<Vec<u8>> {
+    let mut contents = Vec::new();
+    File::open(path).and_then(|mut file| file.read_to_end(&mut contents))?;
+    Ok(contents)
+}
+
+/// Replaces the file at `path` without ever leaving it missing or half written, by writing a
+/// temporary file next to it and renaming that over it. The new file gets the mode and owner of
+/// the file it replaces.
+fn write_atomically(path: &Path, contents: &[u8]) -> io::Result<()> {
+    let temp_path = temp_path(path);
+    let result = write_temp_file(path, &temp_path, contents)
+        .and_then(|_| fs::rename(&temp_path, path));
+    if result.is_err() {
+        let _ = fs::remove_file(&temp_path);
+    }
+    result
+}
+
+fn temp_path(path: &Path) -> PathBuf {
+    path.with_file_name(format!(".{}.mullvad-tmp", file_name(path)))
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
+
+fn write_temp_file(path: &Path, temp_path: &Path, contents: &[u8]) -> io::Result<()> {
+    let mut file = File::create(temp_path)?;
+    file.write_all(contents)?;
+    if let Ok(metadata) = fs::metadata(path) {
+        fs::set_permissions(temp_path, metadata.permissions())?;
+        chown(temp_path, metadata.uid(), metadata.gid())?;
+    }
+    file.sync_all()
+}
+
+fn chown(path: &Path, uid: u32, gid: u32) -> io::Result<()> {
+    let path = CString::new(path.as_os_str().as_bytes())
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    if unsafe { libc::chown(path.as_ptr(), uid, gid) } == 0 {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
+/// Checks if the nameservers in the resolv.conf are exactly the given ones, in the same order.
+fn uses_nameservers(contents: &str, servers: &[IpAddr]) -> bool {
+    let current: Vec<Option<IpAddr>> = contents
+        .lines()
+        .filter_map(|line| {
+            let mut words = line.split_whitespace();
+            match words.next() {
+                Some("nameserver") => Some(words.next().and_then(|address| address.parse().ok())),
+                _ => None,
+            }
+        })
+        .collect();
+    current == servers.iter().cloned().map(Some).collect::<Vec<_>>()
+}
+
+/// Returns the given nameservers followed by the ones in the `original` resolv.conf, as many as
+/// the resolver uses.
+fn prepend_nameservers(servers: &[IpAddr], original: &str) -> Vec<IpAddr> {
+    let mut nameservers = servers.to_vec();
+    let system_nameservers = original.lines().filter_map(|line| {
+        let mut words = line.split_whitespace();
+        match words.next() {
+            Some("nameserver") => words.next().and_then(|address| address.parse().ok()),
+            _ => None,
+        }
+    });
+    for server in system_nameservers {
+        if !nameservers.contains(&server) {
+            nameservers.push(server);
+        }
+    }
+    if nameservers.len() > MAX_NAMESERVERS {
+        warn!(
+            "Only using the first {} of the nameservers, the resolver ignores the rest",
+            MAX_NAMESERVERS
+        );
+        nameservers.truncate(MAX_NAMESERVERS);
+    }
+    nameservers
+}
+
+/// Returns the `original` resolv.conf with its nameservers replaced by the given ones. Every
+/// other line, including comments and directives we do not know about, is kept as it is. The
+/// nameservers go where the first original one was, or last if there were none.
+fn merge_ignoring_nameservers(original: &str, servers: &[IpAddr]) -> String {
+    let nameservers: String = servers
+        .iter()
+        .map(|server| format!("nameserver {}\n", server))
+        .collect();
+    let mut contents = format!("{}\n", HEADER);
+    let mut nameservers_written = false;
+    for line in original.lines() {
+        if line == HEADER {
+            continue;
+        }
+        if line.split_whitespace().next() == Some("nameserver") {
+            if !nameservers_written {
+                contents.push_str(&nameservers);
+                nameservers_written = true;
+            }
+            continue;
+        }
+        contents.push_str(line);
+        contents.push('\n');
+    }
+    if !nameservers_written {
+        contents.push_str(&nameservers);
+    }
+    contents
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mktemp::TempFile;
+    use std::io::{BufRead, BufReader};
+    use std::os::unix::fs::{symlink, PermissionsExt};
+    use std::process::{Command, Stdio};
+
+    /// A temporary file standing in for resolv.conf. Also removes the lock file next to it when
+    /// dropped.
+    struct TestFile(TempFile);
+
+    impl TestFile {
+        fn new() -> Self {
+            TestFile(TempFile::new())
+        }
+
+        fn to_path_buf(&self) -> PathBuf {
+            self.0.to_path_buf()
+        }
+    }
+
+    impl AsRef<Path> for TestFile {
+        fn as_ref(&self) -> &Path {
+            self.0.as_ref()
+        }
+    }
+
+    impl Drop for TestFile {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(StaticFile::new(self.to_path_buf()).lock_path);
+        }
+    }
+
+    fn read(path: &TestFile) -> String {
+        let mut contents = String::new();
+        File::open(path)
+            .unwrap()
+            .read_to_string(&mut contents)
+            .unwrap();
+        contents
+    }
+
+    #[test]
+    fn set_dns_and_restore() {
+        let path = TestFile::new();
+        let original = "# Written by hand\nnameserver 192.168.1.1\n";
+        File::create(&path)
+            .unwrap()
+            .write_all(original.as_bytes())
+            .unwrap();
+
+        let mut manager = StaticFile::new(path.to_path_buf());
+        manager
+            .set_dns("tun0", &["10.8.0.1".parse().unwrap()])
+            .unwrap();
+        manager
+            .set_dns("tun0", &["10.9.0.1".parse().unwrap()])
+            .unwrap();
+        assert_eq!(
+            read(&path),
+            "# Generated by Mullvad VPN\n# Written by hand\nnameserver 10.9.0.1\n"
+        );
+
+        manager.reset().unwrap();
+        assert_eq!(read(&path), original);
+        assert!(!manager.backup_path.exists());
+    }
+
+    #[test]
+    fn keeps_everything_but_nameservers() {
+        let original = "# Written by hand\n\
+                        domain example.com\n\
+                        search example.com corp.example.com\n\
+                        nameserver 192.168.1.1\n\
+                        ; The backup nameserver\n\
+                        nameserver 192.168.1.2\n\
+                        options ndots:2 timeout:3\n\
+                        \n\
+                        sortlist 130.155.160.0/255.255.240.0\n\
+                        lookup file bind\n";
+        let merged = merge_ignoring_nameservers(original, &["10.8.0.1".parse().unwrap()]);
+        assert_eq!(
+            merged,
+            "# Generated by Mullvad VPN\n\
+             # Written by hand\n\
+             domain example.com\n\
+             search example.com corp.example.com\n\
+             nameserver 10.8.0.1\n\
+             ; The backup nameserver\n\
+             options ndots:2 timeout:3\n\
+             \n\
+             sortlist 130.155.160.0/255.255.240.0\n\
+             lookup file bind\n"
+        );
+
+        // Merging into a file we wrote ourselves does not add another header.
+        assert_eq!(
+            merge_ignoring_nameservers(&merged, &["10.9.0.1".parse().unwrap()]),
+            merged.replace("10.8.0.1", "10.9.0.1")
+        );
+    }
+
+    #[test]
+    fn prepends_nameservers_up_to_the_limit() {
+        let original = "search example.com\n\
+                        nameserver 192.168.1.1\n\
+                        nameserver 10.8.0.1\n\
+                        nameserver 192.168.1.2\n\
+                        nameserver 192.168.1.3\n";
+        let servers = ["10.8.0.1".parse().unwrap()];
+        assert_eq!(
+            prepend_nameservers(&servers, original),
+            vec![
+                servers[0],
+                "192.168.1.1".parse::<IpAddr>().unwrap(),
+                "192.168.1.2".parse::<IpAddr>().unwrap(),
+            ]
+        );
+
+        let path = TestFile::new();
+        File::create(&path)
+            .unwrap()
+            .write_all(original.as_bytes())
+            .unwrap();
+        let mut manager = StaticFile::new(path.to_path_buf());
+        manager.prepend_dns("tun0", &servers).unwrap();
+        assert_eq!(
+            read_file(path.as_ref()).unwrap(),
+            b"# Generated by Mullvad VPN\n\
+              search example.com\n\
+              nameserver 10.8.0.1\n\
+              nameserver 192.168.1.1\n\
+              nameserver 192.168.1.2\n"
+                .to_vec()
+        );
+        manager.reset().unwrap();
+        assert_eq!(
+            read_file(path.as_ref()).unwrap(),
+            original.as_bytes().to_vec()
+        );
+    }
+
+    #[test]
+    fn mixed_nameservers_round_trip() {
+        let servers: Vec<IpAddr> = ["10.8.0.1", "fdda:d0d0:cafe:1194::1", "2001:db8::53"]
+            .iter()
+            .map(|server| server.parse().unwrap())
+            .collect();
+        let contents = merge_ignoring_nameservers("search example.com\n", &servers);
+        let parsed: Vec<IpAddr> = contents
+            .lines()
+            .filter(|line| line.starts_with("nameserver "))
+            .map(|line| line["nameserver ".len()..].parse().unwrap())
+            .collect();
+        assert_eq!(parsed, servers);
+        assert!(contents.contains("nameserver fdda:d0d0:cafe:1194::1\n"));
+    }
+
+    #[test]
+    fn missing_file_is_treated_as_empty() {
+        let path = TestFile::new();
+        let mut manager = StaticFile::new(path.to_path_buf());
+        manager
+            .set_dns("tun0", &["10.8.0.1".parse().unwrap()])
+            .unwrap();
+        assert_eq!(
+            read(&path),
+            "# Generated by Mullvad VPN\nnameserver 10.8.0.1\n"
+        );
+
+        manager.reset().unwrap();
+        assert_eq!(read(&path), "");
+        assert!(!manager.backup_path.exists());
+    }
+
+    #[test]
+    fn lock_times_out() {
+        // Every acquire opens the lock file anew, so the two locks are on separate file
+        // descriptors, just like when taken by separate processes.
+        let path = TempFile::new();
+        let _lock = FileLock::acquire(path.as_ref(), Duration::from_millis(0)).unwrap();
+        let error = FileLock::acquire(path.as_ref(), Duration::from_millis(50)).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::TimedOut);
+    }
+
+    #[test]
+    fn lock_is_respected_between_processes() {
+        let path = TempFile::new();
+        // flock(1) takes the same kind of lock, just like a hook script writing the file would.
+        let mut holder = Command::new("flock")
+            .arg(path.as_ref())
+            .args(&["sh", "-c", "echo locked && sleep 0.5"])
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap();
+        let mut line = String::new();
+        BufReader::new(holder.stdout.take().unwrap())
+            .read_line(&mut line)
+            .unwrap();
+        assert_eq!(line, "locked\n");
+
+        let error = FileLock::acquire(path.as_ref(), Duration::from_millis(50)).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::TimedOut);
+
+        assert!(holder.wait().unwrap().success());
+        FileLock::acquire(path.as_ref(), Duration::from_millis(0)).unwrap();
+    }
+
+    #[test]
+    fn concurrent_writers_leave_file_well_formed() {
+        let path = TestFile::new();
+        File::create(&path)
+            .unwrap()
+            .write_all(b"search example.com\n")
+            .unwrap();
+
+        let servers: Vec<IpAddr> = vec!["10.8.0.1".parse().unwrap(), "10.9.0.1".parse().unwrap()];
+        let writers: Vec<_> = servers
+            .iter()
+            .map(|server| {
+                // Each manager takes the lock on its own file descriptor, like separate processes.
+                let mut manager = StaticFile::new(path.to_path_buf());
+                let servers = [*server];
+                thread::spawn(move || {
+                    for _ in 0..50 {
+                        manager.set_dns("tun0", &servers).unwrap();
+                    }
+                    manager
+                })
+            })
+            .collect();
+        let mut managers: Vec<StaticFile> = writers
+            .into_iter()
+            .map(|writer| writer.join().unwrap())
+            .collect();
+
+        let contents = read(&path);
+        let well_formed: Vec<String> = servers
+            .iter()
+            .map(|server| merge_ignoring_nameservers("search example.com\n", &[*server]))
+            .collect();
+        assert!(well_formed.contains(&contents), "{}", contents);
+
+        managers[0].reset().unwrap();
+        assert_eq!(read(&path), "search example.com\n");
+    }
+
+    #[test]
+    fn recovers_after_crash() {
+        let path = TestFile::new();
+        let original = "nameserver 192.168.1.1\n";
+        File::create(&path)
+            .unwrap()
+            .write_all(original.as_bytes())
+            .unwrap();
+        let servers = ["10.8.0.1".parse().unwrap()];
+
+        // The daemon crashes while connected, leaving only the backup file behind.
+        StaticFile::new(path.to_path_buf())
+            .set_dns("tun0", &servers)
+            .unwrap();
+        let mut manager = StaticFile::new(path.to_path_buf());
+        assert!(manager.backup_path.exists());
+
+        // Connecting again must not replace the backup with our own configuration.
+        manager.set_dns("tun0", &servers).unwrap();
+        drop(manager);
+
+        let mut manager = StaticFile::new(path.to_path_buf());
+        manager.recover().unwrap();
+        assert_eq!(read(&path), original);
+        assert!(!manager.backup_path.exists());
+
+        // Nothing to do when there is no backup.
+        manager.recover().unwrap();
+        assert_eq!(read(&path), original);
+    }
+
+    #[test]
+    fn replaces_file_atomically_keeping_permissions() {
+        let path = TestFile::new();
+        File::create(&path).unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o640)).unwrap();
+        let original_inode = fs::metadata(&path).unwrap().ino();
+
+        let mut manager = StaticFile::new(path.to_path_buf());
+        manager
+            .set_dns("tun0", &["10.8.0.1".parse().unwrap()])
+            .unwrap();
+
+        let metadata = fs::metadata(&path).unwrap();
+        assert_ne!(metadata.ino(), original_inode);
+        assert_eq!(metadata.permissions().mode() & 0o777, 0o640);
+        assert!(!temp_path(path.as_ref()).exists());
+        manager.reset().unwrap();
+    }
+
+    #[test]
+    fn refuses_to_replace_symlink() {
+        let target = TestFile::new();
+        File::create(&target)
+            .unwrap()
+            .write_all(b"nameserver 127.0.0.53\n")
+            .unwrap();
+        let link = TestFile::new();
+        symlink(&target, &link).unwrap();
+
+        let mut manager = StaticFile::new(link.to_path_buf());
+        let error = manager
+            .set_dns("tun0", &["10.8.0.1".parse().unwrap()])
+            .unwrap_err();
+        match *error.kind() {
+            ErrorKind::ResolvConfIsSymlink(ref path) => assert_eq!(path, link.as_ref()),
+            ref kind => panic!("Unexpected error: {}", kind),
+        }
+        assert_eq!(read(&target), "nameserver 127.0.0.53\n");
+        assert!(fs::symlink_metadata(&link)
+            .unwrap()
+            .file_type()
+            .is_symlink());
+    }
+
+    #[test]
+    fn compares_nameservers() {
+        let servers = ["10.8.0.1".parse().unwrap(), "fd00::1".parse().unwrap()];
+        let contents = "search example.com\nnameserver 10.8.0.1\nnameserver  fd00::1\n";
+        assert!(uses_nameservers(contents, &servers));
+        assert!(!uses_nameservers(contents, &servers[..1]));
+        assert!(!uses_nameservers(
+            "nameserver fd00::1\nnameserver 10.8.0.1\n",
+            &servers
+        ));
+        assert!(!uses_nameservers(
+            "nameserver 10.8.0.1\nnameserver fd00::1\nnameserver dns.example.com\n",
+            &servers
+        ));
+    }
+
+    #[test]
+    fn is_active_while_the_file_uses_our_nameservers() {
+        let path = TestFile::new();
+        File::create(&path)
+            .unwrap()
+            .write_all(b"nameserver 192.168.1.1\n")
+            .unwrap();
+        let mut manager = StaticFile::new(path.to_path_buf());
+        assert!(!manager.is_active());
+
+        manager
+            .set_dns("tun0", &["10.8.0.1".parse().unwrap()])
+            .unwrap();
+        assert!(manager.is_active());
+
+        File::create(&path)
+            .unwrap()
+            .write_all(b"nameserver 192.168.1.1\n")
+            .unwrap();
+        assert!(!manager.is_active());
+
+        manager
+            .set_dns("tun0", &["10.8.0.1".parse().unwrap()])
+            .unwrap();
+        assert!(manager.is_active());
+        manager.reset().unwrap();
+        assert!(!manager.is_active());
+    }
+
+    #[test]
+    fn puts_nameservers_back_when_overwritten() {
+        let path = TestFile::new();
+        let original = "nameserver 192.168.1.1\n";
+        File::create(&path)
+            .unwrap()
+            .write_all(original.as_bytes())
+            .unwrap();
+        let settle_time = Duration::from_millis(3 * ::mpsc::DEFAULT_DEBOUNCE_WINDOW_MS);
+
+        let mut manager = StaticFile::monitored(path.to_path_buf());
+        manager
+            .set_dns("tun0", &["10.8.0.1".parse().unwrap()])
+            .unwrap();
+
+        // A DHCP client writes the nameserver it got from the network.
+        File::create(&path)
+            .unwrap()
+            .write_all(b"search example.com\nnameserver 192.168.1.1\n")
+            .unwrap();
+        let start = Instant::now();
+        while read(&path) != "# Generated by Mullvad VPN\nsearch example.com\nnameserver 10.8.0.1\n"
+        {
+            assert!(start.elapsed() < Duration::from_secs(5), "Never restored");
+            thread::sleep(Duration::from_millis(50));
+        }
+
+        // Every write replaces the file, so an unchanged inode means it is not written again.
+        let inode = fs::metadata(&path).unwrap().ino();
+        thread::sleep(settle_time);
+        assert_eq!(fs::metadata(&path).unwrap().ino(), inode);
+
+        manager.reset().unwrap();
+        assert_eq!(read(&path), original);
+        File::create(&path)
+            .unwrap()
+            .write_all(b"nameserver 192.168.1.2\n")
+            .unwrap();
+        thread::sleep(settle_time);
+        assert_eq!(read(&path), "nameserver 192.168.1.2\n");
+    }
+}
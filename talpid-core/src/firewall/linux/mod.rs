@@ -0,0 +1,527 @@
+use super::{Firewall, SecurityPolicy, SplitTunnelExclusion};
+
+use error_chain::ChainedError;
+
+use std::fmt;
+use std::net::IpAddr;
+use std::path::Path;
+use std::sync::mpsc;
+use std::time::Duration;
+
+mod dns;
+mod nftables;
+mod ruleset_monitor;
+
+use self::dns::{DnsGuard, DnsManager, DnsManagerKind};
+use self::ruleset_monitor::RulesetMonitor;
+pub use self::dns::DnsEvent;
+
+error_chain! {
+    links {
+        Dns(self::dns::Error, self::dns::ErrorKind) #[doc = "DNS error"];
+        Nftables(self::nftables::Error, self::nftables::ErrorKind) #[doc = "nftables error"];
+    }
+}
+
+/// Undoes DNS changes left behind by a daemon that was killed before it could reset them. Meant
+/// to be run when the daemon is not running, for example after it has stopped or at boot.
+pub fn restore_dns_if_needed() -> Result<()> {
+    restore_dns_if_needed_at(Path::new(dns::DEFAULT_RESOLV_CONF_PATH))
+}
+
+/// Does the same as `restore_dns_if_needed`, for a system whose resolv.conf is at
+/// `resolv_conf`.
+pub fn restore_dns_if_needed_at(resolv_conf: &Path) -> Result<()> {
+    Ok(dns::restore_dns_if_needed(resolv_conf)?)
+}
+
+/// The Linux implementation for the `Firewall` trait. Blocks all traffic not allowed by the
+/// policy with nftables.
+pub struct Netfilter {
+    /// Points DNS at the tunnel, through whatever program is in charge of DNS on the system.
+    dns_manager: DnsGuard,
+    /// If DNS lookups are redirected to the tunnel by the firewall instead, leaving the DNS
+    /// config of the system alone.
+    redirect_dns: bool,
+    /// Loads and removes our nftables rules, and puts them back if they go missing.
+    rules: RulesetMonitor,
+    /// If the rules loaded now redirect DNS lookups to the tunnel.
+    dns_redirected: bool,
+}
+
+impl Firewall for Netfilter {
+    type Error = Error;
+
+    fn new() -> Result<Self> {
+        Self::with_resolv_conf(Path::new(dns::DEFAULT_RESOLV_CONF_PATH))
+    }
+
+    fn apply_policy(&mut self, policy: SecurityPolicy) -> Result<()> {
+        let result = self.set_policy(&policy);
+        if let Err(ref error) = result {
+            error!(
+                "Unable to apply the security policy {:?}: {}",
+                policy,
+                error.display_chain()
+            );
+        }
+        result
+    }
+
+    fn preview_policy(&self, policy: &SecurityPolicy) -> Vec<String> {
+        self.steps(policy)
+            .iter()
+            .map(|step| step.to_string())
+            .collect()
+    }
+
+    fn reset_policy(&mut self) -> Result<()> {
+        self.dns_redirected = false;
+        // Every step is run even if an earlier one fails, to undo as much as possible.
+        let results = teardown_steps()
+            .into_iter()
+            .map(|step| self.run_step(step))
+            .collect::<Vec<_>>();
+        let result = results.into_iter().collect::<Result<Vec<_>>>().map(|_| ());
+        if let Err(ref error) = result {
+            error!(
+                "Unable to reset the security policy: {}",
+                error.display_chain()
+            );
+        }
+        result
+    }
+}
+
+impl Netfilter {
+    /// Creates a firewall for a system whose resolv.conf is at `resolv_conf` rather than at
+    /// the usual place, like in some containers.
+    pub fn with_resolv_conf(resolv_conf: &Path) -> Result<Self> {
+        let kind = dns::detect_dns_manager(resolv_conf);
+        // systemd-resolved and NetworkManager keep nameservers per link, so they can be set on
+        // the tunnel link alone. Other backends change the nameservers of the whole system, which
+        // is only done if the lookups can not be redirected to the tunnel instead.
+        let redirect_dns = match kind {
+            DnsManagerKind::SystemdResolved | DnsManagerKind::NetworkManager => false,
+            DnsManagerKind::Resolvconf | DnsManagerKind::StaticFile => {
+                nftables::supports_dns_redirect()
+            }
+        };
+        if redirect_dns {
+            debug!("Redirecting DNS to the tunnel in the firewall");
+        } else {
+            debug!("Managing DNS with the {:?} backend", kind);
+        }
+        let mut dns_manager = dns::dns_manager(kind, resolv_conf);
+        if let Err(error) = dns_manager.recover() {
+            error!(
+                "Unable to undo DNS changes from an earlier run: {}",
+                error.display_chain()
+            );
+        }
+        Ok(Netfilter {
+            dns_manager,
+            redirect_dns,
+            rules: RulesetMonitor::spawn(
+                Duration::from_millis(ruleset_monitor::DEFAULT_CHECK_INTERVAL_MS),
+                nftables::load,
+                nftables::remove,
+                nftables::is_loaded,
+            ),
+            dns_redirected: false,
+        })
+    }
+
+    /// Checks if DNS lookups currently go to the tunnel nameservers, either because the system
+    /// DNS configuration points at them or because the firewall redirects lookups to them.
+    pub fn is_dns_overridden(&self) -> bool {
+        self.dns_redirected || self.dns_manager.is_active()
+    }
+
+    /// Sets how often to check that our rules have not been removed by some other program.
+    pub fn set_rule_check_interval(&self, interval: Duration) {
+        self.rules.set_interval(interval);
+    }
+
+    /// Returns a channel on which changes to the DNS configuration set by the firewall are
+    /// reported, including other programs overwriting it and it being restored after that.
+    pub fn subscribe_dns_events(&self) -> mpsc::Receiver<DnsEvent> {
+        self.dns_manager.subscribe()
+    }
+
+    fn set_policy(&mut self, policy: &SecurityPolicy) -> Result<()> {
+        self.dns_redirected = false;
+        for step in self.steps(policy) {
+            self.run_step(step)?;
+        }
+        // Only connected policies redirect lookups, see `nftables::ruleset`.
+        self.dns_redirected = match *policy {
+            SecurityPolicy::Connected { .. } => self.redirects_dns(policy),
+            _ => false,
+        };
+        Ok(())
+    }
+
+    fn redirects_dns(&self, policy: &SecurityPolicy) -> bool {
+        // Redirecting would send the lookups meant for the system nameservers to the tunnel too.
+        self.redirect_dns && !keeps_system_nameservers(policy)
+    }
+
+    /// Returns what has to be done to enforce the policy. Both applying and previewing a policy
+    /// go through here, so the preview always shows what would actually be done.
+    fn steps(&self, policy: &SecurityPolicy) -> Vec<Step> {
+        let redirect_dns = self.redirects_dns(policy);
+        let mut steps = vec![Step::LoadRuleset(nftables::ruleset(policy, redirect_dns))];
+        match *policy {
+            SecurityPolicy::Connected {
+                ref tunnel,
+                keep_system_nameservers,
+                ..
+            } => if !redirect_dns {
+                steps.push(Step::SetDns {
+                    interface: tunnel.interface.clone(),
+                    servers: policy.dns_servers(),
+                    keep_system_nameservers,
+                });
+            },
+            // The tunnel is gone, so its nameservers can not be reached anymore.
+            SecurityPolicy::Blocked { .. } => steps.push(Step::ResetDns),
+            // DNS is left as it is until the tunnel is up.
+            SecurityPolicy::Connecting { .. } => (),
+        }
+        steps
+    }
+
+    fn run_step(&mut self, step: Step) -> Result<()> {
+        match step {
+            Step::LoadRuleset(ruleset) => self.rules.load(ruleset)?,
+            Step::SetDns {
+                interface,
+                servers,
+                keep_system_nameservers,
+            } => dns::set_dns(
+                &mut self.dns_manager,
+                &interface,
+                &servers,
+                keep_system_nameservers,
+            )?,
+            Step::ResetDns => self.dns_manager.reset()?,
+            Step::RemoveRuleset => self.rules.remove()?,
+        }
+        Ok(())
+    }
+}
+
+
+/// Returns what has to be done to stop enforcing any policy, in order. DNS is restored while our
+/// rules still block everything but the tunnel. Removing the rules first would open a window
+/// where lookups still go to the tunnel nameservers, which are then reached outside the tunnel
+/// over the default route if the tunnel is down. In this order, lookups to the nameservers of
+/// the system are blocked for a moment instead, which fails them rather than leaking them.
+fn teardown_steps() -> Vec<Step> {
+    vec![Step::ResetDns, Step::RemoveRuleset]
+}
+
+fn keeps_system_nameservers(policy: &SecurityPolicy) -> bool {
+    match *policy {
+        SecurityPolicy::Connected {
+            keep_system_nameservers,
+            ..
+        } => keep_system_nameservers,
+        _ => false,
+    }
+}
+
+
+/// One step of enforcing a security policy.
+#[derive(Debug, Clone, Eq, PartialEq)]
+enum Step {
+    /// Replaces our nftables rules with the given ruleset.
+    LoadRuleset(String),
+    /// Points DNS at the given servers over the given interface, optionally keeping the
+    /// nameservers of the system after them.
+    SetDns {
+        interface: String,
+        servers: Vec<IpAddr>,
+        keep_system_nameservers: bool,
+    },
+    /// Removes our DNS configuration.
+    ResetDns,
+    /// Removes our nftables rules, letting all traffic through.
+    RemoveRuleset,
+}
+
+impl fmt::Display for Step {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Step::LoadRuleset(ref ruleset) => write!(f, "nft -f - <<EOF\n{}EOF", ruleset),
+            Step::SetDns {
+                ref interface,
+                ref servers,
+                keep_system_nameservers,
+            } => {
+                let servers = servers
+                    .iter()
+                    .map(|server| server.to_string())
+                    .collect::<Vec<_>>();
+                write!(f, "set DNS for {} to [{}]", interface, servers.join(", "))?;
+                if keep_system_nameservers {
+                    f.write_str(" followed by the system nameservers")?;
+                }
+                Ok(())
+            }
+            Step::ResetDns => f.write_str("reset DNS"),
+            Step::RemoveRuleset => f.write_str("remove nftables rules"),
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::env;
+    use std::fs::{self, File};
+    use std::io::Read;
+    use talpid_types::net::{Endpoint, TransportProtocol};
+    use tunnel::TunnelMetadata;
+    use uuid::Uuid;
+
+    thread_local! {
+        /// What the test firewall has done, in the format of the preview.
+        static APPLIED_STEPS: RefCell<Vec<String>> = RefCell::new(Vec::new());
+    }
+
+    fn record(step: Step) {
+        APPLIED_STEPS.with(|steps| steps.borrow_mut().push(step.to_string()));
+    }
+
+    fn take_applied_steps() -> Vec<String> {
+        APPLIED_STEPS.with(|steps| steps.borrow_mut().drain(..).collect())
+    }
+
+    fn record_ruleset(ruleset: &str) -> nftables::Result<()> {
+        record(Step::LoadRuleset(ruleset.to_owned()));
+        Ok(())
+    }
+
+    fn record_removal() -> nftables::Result<()> {
+        record(Step::RemoveRuleset);
+        Ok(())
+    }
+
+    struct RecordingDnsManager;
+
+    impl DnsManager for RecordingDnsManager {
+        fn set_dns(&mut self, interface: &str, servers: &[IpAddr]) -> dns::Result<()> {
+            record(Step::SetDns {
+                interface: interface.to_owned(),
+                servers: servers.to_vec(),
+                keep_system_nameservers: false,
+            });
+            Ok(())
+        }
+
+        fn prepend_dns(&mut self, interface: &str, servers: &[IpAddr]) -> dns::Result<()> {
+            record(Step::SetDns {
+                interface: interface.to_owned(),
+                servers: servers.to_vec(),
+                keep_system_nameservers: true,
+            });
+            Ok(())
+        }
+
+        fn reset(&mut self) -> dns::Result<()> {
+            record(Step::ResetDns);
+            Ok(())
+        }
+    }
+
+    fn test_firewall(redirect_dns: bool) -> Netfilter {
+        Netfilter {
+            dns_manager: DnsGuard::new(Box::new(RecordingDnsManager)),
+            redirect_dns,
+            rules: RulesetMonitor::spawn(
+                Duration::from_millis(ruleset_monitor::DEFAULT_CHECK_INTERVAL_MS),
+                record_ruleset,
+                record_removal,
+                || Ok(true),
+            ),
+            dns_redirected: false,
+        }
+    }
+
+    /// Returns a connecting, a connected and a blocked policy.
+    fn policies() -> Vec<SecurityPolicy> {
+        let relay_endpoint = Endpoint::new(
+            "185.65.134.1".parse::<IpAddr>().unwrap(),
+            1194,
+            TransportProtocol::Udp,
+        );
+        vec![
+            SecurityPolicy::Connecting {
+                relay_endpoint,
+                allow_lan: false,
+                allow_ipv6: true,
+                exclusions: vec![],
+            },
+            SecurityPolicy::Connected {
+                relay_endpoint,
+                tunnel: TunnelMetadata {
+                    interface: "tun0".to_owned(),
+                    ip: "10.8.0.2".parse().unwrap(),
+                    gateway: "10.8.0.1".parse().unwrap(),
+                    ipv6_gateway: Some("fdda:d0d0:cafe:1194::1".parse().unwrap()),
+                },
+                dns_servers: vec![
+                    "10.8.0.1".parse().unwrap(),
+                    "fdda:d0d0:cafe:1194::1".parse().unwrap(),
+                ],
+                allow_lan: false,
+                allow_ipv6: true,
+                keep_system_nameservers: false,
+                exclusions: vec![],
+            },
+            SecurityPolicy::Blocked {
+                allow_lan: true,
+                allow_ipv6: true,
+                exclusions: vec![],
+            },
+        ]
+    }
+
+    #[test]
+    fn preview_matches_what_is_applied() {
+        let mut policies = policies();
+        let mut keeping_policy = policies[1].clone();
+        if let SecurityPolicy::Connected {
+            ref mut keep_system_nameservers,
+            ..
+        } = keeping_policy
+        {
+            *keep_system_nameservers = true;
+        }
+        policies.push(keeping_policy);
+
+        for &redirect_dns in &[false, true] {
+            let mut firewall = test_firewall(redirect_dns);
+            // Drop the reset done when the previous firewall was dropped.
+            take_applied_steps();
+            for policy in policies.clone() {
+                let preview = firewall.preview_policy(&policy);
+                firewall.apply_policy(policy).unwrap();
+                assert_eq!(preview, take_applied_steps());
+            }
+        }
+    }
+
+    #[test]
+    fn preview_connected_policy() {
+        let policy = policies().remove(1);
+        let preview = test_firewall(false).preview_policy(&policy);
+        assert_eq!(preview.len(), 2);
+        assert_eq!(
+            preview[0],
+            format!("nft -f - <<EOF\n{}EOF", nftables::ruleset(&policy, false))
+        );
+        assert_eq!(
+            preview[1],
+            "set DNS for tun0 to [10.8.0.1, fdda:d0d0:cafe:1194::1]"
+        );
+
+        let redirect_preview = test_firewall(true).preview_policy(&policy);
+        assert_eq!(redirect_preview.len(), 1);
+        assert!(redirect_preview[0].contains("chain nat_output"));
+    }
+
+    #[test]
+    fn keeping_system_nameservers_disables_redirect() {
+        let mut policy = policies().remove(1);
+        if let SecurityPolicy::Connected {
+            ref mut keep_system_nameservers,
+            ..
+        } = policy
+        {
+            *keep_system_nameservers = true;
+        }
+        let preview = test_firewall(true).preview_policy(&policy);
+        assert!(!preview[0].contains("chain nat_output"));
+        assert_eq!(
+            preview[1],
+            "set DNS for tun0 to [10.8.0.1, fdda:d0d0:cafe:1194::1] followed by the system \
+             nameservers"
+        );
+    }
+
+    #[test]
+    fn dns_is_restored_before_the_rules_are_removed() {
+        let mut firewall = test_firewall(false);
+        firewall.apply_policy(policies().remove(1)).unwrap();
+        take_applied_steps();
+
+        firewall.reset_policy().unwrap();
+        assert_eq!(
+            take_applied_steps(),
+            vec!["reset DNS".to_owned(), "remove nftables rules".to_owned()]
+        );
+    }
+
+    #[test]
+    fn dns_servers_are_written_in_priority_order() {
+        let dir = env::temp_dir().join(Uuid::new_v4().to_string());
+        fs::create_dir(&dir).unwrap();
+        let resolv_conf = dir.join("resolv.conf");
+        File::create(&resolv_conf).unwrap();
+
+        let mut firewall = Netfilter {
+            dns_manager: dns::dns_manager(DnsManagerKind::StaticFile, &resolv_conf),
+            ..test_firewall(false)
+        };
+        let mut policy = policies().remove(1);
+        if let SecurityPolicy::Connected {
+            ref mut dns_servers,
+            ..
+        } = policy
+        {
+            *dns_servers = vec!["10.8.0.1".parse().unwrap(), "10.9.0.1".parse().unwrap()];
+        }
+        firewall.apply_policy(policy).unwrap();
+
+        let mut contents = String::new();
+        File::open(&resolv_conf)
+            .unwrap()
+            .read_to_string(&mut contents)
+            .unwrap();
+        let nameservers = contents
+            .lines()
+            .filter(|line| line.starts_with("nameserver"))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            nameservers,
+            vec!["nameserver 10.8.0.1", "nameserver 10.9.0.1"]
+        );
+
+        drop(firewall);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn reports_if_dns_is_overridden() {
+        let policies = policies();
+        for &redirect_dns in &[false, true] {
+            let mut firewall = test_firewall(redirect_dns);
+            assert!(!firewall.is_dns_overridden());
+            firewall.apply_policy(policies[0].clone()).unwrap();
+            assert!(!firewall.is_dns_overridden());
+            firewall.apply_policy(policies[1].clone()).unwrap();
+            assert!(firewall.is_dns_overridden());
+            firewall.apply_policy(policies[2].clone()).unwrap();
+            assert!(!firewall.is_dns_overridden());
+
+            firewall.apply_policy(policies[1].clone()).unwrap();
+            firewall.reset_policy().unwrap();
+            assert!(!firewall.is_dns_overridden());
+        }
+    }
+}
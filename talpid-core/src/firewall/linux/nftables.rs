@@ -0,0 +1,864 @@
+use duct;
+
+use std::fmt::Write;
+use std::net::IpAddr;
+
+use talpid_types::net::{Endpoint, TransportProtocol};
+
+use super::{SecurityPolicy, SplitTunnelExclusion};
+
+error_chain! {
+    errors {
+        NftFailed(stderr: String) {
+            description("Error while running nft")
+            display("nft failed: {}", stderr)
+        }
+    }
+}
+
+const TABLE_NAME: &str = "mullvad";
+
+/// Lets the computer get and renew DHCP and DHCPv6 leases and solicit IPv6 routers and neighbors,
+/// without which it would drop off the network. DHCPv6 clients always talk from a link-local
+/// address, and neighbor discovery packets always have a hop limit of 255, which keeps these from
+/// matching anything but configuration of the local link.
+const NETWORK_CONFIG_OUTPUT_RULES: &[&str] = &[
+    "meta nfproto ipv4 udp sport 68 udp dport 67 accept",
+    "ip6 saddr fe80::/10 udp sport 546 udp dport 547 accept",
+    "ip6 daddr ff02::2 icmpv6 type nd-router-solicit ip6 hoplimit 255 accept",
+    "icmpv6 type { nd-neighbor-solicit, nd-neighbor-advert } ip6 hoplimit 255 accept",
+];
+const NETWORK_CONFIG_INPUT_RULES: &[&str] = &[
+    "meta nfproto ipv4 udp sport 67 udp dport 68 accept",
+    "ip6 daddr fe80::/10 udp sport 547 udp dport 546 accept",
+    "ip6 saddr fe80::/10 icmpv6 type nd-router-advert ip6 hoplimit 255 accept",
+    "icmpv6 type { nd-neighbor-solicit, nd-neighbor-advert } ip6 hoplimit 255 accept",
+];
+
+/// The private IPv4 networks and the link-local network, reachable when LAN access is allowed.
+const LAN_NETS_V4: &str = "{ 10.0.0.0/8, 172.16.0.0/12, 192.168.0.0/16, 169.254.0.0/16 }";
+/// The IPv4 multicast addresses used for discovering services on the local network.
+const LAN_MULTICAST_V4: &str = "{ 224.0.0.0/24, 239.255.255.250 }";
+const LAN_NETS_V6: &str = "fe80::/10";
+/// The IPv6 link-local scope multicast addresses, also needed for neighbor discovery.
+const LAN_MULTICAST_V6: &str = "ff02::/16";
+
+/// Runs before the output filter chain, so redirected lookups are filtered with their new
+/// destination.
+const NAT_CHAIN_HEADER: &str = "type nat hook output priority -100; policy accept;";
+
+/// The firewall mark given to packets from excluded cgroups, so they can be routed outside the
+/// tunnel.
+pub const EXCLUDED_MARK: u32 = 0x6d6f_6c65;
+
+/// Runs before the NAT and filter chains. Packets whose mark is changed here are routed again.
+const MARK_CHAIN_HEADER: &str = "type route hook output priority -150; policy accept;";
+
+
+/// Loads a ruleset made by `ruleset`, replacing the rules of our table. The whole ruleset is
+/// loaded as one transaction, so traffic is never let through while switching policies.
+pub fn load(ruleset: &str) -> Result<()> {
+    run_nft(&["-f", "-"], ruleset)
+}
+
+/// Checks if the kernel and nft support the NAT chain `redirect_dns` needs.
+pub fn supports_dns_redirect() -> bool {
+    let script = format!(
+        "table inet {table}_check {{\n    chain nat_output {{\n        {header}\n    }}\n}}\n",
+        table = TABLE_NAME,
+        header = NAT_CHAIN_HEADER
+    );
+    run_nft(&["--check", "-f", "-"], &script).is_ok()
+}
+
+/// Removes our table and all rules in it. Does nothing if the table does not exist.
+pub fn remove() -> Result<()> {
+    run_nft(
+        &["-f", "-"],
+        &format!(
+            "table inet {table}\ndelete table inet {table}\n",
+            table = TABLE_NAME
+        ),
+    )
+}
+
+/// Checks if our table is loaded, by listing the tables. Cheap, since the rules in them are not
+/// listed, but that also means a table whose chains have been flushed still counts as loaded.
+pub fn is_loaded() -> Result<bool> {
+    let output = duct::cmd("nft", &["list", "tables"])
+        .stdout_capture()
+        .stderr_capture()
+        .unchecked()
+        .run()
+        .chain_err(|| ErrorKind::NftFailed(String::from("Unable to start nft")))?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr).trim().to_owned();
+        bail!(ErrorKind::NftFailed(stderr));
+    }
+    let our_table = format!("table inet {}", TABLE_NAME);
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .any(|line| line.trim() == our_table))
+}
+
+fn run_nft(args: &[&str], script: &str) -> Result<()> {
+    let output = duct::cmd("nft", args)
+        .input(script)
+        .stdout_null()
+        .stderr_capture()
+        .unchecked()
+        .run()
+        .chain_err(|| ErrorKind::NftFailed(String::from("Unable to start nft")))?;
+    if output.status.success() {
+        Ok(())
+    } else {
+        let stderr = String::from_utf8_lossy(&output.stderr).trim().to_owned();
+        bail!(ErrorKind::NftFailed(stderr));
+    }
+}
+
+/// Returns the nft script blocking all traffic not allowed by the given policy.
+///
+/// With `redirect_dns`, all DNS lookups are redirected to the tunnel resolvers while connected,
+/// so they go through the tunnel without changing the DNS config of the system.
+pub fn ruleset(policy: &SecurityPolicy, redirect_dns: bool) -> String {
+    let mut output_rules = vec!["oif \"lo\" accept".to_owned()];
+    output_rules.extend(
+        NETWORK_CONFIG_OUTPUT_RULES
+            .iter()
+            .map(|rule| rule.to_string()),
+    );
+    let mut input_rules = vec!["iif \"lo\" accept".to_owned()];
+    input_rules.extend(
+        NETWORK_CONFIG_INPUT_RULES
+            .iter()
+            .map(|rule| rule.to_string()),
+    );
+    input_rules.push("ct state established,related accept".to_owned());
+
+    // Excluded traffic is let through before anything is blocked. Replies to it are let in by
+    // the connection tracking rule above.
+    let exclusions = policy.exclusions();
+    let excluded_marks = excluded_marks(exclusions);
+    output_rules.extend(
+        excluded_marks
+            .iter()
+            .map(|mark| format!("meta mark {:#x} accept", mark)),
+    );
+
+    let (allow_lan, allow_ipv6) = match *policy {
+        SecurityPolicy::Connecting {
+            ref relay_endpoint,
+            allow_lan,
+            allow_ipv6,
+            ..
+        }
+        | SecurityPolicy::Connected {
+            ref relay_endpoint,
+            allow_lan,
+            allow_ipv6,
+            ..
+        } => {
+            output_rules.push(allow_relay_rule(relay_endpoint));
+            (allow_lan, allow_ipv6)
+        }
+        SecurityPolicy::Blocked {
+            allow_lan,
+            allow_ipv6,
+            ..
+        } => (allow_lan, allow_ipv6),
+    };
+
+    // Everything below is tunnel and LAN traffic. Without IPv6 in the tunnel, native IPv6 could
+    // leak around it, so only the IPv6 traffic allowed above gets through.
+    if !allow_ipv6 {
+        output_rules.push("meta nfproto ipv6 drop".to_owned());
+        input_rules.push("meta nfproto ipv6 drop".to_owned());
+    }
+
+    let mut nat_rules = Vec::new();
+    if let SecurityPolicy::Connected {
+        ref relay_endpoint,
+        ref tunnel,
+        keep_system_nameservers,
+        ..
+    } = *policy
+    {
+        let dns_servers = policy.dns_servers();
+        if redirect_dns {
+            nat_rules = redirect_dns_rules(relay_endpoint, &dns_servers, &excluded_marks);
+        }
+        for server in dns_servers {
+            for protocol in &["udp", "tcp"] {
+                output_rules.push(format!(
+                    "oif \"{}\" {} daddr {} {} dport 53 accept",
+                    tunnel.interface,
+                    ip_family(&server),
+                    server,
+                    protocol
+                ));
+            }
+        }
+        if !keep_system_nameservers {
+            output_rules.push("udp dport 53 drop".to_owned());
+            output_rules.push("tcp dport 53 drop".to_owned());
+        }
+        output_rules.push(format!("oif \"{}\" accept", tunnel.interface));
+        input_rules.push(format!("iif \"{}\" accept", tunnel.interface));
+    }
+
+    // Comes after the DNS rules, so DNS servers on the LAN can not be used even when LAN access
+    // is allowed, since that would leak lookups outside the tunnel. Unless the policy keeps the
+    // system nameservers, in which case that leak is accepted.
+    if allow_lan {
+        output_rules.extend(allow_lan_output_rules(allow_ipv6));
+        input_rules.extend(allow_lan_input_rules(allow_ipv6));
+    }
+
+    // Declaring the table before deleting it makes the delete work even if it did not exist.
+    let mut script = format!(
+        "table inet {table}\ndelete table inet {table}\ntable inet {table} {{\n",
+        table = TABLE_NAME
+    );
+    write_chain(
+        &mut script,
+        "output",
+        &filter_chain_header("output"),
+        &output_rules,
+    );
+    write_chain(
+        &mut script,
+        "input",
+        &filter_chain_header("input"),
+        &input_rules,
+    );
+    if !nat_rules.is_empty() {
+        write_chain(&mut script, "nat_output", NAT_CHAIN_HEADER, &nat_rules);
+    }
+    let mark_rules = cgroup_mark_rules(exclusions);
+    if !mark_rules.is_empty() {
+        write_chain(&mut script, "mark_output", MARK_CHAIN_HEADER, &mark_rules);
+    }
+    script.push_str("}\n");
+    script
+}
+
+fn allow_lan_output_rules(allow_ipv6: bool) -> Vec<String> {
+    let mut rules = vec![
+        format!("ip daddr {} accept", LAN_NETS_V4),
+        format!("ip daddr {} accept", LAN_MULTICAST_V4),
+    ];
+    if allow_ipv6 {
+        rules.push(format!("ip6 daddr {} accept", LAN_NETS_V6));
+        rules.push(format!("ip6 daddr {} accept", LAN_MULTICAST_V6));
+    }
+    rules
+}
+
+fn allow_lan_input_rules(allow_ipv6: bool) -> Vec<String> {
+    let mut rules = vec![format!("ip saddr {} accept", LAN_NETS_V4)];
+    if allow_ipv6 {
+        rules.push(format!("ip6 saddr {} accept", LAN_NETS_V6));
+    }
+    rules
+}
+
+/// Rules sending all DNS lookups to the tunnel resolvers instead of wherever they were headed.
+/// Lookups to local resolvers are left alone, since those forward them and the forwarded lookups
+/// get redirected. So is the connection to the relay, which can use port 53 too.
+///
+/// A lookup can only be redirected to one resolver, so only the first of the given resolvers of
+/// each IP version is used. Lookups with any of the `excluded_marks` are not redirected, since
+/// they go outside the tunnel.
+fn redirect_dns_rules(
+    relay_endpoint: &Endpoint,
+    resolvers: &[IpAddr],
+    excluded_marks: &[u32],
+) -> Vec<String> {
+    let relay_address = relay_endpoint.address.ip();
+    let mut rules = vec![
+        "oif \"lo\" return".to_owned(),
+        format!(
+            "{} daddr {} return",
+            ip_family(&relay_address),
+            relay_address
+        ),
+    ];
+    rules.extend(
+        excluded_marks
+            .iter()
+            .map(|mark| format!("meta mark {:#x} return", mark)),
+    );
+    let first_v4 = resolvers.iter().find(|resolver| resolver.is_ipv4());
+    let first_v6 = resolvers.iter().find(|resolver| resolver.is_ipv6());
+    for resolver in first_v4.into_iter().chain(first_v6) {
+        let nfproto = match *resolver {
+            IpAddr::V4(_) => "ipv4",
+            IpAddr::V6(_) => "ipv6",
+        };
+        for protocol in &["udp", "tcp"] {
+            rules.push(format!(
+                "meta nfproto {} {} dport 53 dnat {} to {}",
+                nfproto,
+                protocol,
+                ip_family(resolver),
+                resolver
+            ));
+        }
+    }
+    rules
+}
+
+/// Returns the firewall marks of all excluded traffic, without duplicates.
+fn excluded_marks(exclusions: &[SplitTunnelExclusion]) -> Vec<u32> {
+    let mut marks = Vec::new();
+    for exclusion in exclusions {
+        let mark = match *exclusion {
+            SplitTunnelExclusion::Fwmark(mark) => mark,
+            SplitTunnelExclusion::NetClsCgroup(_) => EXCLUDED_MARK,
+        };
+        if !marks.contains(&mark) {
+            marks.push(mark);
+        }
+    }
+    marks
+}
+
+/// Rules giving the packets from excluded cgroups the `EXCLUDED_MARK`.
+fn cgroup_mark_rules(exclusions: &[SplitTunnelExclusion]) -> Vec<String> {
+    exclusions
+        .iter()
+        .filter_map(|exclusion| match *exclusion {
+            SplitTunnelExclusion::NetClsCgroup(classid) => Some(format!(
+                "meta cgroup {:#x} meta mark set {:#x}",
+                classid, EXCLUDED_MARK
+            )),
+            SplitTunnelExclusion::Fwmark(_) => None,
+        })
+        .collect()
+}
+
+fn allow_relay_rule(relay_endpoint: &Endpoint) -> String {
+    let protocol = match relay_endpoint.protocol {
+        TransportProtocol::Udp => "udp",
+        TransportProtocol::Tcp => "tcp",
+    };
+    let address = relay_endpoint.address.ip();
+    format!(
+        "{} daddr {} {} dport {} accept",
+        ip_family(&address),
+        address,
+        protocol,
+        relay_endpoint.address.port()
+    )
+}
+
+fn ip_family(address: &IpAddr) -> &'static str {
+    match *address {
+        IpAddr::V4(_) => "ip",
+        IpAddr::V6(_) => "ip6",
+    }
+}
+
+fn filter_chain_header(hook: &str) -> String {
+    format!("type filter hook {} priority 0; policy drop;", hook)
+}
+
+fn write_chain(script: &mut String, name: &str, header: &str, rules: &[String]) {
+    writeln!(script, "    chain {} {{", name).unwrap();
+    writeln!(script, "        {}", header).unwrap();
+    for rule in rules {
+        writeln!(script, "        {}", rule).unwrap();
+    }
+    writeln!(script, "    }}").unwrap();
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tunnel::TunnelMetadata;
+
+    fn relay_endpoint() -> Endpoint {
+        Endpoint::new(
+            "185.65.134.1".parse::<IpAddr>().unwrap(),
+            1194,
+            TransportProtocol::Udp,
+        )
+    }
+
+    #[test]
+    fn connecting_ruleset() {
+        let policy = SecurityPolicy::Connecting {
+            relay_endpoint: relay_endpoint(),
+            allow_lan: false,
+            allow_ipv6: true,
+            exclusions: vec![],
+        };
+        assert_eq!(
+            ruleset(&policy, false),
+            r#"table inet mullvad
+delete table inet mullvad
+table inet mullvad {
+    chain output {
+        type filter hook output priority 0; policy drop;
+        oif "lo" accept
+        meta nfproto ipv4 udp sport 68 udp dport 67 accept
+        ip6 saddr fe80::/10 udp sport 546 udp dport 547 accept
+        ip6 daddr ff02::2 icmpv6 type nd-router-solicit ip6 hoplimit 255 accept
+        icmpv6 type { nd-neighbor-solicit, nd-neighbor-advert } ip6 hoplimit 255 accept
+        ip daddr 185.65.134.1 udp dport 1194 accept
+    }
+    chain input {
+        type filter hook input priority 0; policy drop;
+        iif "lo" accept
+        meta nfproto ipv4 udp sport 67 udp dport 68 accept
+        ip6 daddr fe80::/10 udp sport 547 udp dport 546 accept
+        ip6 saddr fe80::/10 icmpv6 type nd-router-advert ip6 hoplimit 255 accept
+        icmpv6 type { nd-neighbor-solicit, nd-neighbor-advert } ip6 hoplimit 255 accept
+        ct state established,related accept
+    }
+}
+"#
+        );
+    }
+
+    #[test]
+    fn blocked_ruleset() {
+        let policy = SecurityPolicy::Blocked {
+            allow_lan: false,
+            allow_ipv6: true,
+            exclusions: vec![],
+        };
+        assert_eq!(
+            ruleset(&policy, false),
+            r#"table inet mullvad
+delete table inet mullvad
+table inet mullvad {
+    chain output {
+        type filter hook output priority 0; policy drop;
+        oif "lo" accept
+        meta nfproto ipv4 udp sport 68 udp dport 67 accept
+        ip6 saddr fe80::/10 udp sport 546 udp dport 547 accept
+        ip6 daddr ff02::2 icmpv6 type nd-router-solicit ip6 hoplimit 255 accept
+        icmpv6 type { nd-neighbor-solicit, nd-neighbor-advert } ip6 hoplimit 255 accept
+    }
+    chain input {
+        type filter hook input priority 0; policy drop;
+        iif "lo" accept
+        meta nfproto ipv4 udp sport 67 udp dport 68 accept
+        ip6 daddr fe80::/10 udp sport 547 udp dport 546 accept
+        ip6 saddr fe80::/10 icmpv6 type nd-router-advert ip6 hoplimit 255 accept
+        icmpv6 type { nd-neighbor-solicit, nd-neighbor-advert } ip6 hoplimit 255 accept
+        ct state established,related accept
+    }
+}
+"#
+        );
+    }
+
+    #[test]
+    fn network_configuration_is_allowed() {
+        for policy in vec![
+            SecurityPolicy::Connecting {
+                relay_endpoint: relay_endpoint(),
+                allow_lan: false,
+                allow_ipv6: true,
+                exclusions: vec![],
+            },
+            connected_policy(false),
+            SecurityPolicy::Blocked {
+                allow_lan: false,
+                allow_ipv6: true,
+                exclusions: vec![],
+            },
+        ] {
+            let ruleset = ruleset(&policy, false);
+            let (output_chain, input_chain) = chains(&ruleset);
+            assert!(output_chain.contains(" meta nfproto ipv4 udp sport 68 udp dport 67 accept\n"));
+            assert!(input_chain.contains(" meta nfproto ipv4 udp sport 67 udp dport 68 accept\n"));
+            assert!(
+                output_chain.contains(" ip6 saddr fe80::/10 udp sport 546 udp dport 547 accept\n")
+            );
+            assert!(
+                input_chain.contains(" ip6 daddr fe80::/10 udp sport 547 udp dport 546 accept\n")
+            );
+            assert!(output_chain.contains(
+                " ip6 daddr ff02::2 icmpv6 type nd-router-solicit ip6 hoplimit 255 accept\n"
+            ));
+            assert!(input_chain.contains(
+                " ip6 saddr fe80::/10 icmpv6 type nd-router-advert ip6 hoplimit 255 accept\n"
+            ));
+            for chain in &[output_chain, input_chain] {
+                assert!(chain.contains(
+                    " icmpv6 type { nd-neighbor-solicit, nd-neighbor-advert } ip6 hoplimit 255 \
+                     accept\n"
+                ));
+            }
+            // No other ICMPv6 types, like echo requests or redirects, are let through.
+            assert_eq!(ruleset.matches("icmpv6").count(), 4);
+        }
+    }
+
+    #[test]
+    fn policies_are_switched_in_one_transaction() {
+        // A reconnect goes from connected to blocked or connecting. Every step must replace the
+        // old rules in the same script that adds the new ones, or traffic could leak in between.
+        for policy in vec![
+            connected_policy(false),
+            SecurityPolicy::Blocked {
+                allow_lan: false,
+                allow_ipv6: true,
+                exclusions: vec![],
+            },
+            SecurityPolicy::Connecting {
+                relay_endpoint: relay_endpoint(),
+                allow_lan: false,
+                allow_ipv6: true,
+                exclusions: vec![],
+            },
+        ] {
+            let ruleset = ruleset(&policy, false);
+            assert!(ruleset.starts_with(
+                "table inet mullvad\ndelete table inet mullvad\ntable inet mullvad {\n"
+            ));
+            assert_eq!(ruleset.matches("policy drop;").count(), 2);
+        }
+    }
+
+    #[test]
+    fn connected_ruleset() {
+        let policy = SecurityPolicy::Connected {
+            relay_endpoint: Endpoint::new(
+                "2a03:1b20:1:f011::a01f".parse::<IpAddr>().unwrap(),
+                443,
+                TransportProtocol::Tcp,
+            ),
+            tunnel: TunnelMetadata {
+                interface: "tun0".to_owned(),
+                ip: "10.8.0.2".parse().unwrap(),
+                gateway: "10.8.0.1".parse().unwrap(),
+                ipv6_gateway: Some("fdda:d0d0:cafe:1194::1".parse().unwrap()),
+            },
+            dns_servers: vec![
+                "10.8.0.1".parse().unwrap(),
+                "fdda:d0d0:cafe:1194::1".parse().unwrap(),
+            ],
+            allow_lan: false,
+            allow_ipv6: true,
+            keep_system_nameservers: false,
+            exclusions: vec![],
+        };
+        assert_eq!(
+            ruleset(&policy, false),
+            r#"table inet mullvad
+delete table inet mullvad
+table inet mullvad {
+    chain output {
+        type filter hook output priority 0; policy drop;
+        oif "lo" accept
+        meta nfproto ipv4 udp sport 68 udp dport 67 accept
+        ip6 saddr fe80::/10 udp sport 546 udp dport 547 accept
+        ip6 daddr ff02::2 icmpv6 type nd-router-solicit ip6 hoplimit 255 accept
+        icmpv6 type { nd-neighbor-solicit, nd-neighbor-advert } ip6 hoplimit 255 accept
+        ip6 daddr 2a03:1b20:1:f011::a01f tcp dport 443 accept
+        oif "tun0" ip daddr 10.8.0.1 udp dport 53 accept
+        oif "tun0" ip daddr 10.8.0.1 tcp dport 53 accept
+        oif "tun0" ip6 daddr fdda:d0d0:cafe:1194::1 udp dport 53 accept
+        oif "tun0" ip6 daddr fdda:d0d0:cafe:1194::1 tcp dport 53 accept
+        udp dport 53 drop
+        tcp dport 53 drop
+        oif "tun0" accept
+    }
+    chain input {
+        type filter hook input priority 0; policy drop;
+        iif "lo" accept
+        meta nfproto ipv4 udp sport 67 udp dport 68 accept
+        ip6 daddr fe80::/10 udp sport 547 udp dport 546 accept
+        ip6 saddr fe80::/10 icmpv6 type nd-router-advert ip6 hoplimit 255 accept
+        icmpv6 type { nd-neighbor-solicit, nd-neighbor-advert } ip6 hoplimit 255 accept
+        ct state established,related accept
+        iif "tun0" accept
+    }
+}
+"#
+        );
+    }
+
+    fn connected_policy(allow_lan: bool) -> SecurityPolicy {
+        connected_policy_with_ipv6(allow_lan, true)
+    }
+
+    fn connected_policy_with_ipv6(allow_lan: bool, allow_ipv6: bool) -> SecurityPolicy {
+        SecurityPolicy::Connected {
+            relay_endpoint: relay_endpoint(),
+            tunnel: TunnelMetadata {
+                interface: "tun0".to_owned(),
+                ip: "10.8.0.2".parse().unwrap(),
+                gateway: "10.8.0.1".parse().unwrap(),
+                ipv6_gateway: Some("fdda:d0d0:cafe:1194::1".parse().unwrap()),
+            },
+            dns_servers: vec![
+                "10.8.0.1".parse().unwrap(),
+                "fdda:d0d0:cafe:1194::1".parse().unwrap(),
+            ],
+            allow_lan,
+            allow_ipv6,
+            keep_system_nameservers: false,
+            exclusions: vec![],
+        }
+    }
+
+    /// Splits a ruleset into its output and input chains.
+    fn chains(ruleset: &str) -> (&str, &str) {
+        ruleset.split_at(ruleset.find("chain input").unwrap())
+    }
+
+    #[test]
+    fn allow_lan_ruleset() {
+        for policy in vec![
+            SecurityPolicy::Connecting {
+                relay_endpoint: relay_endpoint(),
+                allow_lan: true,
+                allow_ipv6: true,
+                exclusions: vec![],
+            },
+            connected_policy(true),
+            SecurityPolicy::Blocked {
+                allow_lan: true,
+                allow_ipv6: true,
+                exclusions: vec![],
+            },
+        ] {
+            let ruleset = ruleset(&policy, false);
+            let (output_chain, input_chain) = chains(&ruleset);
+            assert!(output_chain.contains(
+                "ip daddr { 10.0.0.0/8, 172.16.0.0/12, 192.168.0.0/16, 169.254.0.0/16 } accept\n"
+            ));
+            assert!(output_chain.contains("ip daddr { 224.0.0.0/24, 239.255.255.250 } accept\n"));
+            assert!(output_chain.contains("ip6 daddr fe80::/10 accept\n"));
+            assert!(output_chain.contains("ip6 daddr ff02::/16 accept\n"));
+            assert!(input_chain.contains(
+                "ip saddr { 10.0.0.0/8, 172.16.0.0/12, 192.168.0.0/16, 169.254.0.0/16 } accept\n"
+            ));
+            assert!(input_chain.contains("ip6 saddr fe80::/10 accept\n"));
+        }
+    }
+
+    #[test]
+    fn lan_is_blocked_without_allow_lan() {
+        for policy in vec![
+            SecurityPolicy::Connecting {
+                relay_endpoint: relay_endpoint(),
+                allow_lan: false,
+                allow_ipv6: true,
+                exclusions: vec![],
+            },
+            connected_policy(false),
+            SecurityPolicy::Blocked {
+                allow_lan: false,
+                allow_ipv6: true,
+                exclusions: vec![],
+            },
+        ] {
+            let ruleset = ruleset(&policy, false);
+            for net in &[
+                "10.0.0.0/8",
+                "192.168.0.0/16",
+                "224.0.0.0/24",
+                "fe80::/10 accept",
+                "ff02::/16",
+            ] {
+                assert!(!ruleset.contains(net), "{} allowed in {}", net, ruleset);
+            }
+        }
+    }
+
+    #[test]
+    fn lan_dns_is_blocked_with_allow_lan() {
+        let ruleset = ruleset(&connected_policy(true), false);
+        let (output_chain, _) = chains(&ruleset);
+        let dns_drop = output_chain.find("udp dport 53 drop").unwrap();
+        let lan_accept = output_chain.find("ip daddr { 10.0.0.0/8").unwrap();
+        assert!(dns_drop < lan_accept);
+    }
+
+    #[test]
+    fn lan_dns_is_allowed_when_keeping_system_nameservers() {
+        let mut policy = connected_policy(true);
+        if let SecurityPolicy::Connected {
+            ref mut keep_system_nameservers,
+            ..
+        } = policy
+        {
+            *keep_system_nameservers = true;
+        }
+        let ruleset = ruleset(&policy, false);
+        assert!(!ruleset.contains("dport 53 drop"));
+        assert!(ruleset.contains("ip daddr { 10.0.0.0/8"));
+        assert!(ruleset.contains("oif \"tun0\" accept"));
+    }
+
+    #[test]
+    fn ipv6_is_blocked_when_disabled() {
+        let ruleset = ruleset(&connected_policy_with_ipv6(true, false), false);
+        let (output_chain, input_chain) = chains(&ruleset);
+        for chain in &[output_chain, input_chain] {
+            let ipv6_drop = chain.find("meta nfproto ipv6 drop\n").unwrap();
+            let tunnel_accept = chain.find("\"tun0\"").unwrap();
+            assert!(ipv6_drop < tunnel_accept);
+        }
+        assert!(!ruleset.contains("fdda:d0d0:cafe:1194::1"));
+        assert!(!ruleset.contains("fe80::/10 accept"));
+        assert!(!ruleset.contains("ff02::/16 accept"));
+        // Still allowed when LAN access is allowed, since IPv4 does not leak.
+        assert!(output_chain.contains("ip daddr { 10.0.0.0/8"));
+
+        let ipv6_ruleset = super::ruleset(&connected_policy_with_ipv6(false, true), false);
+        assert!(!ipv6_ruleset.contains("meta nfproto ipv6 drop"));
+        assert!(ipv6_ruleset.contains("ip6 daddr fdda:d0d0:cafe:1194::1 udp dport 53 accept"));
+    }
+
+    #[test]
+    fn link_local_ipv6_is_allowed_when_ipv6_is_disabled() {
+        let ruleset = ruleset(
+            &SecurityPolicy::Blocked {
+                allow_lan: true,
+                allow_ipv6: false,
+                exclusions: vec![],
+            },
+            false,
+        );
+        let (output_chain, input_chain) = chains(&ruleset);
+        for chain in &[output_chain, input_chain] {
+            let ipv6_drop = chain.find("meta nfproto ipv6 drop\n").unwrap();
+            let neighbor_discovery = chain.find("icmpv6 type { nd-neighbor-solicit").unwrap();
+            let dhcpv6 = chain.find("udp sport 54").unwrap();
+            assert!(neighbor_discovery < ipv6_drop);
+            assert!(dhcpv6 < ipv6_drop);
+        }
+        let ipv6_drop = output_chain.find("meta nfproto ipv6 drop\n").unwrap();
+        assert!(output_chain.find("nd-router-solicit").unwrap() < ipv6_drop);
+        let ipv6_drop = input_chain.find("meta nfproto ipv6 drop\n").unwrap();
+        assert!(input_chain.find("nd-router-advert").unwrap() < ipv6_drop);
+    }
+
+    #[test]
+    fn dns_is_redirected_to_tunnel() {
+        let ruleset = ruleset(&connected_policy_with_ipv6(false, true), true);
+        let nat_chain = &ruleset[ruleset.find("    chain nat_output").unwrap()..];
+        assert_eq!(
+            nat_chain,
+            r#"    chain nat_output {
+        type nat hook output priority -100; policy accept;
+        oif "lo" return
+        ip daddr 185.65.134.1 return
+        meta nfproto ipv4 udp dport 53 dnat ip to 10.8.0.1
+        meta nfproto ipv4 tcp dport 53 dnat ip to 10.8.0.1
+        meta nfproto ipv6 udp dport 53 dnat ip6 to fdda:d0d0:cafe:1194::1
+        meta nfproto ipv6 tcp dport 53 dnat ip6 to fdda:d0d0:cafe:1194::1
+    }
+}
+"#
+        );
+        // Redirected lookups still have to pass the output filter chain.
+        assert!(ruleset.contains("oif \"tun0\" ip daddr 10.8.0.1 udp dport 53 accept"));
+
+        let ipv4_ruleset = super::ruleset(&connected_policy_with_ipv6(false, false), true);
+        assert!(!ipv4_ruleset.contains("dnat ip6"));
+    }
+
+    #[test]
+    fn all_dns_servers_are_allowed_in_order() {
+        let mut policy = connected_policy_with_ipv6(false, false);
+        if let SecurityPolicy::Connected {
+            ref mut dns_servers,
+            ..
+        } = policy
+        {
+            dns_servers.insert(1, "10.9.0.1".parse().unwrap());
+        }
+        let ruleset = ruleset(&policy, true);
+        let first = ruleset
+            .find("oif \"tun0\" ip daddr 10.8.0.1 udp dport 53 accept")
+            .unwrap();
+        let second = ruleset
+            .find("oif \"tun0\" ip daddr 10.9.0.1 udp dport 53 accept")
+            .unwrap();
+        assert!(first < second);
+        // Lookups can only be redirected to one of them.
+        assert!(ruleset.contains("udp dport 53 dnat ip to 10.8.0.1"));
+        assert!(!ruleset.contains("dnat ip to 10.9.0.1"));
+    }
+
+    #[test]
+    fn excluded_traffic_is_let_through() {
+        let exclusions = vec![
+            SplitTunnelExclusion::Fwmark(0x1234),
+            SplitTunnelExclusion::NetClsCgroup(0x10_0001),
+            SplitTunnelExclusion::NetClsCgroup(0x10_0002),
+        ];
+        let mut policy = connected_policy(false);
+        if let SecurityPolicy::Connected {
+            exclusions: ref mut policy_exclusions,
+            ..
+        } = policy
+        {
+            *policy_exclusions = exclusions.clone();
+        }
+        let ruleset = ruleset(&policy, true);
+        let (output_chain, _) = chains(&ruleset);
+        let dns_drop = output_chain.find("udp dport 53 drop").unwrap();
+        for rule in &["meta mark 0x1234 accept\n", "meta mark 0x6d6f6c65 accept\n"] {
+            assert!(output_chain.find(rule).unwrap() < dns_drop);
+        }
+        assert_eq!(output_chain.matches("meta mark").count(), 2);
+        assert!(ruleset.contains(
+            "        ip daddr 185.65.134.1 return
+        meta mark 0x1234 return
+        meta mark 0x6d6f6c65 return
+"
+        ));
+        assert!(ruleset.contains(
+            r#"    chain mark_output {
+        type route hook output priority -150; policy accept;
+        meta cgroup 0x100001 meta mark set 0x6d6f6c65
+        meta cgroup 0x100002 meta mark set 0x6d6f6c65
+    }
+"#
+        ));
+
+        // The kill switch does not block it either.
+        let blocked_ruleset = super::ruleset(
+            &SecurityPolicy::Blocked {
+                allow_lan: false,
+                allow_ipv6: true,
+                exclusions,
+            },
+            false,
+        );
+        assert!(blocked_ruleset.contains("meta mark 0x1234 accept\n"));
+        assert!(blocked_ruleset.contains("chain mark_output"));
+        assert!(!super::ruleset(&connected_policy(false), false).contains("meta mark"));
+    }
+
+    #[test]
+    fn dns_is_only_redirected_when_connected() {
+        for policy in &[
+            SecurityPolicy::Connecting {
+                relay_endpoint: relay_endpoint(),
+                allow_lan: false,
+                allow_ipv6: true,
+                exclusions: vec![],
+            },
+            SecurityPolicy::Blocked {
+                allow_lan: false,
+                allow_ipv6: true,
+                exclusions: vec![],
+            },
+        ] {
+            assert!(!ruleset(policy, true).contains("nat"));
+        }
+        assert!(!ruleset(&connected_policy(false), false).contains("nat"));
+    }
+}
@@ -0,0 +1,169 @@
+use error_chain::ChainedError;
+
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use super::nftables::Result;
+
+/// How often to check that our rules are still loaded, unless told otherwise.
+pub const DEFAULT_CHECK_INTERVAL_MS: u64 = 2000;
+
+
+/// Loads and removes our nftables rules, and puts them back if some other program removes them,
+/// like by running `nft flush ruleset`. Whether they are still loaded is checked regularly in a
+/// background thread, which stops when this is dropped.
+///
+/// Only a missing ruleset is noticed. What counts as loaded is up to the `is_loaded` check, and
+/// the one used for nftables only looks for our table, so chains or rules removed from inside
+/// the table are not put back.
+pub struct RulesetMonitor {
+    rules: Arc<Rules>,
+    interval_tx: mpsc::Sender<Duration>,
+}
+
+/// The ruleset that should be loaded, and the ways of loading, removing and looking for it.
+struct Rules {
+    /// The ruleset we last loaded, or `None` if our rules should not be loaded. Locked while
+    /// loading or removing rules, so the monitor can not put back rules being replaced.
+    current: Mutex<Option<String>>,
+    load: Box<Fn(&str) -> Result<()> + Send + Sync>,
+    remove: Box<Fn() -> Result<()> + Send + Sync>,
+    is_loaded: Box<Fn() -> Result<bool> + Send + Sync>,
+}
+
+impl RulesetMonitor {
+    /// Starts checking every `interval` that the rules are loaded, using `is_loaded`. Rules are
+    /// loaded with `load` and removed with `remove`.
+    pub fn spawn<L, R, C>(interval: Duration, load: L, remove: R, is_loaded: C) -> Self
+    where
+        L: Fn(&str) -> Result<()> + Send + Sync + 'static,
+        R: Fn() -> Result<()> + Send + Sync + 'static,
+        C: Fn() -> Result<bool> + Send + Sync + 'static,
+    {
+        let rules = Arc::new(Rules {
+            current: Mutex::new(None),
+            load: Box::new(load),
+            remove: Box::new(remove),
+            is_loaded: Box::new(is_loaded),
+        });
+        let (interval_tx, interval_rx) = mpsc::channel();
+        let thread_rules = rules.clone();
+        thread::spawn(move || {
+            let mut interval = interval;
+            loop {
+                match interval_rx.recv_timeout(interval) {
+                    Ok(new_interval) => interval = new_interval,
+                    Err(RecvTimeoutError::Timeout) => thread_rules.restore_if_missing(),
+                    Err(RecvTimeoutError::Disconnected) => break,
+                }
+            }
+            trace!("Ruleset monitor thread exit");
+        });
+        RulesetMonitor { rules, interval_tx }
+    }
+
+    /// Changes how often the rules are checked. Takes effect immediately, the next check happens
+    /// `interval` from now.
+    pub fn set_interval(&self, interval: Duration) {
+        let _ = self.interval_tx.send(interval);
+    }
+
+    /// Loads `ruleset`, replacing our rules, and keeps it loaded.
+    pub fn load(&self, ruleset: String) -> Result<()> {
+        let mut current = self.rules.current.lock().unwrap();
+        (self.rules.load)(&ruleset)?;
+        *current = Some(ruleset);
+        Ok(())
+    }
+
+    /// Removes our rules, and stops keeping them loaded.
+    pub fn remove(&self) -> Result<()> {
+        let mut current = self.rules.current.lock().unwrap();
+        *current = None;
+        (self.rules.remove)()
+    }
+}
+
+impl Rules {
+    fn restore_if_missing(&self) {
+        let current = self.current.lock().unwrap();
+        let ruleset = match *current {
+            Some(ref ruleset) => ruleset,
+            None => return,
+        };
+        match (self.is_loaded)() {
+            Ok(true) => (),
+            Ok(false) => {
+                warn!("The firewall rules were removed by another program, loading them again");
+                if let Err(error) = (self.load)(ruleset) {
+                    error!(
+                        "Unable to load the firewall rules again: {}",
+                        error.display_chain()
+                    );
+                }
+            }
+            Err(error) => debug!(
+                "Unable to check if the firewall rules are loaded: {}",
+                error.display_chain()
+            ),
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Instant;
+
+    /// Stands in for the rules loaded in the kernel.
+    #[derive(Clone, Default)]
+    struct Kernel(Arc<Mutex<Option<String>>>);
+
+    impl Kernel {
+        fn rules(&self) -> Option<String> {
+            self.0.lock().unwrap().clone()
+        }
+
+        fn monitor(&self, interval: Duration) -> RulesetMonitor {
+            let (load_kernel, remove_kernel, check_kernel) =
+                (self.clone(), self.clone(), self.clone());
+            RulesetMonitor::spawn(
+                interval,
+                move |ruleset| {
+                    *load_kernel.0.lock().unwrap() = Some(ruleset.to_owned());
+                    Ok(())
+                },
+                move || {
+                    *remove_kernel.0.lock().unwrap() = None;
+                    Ok(())
+                },
+                move || Ok(check_kernel.rules().is_some()),
+            )
+        }
+    }
+
+    #[test]
+    fn restores_removed_rules() {
+        let kernel = Kernel::default();
+        let interval = Duration::from_millis(10);
+        let monitor = kernel.monitor(interval);
+        monitor.load("ruleset".to_owned()).unwrap();
+
+        // Another program flushes the ruleset.
+        *kernel.0.lock().unwrap() = None;
+        let start = Instant::now();
+        while kernel.rules().is_none() {
+            assert!(start.elapsed() < Duration::from_secs(5), "Never restored");
+            thread::sleep(interval);
+        }
+        assert_eq!(kernel.rules(), Some("ruleset".to_owned()));
+
+        // Rules we removed ourselves are not put back.
+        monitor.remove().unwrap();
+        thread::sleep(interval * 10);
+        assert_eq!(kernel.rules(), None);
+    }
+}
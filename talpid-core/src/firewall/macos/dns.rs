@@ -35,6 +35,11 @@ struct State {
     backup: HashMap<ServicePath, Option<Vec<DnsServer>>>,
 }
 
+/// Sets DNS through the SystemConfiguration dynamic store, backing up and restoring the earlier
+/// settings of every network service. The tunnel interface OpenVPN creates has no network service
+/// of its own, so there is no tunnel service to set DNS on. Lookups go through the services of
+/// the physical interfaces, which is why this sets the servers on all of them instead of having a
+/// separate interface for just the tunnel.
 pub struct DnsMonitor {
     store: SCDynamicStore,
 
@@ -239,27 +244,39 @@ fn set_dns(store: &SCDynamicStore, path: CFString, servers: &[DnsServer]) -> Res
 
 /// Read all existing DNS settings and return them.
 fn read_all_dns(store: &SCDynamicStore) -> HashMap<ServicePath, Option<Vec<DnsServer>>> {
-    let mut backup = HashMap::new();
-    // Backup all "state" DNS, and all corresponding "setup" DNS even if they don't exist
-    if let Some(paths) = store.get_keys(STATE_PATH_PATTERN) {
-        for state_path in paths.iter() {
-            let state_path_str = state_path.to_string();
-            let setup_path_str = state_to_setup_path(&state_path_str).unwrap();
-            let setup_path = CFString::new(&setup_path_str);
-            backup.insert(state_path_str, read_dns(store, state_path.clone()));
-            backup.insert(setup_path_str, read_dns(store, setup_path));
-        }
+    let state_paths = read_keys(store, STATE_PATH_PATTERN);
+    let setup_paths = read_keys(store, SETUP_PATH_PATTERN);
+    paths_to_back_up(&state_paths, &setup_paths)
+        .into_iter()
+        .map(|path| {
+            let servers = read_dns(store, CFString::new(&path));
+            (path, servers)
+        })
+        .collect()
+}
+
+fn read_keys(store: &SCDynamicStore, pattern: &str) -> Vec<ServicePath> {
+    store
+        .get_keys(pattern)
+        .map(|paths| paths.iter().map(|path| path.to_string()).collect())
+        .unwrap_or_default()
+}
+
+/// Returns the paths whose DNS settings have to be backed up, given the paths that have DNS
+/// settings now. That is all "state" DNS, all corresponding "setup" DNS even if they don't
+/// exist, since they are written together with the "state" DNS, and all other "setup" DNS.
+fn paths_to_back_up(state_paths: &[ServicePath], setup_paths: &[ServicePath]) -> Vec<ServicePath> {
+    let mut paths = Vec::new();
+    for state_path in state_paths {
+        paths.push(state_path.clone());
+        paths.extend(state_to_setup_path(state_path));
     }
-    // Backup all "setup" DNS not already covered
-    if let Some(paths) = store.get_keys(SETUP_PATH_PATTERN) {
-        for setup_path in paths.iter() {
-            let setup_path_str = setup_path.to_string();
-            if !backup.contains_key(&setup_path_str) {
-                backup.insert(setup_path_str, read_dns(store, setup_path.clone()));
-            }
+    for setup_path in setup_paths {
+        if !paths.contains(setup_path) {
+            paths.push(setup_path.clone());
         }
     }
-    backup
+    paths
 }
 
 fn state_to_setup_path(state_path: &str) -> Option<String> {
@@ -305,3 +322,45 @@ fn parse_cf_array_to_strings(array: CFArray<CFType>) -> Option<Vec<String>> {
     }
     Some(strings)
 }
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn paths(paths: &[&str]) -> Vec<ServicePath> {
+        paths.iter().map(|path| path.to_string()).collect()
+    }
+
+    #[test]
+    fn setup_path_of_state_path() {
+        assert_eq!(
+            state_to_setup_path("State:/Network/Service/0A1B/DNS"),
+            Some("Setup:/Network/Service/0A1B/DNS".to_owned())
+        );
+        assert_eq!(state_to_setup_path("Setup:/Network/Service/0A1B/DNS"), None);
+    }
+
+    #[test]
+    fn backs_up_setup_paths_of_all_services() {
+        let state_paths = paths(&[
+            "State:/Network/Service/A/DNS",
+            "State:/Network/Service/B/DNS",
+        ]);
+        let setup_paths = paths(&[
+            "Setup:/Network/Service/B/DNS",
+            "Setup:/Network/Service/C/DNS",
+        ]);
+        assert_eq!(
+            paths_to_back_up(&state_paths, &setup_paths),
+            paths(&[
+                "State:/Network/Service/A/DNS",
+                "Setup:/Network/Service/A/DNS",
+                "State:/Network/Service/B/DNS",
+                "Setup:/Network/Service/B/DNS",
+                "Setup:/Network/Service/C/DNS",
+            ])
+        );
+        assert!(paths_to_back_up(&[], &[]).is_empty());
+    }
+}
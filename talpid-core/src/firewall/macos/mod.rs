@@ -43,15 +43,41 @@ impl Firewall for PacketFilter {
     fn apply_policy(&mut self, policy: SecurityPolicy) -> Result<()> {
         self.enable()?;
         self.add_anchor()?;
-        self.set_rules(policy)
+        self.set_rules(&policy)?;
+        self.set_dns(&policy)
+    }
+
+    fn preview_policy(&self, policy: &SecurityPolicy) -> Vec<String> {
+        let mut preview = match Self::get_rules(policy) {
+            Ok(rules) => rules
+                .iter()
+                .map(|rule| format!("pfctl anchor {}: {:?}", ANCHOR_NAME, rule))
+                .collect(),
+            Err(error) => vec![format!("Unable to build the PF rules: {}", error)],
+        };
+        match *policy {
+            SecurityPolicy::Connected { .. } => {
+                let servers = policy
+                    .dns_servers()
+                    .iter()
+                    .map(|server| server.to_string())
+                    .collect::<Vec<_>>();
+                preview.push(format!("set DNS to [{}]", servers.join(", ")))
+            }
+            SecurityPolicy::Blocked { .. } => preview.push("reset DNS".to_owned()),
+            SecurityPolicy::Connecting { .. } => (),
+        }
+        preview
     }
 
     fn reset_policy(&mut self) -> Result<()> {
+        // DNS is restored first, while the rules still keep lookups to the tunnel nameservers
+        // from leaking out over the default route.
         vec![
+            self.restore_dns(),
             self.remove_rules(),
             self.remove_anchor(),
             self.restore_state(),
-            self.restore_dns(),
         ].into_iter()
             .collect::<Result<Vec<_>>>()
             .map(|_| ())
@@ -59,32 +85,50 @@ impl Firewall for PacketFilter {
 }
 
 impl PacketFilter {
-    fn set_rules(&mut self, policy: SecurityPolicy) -> Result<()> {
+    fn set_rules(&mut self, policy: &SecurityPolicy) -> Result<()> {
+        let mut anchor_change = pfctl::AnchorChange::new();
+        anchor_change.set_filter_rules(Self::get_rules(policy)?);
+        Ok(self.pf.set_rules(ANCHOR_NAME, anchor_change)?)
+    }
+
+    fn set_dns(&mut self, policy: &SecurityPolicy) -> Result<()> {
+        match *policy {
+            SecurityPolicy::Connected { .. } => {
+                let servers = policy
+                    .dns_servers()
+                    .iter()
+                    .map(|server| server.to_string())
+                    .collect();
+                Ok(self.dns_monitor.set_dns(servers)?)
+            }
+            SecurityPolicy::Blocked { .. } => self.restore_dns(),
+            SecurityPolicy::Connecting { .. } => Ok(()),
+        }
+    }
+
+    /// Returns all the filter rules enforcing the policy. Both applying and previewing a policy
+    /// go through here, so the preview always shows the rules that would be applied.
+    fn get_rules(policy: &SecurityPolicy) -> Result<Vec<pfctl::FilterRule>> {
         let mut new_filter_rules = vec![];
 
         new_filter_rules.append(&mut Self::get_allow_loopback_rules()?);
         new_filter_rules.append(&mut Self::get_allow_dhcp_rules()?);
-        new_filter_rules.append(&mut self.get_policy_specific_rules(policy)?);
+        new_filter_rules.append(&mut Self::get_policy_specific_rules(policy)?);
 
         let drop_all_rule = pfctl::FilterRuleBuilder::default()
             .action(pfctl::FilterRuleAction::Drop)
             .quick(true)
             .build()?;
         new_filter_rules.push(drop_all_rule);
-
-        let mut anchor_change = pfctl::AnchorChange::new();
-        anchor_change.set_filter_rules(new_filter_rules);
-        Ok(self.pf.set_rules(ANCHOR_NAME, anchor_change)?)
+        Ok(new_filter_rules)
     }
 
-    fn get_policy_specific_rules(
-        &mut self,
-        policy: SecurityPolicy,
-    ) -> Result<Vec<pfctl::FilterRule>> {
-        match policy {
+    fn get_policy_specific_rules(policy: &SecurityPolicy) -> Result<Vec<pfctl::FilterRule>> {
+        match *policy {
             SecurityPolicy::Connecting {
-                relay_endpoint,
+                ref relay_endpoint,
                 allow_lan,
+                ..
             } => {
                 let mut rules = vec![Self::get_allow_relay_rule(relay_endpoint)?];
                 if allow_lan {
@@ -93,28 +137,25 @@ impl PacketFilter {
                 Ok(rules)
             }
             SecurityPolicy::Connected {
-                relay_endpoint,
-                tunnel,
+                ref relay_endpoint,
+                ref tunnel,
                 allow_lan,
+                ..
             } => {
-                self.dns_monitor.set_dns(vec![tunnel.gateway.to_string()])?;
-
-                let allow_tcp_dns_to_relay_rule = pfctl::FilterRuleBuilder::default()
-                    .action(pfctl::FilterRuleAction::Pass)
-                    .direction(pfctl::Direction::Out)
-                    .quick(true)
-                    .interface(&tunnel.interface)
-                    .proto(pfctl::Proto::Tcp)
-                    .to(pfctl::Endpoint::new(tunnel.gateway, 53))
-                    .build()?;
-                let allow_udp_dns_to_relay_rule = pfctl::FilterRuleBuilder::default()
-                    .action(pfctl::FilterRuleAction::Pass)
-                    .direction(pfctl::Direction::Out)
-                    .quick(true)
-                    .interface(&tunnel.interface)
-                    .proto(pfctl::Proto::Udp)
-                    .to(pfctl::Endpoint::new(tunnel.gateway, 53))
-                    .build()?;
+                let mut rules = vec![];
+                for server in policy.dns_servers() {
+                    for proto in &[pfctl::Proto::Tcp, pfctl::Proto::Udp] {
+                        let allow_dns_rule = pfctl::FilterRuleBuilder::default()
+                            .action(pfctl::FilterRuleAction::Pass)
+                            .direction(pfctl::Direction::Out)
+                            .quick(true)
+                            .interface(&tunnel.interface)
+                            .proto(*proto)
+                            .to(pfctl::Endpoint::new(server, 53))
+                            .build()?;
+                        rules.push(allow_dns_rule);
+                    }
+                }
                 let block_tcp_dns_rule = pfctl::FilterRuleBuilder::default()
                     .action(pfctl::FilterRuleAction::Drop)
                     .direction(pfctl::Direction::Out)
@@ -130,24 +171,27 @@ impl PacketFilter {
                     .to(pfctl::Port::from(53))
                     .build()?;
 
-                let mut rules = vec![
-                    allow_tcp_dns_to_relay_rule,
-                    allow_udp_dns_to_relay_rule,
+                rules.append(&mut vec![
                     block_tcp_dns_rule,
                     block_udp_dns_rule,
                     Self::get_allow_relay_rule(relay_endpoint)?,
                     Self::get_allow_tunnel_rule(tunnel.interface.as_str())?,
-                ];
+                ]);
 
                 if allow_lan {
                     rules.append(&mut Self::get_allow_lan_rules()?);
                 }
                 Ok(rules)
             }
+            SecurityPolicy::Blocked { allow_lan, .. } => if allow_lan {
+                Self::get_allow_lan_rules()
+            } else {
+                Ok(vec![])
+            },
         }
     }
 
-    fn get_allow_relay_rule(relay_endpoint: net::Endpoint) -> Result<pfctl::FilterRule> {
+    fn get_allow_relay_rule(relay_endpoint: &net::Endpoint) -> Result<pfctl::FilterRule> {
         let pfctl_proto = as_pfctl_proto(relay_endpoint.protocol);
 
         Ok(pfctl::FilterRuleBuilder::default()
@@ -1,3 +1,4 @@
+use std::net::IpAddr;
 use talpid_types::net::Endpoint;
 
 
@@ -10,6 +11,11 @@ pub enum SecurityPolicy {
         relay_endpoint: Endpoint,
         /// Flag setting if communication with LAN networks should be possible.
         allow_lan: bool,
+        /// Flag setting if IPv6 traffic should be possible. Link-local traffic needed to
+        /// configure the network is always allowed.
+        allow_ipv6: bool,
+        /// Traffic that should go outside the tunnel and be let through anyway.
+        exclusions: Vec<SplitTunnelExclusion>,
     },
 
     /// Allow traffic only to relay server and over tunnel interface
@@ -18,9 +24,75 @@ pub enum SecurityPolicy {
         relay_endpoint: Endpoint,
         /// Metadata about the tunnel and tunnel interface.
         tunnel: ::tunnel::TunnelMetadata,
+        /// The nameservers to use, reached through the tunnel. In the order they should be
+        /// tried, so later ones are only used if earlier ones do not respond. Usually the tunnel
+        /// gateways.
+        dns_servers: Vec<IpAddr>,
         /// Flag setting if communication with LAN networks should be possible.
         allow_lan: bool,
+        /// Flag setting if IPv6 traffic should be possible, in the tunnel and on the LAN. Should
+        /// only be set if the tunnel carries IPv6. Link-local traffic needed to configure the
+        /// network is always allowed.
+        allow_ipv6: bool,
+        /// Flag setting if the nameservers of the system should be kept after the tunnel
+        /// nameservers. Lookups to them go outside the tunnel, and are only allowed to nameservers
+        /// on the LAN when communication with LAN networks is allowed.
+        keep_system_nameservers: bool,
+        /// Traffic that should go outside the tunnel and be let through anyway.
+        exclusions: Vec<SplitTunnelExclusion>,
     },
+
+    /// Block all traffic, used when the tunnel is down but the user does not want any traffic
+    /// to leave the computer unprotected
+    Blocked {
+        /// Flag setting if communication with LAN networks should be possible.
+        allow_lan: bool,
+        /// Flag setting if IPv6 traffic should be possible. Link-local traffic needed to
+        /// configure the network is always allowed.
+        allow_ipv6: bool,
+        /// Traffic that should go outside the tunnel and be let through anyway.
+        exclusions: Vec<SplitTunnelExclusion>,
+    },
+}
+
+/// Traffic let through the firewall no matter the policy, so certain programs can bypass the
+/// tunnel. Only the firewall is affected, so the excluded traffic also has to be routed outside
+/// the tunnel, like with a routing rule for the firewall mark. Only implemented on Linux.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum SplitTunnelExclusion {
+    /// Packets with the given firewall mark.
+    Fwmark(u32),
+    /// Packets sent by processes in the net_cls cgroup with the given class id. The packets are
+    /// also given a firewall mark, so they can be routed.
+    NetClsCgroup(u32),
+}
+
+impl SecurityPolicy {
+    /// Returns the nameservers DNS should point at under this policy, in priority order. IPv6
+    /// nameservers are left out unless IPv6 traffic is allowed. Empty unless connected.
+    pub fn dns_servers(&self) -> Vec<IpAddr> {
+        match *self {
+            SecurityPolicy::Connected {
+                ref dns_servers,
+                allow_ipv6,
+                ..
+            } => dns_servers
+                .iter()
+                .filter(|server| allow_ipv6 || server.is_ipv4())
+                .cloned()
+                .collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    /// Returns the traffic that should bypass the tunnel under this policy.
+    pub fn exclusions(&self) -> &[SplitTunnelExclusion] {
+        match *self {
+            SecurityPolicy::Connecting { ref exclusions, .. }
+            | SecurityPolicy::Connected { ref exclusions, .. }
+            | SecurityPolicy::Blocked { ref exclusions, .. } => exclusions,
+        }
+    }
 }
 
 /// Abstract firewall interaction trait
@@ -36,6 +108,10 @@ pub trait Firewall {
     /// Enable firewall and set firewall rules based on SecurityPolicy
     fn apply_policy(&mut self, policy: SecurityPolicy) -> ::std::result::Result<(), Self::Error>;
 
+    /// Returns the commands `apply_policy` would run to enforce the given policy, without
+    /// running them. Meant for debugging.
+    fn preview_policy(&self, policy: &SecurityPolicy) -> Vec<String>;
+
     /// Remove firewall rules applied by active SecurityPolicy and
     /// revert firewall to its original state
     fn reset_policy(&mut self) -> ::std::result::Result<(), Self::Error>;
@@ -50,7 +126,8 @@ pub use self::macos::{Error, ErrorKind, PacketFilter as FirewallProxy, Result};
 #[cfg(target_os = "linux")]
 mod linux;
 #[cfg(target_os = "linux")]
-pub use self::linux::{Error, ErrorKind, Netfilter as FirewallProxy, Result};
+pub use self::linux::{restore_dns_if_needed, restore_dns_if_needed_at, DnsEvent, Error,
+                      ErrorKind, Netfilter as FirewallProxy, Result};
 
 #[cfg(windows)]
 mod windows;
@@ -15,6 +15,10 @@ impl Firewall for WindowsFirewall {
         Ok(())
     }
 
+    fn preview_policy(&self, _policy: &SecurityPolicy) -> Vec<String> {
+        Vec::new()
+    }
+
     fn reset_policy(&mut self) -> Result<()> {
         Ok(())
     }
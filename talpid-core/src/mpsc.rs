@@ -1,5 +1,11 @@
 use std::marker::PhantomData;
 use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+/// The window `debounce` is usually given, long enough to cover the bursts of writes done when
+/// a program replaces a config file.
+pub const DEFAULT_DEBOUNCE_WINDOW_MS: u64 = 200;
 
 /// Abstraction over an `mpsc::Sender` that first converts the value to another type before sending.
 #[derive(Debug, Clone)]
@@ -30,11 +36,40 @@ where
     }
 }
 
+/// Forwards values from `rx` to `tx` on a new thread, coalescing bursts. Values are only
+/// forwarded once no new value has arrived within `window`, and then only the last value of the
+/// burst is sent. When `rx` disconnects, any value still held back is forwarded before the thread
+/// exits.
+pub fn debounce<T: Send + 'static>(
+    rx: mpsc::Receiver<T>,
+    tx: mpsc::Sender<T>,
+    window: Duration,
+) -> thread::JoinHandle<()> {
+    thread::spawn(move || {
+        while let Ok(mut latest) = rx.recv() {
+            loop {
+                match rx.recv_timeout(window) {
+                    Ok(value) => latest = value,
+                    Err(mpsc::RecvTimeoutError::Timeout) => break,
+                    Err(mpsc::RecvTimeoutError::Disconnected) => {
+                        let _ = tx.send(latest);
+                        return;
+                    }
+                }
+            }
+            if tx.send(latest).is_err() {
+                return;
+            }
+        }
+    })
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use std::sync::mpsc;
     use std::thread;
+    use std::time::Duration;
 
     #[derive(Debug, Eq, PartialEq)]
     enum Inner {
@@ -77,4 +112,42 @@ mod tests {
 
         assert_eq!(Outer::Inner(Inner::One), rx.recv().unwrap());
     }
+
+    #[test]
+    fn debounce_coalesces_bursts() {
+        let (event_tx, event_rx) = mpsc::channel();
+        let (tx, rx) = mpsc::channel();
+        for event in 0..5 {
+            event_tx.send(event).unwrap();
+        }
+        debounce(
+            event_rx,
+            tx,
+            Duration::from_millis(DEFAULT_DEBOUNCE_WINDOW_MS),
+        );
+
+        assert_eq!(rx.recv(), Ok(4));
+        assert_eq!(
+            rx.recv_timeout(Duration::from_millis(2 * DEFAULT_DEBOUNCE_WINDOW_MS)),
+            Err(mpsc::RecvTimeoutError::Timeout)
+        );
+
+        event_tx.send(5).unwrap();
+        event_tx.send(6).unwrap();
+        assert_eq!(rx.recv(), Ok(6));
+    }
+
+    #[test]
+    fn debounce_delivers_last_value_on_disconnect() {
+        let (event_tx, event_rx) = mpsc::channel();
+        let (tx, rx) = mpsc::channel();
+        event_tx.send(1).unwrap();
+        event_tx.send(2).unwrap();
+        drop(event_tx);
+        debounce(event_rx, tx, Duration::from_secs(60))
+            .join()
+            .unwrap();
+
+        assert_eq!(rx.try_iter().collect::<Vec<_>>(), vec![2]);
+    }
 }
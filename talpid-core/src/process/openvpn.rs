@@ -110,7 +110,7 @@ impl OpenVpnCommand {
 
     /// Sets extra options
     pub fn set_tunnel_options(&mut self, tunnel_options: &net::OpenVpnTunnelOptions) -> &mut Self {
-        self.tunnel_options = *tunnel_options;
+        self.tunnel_options = tunnel_options.clone();
         self
     }
 
@@ -150,6 +150,10 @@ impl OpenVpnCommand {
             args.push(OsString::from("--mssfix"));
             args.push(OsString::from(mssfix.to_string()));
         }
+        if let Some(fragment) = self.tunnel_options.fragment {
+            args.push(OsString::from("--fragment"));
+            args.push(OsString::from(fragment.to_string()));
+        }
 
         args.extend(Self::security_arguments().iter().map(OsString::from));
 
@@ -217,7 +221,7 @@ mod tests {
     use super::OpenVpnCommand;
     use std::ffi::OsString;
     use std::net::Ipv4Addr;
-    use talpid_types::net::{Endpoint, TransportProtocol};
+    use talpid_types::net::{Endpoint, OpenVpnTunnelOptions, TransportProtocol};
 
     #[test]
     fn passes_one_remote() {
@@ -244,4 +248,20 @@ mod tests {
         assert!(testee_args.contains(&OsString::from("123")));
         assert!(testee_args.contains(&OsString::from("cde")));
     }
+
+    #[test]
+    fn passes_tunnel_options() {
+        let options = OpenVpnTunnelOptions {
+            mssfix: Some(1400),
+            fragment: Some(1300),
+            proxy: None,
+        };
+        let testee_args = OpenVpnCommand::new("")
+            .set_tunnel_options(&options)
+            .get_arguments();
+        assert!(testee_args.contains(&OsString::from("--mssfix")));
+        assert!(testee_args.contains(&OsString::from("1400")));
+        assert!(testee_args.contains(&OsString::from("--fragment")));
+        assert!(testee_args.contains(&OsString::from("1300")));
+    }
 }
@@ -9,7 +9,7 @@ use std::env;
 use std::ffi::{OsStr, OsString};
 use std::fs;
 use std::io::{self, Write};
-use std::net::Ipv4Addr;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
 use std::path::{Path, PathBuf};
 
 use talpid_types::net::{Endpoint, OpenVpnTunnelOptions, TunnelEndpoint, TunnelEndpointData,
@@ -67,6 +67,17 @@ pub struct TunnelMetadata {
     pub ip: Ipv4Addr,
     /// The IP to the default gateway on the tunnel interface.
     pub gateway: Ipv4Addr,
+    /// The IPv6 gateway on the tunnel interface, if the tunnel carries IPv6.
+    pub ipv6_gateway: Option<Ipv6Addr>,
+}
+
+impl TunnelMetadata {
+    /// Returns the gateways on the tunnel interface, the IPv4 one first.
+    pub fn gateways(&self) -> Vec<IpAddr> {
+        let mut gateways = vec![IpAddr::V4(self.gateway)];
+        gateways.extend(self.ipv6_gateway.map(IpAddr::V6));
+        gateways
+    }
 }
 
 impl TunnelEvent {
@@ -89,10 +100,16 @@ impl TunnelEvent {
                     .expect("No \"route_vpn_gateway\" in tunnel up event")
                     .parse()
                     .expect("Tunnel gateway IP not in valid format");
+                let ipv6_gateway = env.get("ifconfig_ipv6_remote").map(|gateway| {
+                    gateway
+                        .parse()
+                        .expect("Tunnel IPv6 gateway IP not in valid format")
+                });
                 Some(TunnelEvent::Up(TunnelMetadata {
                     interface,
                     ip,
                     gateway,
+                    ipv6_gateway,
                 }))
             }
             OpenVpnPluginEvent::RoutePredown => Some(TunnelEvent::Down),
@@ -9,4 +9,7 @@
 #[macro_use]
 extern crate serde_derive;
 
+#[cfg(test)]
+extern crate serde_json;
+
 pub mod net;
@@ -126,21 +126,471 @@ impl Error for TransportProtocolParseError {
     }
 }
 
+/// The smallest and largest values accepted for the OpenVPN `mssfix` option.
+pub const MSSFIX_RANGE: (u16, u16) = (1000, 1450);
+/// The smallest and largest values accepted for the OpenVPN `fragment` option.
+pub const FRAGMENT_RANGE: (u16, u16) = (1000, 1500);
+/// The smallest and largest MTU accepted for WireGuard tunnel interfaces. The lower bound is the
+/// smallest MTU allowed for IPv6.
+pub const WIREGUARD_MTU_RANGE: (u16, u16) = (1280, 1500);
+
 /// TunnelOptions holds optional settings for tunnels, that are to be applied to any tunnel of the
 /// appropriate type.
-#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize, Default)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Default)]
+#[serde(default)]
 pub struct TunnelOptions {
     /// openvpn holds OpenVPN specific tunnel options.
     pub openvpn: OpenVpnTunnelOptions,
+    /// wireguard holds WireGuard specific tunnel options.
+    pub wireguard: WireguardTunnelOptions,
+    /// If IPv6 traffic should be allowed through the tunnel. Disabled unless explicitly enabled,
+    /// since IPv6 can leak outside the tunnel on networks where it is misconfigured.
+    pub enable_ipv6: bool,
+    /// If the nameservers of the system should be kept after the tunnel nameservers instead of
+    /// being replaced, so a resolver on the LAN can be used while connected. Lookups sent to
+    /// them do not go through the tunnel, so they can leak which names are looked up. They are
+    /// only let through to nameservers on the LAN, and only when LAN access is allowed.
+    pub keep_system_nameservers: bool,
+}
+
+impl TunnelOptions {
+    /// Returns these options with IPv6 in the tunnel enabled or disabled.
+    pub fn with_ipv6(mut self, enable_ipv6: bool) -> Self {
+        self.enable_ipv6 = enable_ipv6;
+        self
+    }
+
+    /// Checks that the options for all tunnel types are valid. Every problem found is returned,
+    /// not only the first one.
+    pub fn validate(&self) -> Result<(), Vec<InvalidTunnelOptions>> {
+        let mut errors = self.openvpn.validation_errors();
+        errors.extend(self.wireguard.validate().err());
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
 }
 
 
 /// OpenVpnTunnelOptions contains options for an openvpn tunnel that should be applied irrespective
 /// of the relay parameters - i.e. have nothing to do with the particular OpenVPN server, but do
 /// affect the connection.
-#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Serialize, Deserialize, Default)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize, Default)]
+#[serde(default)]
 pub struct OpenVpnTunnelOptions {
     /// Optional argument for openvpn to try and limit TCP packet size,
     /// as discussed [here](https://openvpn.net/archive/openvpn-users/2003-11/msg00154.html)
     pub mssfix: Option<u16>,
+    /// Optional argument for openvpn to fragment UDP packets larger than this many bytes inside
+    /// the tunnel.
+    pub fragment: Option<u16>,
+    /// A proxy to send the OpenVPN traffic through.
+    pub proxy: Option<ProxySettings>,
+}
+
+impl OpenVpnTunnelOptions {
+    /// Checks that `mssfix` and `fragment` are within `MSSFIX_RANGE` and `FRAGMENT_RANGE`, and
+    /// that the proxy settings are valid. Returns the first problem found.
+    pub fn validate(&self) -> Result<(), InvalidTunnelOptions> {
+        match self.validation_errors().into_iter().next() {
+            Some(error) => Err(error),
+            None => Ok(()),
+        }
+    }
+
+    /// Returns all problems checked for by `validate`.
+    fn validation_errors(&self) -> Vec<InvalidTunnelOptions> {
+        let mut errors = Vec::new();
+        if let Some(mssfix) = self.mssfix {
+            if !in_range(mssfix, MSSFIX_RANGE) {
+                errors.push(InvalidTunnelOptions::MssfixOutOfRange(mssfix));
+            }
+        }
+        if let Some(fragment) = self.fragment {
+            if !in_range(fragment, FRAGMENT_RANGE) {
+                errors.push(InvalidTunnelOptions::FragmentOutOfRange(fragment));
+            }
+        }
+        if let Some(ref proxy) = self.proxy {
+            if let Err(error) = proxy.validate() {
+                errors.push(InvalidTunnelOptions::InvalidProxy(error));
+            }
+        }
+        errors
+    }
+}
+
+/// WireguardTunnelOptions contains options for a WireGuard tunnel that should be applied
+/// irrespective of the relay parameters.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Serialize, Deserialize, Default)]
+#[serde(default)]
+pub struct WireguardTunnelOptions {
+    /// The MTU of the tunnel interface. The WireGuard default is used if not set.
+    pub mtu: Option<u16>,
+    /// How often, in seconds, to send keepalive packets to the peer. No keepalive packets are sent
+    /// if not set.
+    pub keepalive: Option<u16>,
+}
+
+impl WireguardTunnelOptions {
+    /// Checks that `mtu` is within `WIREGUARD_MTU_RANGE`.
+    pub fn validate(&self) -> Result<(), InvalidTunnelOptions> {
+        match self.mtu {
+            Some(mtu) if !in_range(mtu, WIREGUARD_MTU_RANGE) => {
+                Err(InvalidTunnelOptions::MtuOutOfRange(mtu))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+fn in_range(value: u16, (min, max): (u16, u16)) -> bool {
+    min <= value && value <= max
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InvalidTunnelOptions {
+    MssfixOutOfRange(u16),
+    FragmentOutOfRange(u16),
+    MtuOutOfRange(u16),
+    InvalidProxy(InvalidProxySettings),
+}
+
+impl fmt::Display for InvalidTunnelOptions {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            InvalidTunnelOptions::MssfixOutOfRange(mssfix) => write!(
+                fmt,
+                "mssfix {} is not between {} and {}",
+                mssfix, MSSFIX_RANGE.0, MSSFIX_RANGE.1
+            ),
+            InvalidTunnelOptions::FragmentOutOfRange(fragment) => write!(
+                fmt,
+                "fragment {} is not between {} and {}",
+                fragment, FRAGMENT_RANGE.0, FRAGMENT_RANGE.1
+            ),
+            InvalidTunnelOptions::MtuOutOfRange(mtu) => write!(
+                fmt,
+                "MTU {} is not between {} and {}",
+                mtu, WIREGUARD_MTU_RANGE.0, WIREGUARD_MTU_RANGE.1
+            ),
+            InvalidTunnelOptions::InvalidProxy(ref error) => error.fmt(fmt),
+        }
+    }
+}
+
+impl Error for InvalidTunnelOptions {
+    fn description(&self) -> &str {
+        match *self {
+            InvalidTunnelOptions::MssfixOutOfRange(_) => "mssfix out of range",
+            InvalidTunnelOptions::FragmentOutOfRange(_) => "fragment out of range",
+            InvalidTunnelOptions::MtuOutOfRange(_) => "MTU out of range",
+            InvalidTunnelOptions::InvalidProxy(_) => "Invalid proxy settings",
+        }
+    }
+
+    fn cause(&self) -> Option<&Error> {
+        match *self {
+            InvalidTunnelOptions::InvalidProxy(ref error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+
+/// Settings for a proxy that the tunnel traffic is sent through, for example to get past
+/// firewalls blocking VPN traffic.
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ProxySettings {
+    Socks5 {
+        /// The address of the proxy server, as an IP and a port.
+        address: String,
+        /// The username and password to authenticate with, if the proxy requires it.
+        auth: Option<(String, String)>,
+    },
+}
+
+impl ProxySettings {
+    /// Checks that the proxy address is a valid socket address and that the authentication, if
+    /// given, has both a username and a password.
+    pub fn validate(&self) -> Result<(), InvalidProxySettings> {
+        match *self {
+            ProxySettings::Socks5 {
+                ref address,
+                ref auth,
+            } => {
+                if address.parse::<SocketAddr>().is_err() {
+                    return Err(InvalidProxySettings::InvalidAddress(address.clone()));
+                }
+                if let Some((ref username, ref password)) = *auth {
+                    if username.is_empty() || password.is_empty() {
+                        return Err(InvalidProxySettings::IncompleteAuth);
+                    }
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InvalidProxySettings {
+    InvalidAddress(String),
+    IncompleteAuth,
+}
+
+impl fmt::Display for InvalidProxySettings {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            InvalidProxySettings::InvalidAddress(ref address) => {
+                write!(fmt, "Invalid proxy address: {}", address)
+            }
+            InvalidProxySettings::IncompleteAuth => fmt.write_str(self.description()),
+        }
+    }
+}
+
+impl Error for InvalidProxySettings {
+    fn description(&self) -> &str {
+        match *self {
+            InvalidProxySettings::InvalidAddress(_) => "Invalid proxy address",
+            InvalidProxySettings::IncompleteAuth => {
+                "Both a username and a password must be given for proxy authentication"
+            }
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json;
+
+    fn socks5(address: &str, auth: Option<(&str, &str)>) -> ProxySettings {
+        ProxySettings::Socks5 {
+            address: address.to_owned(),
+            auth: auth.map(|(username, password)| (username.to_owned(), password.to_owned())),
+        }
+    }
+
+    fn round_trip(options: &OpenVpnTunnelOptions) -> OpenVpnTunnelOptions {
+        let json = serde_json::to_string(options).unwrap();
+        serde_json::from_str(&json).unwrap()
+    }
+
+    #[test]
+    fn openvpn_options_without_new_fields() {
+        let options: OpenVpnTunnelOptions = serde_json::from_str(r#"{"mssfix": 1400}"#).unwrap();
+        assert_eq!(
+            options,
+            OpenVpnTunnelOptions {
+                mssfix: Some(1400),
+                fragment: None,
+                proxy: None,
+            }
+        );
+        assert_eq!(round_trip(&options), options);
+
+        let options: TunnelOptions = serde_json::from_str(r#"{"openvpn": {}}"#).unwrap();
+        assert_eq!(options, TunnelOptions::default());
+    }
+
+    #[test]
+    fn wireguard_mtu_bounds() {
+        let mtu = |mtu| WireguardTunnelOptions {
+            mtu: Some(mtu),
+            keepalive: None,
+        };
+        assert_eq!(mtu(1280).validate(), Ok(()));
+        assert_eq!(mtu(1420).validate(), Ok(()));
+        assert_eq!(mtu(1500).validate(), Ok(()));
+        assert_eq!(
+            mtu(1279).validate(),
+            Err(InvalidTunnelOptions::MtuOutOfRange(1279))
+        );
+        assert_eq!(
+            mtu(1501).validate(),
+            Err(InvalidTunnelOptions::MtuOutOfRange(1501))
+        );
+        assert_eq!(WireguardTunnelOptions::default().validate(), Ok(()));
+
+        let options = TunnelOptions {
+            wireguard: mtu(9000),
+            ..TunnelOptions::default()
+        };
+        assert_eq!(
+            options.validate(),
+            Err(vec![InvalidTunnelOptions::MtuOutOfRange(9000)])
+        );
+    }
+
+    #[test]
+    fn validate_reports_all_problems() {
+        let options = TunnelOptions {
+            openvpn: OpenVpnTunnelOptions {
+                mssfix: Some(9000),
+                fragment: Some(0),
+                proxy: Some(socks5("localhost", None)),
+            },
+            wireguard: WireguardTunnelOptions {
+                mtu: Some(100),
+                keepalive: None,
+            },
+            enable_ipv6: false,
+            keep_system_nameservers: false,
+        };
+        assert_eq!(
+            options.validate(),
+            Err(vec![
+                InvalidTunnelOptions::MssfixOutOfRange(9000),
+                InvalidTunnelOptions::FragmentOutOfRange(0),
+                InvalidTunnelOptions::InvalidProxy(InvalidProxySettings::InvalidAddress(
+                    "localhost".to_owned(),
+                )),
+                InvalidTunnelOptions::MtuOutOfRange(100),
+            ])
+        );
+        assert_eq!(
+            options.openvpn.validate(),
+            Err(InvalidTunnelOptions::MssfixOutOfRange(9000))
+        );
+
+        let options = TunnelOptions {
+            openvpn: OpenVpnTunnelOptions {
+                fragment: Some(1300),
+                proxy: Some(socks5("127.0.0.1:1080", Some(("user", "")))),
+                ..OpenVpnTunnelOptions::default()
+            },
+            ..TunnelOptions::default()
+        };
+        assert_eq!(
+            options.validate(),
+            Err(vec![
+                InvalidTunnelOptions::InvalidProxy(InvalidProxySettings::IncompleteAuth),
+            ])
+        );
+        assert_eq!(TunnelOptions::default().validate(), Ok(()));
+    }
+
+    #[test]
+    fn combined_tunnel_options_round_trip() {
+        let options = TunnelOptions {
+            openvpn: OpenVpnTunnelOptions {
+                mssfix: Some(1400),
+                fragment: None,
+                proxy: None,
+            },
+            wireguard: WireguardTunnelOptions {
+                mtu: Some(1380),
+                keepalive: Some(25),
+            },
+            enable_ipv6: true,
+            keep_system_nameservers: true,
+        };
+        let json = serde_json::to_string(&options).unwrap();
+        assert_eq!(
+            serde_json::from_str::<TunnelOptions>(&json).unwrap(),
+            options
+        );
+
+        let options: TunnelOptions =
+            serde_json::from_str(r#"{"openvpn": {"mssfix": 1400}}"#).unwrap();
+        assert_eq!(options.wireguard, WireguardTunnelOptions::default());
+    }
+
+    #[test]
+    fn ipv6_is_disabled_by_default() {
+        let options: TunnelOptions =
+            serde_json::from_str(r#"{"openvpn": {"mssfix": 1400}}"#).unwrap();
+        assert!(!options.enable_ipv6);
+        assert_eq!(options.openvpn.mssfix, Some(1400));
+        assert!(!TunnelOptions::default().enable_ipv6);
+
+        let options = TunnelOptions::default().with_ipv6(true);
+        let json = serde_json::to_string(&options).unwrap();
+        assert_eq!(
+            serde_json::from_str::<TunnelOptions>(&json).unwrap(),
+            options
+        );
+        assert!(options.enable_ipv6);
+        assert!(!options.with_ipv6(false).enable_ipv6);
+    }
+
+    #[test]
+    fn openvpn_options_with_new_fields() {
+        let options = OpenVpnTunnelOptions {
+            mssfix: Some(1400),
+            fragment: Some(1300),
+            proxy: Some(socks5("127.0.0.1:1080", Some(("user", "pass")))),
+        };
+        assert_eq!(round_trip(&options), options);
+
+        let json = r#"{
+            "mssfix": null,
+            "fragment": 1300,
+            "proxy": {"socks5": {"address": "127.0.0.1:1080", "auth": null}}
+        }"#;
+        let options: OpenVpnTunnelOptions = serde_json::from_str(json).unwrap();
+        assert_eq!(options.mssfix, None);
+        assert_eq!(options.fragment, Some(1300));
+        assert_eq!(options.proxy, Some(socks5("127.0.0.1:1080", None)));
+    }
+
+    #[test]
+    fn validate_openvpn_options() {
+        let valid = OpenVpnTunnelOptions {
+            mssfix: Some(MSSFIX_RANGE.0),
+            fragment: Some(FRAGMENT_RANGE.1),
+            proxy: Some(socks5("127.0.0.1:1080", None)),
+        };
+        assert_eq!(valid.validate(), Ok(()));
+        assert_eq!(OpenVpnTunnelOptions::default().validate(), Ok(()));
+
+        let invalid = [
+            (
+                OpenVpnTunnelOptions {
+                    mssfix: Some(MSSFIX_RANGE.0 - 1),
+                    ..valid.clone()
+                },
+                InvalidTunnelOptions::MssfixOutOfRange(MSSFIX_RANGE.0 - 1),
+            ),
+            (
+                OpenVpnTunnelOptions {
+                    mssfix: Some(MSSFIX_RANGE.1 + 1),
+                    ..valid.clone()
+                },
+                InvalidTunnelOptions::MssfixOutOfRange(MSSFIX_RANGE.1 + 1),
+            ),
+            (
+                OpenVpnTunnelOptions {
+                    fragment: Some(0),
+                    ..valid.clone()
+                },
+                InvalidTunnelOptions::FragmentOutOfRange(0),
+            ),
+            (
+                OpenVpnTunnelOptions {
+                    proxy: Some(socks5("localhost", None)),
+                    ..valid.clone()
+                },
+                InvalidTunnelOptions::InvalidProxy(InvalidProxySettings::InvalidAddress(
+                    "localhost".to_owned(),
+                )),
+            ),
+            (
+                OpenVpnTunnelOptions {
+                    proxy: Some(socks5("127.0.0.1:1080", Some(("user", "")))),
+                    ..valid.clone()
+                },
+                InvalidTunnelOptions::InvalidProxy(InvalidProxySettings::IncompleteAuth),
+            ),
+        ];
+        for &(ref options, ref expected) in invalid.iter() {
+            assert_eq!(options.validate().as_ref(), Err(expected), "{:?}", options);
+        }
+    }
 }